@@ -0,0 +1,451 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use super::calls::CallSite;
+use super::flow::{FlowGraph, Transfer};
+use crate::json::Json;
+use crate::{ConditionType, Instruction, Program};
+
+/// How control gets from one basic block to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// The next instruction, after one that doesn't jump or a branch that isn't taken.
+    FallThrough,
+    Jump,
+    /// A conditional jump that's taken.
+    Branch(ConditionType),
+    /// A JUMP@ whose target was resolved.
+    Computed,
+}
+
+impl EdgeKind {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            EdgeKind::FallThrough => "fall-through",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Branch(_) => "branch",
+            EdgeKind::Computed => "computed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+/// Instructions that always run one after the other, from `start` to `end` (included).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    pub successors: Vec<Edge>,
+    /// The CALL or CALL@ that ends the block, if any.
+    pub call: Option<CallSite>,
+    /// Whether the block ends with a RETURN, LOAD&RETURN or RETURNI, taken or not.
+    pub returns: bool,
+    /// Whether the block can run, from the reset address or the interrupt vector.
+    pub reachable: bool,
+}
+
+/// The basic blocks of a whole program, unreachable ones included.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    blocks: BTreeMap<usize, BasicBlock>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(graph: &FlowGraph) -> ControlFlowGraph {
+        let program = graph.get_program();
+        let addresses: Vec<usize> = program
+            .get_instructions()
+            .iter()
+            .map(|(address, _)| *address)
+            .collect();
+
+        // Blocks start where execution can come from somewhere else than the instruction before.
+        let mut leaders: BTreeSet<usize> = graph.get_entry_points().into_iter().collect();
+
+        leaders.extend(graph.get_branch_targets());
+        leaders.extend(graph.get_computed_targets());
+
+        for address in &addresses {
+            if *address == 0 || graph.get_transfer(address - 1) != Some(Transfer::Next) {
+                leaders.insert(*address);
+            }
+        }
+
+        let reachable = graph.find_reachable(&graph.get_entry_points());
+        let mut blocks = BTreeMap::new();
+        let mut start = None;
+
+        for address in addresses {
+            let first = *start.get_or_insert(address);
+
+            if leaders.contains(&(address + 1)) || graph.get_instruction(address + 1).is_none() {
+                blocks.insert(first, find_exits(graph, first, address, &reachable));
+                start = None;
+            }
+        }
+
+        ControlFlowGraph { blocks }
+    }
+
+    /// Basic blocks by start address.
+    pub fn get_blocks(&self) -> &BTreeMap<usize, BasicBlock> {
+        &self.blocks
+    }
+
+    /// The block an instruction is part of.
+    pub fn find_block(&self, address: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .range(..=address)
+            .next_back()
+            .map(|(_, block)| block)
+            .filter(|block| address <= block.end)
+    }
+
+    /// Writes the graph in Graphviz DOT, one box per block with its label, addresses, source lines
+    /// and instructions. Calls are dashed edges, unreachable blocks are grey.
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut dot =
+            String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in self.blocks.values() {
+            let mut text = format!("{}\\l", describe_block(program, block));
+
+            for address in block.start..=block.end {
+                if let Some(instruction) = program.get_instruction(address) {
+                    text.push_str(&format!(
+                        "{:03X}  {}\\l",
+                        address,
+                        escape_dot(&instruction.to_string())
+                    ));
+                }
+            }
+
+            dot.push_str(&format!(
+                "    b{:03X} [label=\"{}\"{}];\n",
+                block.start,
+                text,
+                if block.reachable {
+                    ""
+                } else {
+                    ", color=grey, fontcolor=grey"
+                }
+            ));
+        }
+
+        for block in self.blocks.values() {
+            for edge in &block.successors {
+                let label = match edge.kind {
+                    EdgeKind::Branch(condition) => format!(" [label=\"{}\"]", condition),
+                    EdgeKind::Computed => " [label=\"@\"]".to_string(),
+                    _ => String::new(),
+                };
+
+                dot.push_str(&format!(
+                    "    b{:03X} -> b{:03X}{};\n",
+                    block.start, edge.target, label
+                ));
+            }
+
+            if let Some(CallSite {
+                target: Some(target),
+                ..
+            }) = block.call
+            {
+                dot.push_str(&format!(
+                    "    b{:03X} -> b{:03X} [style=dashed, label=\"call\"];\n",
+                    block.start, target
+                ));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self, program: &Program) -> Json {
+        let blocks = self.blocks.values().map(|block| {
+            let addresses = block.start..=block.end;
+            let (file, lines) = line_range(program, addresses.clone());
+            let instructions = addresses
+                .clone()
+                .filter_map(|address| program.get_instruction(address))
+                .map(|instruction| instruction.to_string());
+            let successors = block.successors.iter().map(|edge| {
+                let condition = match edge.kind {
+                    EdgeKind::Branch(condition) => Some(condition.to_string()),
+                    _ => None,
+                };
+
+                Json::object([
+                    ("target", Json::from(edge.target)),
+                    ("kind", Json::from(edge.kind.get_name())),
+                    ("condition", Json::from(condition)),
+                ])
+            });
+
+            Json::object([
+                ("start", Json::from(block.start)),
+                ("end", Json::from(block.end)),
+                ("label", Json::from(program.get_label_at(block.start))),
+                ("file", file),
+                ("lines", lines),
+                ("reachable", Json::from(block.reachable)),
+                ("instructions", Json::array(instructions)),
+                ("successors", Json::Array(successors.collect())),
+                (
+                    "call",
+                    block
+                        .call
+                        .map_or(Json::Null, |call| call_to_json(program, &call)),
+                ),
+                ("returns", Json::from(block.returns)),
+            ])
+        });
+
+        Json::object([("blocks", Json::Array(blocks.collect()))])
+    }
+}
+
+fn find_exits(
+    graph: &FlowGraph,
+    start: usize,
+    end: usize,
+    reachable: &BTreeSet<usize>,
+) -> BasicBlock {
+    let mut block = BasicBlock {
+        start,
+        end,
+        successors: Vec::new(),
+        call: None,
+        returns: false,
+        reachable: reachable.contains(&start),
+    };
+
+    let transfer = graph.get_transfer(end).unwrap_or(Transfer::Next);
+
+    match transfer {
+        Transfer::Jump { address, .. } => {
+            let kind = match graph.get_instruction(end) {
+                Some(Instruction::JumpConditional { condition, .. }) => {
+                    EdgeKind::Branch(*condition)
+                }
+                _ => EdgeKind::Jump,
+            };
+
+            block.successors.push(Edge {
+                target: address,
+                kind,
+            });
+        }
+        Transfer::JumpAt => {
+            if let Some(target) = graph.resolve_computed_target(end) {
+                block.successors.push(Edge {
+                    target,
+                    kind: EdgeKind::Computed,
+                });
+            }
+        }
+        Transfer::Call { address, .. } => {
+            block.call = Some(CallSite {
+                address: end,
+                target: Some(address),
+            })
+        }
+        Transfer::CallAt => {
+            block.call = Some(CallSite {
+                address: end,
+                target: graph.resolve_computed_target(end),
+            })
+        }
+        Transfer::Return { .. } | Transfer::ReturnInterrupt => block.returns = true,
+        Transfer::Next => {}
+    }
+
+    if transfer.falls_through() && graph.get_instruction(end + 1).is_some() {
+        block.successors.push(Edge {
+            target: end + 1,
+            kind: EdgeKind::FallThrough,
+        });
+    }
+
+    block
+}
+
+/// First and last source lines of some instructions, in the file the first one was written in:
+/// the path of the file (`null` for programs assembled from a string) and a `[first, last]` array.
+pub(crate) fn line_range(
+    program: &Program,
+    addresses: impl Iterator<Item = usize>,
+) -> (Json, Json) {
+    let locations: Vec<(Option<&Path>, usize)> = addresses
+        .filter_map(|address| program.get_source_map().get_line(address))
+        .filter_map(|line| program.get_location(line))
+        .collect();
+    let Some(&(file, first)) = locations.iter().min_by_key(|(_, line)| *line) else {
+        return (Json::Null, Json::Null);
+    };
+    let last = locations
+        .iter()
+        .filter(|(other, _)| *other == file)
+        .map(|(_, line)| *line)
+        .max()
+        .unwrap_or(first);
+
+    (file_to_json(file), Json::array([first, last]))
+}
+
+/// First and last source lines of some instructions for reports, e.g. `main.psm:12-15`.
+pub(crate) fn describe_lines(program: &Program, first: usize, last: usize) -> String {
+    match (program.get_location(first), program.get_location(last)) {
+        _ if first == last => program.describe_line(first),
+        (Some((None, first)), Some((None, last))) => format!("lines {}-{}", first, last),
+        (Some((file, _)), Some((other, line))) if file == other => {
+            format!("{}-{}", program.describe_line(first), line)
+        }
+        _ => format!(
+            "{} to {}",
+            program.describe_line(first),
+            program.describe_line(last)
+        ),
+    }
+}
+
+pub(crate) fn call_to_json(program: &Program, call: &CallSite) -> Json {
+    let location = program
+        .get_source_map()
+        .get_line(call.address)
+        .and_then(|line| program.get_location(line));
+
+    Json::object([
+        ("address", Json::from(call.address)),
+        (
+            "file",
+            location.map_or(Json::Null, |(file, _)| file_to_json(file)),
+        ),
+        ("line", Json::from(location.map(|(_, line)| line))),
+        ("target", Json::from(call.target)),
+    ])
+}
+
+fn file_to_json(file: Option<&Path>) -> Json {
+    Json::from(file.map(|file| file.display().to_string()))
+}
+
+/// Escapes text for a double-quoted DOT string.
+pub(crate) fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Heading of a block in the DOT graph, e.g. `loop+2  004-007  lines 12-15`.
+fn describe_block(program: &Program, block: &BasicBlock) -> String {
+    let mut parts = Vec::new();
+
+    match program.find_label_before(block.start) {
+        Some((label, 0)) => parts.push(escape_dot(label)),
+        Some((label, offset)) => parts.push(format!("{}+{}", escape_dot(label), offset)),
+        None => {}
+    }
+
+    parts.push(format!("{:03X}-{:03X}", block.start, block.end));
+
+    let map = program.get_source_map();
+
+    if let (Some(first), Some(last)) = (map.get_line(block.start), map.get_line(block.end)) {
+        parts.push(describe_lines(program, first, last));
+    }
+
+    parts.join("  ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, DEFAULT_INTERRUPT_VECTOR};
+
+    const PROGRAM: &str = "\
+start: load s0, 05
+loop:  call send
+       sub s0, 01
+       jump nz, loop
+       load s1, table'upper
+       load s2, table'lower
+       jump@ (s1, s2)
+table: jump start
+send:  output s0, 01
+       return
+       load s3, 01
+";
+
+    #[test]
+    fn blocks() {
+        let program = assemble_str(PROGRAM);
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+        let cfg = ControlFlowGraph::new(&graph);
+        let starts: Vec<usize> = cfg.get_blocks().keys().copied().collect();
+
+        assert_eq!(starts, vec![0, 1, 2, 4, 7, 8, 10]);
+
+        let looping = cfg.find_block(3).unwrap();
+
+        assert_eq!((looping.start, looping.end), (2, 3));
+        assert_eq!(
+            looping.successors,
+            vec![
+                Edge {
+                    target: 1,
+                    kind: EdgeKind::Branch(ConditionType::IfNonZero)
+                },
+                Edge {
+                    target: 4,
+                    kind: EdgeKind::FallThrough
+                },
+            ]
+        );
+        assert_eq!(
+            cfg.find_block(1).unwrap().call,
+            Some(CallSite {
+                address: 1,
+                target: Some(8)
+            })
+        );
+        assert_eq!(
+            cfg.find_block(6).unwrap().successors,
+            vec![Edge {
+                target: 7,
+                kind: EdgeKind::Computed
+            }]
+        );
+        assert!(cfg.find_block(9).unwrap().returns);
+        assert!(!cfg.find_block(10).unwrap().reachable);
+    }
+
+    #[test]
+    fn exports() {
+        let program = assemble_str(PROGRAM);
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+        let cfg = ControlFlowGraph::new(&graph);
+        let dot = cfg.to_dot(&program);
+
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b002 [label=\"loop+1  002-003  lines 3-4\\l002  SUB s0, 01\\l"));
+        assert!(dot.contains("b002 -> b001 [label=\"NZ\"];"));
+        assert!(dot.contains("b001 -> b008 [style=dashed, label=\"call\"];"));
+        assert!(dot.contains("b00A [label=\"send+2"));
+        assert!(dot.contains("color=grey"));
+
+        let json = cfg.to_json(&program).to_string();
+
+        assert!(json.starts_with(
+            "{\"blocks\":[{\"start\":0,\"end\":0,\"label\":\"start\",\"file\":null,\"lines\":[1,1],\
+             \"reachable\":true,\"instructions\":[\"LOAD s0, 05\"],\
+             \"successors\":[{\"target\":1,\"kind\":\"fall-through\",\"condition\":null}],\
+             \"call\":null,\"returns\":false}"
+        ));
+        assert!(json.contains("\"call\":{\"address\":1,\"file\":null,\"line\":2,\"target\":8}"));
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::blocks::{call_to_json, describe_lines, escape_dot, line_range};
+use super::flow::{FlowGraph, Transfer};
+use super::stack::describe_routine;
+use crate::json::Json;
+use crate::Program;
+
+/// A CALL or CALL@ instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSite {
+    /// Address of the call instruction.
+    pub address: usize,
+    /// Routine that's called, `None` for a CALL@ whose target couldn't be resolved.
+    pub target: Option<usize>,
+}
+
+/// Code that runs from an entry point (the reset address, the interrupt vector or the target of
+/// a call) until it returns.
+#[derive(Debug, Clone)]
+pub struct Routine {
+    pub entry: usize,
+    /// Every instruction that can run before the routine returns, leaving out called routines.
+    pub body: BTreeSet<usize>,
+    pub calls: Vec<CallSite>,
+}
+
+/// Which routines call which.
+#[derive(Debug, Clone)]
+pub struct CallGraph {
+    routines: BTreeMap<usize, Routine>,
+}
+
+impl CallGraph {
+    pub fn new(graph: &FlowGraph) -> CallGraph {
+        let mut routines = BTreeMap::new();
+        let mut pending = graph.get_entry_points();
+
+        while let Some(entry) = pending.pop() {
+            if routines.contains_key(&entry) || graph.get_instruction(entry).is_none() {
+                continue;
+            }
+
+            let routine = find_routine(graph, entry);
+
+            pending.extend(routine.calls.iter().filter_map(|call| call.target));
+            routines.insert(entry, routine);
+        }
+
+        CallGraph { routines }
+    }
+
+    /// Routines reachable from the entry points, by entry address.
+    pub fn get_routines(&self) -> &BTreeMap<usize, Routine> {
+        &self.routines
+    }
+
+    pub fn get_routine(&self, entry: usize) -> Option<&Routine> {
+        self.routines.get(&entry)
+    }
+
+    /// Every CALL@ whose target couldn't be resolved.
+    pub fn get_unresolved_calls(&self) -> Vec<usize> {
+        let mut calls: Vec<usize> = self
+            .routines
+            .values()
+            .flat_map(|routine| routine.calls.iter())
+            .filter(|call| call.target.is_none())
+            .map(|call| call.address)
+            .collect();
+
+        calls.sort();
+        calls.dedup();
+        calls
+    }
+
+    /// Writes the graph in Graphviz DOT, one box per routine with its label, addresses and source
+    /// lines, and one edge per called routine with the lines of the calls.
+    pub fn to_dot(&self, program: &Program) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box];\n");
+
+        for routine in self.routines.values() {
+            let mut text = format!(
+                "{}\\n{:03X}-{:03X}",
+                escape_dot(&describe_routine(program, routine.entry)),
+                routine.body.first().unwrap_or(&routine.entry),
+                routine.body.last().unwrap_or(&routine.entry)
+            );
+
+            let lines = routine
+                .body
+                .iter()
+                .filter_map(|address| program.get_source_map().get_line(*address));
+
+            if let (Some(first), Some(last)) = (lines.clone().min(), lines.max()) {
+                text.push_str(&format!("  {}", describe_lines(program, first, last)));
+            }
+
+            dot.push_str(&format!(
+                "    r{:03X} [label=\"{}\"];\n",
+                routine.entry, text
+            ));
+        }
+
+        if !self.get_unresolved_calls().is_empty() {
+            dot.push_str("    unresolved [label=\"?\", shape=ellipse];\n");
+        }
+
+        for routine in self.routines.values() {
+            let mut edges: BTreeMap<Option<usize>, Vec<String>> = BTreeMap::new();
+
+            for call in &routine.calls {
+                let line = program.get_source_map().get_line(call.address);
+
+                edges
+                    .entry(call.target)
+                    .or_default()
+                    .extend(line.map(|line| program.describe_line(line)));
+            }
+
+            for (target, lines) in edges {
+                let target = match target {
+                    Some(target) => format!("r{:03X}", target),
+                    None => "unresolved".to_string(),
+                };
+
+                dot.push_str(&format!(
+                    "    r{:03X} -> {} [label=\"{}\"];\n",
+                    routine.entry,
+                    target,
+                    lines.join(", ")
+                ));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self, program: &Program) -> Json {
+        let routines = self.routines.values().map(|routine| {
+            let calls = routine.calls.iter().map(|call| call_to_json(program, call));
+            let (file, lines) = line_range(program, routine.body.iter().copied());
+
+            Json::object([
+                ("entry", Json::from(routine.entry)),
+                ("label", Json::from(program.get_label_at(routine.entry))),
+                ("start", Json::from(routine.body.first().copied())),
+                ("end", Json::from(routine.body.last().copied())),
+                ("file", file),
+                ("lines", lines),
+                ("instructions", Json::from(routine.body.len())),
+                ("calls", Json::Array(calls.collect())),
+            ])
+        });
+
+        Json::object([("routines", Json::Array(routines.collect()))])
+    }
+}
+
+fn find_routine(graph: &FlowGraph, entry: usize) -> Routine {
+    let mut body = BTreeSet::new();
+    let mut calls = Vec::new();
+    let mut pending = vec![entry];
+
+    while let Some(address) = pending.pop() {
+        let Some(transfer) = graph.get_transfer(address) else {
+            continue;
+        };
+
+        if !body.insert(address) {
+            continue;
+        }
+
+        match transfer {
+            Transfer::Jump {
+                address: target, ..
+            } => pending.push(target),
+            Transfer::JumpAt => pending.extend(graph.resolve_computed_target(address)),
+            Transfer::Call {
+                address: target, ..
+            } => calls.push(CallSite {
+                address,
+                target: Some(target),
+            }),
+            Transfer::CallAt => calls.push(CallSite {
+                address,
+                target: graph.resolve_computed_target(address),
+            }),
+            _ => {}
+        }
+
+        if transfer.falls_through() {
+            pending.push(address + 1);
+        }
+    }
+
+    calls.sort_by_key(|call| call.address);
+
+    Routine { entry, body, calls }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, DEFAULT_INTERRUPT_VECTOR};
+
+    #[test]
+    fn call_graph() {
+        let program = assemble_str(
+            "\
+start: call first
+       jump start
+first: load s0, table'upper
+       load s1, table'lower
+       call@ (s0, s1)
+       jump z, skip
+       call second
+skip:  return
+second: call@ (s2, s3)
+       return
+table: load&return s4, 01
+address 3FF
+       jump second
+",
+        );
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+        let calls = CallGraph::new(&graph);
+        let entries: Vec<usize> = calls.get_routines().keys().copied().collect();
+
+        assert_eq!(entries, vec![0, 2, 8, 10, 0x3FF]);
+        assert_eq!(
+            calls.get_routine(2).unwrap().calls,
+            vec![
+                CallSite {
+                    address: 4,
+                    target: Some(10)
+                },
+                CallSite {
+                    address: 6,
+                    target: Some(8)
+                },
+            ]
+        );
+        assert_eq!(
+            calls.get_routine(2).unwrap().body,
+            BTreeSet::from([2, 3, 4, 5, 6, 7])
+        );
+        assert_eq!(
+            calls.get_routine(0x3FF).unwrap().body,
+            BTreeSet::from([0x3FF, 8, 9])
+        );
+        assert_eq!(calls.get_unresolved_calls(), vec![8]);
+
+        let dot = calls.to_dot(&program);
+
+        assert!(dot.contains("r002 [label=\"first\\n002-007  lines 3-8\"];"));
+        assert!(dot.contains("r002 -> r008 [label=\"line 7\"];"));
+        assert!(dot.contains("r008 -> unresolved [label=\"line 9\"];"));
+
+        let json = calls.to_json(&program).to_string();
+
+        assert!(json.contains(
+            "{\"entry\":8,\"label\":\"second\",\"start\":8,\"end\":9,\"file\":null,\
+             \"lines\":[9,10],\"instructions\":2,\
+             \"calls\":[{\"address\":8,\"file\":null,\"line\":9,\"target\":null}]}"
+        ));
+    }
+}
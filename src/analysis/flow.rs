@@ -0,0 +1,474 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{Instruction, Program, SymbolKind};
+
+/// How an instruction passes control on to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    /// Continues with the next instruction.
+    Next,
+    /// JUMP. Conditional jumps continue with the next instruction when they aren't taken.
+    Jump { address: usize, conditional: bool },
+    /// JUMP@, whose target is only known at run time.
+    JumpAt,
+    /// CALL. Calls are assumed to return to the next instruction.
+    Call { address: usize, conditional: bool },
+    /// CALL@, whose target is only known at run time.
+    CallAt,
+    /// RETURN and LOAD&RETURN. Conditional returns continue with the next instruction when they
+    /// aren't taken.
+    Return { conditional: bool },
+    /// RETURNI.
+    ReturnInterrupt,
+}
+
+impl Transfer {
+    pub fn of(instruction: &Instruction) -> Transfer {
+        match instruction {
+            Instruction::Jump { address } => Transfer::Jump {
+                address: *address as usize,
+                conditional: false,
+            },
+            Instruction::JumpConditional { address, .. } => Transfer::Jump {
+                address: *address as usize,
+                conditional: true,
+            },
+            Instruction::JumpAt { .. } => Transfer::JumpAt,
+            Instruction::Call { address } => Transfer::Call {
+                address: *address as usize,
+                conditional: false,
+            },
+            Instruction::CallConditional { address, .. } => Transfer::Call {
+                address: *address as usize,
+                conditional: true,
+            },
+            Instruction::CallAt { .. } => Transfer::CallAt,
+            Instruction::Return | Instruction::LoadAndReturn { .. } => {
+                Transfer::Return { conditional: false }
+            }
+            Instruction::ReturnCondition { .. } => Transfer::Return { conditional: true },
+            Instruction::ReturnInterrupt { .. } => Transfer::ReturnInterrupt,
+            _ => Transfer::Next,
+        }
+    }
+
+    /// Whether the next instruction can run right after this one, counting returns from calls.
+    pub fn falls_through(&self) -> bool {
+        match self {
+            Transfer::Next | Transfer::Call { .. } | Transfer::CallAt => true,
+            Transfer::Jump { conditional, .. } | Transfer::Return { conditional } => *conditional,
+            Transfer::JumpAt | Transfer::ReturnInterrupt => false,
+        }
+    }
+
+    /// Target of a JUMP or CALL.
+    pub fn get_target(&self) -> Option<usize> {
+        match self {
+            Transfer::Jump { address, .. } | Transfer::Call { address, .. } => Some(*address),
+            _ => None,
+        }
+    }
+}
+
+/// Whether an instruction changes the zero and carry flags. No KCPSM6 instruction changes only
+/// one of them.
+pub fn affects_flags(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::None
+            | Instruction::Load { .. }
+            | Instruction::LoadConstant { .. }
+            | Instruction::LoadAndReturn { .. }
+            | Instruction::Star { .. }
+            | Instruction::StarConstant { .. }
+            | Instruction::FetchConstant { .. }
+            | Instruction::FetchDeref { .. }
+            | Instruction::StoreConstant { .. }
+            | Instruction::StoreDeref { .. }
+            | Instruction::InputConstant { .. }
+            | Instruction::InputDeref { .. }
+            | Instruction::OutputConstant { .. }
+            | Instruction::OutputDeref { .. }
+            | Instruction::OutputDoubleConstant { .. }
+            | Instruction::Regbank { .. }
+            | Instruction::Interrupt { .. }
+            | Instruction::Jump { .. }
+            | Instruction::JumpConditional { .. }
+            | Instruction::JumpAt { .. }
+            | Instruction::Call { .. }
+            | Instruction::CallConditional { .. }
+            | Instruction::CallAt { .. }
+            | Instruction::Return
+            | Instruction::ReturnCondition { .. }
+    )
+}
+
+/// Whether an instruction changes a register of the selected bank.
+pub fn writes_register(instruction: &Instruction, register: u8) -> bool {
+    match instruction {
+        Instruction::Add { lhs, .. }
+        | Instruction::AddConstant { lhs, .. }
+        | Instruction::AddCarry { lhs, .. }
+        | Instruction::AddCarryConstant { lhs, .. }
+        | Instruction::And { lhs, .. }
+        | Instruction::AndConstant { lhs, .. }
+        | Instruction::Or { lhs, .. }
+        | Instruction::OrConstant { lhs, .. }
+        | Instruction::Xor { lhs, .. }
+        | Instruction::XorConstant { lhs, .. }
+        | Instruction::Subtract { lhs, .. }
+        | Instruction::SubtractConstant { lhs, .. }
+        | Instruction::SubtractCarry { lhs, .. }
+        | Instruction::SubtractCarryConstant { lhs, .. }
+        | Instruction::Load { lhs, .. }
+        | Instruction::LoadConstant { lhs, .. }
+        | Instruction::LoadAndReturn { lhs, .. }
+        | Instruction::FetchConstant { lhs, .. }
+        | Instruction::FetchDeref { lhs, .. }
+        | Instruction::InputConstant { lhs, .. }
+        | Instruction::InputDeref { lhs, .. } => *lhs == register,
+        Instruction::HardwareBuild { register: r }
+        | Instruction::RotateLeft { register: r }
+        | Instruction::RotateRight { register: r }
+        | Instruction::ShiftLeftZero { register: r }
+        | Instruction::ShiftLeftOne { register: r }
+        | Instruction::ShiftLeftCarry { register: r }
+        | Instruction::ShiftLeftArth { register: r }
+        | Instruction::ShiftRightZero { register: r }
+        | Instruction::ShiftRightOne { register: r }
+        | Instruction::ShiftRightCarry { register: r }
+        | Instruction::ShiftRightArth { register: r } => *r == register,
+        // REGBANK swaps every register at once.
+        Instruction::Regbank { .. } => true,
+        _ => false,
+    }
+}
+
+/// The instructions of a program by address, along with how control flows between them.
+pub struct FlowGraph<'a> {
+    program: &'a Program,
+    instructions: HashMap<usize, &'a Instruction>,
+    interrupt_vector: usize,
+}
+
+impl<'a> FlowGraph<'a> {
+    pub fn new(program: &'a Program, interrupt_vector: usize) -> FlowGraph<'a> {
+        FlowGraph {
+            program,
+            instructions: program
+                .get_instructions()
+                .iter()
+                .map(|(address, instruction)| (*address, instruction))
+                .collect(),
+            interrupt_vector,
+        }
+    }
+
+    pub fn get_program(&self) -> &'a Program {
+        self.program
+    }
+
+    pub fn get_interrupt_vector(&self) -> usize {
+        self.interrupt_vector
+    }
+
+    pub fn get_instruction(&self, address: usize) -> Option<&'a Instruction> {
+        self.instructions.get(&address).copied()
+    }
+
+    pub fn get_transfer(&self, address: usize) -> Option<Transfer> {
+        self.get_instruction(address).map(Transfer::of)
+    }
+
+    /// Whether the program uses JUMP@ or CALL@ anywhere.
+    pub fn has_computed_transfers(&self) -> bool {
+        self.instructions.values().any(|instruction| {
+            matches!(
+                Transfer::of(instruction),
+                Transfer::JumpAt | Transfer::CallAt
+            )
+        })
+    }
+
+    /// Addresses execution starts from: the reset address and, if there's an instruction there,
+    /// the interrupt vector.
+    pub fn get_entry_points(&self) -> Vec<usize> {
+        let mut entries = vec![0];
+
+        if self.interrupt_vector != 0 && self.instructions.contains_key(&self.interrupt_vector) {
+            entries.push(self.interrupt_vector);
+        }
+
+        entries
+    }
+
+    /// Targets of every direct CALL, which is what makes a label a subroutine.
+    pub fn get_call_targets(&self) -> BTreeSet<usize> {
+        self.instructions
+            .values()
+            .filter_map(|instruction| match Transfer::of(instruction) {
+                Transfer::Call { address, .. } => Some(address),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Targets of every direct JUMP and CALL.
+    pub fn get_branch_targets(&self) -> BTreeSet<usize> {
+        self.instructions
+            .values()
+            .filter_map(|instruction| Transfer::of(instruction).get_target())
+            .collect()
+    }
+
+    /// Labels used for something else than a direct JUMP or CALL, such as `table'upper`. These
+    /// are how JUMP@ and CALL@ targets are usually loaded into registers.
+    pub fn get_address_taken_labels(&self) -> BTreeSet<usize> {
+        self.program
+            .get_references()
+            .iter()
+            .filter(|symbol| symbol.kind == SymbolKind::Label)
+            .filter_map(|symbol| {
+                let address = self.program.find_label(&symbol.name)? as usize;
+                let user = self.program.get_source_map().get_address(symbol.line)?;
+                let direct = self.get_transfer(user)?.get_target() == Some(address);
+
+                (!direct).then_some(address)
+            })
+            .collect()
+    }
+
+    /// Possible targets of JUMP@ and CALL@: every address-taken label, and the rest of the table
+    /// when it starts a run of JUMP or LOAD&RETURN instructions.
+    pub fn get_computed_targets(&self) -> BTreeSet<usize> {
+        let mut targets = BTreeSet::new();
+
+        for start in self.get_address_taken_labels() {
+            let mut address = start;
+
+            targets.insert(address);
+
+            while let (Some(current), Some(next)) = (
+                self.get_instruction(address),
+                self.get_instruction(address + 1),
+            ) {
+                let is_table_entry = |instruction: &Instruction| {
+                    matches!(
+                        instruction,
+                        Instruction::Jump { .. } | Instruction::LoadAndReturn { .. }
+                    )
+                };
+
+                if !is_table_entry(current)
+                    || std::mem::discriminant(current) != std::mem::discriminant(next)
+                {
+                    break;
+                }
+
+                address += 1;
+                targets.insert(address);
+            }
+        }
+
+        targets
+    }
+
+    /// Finds where a JUMP@ or CALL@ goes from the `LOAD sX, label'upper` and
+    /// `LOAD sY, label'lower` before it. Offsets added to the registers in between (to index a
+    /// table) are allowed, the label is used as the target then.
+    pub fn resolve_computed_target(&self, address: usize) -> Option<usize> {
+        let (first, second) = match self.get_instruction(address)? {
+            Instruction::JumpAt { first, second } | Instruction::CallAt { first, second } => {
+                (*first, *second)
+            }
+            _ => return None,
+        };
+
+        let upper = self.find_label_loaded_into(address, first)?;
+        let lower = self.find_label_loaded_into(address, second)?;
+
+        (upper == lower).then_some(upper)
+    }
+
+    /// Address of the label whose address was last loaded into a register, looking back through
+    /// the straight-line code before an instruction.
+    fn find_label_loaded_into(&self, address: usize, register: u8) -> Option<usize> {
+        let mut current = address;
+
+        loop {
+            current = current.checked_sub(1)?;
+
+            let instruction = self.get_instruction(current)?;
+
+            if Transfer::of(instruction) != Transfer::Next {
+                return None;
+            }
+
+            match instruction {
+                Instruction::LoadConstant { lhs, .. } if *lhs == register => {
+                    let line = self.program.get_source_map().get_line(current)?;
+                    let label =
+                        self.program.get_references().iter().find(|symbol| {
+                            symbol.kind == SymbolKind::Label && symbol.line == line
+                        })?;
+
+                    return self.program.find_label(&label.name).map(|a| a as usize);
+                }
+                Instruction::AddConstant { lhs, .. }
+                | Instruction::Add { lhs, .. }
+                | Instruction::AddCarryConstant { lhs, .. }
+                | Instruction::AddCarry { lhs, .. }
+                    if *lhs == register => {}
+                _ if writes_register(instruction, register) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Addresses that can run right after the given one, following calls into the routine they
+    /// call. Returns are left out, as where they go depends on the call stack, and so are JUMP@
+    /// and CALL@ targets that can't be resolved.
+    pub fn get_successors(&self, address: usize) -> Vec<usize> {
+        let Some(transfer) = self.get_transfer(address) else {
+            return Vec::new();
+        };
+
+        let mut successors = Vec::new();
+
+        if let Some(target) = transfer.get_target() {
+            successors.push(target);
+        }
+
+        if let Some(target) = self.resolve_computed_target(address) {
+            successors.push(target);
+        }
+
+        if transfer.falls_through() && self.instructions.contains_key(&(address + 1)) {
+            successors.push(address + 1);
+        }
+
+        successors
+    }
+
+    /// Every address that can be reached from the given ones.
+    pub fn find_reachable(&self, roots: &[usize]) -> BTreeSet<usize> {
+        let mut reachable = BTreeSet::new();
+        let mut pending: Vec<usize> = roots
+            .iter()
+            .copied()
+            .filter(|address| self.instructions.contains_key(address))
+            .collect();
+
+        while let Some(address) = pending.pop() {
+            if reachable.insert(address) {
+                pending.extend(self.get_successors(address));
+            }
+        }
+
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, DEFAULT_INTERRUPT_VECTOR};
+
+    #[test]
+    fn transfers() {
+        let program = assemble_str(
+            "jump z, 010\ncall 020\nreturn c\nload&return s0, 01\njump@ (s0, s1)\nreturni enable\nadd s0, 01\n",
+        );
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+        let transfers: Vec<Transfer> = (0..7).map(|a| graph.get_transfer(a).unwrap()).collect();
+
+        assert_eq!(
+            transfers,
+            vec![
+                Transfer::Jump {
+                    address: 0x10,
+                    conditional: true
+                },
+                Transfer::Call {
+                    address: 0x20,
+                    conditional: false
+                },
+                Transfer::Return { conditional: true },
+                Transfer::Return { conditional: false },
+                Transfer::JumpAt,
+                Transfer::ReturnInterrupt,
+                Transfer::Next,
+            ]
+        );
+        assert!(graph.has_computed_transfers());
+        assert!(affects_flags(graph.get_instruction(6).unwrap()));
+        assert!(!affects_flags(graph.get_instruction(3).unwrap()));
+    }
+
+    #[test]
+    fn reachability() {
+        let program = assemble_str(
+            "\
+start: call routine
+       jump start
+       load s0, 01
+routine: return
+address 3FF
+       jump start
+",
+        );
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+
+        assert_eq!(graph.get_entry_points(), vec![0, 0x3FF]);
+        assert_eq!(
+            graph.find_reachable(&graph.get_entry_points()),
+            BTreeSet::from([0, 1, 3, 0x3FF])
+        );
+    }
+
+    #[test]
+    fn computed_targets() {
+        let program = assemble_str(
+            "\
+load s0, table'upper
+load s1, table'lower
+call@ (s0, s1)
+jump 000
+table: load&return s2, 01
+       load&return s2, 02
+       load&return s2, 03
+other: add s0, 01
+",
+        );
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+
+        assert_eq!(graph.get_address_taken_labels(), BTreeSet::from([4]));
+        assert_eq!(graph.get_computed_targets(), BTreeSet::from([4, 5, 6]));
+        assert_eq!(graph.resolve_computed_target(2), Some(4));
+    }
+
+    #[test]
+    fn resolve_computed_targets() {
+        let program = assemble_str(
+            "\
+load s0, table'upper
+load s1, table'lower
+add s1, s2
+addcy s0, 00
+jump@ (s0, s1)
+load s1, s3
+call@ (s0, s1)
+load s1, other'lower
+call@ (s0, s1)
+table: jump 000
+other: jump 000
+",
+        );
+        let graph = FlowGraph::new(&program, DEFAULT_INTERRUPT_VECTOR);
+
+        assert_eq!(graph.resolve_computed_target(4), Some(9));
+        assert_eq!(graph.resolve_computed_target(6), None);
+        assert_eq!(graph.resolve_computed_target(8), None);
+        assert_eq!(graph.resolve_computed_target(0), None);
+    }
+}
@@ -0,0 +1,711 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use super::blocks::ControlFlowGraph;
+use super::flow::{affects_flags, FlowGraph, Transfer};
+use crate::{
+    ConditionType, Diagnostic, Instruction, Program, SymbolKind, DEFAULT_INTERRUPT_VECTOR,
+};
+
+/// A check for a common mistake in KCPSM6 programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// Instructions that no jump, call or previous instruction leads to.
+    UnreachableCode,
+    UnusedLabel,
+    UnusedConstant,
+    UnusedRegisterName,
+    /// Subroutines that can run into the next one without a RETURN.
+    FallThrough,
+    /// Conditional jumps, calls and returns right after instructions that don't set the flags,
+    /// such as `LOAD` followed by `JUMP Z`.
+    UnsetFlags,
+    /// OUTPUTK ports that don't fit in 4 bits, usually 8-bit OUTPUT port constants.
+    OutputKPort,
+    /// STORE and FETCH addresses beyond the scratch pad memory.
+    ScratchPadAddress,
+}
+
+/// What to do with the problems a lint finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    /// Report them as errors.
+    Deny,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 8] = [
+        Lint::UnreachableCode,
+        Lint::UnusedLabel,
+        Lint::UnusedConstant,
+        Lint::UnusedRegisterName,
+        Lint::FallThrough,
+        Lint::UnsetFlags,
+        Lint::OutputKPort,
+        Lint::ScratchPadAddress,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::UnusedLabel => "unused-label",
+            Lint::UnusedConstant => "unused-constant",
+            Lint::UnusedRegisterName => "unused-namereg",
+            Lint::FallThrough => "fall-through",
+            Lint::UnsetFlags => "unset-flags",
+            Lint::OutputKPort => "outputk-port",
+            Lint::ScratchPadAddress => "scratch-pad-address",
+        }
+    }
+
+    pub fn get_description(&self) -> &'static str {
+        match self {
+            Lint::UnreachableCode => "instructions that can never run",
+            Lint::UnusedLabel => "labels that are never used",
+            Lint::UnusedConstant => "constants that are never used",
+            Lint::UnusedRegisterName => "NAMEREG names that are never used",
+            Lint::FallThrough => "subroutines that run into the next one without a RETURN",
+            Lint::UnsetFlags => "conditions tested right after instructions that don't set them",
+            Lint::OutputKPort => "OUTPUTK ports above 0xF",
+            Lint::ScratchPadAddress => "STORE and FETCH addresses beyond the scratch pad",
+        }
+    }
+
+    pub fn get_default_level(&self) -> LintLevel {
+        match self {
+            // The port would be silently cut down to 4 bits.
+            Lint::OutputKPort => LintLevel::Deny,
+            _ => LintLevel::Warn,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL
+            .iter()
+            .copied()
+            .find(|lint| lint.get_name() == name)
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+/// Which lints to run and what the processor they're checked against looks like.
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    levels: HashMap<Lint, LintLevel>,
+    /// Scratch pad memory size in bytes (64, 128 or 256).
+    pub scratch_pad_size: usize,
+    pub interrupt_vector: usize,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            levels: HashMap::new(),
+            scratch_pad_size: 64,
+            interrupt_vector: DEFAULT_INTERRUPT_VECTOR,
+        }
+    }
+}
+
+impl LintConfig {
+    pub fn new() -> LintConfig {
+        LintConfig::default()
+    }
+
+    pub fn get_level(&self, lint: Lint) -> LintLevel {
+        self.levels
+            .get(&lint)
+            .copied()
+            .unwrap_or(lint.get_default_level())
+    }
+
+    pub fn set_level(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    /// Sets the level of every lint at once.
+    pub fn set_all_levels(&mut self, level: LintLevel) {
+        for lint in Lint::ALL {
+            self.set_level(lint, level);
+        }
+    }
+}
+
+/// Runs the enabled lints on a program. Programs with errors aren't linted, as their
+/// instructions are incomplete.
+pub fn lint(program: &Program, config: &LintConfig) -> Vec<Diagnostic> {
+    if program.has_errors() {
+        return Vec::new();
+    }
+
+    let mut linter = Linter {
+        graph: FlowGraph::new(program, config.interrupt_vector),
+        config,
+        diagnostics: Vec::new(),
+    };
+
+    linter.unreachable_code();
+    linter.unused_symbols();
+    linter.fall_through();
+    linter.unset_flags();
+    linter.operands();
+
+    linter.diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    linter.diagnostics
+}
+
+struct Linter<'a> {
+    graph: FlowGraph<'a>,
+    config: &'a LintConfig,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, lint: Lint, line: usize, message: String) {
+        let diagnostic = match self.config.get_level(lint) {
+            LintLevel::Allow => return,
+            LintLevel::Warn => Diagnostic::warning(line, message),
+            LintLevel::Deny => Diagnostic::error(line, message),
+        };
+
+        self.diagnostics.push(diagnostic.with_lint(lint.get_name()));
+    }
+
+    fn is_enabled(&self, lint: Lint) -> bool {
+        self.config.get_level(lint) != LintLevel::Allow
+    }
+
+    fn line_of(&self, address: usize) -> usize {
+        self.graph
+            .get_program()
+            .get_source_map()
+            .get_line(address)
+            .unwrap_or(0)
+    }
+
+    /// `name` for labelled addresses, `0x012` otherwise.
+    fn describe(&self, address: usize) -> String {
+        match self.graph.get_program().get_label_at(address) {
+            Some(label) => format!("'{}'", label),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    fn unreachable_code(&mut self) {
+        if !self.is_enabled(Lint::UnreachableCode) {
+            return;
+        }
+
+        let mut roots = self.graph.get_entry_points();
+
+        if self.graph.has_computed_transfers() {
+            let targets = self.graph.get_computed_targets();
+
+            // Without any idea where JUMP@ and CALL@ go, anything could be reachable.
+            if targets.is_empty() {
+                return;
+            }
+
+            roots.extend(targets);
+        }
+
+        let reachable = self.graph.find_reachable(&roots);
+        let program = self.graph.get_program();
+
+        // Report each run of unreachable instructions once.
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+
+        for (address, _) in program.get_instructions() {
+            if reachable.contains(address) {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some((_, last)) if *last + 1 == *address => *last = *address,
+                _ => runs.push((*address, *address)),
+            }
+        }
+
+        for (first, last) in runs {
+            let message = if first == last {
+                format!("The instruction at 0x{:03X} can never run.", first)
+            } else {
+                format!(
+                    "The {} instructions from 0x{:03X} to 0x{:03X} can never run.",
+                    last - first + 1,
+                    first,
+                    last
+                )
+            };
+
+            self.report(Lint::UnreachableCode, self.line_of(first), message);
+        }
+    }
+
+    fn unused_symbols(&mut self) {
+        let program = self.graph.get_program();
+
+        for symbol in program.get_definitions() {
+            if !program
+                .find_references(symbol.kind, &symbol.name)
+                .is_empty()
+            {
+                continue;
+            }
+
+            let (lint, kind) = match symbol.kind {
+                SymbolKind::Label => (Lint::UnusedLabel, "Label"),
+                SymbolKind::Constant => (Lint::UnusedConstant, "Constant"),
+                SymbolKind::RegisterName => (Lint::UnusedRegisterName, "Register name"),
+            };
+
+            self.report(
+                lint,
+                symbol.line,
+                format!("{} '{}' is never used.", kind, symbol.name),
+            );
+        }
+    }
+
+    fn fall_through(&mut self) {
+        if !self.is_enabled(Lint::FallThrough) {
+            return;
+        }
+
+        let routines = self.graph.get_call_targets();
+        let mut reported = BTreeSet::new();
+
+        for routine in &routines {
+            let mut visited = BTreeSet::new();
+            let mut pending = vec![*routine];
+
+            while let Some(address) = pending.pop() {
+                if !visited.insert(address) {
+                    continue;
+                }
+
+                let Some(transfer) = self.graph.get_transfer(address) else {
+                    continue;
+                };
+
+                // Calls return to the routine, so only follow jumps.
+                if let Transfer::Jump {
+                    address: target, ..
+                } = transfer
+                {
+                    pending.push(target);
+                }
+
+                if !transfer.falls_through() {
+                    continue;
+                }
+
+                let next = address + 1;
+
+                if routines.contains(&next) {
+                    if reported.insert(address) {
+                        let message = format!(
+                            "Subroutine {} runs into {} without a RETURN.",
+                            self.describe(*routine),
+                            self.describe(next)
+                        );
+
+                        self.report(Lint::FallThrough, self.line_of(address), message);
+                    }
+                } else if self.graph.get_instruction(next).is_none() {
+                    if reported.insert(address) {
+                        let message = format!(
+                            "Subroutine {} runs past its last instruction without a RETURN.",
+                            self.describe(*routine)
+                        );
+
+                        self.report(Lint::FallThrough, self.line_of(address), message);
+                    }
+                } else {
+                    pending.push(next);
+                }
+            }
+        }
+    }
+
+    fn unset_flags(&mut self) {
+        if !self.is_enabled(Lint::UnsetFlags) {
+            return;
+        }
+
+        let blocks = ControlFlowGraph::new(&self.graph);
+        let unset = self.find_unset_flags(&blocks);
+        let program = self.graph.get_program();
+        for (address, instruction) in program.get_instructions() {
+            let condition = match instruction {
+                Instruction::JumpConditional { condition, .. }
+                | Instruction::CallConditional { condition, .. }
+                | Instruction::ReturnCondition { condition } => condition,
+                _ => continue,
+            };
+
+            // Testing the flags again after another conditional instruction is how several
+            // conditions are checked at once, so only look at ordinary instructions.
+            let Some(previous) = address.checked_sub(1) else {
+                continue;
+            };
+
+            match self.graph.get_instruction(previous) {
+                Some(before)
+                    if Transfer::of(before) == Transfer::Next && !affects_flags(before) => {}
+                _ => continue,
+            }
+
+            // The instruction before isn't what ran last when the block starts here.
+            let Some(block) = blocks
+                .find_block(*address)
+                .filter(|block| block.start != *address)
+            else {
+                continue;
+            };
+
+            // Flags set earlier in the block, or on every way into it, are fine.
+            if (block.start..*address).any(|address| {
+                self.graph
+                    .get_instruction(address)
+                    .is_some_and(affects_flags)
+            }) {
+                continue;
+            }
+
+            let Some(Some(origin)) = unset.get(&block.start) else {
+                continue;
+            };
+
+            let mnemonic = instruction.to_string();
+            let mnemonic = mnemonic.split(',').next().unwrap_or_default();
+            let flag = match condition {
+                ConditionType::IfZero | ConditionType::IfNonZero => "zero",
+                ConditionType::IfCarry | ConditionType::IfNonCarry => "carry",
+            };
+            let previous_instruction = self.graph.get_instruction(previous).unwrap();
+            let previous_mnemonic = previous_instruction.to_string();
+            let previous_mnemonic = previous_mnemonic.split(' ').next().unwrap_or_default();
+
+            self.report(
+                Lint::UnsetFlags,
+                self.line_of(*address),
+                format!(
+                    "'{}' tests the {} flag, but {} doesn't change it and nothing since line {} does.",
+                    mnemonic,
+                    flag,
+                    previous_mnemonic,
+                    self.line_of(*origin)
+                ),
+            );
+        }
+    }
+
+    /// For each basic block, where execution can start (the reset address, the interrupt vector
+    /// or a routine) and get to the block without anything changing the flags on the way. The
+    /// state of a block is merged from those of its predecessors until nothing changes.
+    fn find_unset_flags(&self, blocks: &ControlFlowGraph) -> BTreeMap<usize, Option<usize>> {
+        let mut entries: BTreeSet<usize> = self.graph.get_entry_points().into_iter().collect();
+
+        entries.extend(self.graph.get_call_targets());
+        entries.extend(self.graph.get_computed_targets());
+
+        let mut unset: BTreeMap<usize, Option<usize>> = blocks
+            .get_blocks()
+            .keys()
+            .map(|start| (*start, entries.contains(start).then_some(*start)))
+            .collect();
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for block in blocks.get_blocks().values() {
+                let Some(origin) = unset[&block.start] else {
+                    continue;
+                };
+
+                // Routines that are called usually set the flags for the caller to test.
+                if block.call.is_some()
+                    || (block.start..=block.end).any(|address| {
+                        self.graph
+                            .get_instruction(address)
+                            .is_some_and(affects_flags)
+                    })
+                {
+                    continue;
+                }
+
+                for edge in &block.successors {
+                    if let Some(state @ None) = unset.get_mut(&edge.target) {
+                        *state = Some(origin);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        unset
+    }
+
+    fn operands(&mut self) {
+        let scratch_pad_size = self.config.scratch_pad_size;
+
+        for (address, instruction) in self.graph.get_program().get_instructions() {
+            match instruction {
+                Instruction::OutputDoubleConstant { rhs, .. } if *rhs > 0xF => self.report(
+                    Lint::OutputKPort,
+                    self.line_of(*address),
+                    format!(
+                        "OUTPUTK port 0x{:02X} doesn't fit in 4 bits, it would be written to port 0x{:X}.",
+                        rhs,
+                        rhs & 0xF
+                    ),
+                ),
+                Instruction::StoreConstant { rhs, .. } | Instruction::FetchConstant { rhs, .. }
+                    if *rhs as usize >= scratch_pad_size =>
+                {
+                    let mnemonic = match instruction {
+                        Instruction::StoreConstant { .. } => "STORE",
+                        _ => "FETCH",
+                    };
+
+                    self.report(
+                        Lint::ScratchPadAddress,
+                        self.line_of(*address),
+                        format!(
+                            "{} address 0x{:02X} is beyond the {} byte scratch pad.",
+                            mnemonic, rhs, scratch_pad_size
+                        ),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+    fn lints(source: &str, config: &LintConfig) -> Vec<(Option<&'static str>, usize)> {
+        lint(&assemble_str(source), config)
+            .iter()
+            .map(|diagnostic| (diagnostic.lint, diagnostic.line))
+            .collect()
+    }
+
+    fn only(lint: Lint) -> LintConfig {
+        let mut config = LintConfig::new();
+
+        config.set_all_levels(LintLevel::Allow);
+        config.set_level(lint, LintLevel::Warn);
+        config
+    }
+
+    #[test]
+    fn unreachable_code() {
+        let source = "\
+start: call routine
+       jump start
+       load s0, 01
+       load s1, 02
+routine: return
+       add s0, 01
+";
+
+        assert_eq!(
+            lints(source, &only(Lint::UnreachableCode)),
+            vec![(Some("unreachable-code"), 3), (Some("unreachable-code"), 6)]
+        );
+    }
+
+    #[test]
+    fn computed_jumps_make_tables_reachable() {
+        let source = "\
+start: load s0, table'upper
+       load s1, table'lower
+       call@ (s0, s1)
+       jump start
+table: load&return s2, 01
+       load&return s2, 02
+";
+
+        assert!(lints(source, &only(Lint::UnreachableCode)).is_empty());
+
+        // Without any known target, nothing can be said about what's reachable.
+        let source = "start: input s0, 00\njump@ (s0, s0)\nload s1, 01\n";
+
+        assert!(lints(source, &only(Lint::UnreachableCode)).is_empty());
+    }
+
+    #[test]
+    fn unused_symbols() {
+        let source = "\
+constant used, 01
+constant unused, 02
+namereg s1, count
+namereg s2, other
+start: load count, used
+unused_label: load other, 01
+       jump start
+";
+        let mut config = only(Lint::UnusedLabel);
+
+        config.set_level(Lint::UnusedConstant, LintLevel::Warn);
+        config.set_level(Lint::UnusedRegisterName, LintLevel::Deny);
+
+        let diagnostics = lint(&assemble_str(source), &config);
+        let found: Vec<(usize, bool)> =
+            diagnostics.iter().map(|d| (d.line, d.is_error())).collect();
+
+        assert_eq!(found, vec![(2, false), (6, false)]);
+        assert_eq!(diagnostics[0].message, "Constant 'unused' is never used.");
+
+        // `other` is used on line 6, but `count` isn't once line 5 is gone.
+        let diagnostics = lint(
+            &assemble_str(&source.replace("load count, used", "load s3, used")),
+            &config,
+        );
+
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.line == 3 && d.is_error() && d.lint == Some("unused-namereg")));
+    }
+
+    #[test]
+    fn fall_through() {
+        let source = "\
+start: call first
+       call second
+       jump start
+first: load s0, 01
+       jump z, done
+       add s0, 01
+second: load s1, 02
+done:  return
+";
+
+        assert_eq!(
+            lints(source, &only(Lint::FallThrough)),
+            vec![(Some("fall-through"), 6)]
+        );
+
+        let diagnostics = lint(&assemble_str(source), &only(Lint::FallThrough));
+
+        assert_eq!(
+            diagnostics[0].message,
+            "Subroutine 'first' runs into 'second' without a RETURN."
+        );
+
+        let source = "start: call last\njump start\nlast: load s0, 01\n";
+
+        assert_eq!(
+            lints(source, &only(Lint::FallThrough)),
+            vec![(Some("fall-through"), 3)]
+        );
+    }
+
+    #[test]
+    fn unset_flags() {
+        let source = "\
+start: input s0, 00
+       load s1, s0
+       jump z, start
+       sub s0, 01
+       load s1, 05
+       jump nz, start
+       compare s0, 10
+       jump z, start
+       jump c, start
+       call start
+       return c
+";
+
+        assert_eq!(
+            lints(source, &only(Lint::UnsetFlags)),
+            vec![(Some("unset-flags"), 3)]
+        );
+
+        let diagnostics = lint(&assemble_str(source), &only(Lint::UnsetFlags));
+
+        assert_eq!(
+            diagnostics[0].message,
+            "'JUMP Z' tests the zero flag, but LOAD doesn't change it and nothing since line 1 does."
+        );
+    }
+
+    #[test]
+    fn flags_set_before_a_loop() {
+        // The flags at `loop` come from the COMPARE, the first time and every time around.
+        let source = "\
+start: load s0, 10
+       compare s0, 00
+       jump nz, loop
+       return
+loop:  load s1, 01
+       jump nz, loop
+       jump start
+";
+
+        assert!(lints(source, &only(Lint::UnsetFlags)).is_empty());
+
+        // Unless one way into the loop doesn't go through it.
+        let source = source.replace("       compare", "       jump loop\n       compare");
+
+        assert_eq!(
+            lints(&source, &only(Lint::UnsetFlags)),
+            vec![(Some("unset-flags"), 7)]
+        );
+    }
+
+    #[test]
+    fn operands() {
+        let source = "\
+constant uart_port, 12
+outputk 41, uart_port
+store s0, 3F
+fetch s0, 40
+store s0, 80
+";
+        let diagnostics = lint(&assemble_str(source), &LintConfig::new());
+        let found: Vec<(usize, bool)> =
+            diagnostics.iter().map(|d| (d.line, d.is_error())).collect();
+
+        assert_eq!(found, vec![(2, true), (4, false), (5, false)]);
+
+        let mut config = LintConfig::new();
+
+        config.scratch_pad_size = 256;
+        config.set_level(Lint::OutputKPort, LintLevel::Allow);
+
+        assert!(lint(&assemble_str(source), &config).is_empty());
+    }
+
+    #[test]
+    fn programs_with_errors_are_not_linted() {
+        assert!(lint(
+            &assemble_str("jump nowhere\nstore s0, FF\n"),
+            &LintConfig::new()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn lint_names() {
+        for lint in Lint::ALL {
+            assert_eq!(Lint::from_name(lint.get_name()), Some(lint));
+        }
+
+        assert_eq!(Lint::from_name("frobnicate"), None);
+    }
+}
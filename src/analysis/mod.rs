@@ -0,0 +1,6 @@
+pub mod blocks;
+pub mod calls;
+pub mod flow;
+pub mod lints;
+pub mod stack;
+pub mod timing;
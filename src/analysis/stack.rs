@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+
+use super::calls::{CallGraph, CallSite};
+use super::flow::FlowGraph;
+use crate::{Diagnostic, Program, CALL_STACK_SIZE};
+
+/// The deepest the call stack gets from an entry point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackDepth {
+    pub entry: usize,
+    /// Call stack entries used, counting the one pushed by the interrupt itself for the
+    /// interrupt vector.
+    pub depth: usize,
+    /// The calls that lead to that depth, outermost first.
+    pub chain: Vec<CallSite>,
+}
+
+/// Worst-case call stack usage of a program, found without running it.
+#[derive(Debug, Clone)]
+pub struct StackReport {
+    pub reset: StackDepth,
+    /// `None` when there's no instruction at the interrupt vector.
+    pub interrupt: Option<StackDepth>,
+    /// Routines that call themselves, directly or through others. Each cycle starts and ends at
+    /// the same routine.
+    pub recursion: Vec<Vec<usize>>,
+    /// CALL@ instructions whose target couldn't be resolved. Each is counted as a single call.
+    pub unresolved: Vec<usize>,
+}
+
+impl StackReport {
+    /// An interrupt can happen at the deepest point of the main program.
+    pub fn get_worst_case(&self) -> usize {
+        self.reset.depth
+            + self
+                .interrupt
+                .as_ref()
+                .map_or(0, |interrupt| interrupt.depth)
+    }
+
+    pub fn can_overflow(&self) -> bool {
+        self.get_worst_case() > CALL_STACK_SIZE
+    }
+
+    /// Problems with the call stack, pointing at the calls behind them.
+    pub fn get_diagnostics(&self, program: &Program) -> Vec<Diagnostic> {
+        let line_of = |address: usize| program.get_source_map().get_line(address).unwrap_or(0);
+        let mut diagnostics = Vec::new();
+
+        for cycle in &self.recursion {
+            let names: Vec<String> = cycle
+                .iter()
+                .map(|entry| describe_routine(program, *entry))
+                .collect();
+
+            diagnostics.push(Diagnostic::warning(
+                program.get_source_map().get_line(cycle[0]).unwrap_or(0),
+                format!(
+                    "Recursive calls ({}), the call stack depth has no bound.",
+                    names.join(" -> ")
+                ),
+            ));
+        }
+
+        for address in &self.unresolved {
+            diagnostics.push(Diagnostic::warning(
+                line_of(*address),
+                "Unable to tell which routine this CALL@ calls, it's counted as a single call."
+                    .to_string(),
+            ));
+        }
+
+        if self.can_overflow() {
+            let deepest = self
+                .reset
+                .chain
+                .last()
+                .map(|call| call.address)
+                .unwrap_or(self.reset.entry);
+
+            diagnostics.push(Diagnostic::error(
+                line_of(deepest),
+                format!(
+                    "The call stack can need {} entries, but only holds {}.",
+                    self.get_worst_case(),
+                    CALL_STACK_SIZE
+                ),
+            ));
+        }
+
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+        diagnostics
+    }
+}
+
+/// Name of a routine for reports: its label, or its address if it has none.
+pub fn describe_routine(program: &Program, entry: usize) -> String {
+    match program.get_label_at(entry) {
+        Some(label) => label.to_string(),
+        None => format!("0x{:03X}", entry),
+    }
+}
+
+/// Formats a call chain with the label names and where the calls are, e.g.
+/// `start -> send (main.psm:12) -> delay (delay.psm:40)`.
+pub fn format_chain(program: &Program, depth: &StackDepth) -> String {
+    let mut parts = vec![describe_routine(program, depth.entry)];
+
+    for call in &depth.chain {
+        let name = match call.target {
+            Some(target) => describe_routine(program, target),
+            None => "?".to_string(),
+        };
+
+        match program.get_source_map().get_line(call.address) {
+            Some(line) => parts.push(format!("{} ({})", name, program.describe_line(line))),
+            None => parts.push(name),
+        }
+    }
+
+    parts.join(" -> ")
+}
+
+/// Finds the worst-case call stack depth from the reset address and the interrupt vector.
+pub fn analyze_stack(program: &Program, interrupt_vector: usize) -> StackReport {
+    let graph = FlowGraph::new(program, interrupt_vector);
+    let calls = CallGraph::new(&graph);
+    let mut search = DepthSearch {
+        calls: &calls,
+        deepest: BTreeMap::new(),
+        path: Vec::new(),
+        recursion: Vec::new(),
+    };
+
+    let reset = search.find(0);
+    let interrupt = graph
+        .get_entry_points()
+        .contains(&interrupt_vector)
+        .then(|| {
+            let (depth, chain) = search.find(interrupt_vector);
+
+            StackDepth {
+                entry: interrupt_vector,
+                depth: depth + 1,
+                chain,
+            }
+        });
+
+    StackReport {
+        reset: StackDepth {
+            entry: 0,
+            depth: reset.0,
+            chain: reset.1,
+        },
+        interrupt,
+        recursion: search.recursion,
+        unresolved: calls.get_unresolved_calls(),
+    }
+}
+
+struct DepthSearch<'a> {
+    calls: &'a CallGraph,
+    // Depth and chain of every routine looked at so far.
+    deepest: BTreeMap<usize, (usize, Vec<CallSite>)>,
+    // Routines being looked at, to find recursion.
+    path: Vec<usize>,
+    recursion: Vec<Vec<usize>>,
+}
+
+impl<'a> DepthSearch<'a> {
+    fn find(&mut self, entry: usize) -> (usize, Vec<CallSite>) {
+        if let Some(found) = self.deepest.get(&entry) {
+            return found.clone();
+        }
+
+        let Some(routine) = self.calls.get_routine(entry) else {
+            return (0, Vec::new());
+        };
+
+        self.path.push(entry);
+
+        let mut deepest = (0, Vec::new());
+
+        for call in &routine.calls {
+            let (depth, chain) = match call.target {
+                Some(target) if self.path.contains(&target) => {
+                    let start = self.path.iter().position(|e| *e == target).unwrap();
+                    let mut cycle = self.path[start..].to_vec();
+
+                    cycle.push(target);
+
+                    if !self.recursion.contains(&cycle) {
+                        self.recursion.push(cycle);
+                    }
+
+                    (0, Vec::new())
+                }
+                Some(target) => self.find(target),
+                None => (0, Vec::new()),
+            };
+
+            if depth + 1 > deepest.0 {
+                let mut full_chain = vec![*call];
+
+                full_chain.extend(chain);
+                deepest = (depth + 1, full_chain);
+            }
+        }
+
+        self.path.pop();
+        self.deepest.insert(entry, deepest.clone());
+
+        deepest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, DEFAULT_INTERRUPT_VECTOR};
+
+    const PROGRAM: &str = "\
+start: call outer
+       call leaf
+       jump start
+outer: call inner
+       return
+inner: call leaf
+       return
+leaf:  return
+isr:   call leaf
+       returni enable
+address 3FF
+       jump isr
+";
+
+    #[test]
+    fn depths() {
+        let program = assemble_str(PROGRAM);
+        let report = analyze_stack(&program, DEFAULT_INTERRUPT_VECTOR);
+
+        assert_eq!(report.reset.depth, 3);
+        assert_eq!(
+            format_chain(&program, &report.reset),
+            "start -> outer (line 1) -> inner (line 4) -> leaf (line 6)"
+        );
+
+        let interrupt = report.interrupt.as_ref().unwrap();
+
+        assert_eq!(interrupt.depth, 2);
+        assert_eq!(format_chain(&program, interrupt), "0x3FF -> leaf (line 9)");
+        assert_eq!(report.get_worst_case(), 5);
+        assert!(report.recursion.is_empty());
+        assert!(report.get_diagnostics(&program).is_empty());
+    }
+
+    #[test]
+    fn recursion_and_unresolved_calls() {
+        let program = assemble_str(
+            "\
+start: call ping
+       call@ (s0, s1)
+ping:  call pong
+       return
+pong:  call z, ping
+       return
+",
+        );
+        let report = analyze_stack(&program, DEFAULT_INTERRUPT_VECTOR);
+        let diagnostics = report.get_diagnostics(&program);
+
+        assert_eq!(report.recursion, vec![vec![2, 4, 2]]);
+        assert_eq!(report.unresolved, vec![1]);
+        assert!(report.interrupt.is_none());
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(
+            diagnostics[1].message,
+            "Recursive calls (ping -> pong -> ping), the call stack depth has no bound."
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let mut source = String::from("start: call level0\n");
+
+        for level in 0..30 {
+            source.push_str(&format!(
+                "level{}: call level{}\nreturn\n",
+                level,
+                level + 1
+            ));
+        }
+
+        source.push_str("level30: return\n");
+
+        let program = assemble_str(&source);
+        let report = analyze_stack(&program, DEFAULT_INTERRUPT_VECTOR);
+
+        assert_eq!(report.reset.depth, 31);
+        assert!(report.can_overflow());
+        assert!(report.get_diagnostics(&program)[0].is_error());
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::flow::{FlowGraph, Transfer};
+use crate::{Diagnostic, Program, CLOCK_CYCLES_PER_INSTRUCTION, DEFAULT_INTERRUPT_VECTOR};
+
+/// How many times a loop runs, counting every time its first instruction is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopBound {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl LoopBound {
+    /// Reads `10` or a `1..10` range, in decimal.
+    pub fn parse(text: &str) -> Option<LoopBound> {
+        let parse = |text: &str| text.trim().replace('_', "").parse::<u64>().ok();
+
+        let (min, max) = match text.split_once("..") {
+            Some((min, max)) => (parse(min)?, parse(max)?),
+            None => (parse(text)?, parse(text)?),
+        };
+
+        (min >= 1 && min <= max).then_some(LoopBound { min, max })
+    }
+}
+
+/// Loop bounds by source line: the line of the first instruction of a loop, or of the jump that
+/// goes back to it.
+#[derive(Debug, Clone, Default)]
+pub struct LoopBounds {
+    bounds: HashMap<usize, LoopBound>,
+}
+
+impl LoopBounds {
+    pub fn new() -> LoopBounds {
+        LoopBounds::default()
+    }
+
+    /// Reads the `; @loop 10` and `; @loop 1..10` annotations in the comments of a program.
+    pub fn from_annotations(program: &Program) -> LoopBounds {
+        let mut bounds = LoopBounds::new();
+
+        for (index, text) in program.get_source().iter().enumerate() {
+            let annotation = text
+                .split_once(';')
+                .and_then(|(_, comment)| comment.split_once("@loop"))
+                .and_then(|(_, bound)| LoopBound::parse(bound));
+
+            if let Some(bound) = annotation {
+                bounds.set(index + 1, bound);
+            }
+        }
+
+        bounds
+    }
+
+    pub fn set(&mut self, line: usize, bound: LoopBound) {
+        self.bounds.insert(line, bound);
+    }
+
+    pub fn get(&self, line: usize) -> Option<LoopBound> {
+        self.bounds.get(&line).copied()
+    }
+}
+
+/// Best and worst case amount of instructions executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub best: u64,
+    pub worst: u64,
+}
+
+impl Timing {
+    pub fn get_best_cycles(&self) -> u64 {
+        self.best * CLOCK_CYCLES_PER_INSTRUCTION
+    }
+
+    pub fn get_worst_cycles(&self) -> u64 {
+        self.worst * CLOCK_CYCLES_PER_INSTRUCTION
+    }
+
+    fn add(self, other: Timing) -> Timing {
+        Timing {
+            best: self.best.saturating_add(other.best),
+            worst: self.worst.saturating_add(other.worst),
+        }
+    }
+
+    /// Either of two paths.
+    fn either(self, other: Timing) -> Timing {
+        Timing {
+            best: self.best.min(other.best),
+            worst: self.worst.max(other.worst),
+        }
+    }
+}
+
+/// Finds the best and worst case amount of instructions executed from `from` until the routine
+/// returns or, if given, until `to` is reached. Called routines are included, interrupts aren't.
+/// Every loop needs a bound, problems are reported at the line they're about.
+pub fn analyze_timing(
+    program: &Program,
+    from: usize,
+    to: Option<usize>,
+    bounds: &LoopBounds,
+) -> Result<Timing, Diagnostic> {
+    let mut analyzer = Analyzer {
+        graph: FlowGraph::new(program, DEFAULT_INTERRUPT_VECTOR),
+        bounds,
+        routines: HashMap::new(),
+        routine_stack: Vec::new(),
+    };
+
+    let region = Region::new(&analyzer.graph, from, to);
+
+    match analyzer.evaluate(&region)? {
+        Some(timing) => Ok(timing),
+        None => Err(Diagnostic::error(
+            analyzer.line_of(from),
+            match to {
+                Some(to) => format!(
+                    "There's no path from {} to {}.",
+                    analyzer.describe(from),
+                    analyzer.describe(to)
+                ),
+                None => format!("{} never returns.", analyzer.describe(from)),
+            },
+        )),
+    }
+}
+
+/// Code that runs from a start address until it returns or reaches an end address, with the
+/// loops in it.
+struct Region {
+    start: usize,
+    end: Option<usize>,
+    /// Loop bodies by the address of their first instruction.
+    loops: HashMap<usize, BTreeSet<usize>>,
+    /// Jumps back to the first instruction of each loop.
+    latches: HashMap<usize, Vec<usize>>,
+}
+
+impl Region {
+    fn new(graph: &FlowGraph, start: usize, end: Option<usize>) -> Region {
+        let successors = |address: usize| -> Vec<usize> {
+            if Some(address) == end {
+                Vec::new()
+            } else {
+                local_successors(graph, address)
+            }
+        };
+
+        // Find the jumps back to an instruction that's still being explored.
+        let mut latches: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut visited = BTreeSet::new();
+        let mut on_path = BTreeSet::new();
+        let mut stack = vec![(start, 0usize)];
+
+        visited.insert(start);
+        on_path.insert(start);
+
+        while let Some((address, index)) = stack.pop() {
+            let next = successors(address);
+
+            if index == 0 {
+                for successor in &next {
+                    predecessors.entry(*successor).or_default().push(address);
+                }
+            }
+
+            match next.get(index) {
+                Some(successor) => {
+                    stack.push((address, index + 1));
+
+                    if on_path.contains(successor) {
+                        latches.entry(*successor).or_default().push(address);
+                    } else if visited.insert(*successor) {
+                        on_path.insert(*successor);
+                        stack.push((*successor, 0));
+                    }
+                }
+                None => {
+                    on_path.remove(&address);
+                }
+            }
+        }
+
+        // A loop is everything that can get to one of its latches without going through its
+        // first instruction.
+        let mut loops = HashMap::new();
+
+        for (header, sources) in &latches {
+            let mut body = BTreeSet::from([*header]);
+            let mut pending = sources.clone();
+
+            while let Some(address) = pending.pop() {
+                if body.insert(address) {
+                    pending.extend(predecessors.get(&address).into_iter().flatten());
+                }
+            }
+
+            loops.insert(*header, body);
+        }
+
+        Region {
+            start,
+            end,
+            loops,
+            latches,
+        }
+    }
+}
+
+/// Where the instructions of a routine go, leaving out calls and returns.
+fn local_successors(graph: &FlowGraph, address: usize) -> Vec<usize> {
+    let Some(transfer) = graph.get_transfer(address) else {
+        return Vec::new();
+    };
+
+    let mut successors = Vec::new();
+
+    match transfer {
+        Transfer::Jump {
+            address: target, ..
+        } => successors.push(target),
+        Transfer::JumpAt => successors.extend(graph.resolve_computed_target(address)),
+        _ => {}
+    }
+
+    if transfer.falls_through() && graph.get_instruction(address + 1).is_some() {
+        successors.push(address + 1);
+    }
+
+    successors
+}
+
+/// What the paths being looked at have to do.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Goal {
+    /// Go back to the first instruction of this loop, without leaving it.
+    iteration: Option<usize>,
+    /// First instructions of loops that were already left.
+    left: Vec<usize>,
+}
+
+struct Analyzer<'a> {
+    graph: FlowGraph<'a>,
+    bounds: &'a LoopBounds,
+    routines: HashMap<usize, Timing>,
+    routine_stack: Vec<usize>,
+}
+
+type Paths = Result<Option<Timing>, Diagnostic>;
+
+impl<'a> Analyzer<'a> {
+    fn line_of(&self, address: usize) -> usize {
+        self.graph
+            .get_program()
+            .get_source_map()
+            .get_line(address)
+            .unwrap_or(0)
+    }
+
+    fn describe(&self, address: usize) -> String {
+        match self.graph.get_program().get_label_at(address) {
+            Some(label) => format!("'{}'", label),
+            None => format!("0x{:03X}", address),
+        }
+    }
+
+    fn evaluate(&mut self, region: &Region) -> Paths {
+        let goal = Goal {
+            iteration: None,
+            left: Vec::new(),
+        };
+        let mut memo = HashMap::new();
+
+        self.paths(region, region.start, &goal, &mut memo)
+    }
+
+    fn routine(&mut self, entry: usize, call: usize) -> Result<Timing, Diagnostic> {
+        if let Some(timing) = self.routines.get(&entry) {
+            return Ok(*timing);
+        }
+
+        if self.routine_stack.contains(&entry) {
+            return Err(Diagnostic::error(
+                self.line_of(call),
+                format!(
+                    "{} calls itself, so it can't be timed.",
+                    self.describe(entry)
+                ),
+            ));
+        }
+
+        self.routine_stack.push(entry);
+
+        let region = Region::new(&self.graph, entry, None);
+        let timing = self.evaluate(&region)?.ok_or_else(|| {
+            Diagnostic::error(
+                self.line_of(call),
+                format!("{} never returns.", self.describe(entry)),
+            )
+        })?;
+
+        self.routine_stack.pop();
+        self.routines.insert(entry, timing);
+
+        Ok(timing)
+    }
+
+    /// Timing of the paths from an address that meet the goal, `None` if there aren't any.
+    fn paths(
+        &mut self,
+        region: &Region,
+        address: usize,
+        goal: &Goal,
+        memo: &mut HashMap<(usize, Goal), Option<Timing>>,
+    ) -> Paths {
+        if goal.iteration == Some(address) {
+            return Ok(Some(Timing { best: 0, worst: 0 }));
+        }
+
+        if goal.left.contains(&address) {
+            return Ok(None);
+        }
+
+        if let Some(header) = goal.iteration {
+            if !region.loops[&header].contains(&address) {
+                return Ok(None);
+            }
+        }
+
+        if Some(address) == region.end {
+            let done = goal.iteration.is_none();
+
+            return Ok(done.then_some(Timing { best: 0, worst: 0 }));
+        }
+
+        let key = (address, goal.clone());
+
+        if let Some(found) = memo.get(&key) {
+            return Ok(*found);
+        }
+
+        let result = match region.loops.contains_key(&address) {
+            true => self.run_loop(region, address, goal, memo)?,
+            false => self.step(region, address, goal, memo)?,
+        };
+
+        memo.insert(key, result);
+
+        Ok(result)
+    }
+
+    /// A loop runs its body a few times, then leaves it on the last run.
+    fn run_loop(
+        &mut self,
+        region: &Region,
+        header: usize,
+        goal: &Goal,
+        memo: &mut HashMap<(usize, Goal), Option<Timing>>,
+    ) -> Paths {
+        let iteration = Goal {
+            iteration: Some(header),
+            left: Vec::new(),
+        };
+        let mut leaving = goal.clone();
+
+        leaving.left.push(header);
+
+        // A loop that never ends doesn't need a bound.
+        let Some(last) = self.step(region, header, &leaving, memo)? else {
+            return Ok(None);
+        };
+        let bound = self.find_bound(region, header)?;
+        let once = self.step(region, header, &iteration, memo)?;
+
+        Ok(Some(match once {
+            Some(once) => Timing {
+                best: once.best.saturating_mul(bound.min - 1),
+                worst: once.worst.saturating_mul(bound.max - 1),
+            }
+            .add(last),
+            // The loop can't go around, so it runs once whatever the bound says.
+            None => last,
+        }))
+    }
+
+    fn find_bound(&self, region: &Region, header: usize) -> Result<LoopBound, Diagnostic> {
+        let lines = std::iter::once(header)
+            .chain(region.latches[&header].iter().copied())
+            .map(|address| self.line_of(address));
+
+        for line in lines {
+            if let Some(bound) = self.bounds.get(line) {
+                return Ok(bound);
+            }
+        }
+
+        Err(Diagnostic::error(
+            self.line_of(header),
+            format!(
+                "The loop at {} needs an iteration bound, e.g. a '; @loop 10' comment.",
+                self.describe(header)
+            ),
+        ))
+    }
+
+    /// Runs a single instruction and follows the paths after it.
+    fn step(
+        &mut self,
+        region: &Region,
+        address: usize,
+        goal: &Goal,
+        memo: &mut HashMap<(usize, Goal), Option<Timing>>,
+    ) -> Paths {
+        let transfer = match self.graph.get_transfer(address) {
+            Some(transfer) => transfer,
+            None => return Ok(None),
+        };
+
+        let mut own = Timing { best: 1, worst: 1 };
+
+        match transfer {
+            Transfer::Call {
+                address: target,
+                conditional,
+            } => {
+                let callee = self.routine(target, address)?;
+
+                own = own.add(Timing {
+                    best: if conditional { 0 } else { callee.best },
+                    worst: callee.worst,
+                });
+            }
+            Transfer::CallAt | Transfer::JumpAt => {
+                let target = self.graph.resolve_computed_target(address).ok_or_else(|| {
+                    Diagnostic::error(
+                        self.line_of(address),
+                        "Unable to tell where this JUMP@ or CALL@ goes, so it can't be timed."
+                            .to_string(),
+                    )
+                })?;
+
+                if transfer == Transfer::CallAt {
+                    own = own.add(self.routine(target, address)?);
+                }
+            }
+            _ => {}
+        }
+
+        // Returning only completes the paths of the routine itself.
+        let returned = goal.iteration.is_none() && region.end.is_none();
+        let mut result = match transfer {
+            Transfer::Return { .. } | Transfer::ReturnInterrupt if returned => Some(own),
+            _ => None,
+        };
+
+        for successor in local_successors(&self.graph, address) {
+            if let Some(rest) = self.paths(region, successor, goal, memo)? {
+                let path = own.add(rest);
+
+                result = Some(match result {
+                    Some(other) => other.either(path),
+                    None => path,
+                });
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_file, assemble_str};
+
+    fn timing(source: &str, from: &str, to: Option<&str>) -> Result<Timing, Diagnostic> {
+        let program = assemble_str(source);
+        let label = |name: &str| program.find_label(name).unwrap() as usize;
+
+        analyze_timing(
+            &program,
+            label(from),
+            to.map(label),
+            &LoopBounds::from_annotations(&program),
+        )
+    }
+
+    #[test]
+    fn bounds() {
+        assert_eq!(LoopBound::parse("10"), Some(LoopBound { min: 10, max: 10 }));
+        assert_eq!(
+            LoopBound::parse(" 1..1_000"),
+            Some(LoopBound { min: 1, max: 1000 })
+        );
+        assert_eq!(LoopBound::parse("0"), None);
+        assert_eq!(LoopBound::parse("5..2"), None);
+    }
+
+    #[test]
+    fn branches_and_calls() {
+        let source = "\
+start: compare s0, 01
+       jump z, short
+       add s0, 01
+       add s0, 01
+short: call c, leaf
+       return
+leaf:  load s1, 01
+       return
+";
+
+        assert_eq!(
+            timing(source, "start", None),
+            Ok(Timing { best: 4, worst: 8 })
+        );
+        assert_eq!(
+            timing(source, "start", Some("short")),
+            Ok(Timing { best: 2, worst: 4 })
+        );
+        assert!(timing(source, "leaf", Some("start")).is_err());
+    }
+
+    #[test]
+    fn soft_delays() {
+        let program = assemble_file("tests/soft_delays_100mhz.psm").unwrap();
+        let delay = |name: &str, iterations: u64| {
+            let mut bounds = LoopBounds::new();
+            let loop_line = program
+                .get_source_map()
+                .get_line(program.find_label("software_delay").unwrap() as usize)
+                .unwrap();
+
+            bounds.set(
+                loop_line,
+                LoopBound {
+                    min: iterations,
+                    max: iterations,
+                },
+            );
+
+            analyze_timing(
+                &program,
+                program.find_label(name).unwrap() as usize,
+                None,
+                &bounds,
+            )
+            .unwrap()
+        };
+
+        // Each run of the loop is 10 clock cycles, 100 ns at 100 MHz.
+        assert_eq!(delay("delay_1ms", 10_000).get_worst_cycles(), 100_010);
+        assert_eq!(delay("delay_20ms", 200_000).get_worst_cycles(), 2_000_010);
+        assert_eq!(delay("delay_1s", 10_000_000).get_best_cycles(), 100_000_010);
+    }
+
+    #[test]
+    fn nested_loops() {
+        let source = "\
+start: load s1, 04
+outer: load s0, 10
+inner: sub s0, 01
+       jump nz, inner ; @loop 16
+       sub s1, 01
+       jump nz, outer ; @loop 1..4
+       return
+";
+
+        // The inner loop runs 16 times (32 instructions) for every run of the outer one.
+        assert_eq!(
+            timing(source, "start", None),
+            Ok(Timing {
+                best: 1 + 35 + 1,
+                worst: 1 + 4 * 35 + 1
+            })
+        );
+    }
+
+    #[test]
+    fn missing_bounds() {
+        let source = "start: sub s0, 01\n       jump nz, start\n       return\n";
+        let error = timing(source, "start", None).unwrap_err();
+
+        assert_eq!(error.line, 1);
+        assert!(error.message.contains("needs an iteration bound"));
+
+        assert!(timing("start: jump start\n", "start", None)
+            .unwrap_err()
+            .message
+            .contains("never returns"));
+    }
+}
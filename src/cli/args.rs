@@ -0,0 +1,106 @@
+use super::Failure;
+
+/// Something given on the command line of a subcommand.
+#[derive(Debug, PartialEq)]
+pub enum Arg {
+    /// An option such as `--max-cycles` or `-o`. Its value, if it takes one, is read with
+    /// [`Args::value`].
+    Option(String),
+    Positional(String),
+}
+
+/// Walks through the arguments of a subcommand. Options can be given as `--name value` or
+/// `--name=value`, and everything after `--` is positional.
+pub struct Args {
+    args: std::vec::IntoIter<String>,
+    // Value given with `--name=value`, waiting to be read.
+    pending: Option<(String, String)>,
+    only_positional: bool,
+}
+
+impl Args {
+    pub fn new(args: Vec<String>) -> Args {
+        Args {
+            args: args.into_iter(),
+            pending: None,
+            only_positional: false,
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Option<Arg>, Failure> {
+        if let Some((option, _)) = self.pending.take() {
+            return Err(Failure::Usage(format!("{} doesn't take a value", option)));
+        }
+
+        let arg = match self.args.next() {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+
+        if self.only_positional || arg == "-" || !arg.starts_with('-') {
+            return Ok(Some(Arg::Positional(arg)));
+        }
+
+        if arg == "--" {
+            self.only_positional = true;
+            return self.next();
+        }
+
+        match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => {
+                self.pending = Some((option.to_string(), value.to_string()));
+                Ok(Some(Arg::Option(option.to_string())))
+            }
+            _ => Ok(Some(Arg::Option(arg))),
+        }
+    }
+
+    /// Reads the value of the option that was just returned by [`Args::next`].
+    pub fn value(&mut self, option: &str) -> Result<String, Failure> {
+        if let Some((_, value)) = self.pending.take() {
+            return Ok(value);
+        }
+
+        self.args
+            .next()
+            .ok_or_else(|| Failure::Usage(format!("{} needs a value", option)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Args {
+        Args::new(list.iter().map(|s| s.to_string()).collect())
+    }
+
+    #[test]
+    fn options_and_values() {
+        let mut args = args(&["a.psm", "--max-cycles", "10", "--clock=50MHz", "--", "-b"]);
+
+        assert_eq!(args.next().unwrap(), Some(Arg::Positional("a.psm".into())));
+        assert_eq!(
+            args.next().unwrap(),
+            Some(Arg::Option("--max-cycles".into()))
+        );
+        assert_eq!(args.value("--max-cycles").unwrap(), "10");
+        assert_eq!(args.next().unwrap(), Some(Arg::Option("--clock".into())));
+        assert_eq!(args.value("--clock").unwrap(), "50MHz");
+        assert_eq!(args.next().unwrap(), Some(Arg::Positional("-b".into())));
+        assert_eq!(args.next().unwrap(), None);
+    }
+
+    #[test]
+    fn unexpected_and_missing_values() {
+        let mut args = args(&["--quiet=yes"]);
+
+        assert_eq!(args.next().unwrap(), Some(Arg::Option("--quiet".into())));
+        assert!(args.next().is_err());
+
+        let mut args = self::args(&["--reg"]);
+
+        args.next().unwrap();
+        assert!(args.value("--reg").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::Path;
+
+use kcpsm6sim::{write_hex, LintConfig};
+
+use super::{io_failure, load_program, parse_lint_option, unknown_option, Arg, Args, Failure};
+
+pub fn check(mut args: Args) -> Result<(), Failure> {
+    let mut paths = Vec::new();
+    let mut deny_warnings = false;
+    let mut lints = LintConfig::new();
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) if option == "--deny-warnings" => deny_warnings = true,
+            Arg::Option(option) => {
+                if !parse_lint_option(&option, &mut args, &mut lints)? {
+                    return Err(unknown_option(&option));
+                }
+            }
+            Arg::Positional(path) => paths.push(path),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err(Failure::Usage("check needs at least one file".to_string()));
+    }
+
+    // Check every file before failing, so all of their diagnostics are reported at once.
+    let mut failure = None;
+
+    for path in &paths {
+        if let Err(error) = load_program(path, Some(&lints), deny_warnings) {
+            if let Failure::Io(message) = &error {
+                eprintln!("error: {}", message);
+            }
+
+            // I/O errors take precedence, as the file couldn't even be checked.
+            if !matches!(failure, Some(Failure::Io(_))) {
+                failure = Some(error);
+            }
+        }
+    }
+
+    match failure {
+        Some(Failure::Io(_)) => Err(Failure::Io("some files couldn't be read".to_string())),
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+pub fn assemble(mut args: Args) -> Result<(), Failure> {
+    let mut source = None;
+    let mut hex_path = None;
+    let mut listing_path = None;
+    let mut listing = true;
+    let mut lints = LintConfig::new();
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) => match option.as_str() {
+                "-o" | "--hex" => hex_path = Some(args.value(&option)?),
+                "--listing" => listing_path = Some(args.value(&option)?),
+                "--no-listing" => listing = false,
+                _ => {
+                    if !parse_lint_option(&option, &mut args, &mut lints)? {
+                        return Err(unknown_option(&option));
+                    }
+                }
+            },
+            Arg::Positional(path) if source.is_none() => source = Some(path),
+            Arg::Positional(path) => {
+                return Err(Failure::Usage(format!("unexpected argument '{}'", path)))
+            }
+        }
+    }
+
+    let source = source.ok_or_else(|| Failure::Usage("assemble needs a file".to_string()))?;
+    let program = load_program(&source, Some(&lints), false)?;
+
+    // Like the KCPSM6 assembler, outputs go next to the source by default.
+    let default_path = |extension: &str| {
+        Path::new(&source)
+            .with_extension(extension)
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let hex_path = hex_path.unwrap_or_else(|| default_path("hex"));
+
+    let image = program
+        .create_image()
+        .map_err(|error| io_failure(&hex_path, error))?;
+
+    fs::write(&hex_path, write_hex(&image)).map_err(|error| io_failure(&hex_path, error))?;
+
+    if listing {
+        let listing_path = listing_path.unwrap_or_else(|| default_path("log"));
+
+        fs::write(&listing_path, program.create_listing())
+            .map_err(|error| io_failure(&listing_path, error))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+use kcpsm6sim::peripherals::cosim::CoSimulation;
+
+use super::{parse_endpoint, Endpoint};
+
+/// Reads `tcp:[host:]port` or `unix:path`, where the co-simulation client connects.
+pub fn parse_socket(text: &str) -> Result<Endpoint, String> {
+    parse_endpoint(text)
+        .ok_or_else(|| format!("expected tcp:[host:]port or unix:path, got '{}'", text))
+}
+
+/// Waits for the client to connect, printing where on standard error.
+pub fn accept(socket: &Endpoint, clock: f64) -> io::Result<CoSimulation> {
+    match socket {
+        Endpoint::Tcp(address) => {
+            let listener = TcpListener::bind(address)?;
+
+            eprintln!(
+                "Co-simulation waiting for a connection on {}",
+                listener.local_addr()?
+            );
+
+            let (stream, _) = listener.accept()?;
+
+            CoSimulation::from_tcp(stream, clock)
+        }
+        Endpoint::Unix(path) => accept_unix(path, clock),
+    }
+}
+
+#[cfg(unix)]
+fn accept_unix(path: &PathBuf, clock: f64) -> io::Result<CoSimulation> {
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left over from an earlier run would make binding fail.
+    if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    eprintln!(
+        "Co-simulation waiting for a connection on {}",
+        path.display()
+    );
+
+    let (stream, _) = listener.accept()?;
+    let cosim = CoSimulation::from_unix(stream, clock);
+
+    fs::remove_file(path)?;
+    cosim
+}
+
+#[cfg(not(unix))]
+fn accept_unix(_path: &PathBuf, _clock: f64) -> io::Result<CoSimulation> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets aren't supported on this platform",
+    ))
+}
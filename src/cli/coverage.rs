@@ -0,0 +1,81 @@
+use std::fs;
+
+use kcpsm6sim::coverage::Coverage;
+use kcpsm6sim::StepEvent;
+
+use super::run::{report_fault, SimulationOptions};
+use super::{describe_address, io_failure, Args, Failure};
+
+pub fn coverage(args: Args) -> Result<(), Failure> {
+    let mut lcov_path = None;
+    let options = SimulationOptions::parse_with(args, |option, args| {
+        match option {
+            "--lcov" => lcov_path = Some(args.value(option)?),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    })?;
+    let program = options.load_program()?;
+    let mut sim = options.create_simulation(&program)?;
+    let mut coverage = Coverage::new(&sim);
+    let mut fault = false;
+
+    let stop = loop {
+        let event = match sim.step() {
+            Ok(StepEvent::Halted) => {
+                break format!(
+                    "Halted at {}, which has no instruction,",
+                    describe_address(&program, sim.get_program_counter())
+                )
+            }
+            Ok(event) => event,
+            Err(error) => {
+                // What ran until the fault is still worth reporting.
+                report_fault(&program, &sim, &options, &error);
+                fault = true;
+                break "Stopped by the fault".to_string();
+            }
+        };
+
+        coverage.record(&sim, &event);
+
+        if options
+            .max_cycles
+            .is_some_and(|max| sim.get_cycles() >= max)
+        {
+            break format!(
+                "Reached the cycle limit at {}",
+                describe_address(&program, sim.get_program_counter())
+            );
+        }
+    };
+
+    eprintln!(
+        "{} after {} clock cycles ({}).",
+        stop,
+        sim.get_cycles(),
+        options.time(sim.get_cycles())
+    );
+    print!("{}", coverage.format_report(&program));
+
+    if let Some(path) = lcov_path {
+        // The test name is the program's, without characters lcov doesn't allow in it.
+        let name: String = program
+            .get_path()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+
+        fs::write(&path, coverage.to_lcov(&program, &name))
+            .map_err(|error| io_failure(&path, error))?;
+    }
+
+    match fault {
+        true => Err(Failure::Runtime),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,352 @@
+use std::io::{self, BufRead, Write};
+
+use kcpsm6sim::{Program, RunMode, SimulationContext, StopReason};
+
+use super::run::{format_state, SimulationOptions};
+use super::{
+    describe_address, describe_frame, parse_byte, parse_count, parse_register, Args, Failure,
+};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          Execute n instructions (default 1)
+  n, next              Execute an instruction, running called routines until they return
+  f, finish            Run until the current routine returns
+  c, continue          Run until a breakpoint, the end of the program or the cycle limit
+  b, break <location>  Set a breakpoint at a source line, a label or an address (0x1A3)
+  d, delete <location> Remove a breakpoint, or every breakpoint with 'delete all'
+  breakpoints          List the breakpoints
+  r, regs              Show the registers, flags and interrupt state
+  m, mem               Show the scratch pad memory
+  bt, stack            Show the call stack
+  l, list              Show the source around the current instruction
+  set <sX> <value>     Change a register of the selected bank
+  reset                Reset the processor
+  q, quit              Leave the debugger
+";
+
+pub fn debug(args: Args) -> Result<(), Failure> {
+    let options = SimulationOptions::parse(args)?;
+    let program = options.load_program()?;
+    let sim = options.create_simulation(&program)?;
+
+    let stdin = io::stdin();
+    let mut debugger = Debugger {
+        program: &program,
+        options: &options,
+        sim,
+        output: io::stdout(),
+    };
+
+    debugger
+        .session(stdin.lock())
+        .map_err(|error| Failure::Io(error.to_string()))
+}
+
+struct Debugger<'a, W: Write> {
+    program: &'a Program,
+    options: &'a SimulationOptions,
+    sim: SimulationContext,
+    output: W,
+}
+
+impl<'a, W: Write> Debugger<'a, W> {
+    fn session<R: BufRead>(&mut self, input: R) -> io::Result<()> {
+        writeln!(self.output, "Type 'help' for a list of commands.")?;
+        self.show_location()?;
+
+        let mut lines = input.lines();
+
+        loop {
+            write!(self.output, "(kcpsm6) ")?;
+            self.output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if let Some((command, arguments)) = words.split_first() {
+                if !self.execute(command, arguments)? {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs a command. Returns false when the session should end.
+    fn execute(&mut self, command: &str, arguments: &[&str]) -> io::Result<bool> {
+        match command {
+            "s" | "step" => match arguments.first().map(|n| parse_count(n)) {
+                None => self.run(RunMode::Steps(1))?,
+                Some(Ok(count)) => self.run(RunMode::Steps(count))?,
+                Some(Err(message)) => writeln!(self.output, "{}", message)?,
+            },
+            "n" | "next" => self.run(self.sim.step_over())?,
+            "f" | "finish" => match self.sim.step_out() {
+                None => writeln!(self.output, "Not inside a routine.")?,
+                Some(mode) => self.run(mode)?,
+            },
+            "c" | "continue" => self.run(RunMode::Continue)?,
+            "b" | "break" => match self.find_location(arguments) {
+                Ok(address) => {
+                    self.sim.add_breakpoint(address);
+                    writeln!(
+                        self.output,
+                        "Breakpoint at {}",
+                        describe_address(self.program, address)
+                    )?;
+                }
+                Err(message) => writeln!(self.output, "{}", message)?,
+            },
+            "d" | "delete" if arguments == ["all"] => self.sim.clear_breakpoints(),
+            "d" | "delete" => match self.find_location(arguments) {
+                Ok(address) if self.sim.remove_breakpoint(address) => {}
+                Ok(address) => writeln!(
+                    self.output,
+                    "There's no breakpoint at {}",
+                    describe_address(self.program, address)
+                )?,
+                Err(message) => writeln!(self.output, "{}", message)?,
+            },
+            "breakpoints" => {
+                let breakpoints: Vec<usize> = self.sim.get_breakpoints().iter().copied().collect();
+
+                for address in breakpoints {
+                    writeln!(self.output, "  {}", describe_address(self.program, address))?;
+                }
+            }
+            "r" | "regs" => write!(self.output, "{}", format_state(&self.sim))?,
+            "m" | "mem" => self.show_memory()?,
+            "bt" | "stack" => self.show_stack()?,
+            "l" | "list" => self.show_source()?,
+            "set" => self.set_register(arguments)?,
+            "reset" => {
+                self.sim.reset();
+                self.show_location()?;
+            }
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => write!(self.output, "{}", HELP)?,
+            _ => writeln!(self.output, "Unknown command '{}', try 'help'.", command)?,
+        }
+
+        Ok(true)
+    }
+
+    fn run(&mut self, mode: RunMode) -> io::Result<()> {
+        let reason = match self
+            .sim
+            .run_until(mode, self.options.max_cycles, usize::MAX, |_, _| {})
+        {
+            Ok(reason) => reason,
+            Err(error) => {
+                writeln!(
+                    self.output,
+                    "Runtime fault at {}: {}",
+                    describe_address(self.program, self.sim.get_program_counter()),
+                    error
+                )?;
+                return Ok(());
+            }
+        };
+
+        match reason {
+            Some(StopReason::Halted) => writeln!(
+                self.output,
+                "The program halted, there's no instruction at {:03X}.",
+                self.sim.get_program_counter()
+            )?,
+            Some(StopReason::CycleLimit) => writeln!(self.output, "Reached the cycle limit.")?,
+            Some(StopReason::Breakpoint(_)) => writeln!(self.output, "Breakpoint reached.")?,
+            Some(StopReason::Done) | None => {}
+        }
+
+        self.show_location()
+    }
+
+    fn show_location(&mut self) -> io::Result<()> {
+        let pc = self.sim.get_program_counter();
+        let source = self
+            .program
+            .get_source_map()
+            .get_line(pc)
+            .and_then(|line| self.program.get_source_line(line))
+            .map(|text| text.trim())
+            .unwrap_or("");
+
+        writeln!(
+            self.output,
+            "{}  [cycle {}, {}]  {}",
+            describe_address(self.program, pc),
+            self.sim.get_cycles(),
+            self.options.time(self.sim.get_cycles()),
+            source
+        )
+    }
+
+    fn show_source(&mut self) -> io::Result<()> {
+        let current = self
+            .program
+            .get_source_map()
+            .get_line(self.sim.get_program_counter());
+        let center = current.unwrap_or(1);
+        let first = center.saturating_sub(5).max(1);
+
+        for line in first..first + 11 {
+            let text = match self.program.get_source_line(line) {
+                Some(text) => text,
+                None => break,
+            };
+            let marker = if Some(line) == current { "=>" } else { "  " };
+
+            writeln!(self.output, "{} {:>5}  {}", marker, line, text)?;
+        }
+
+        Ok(())
+    }
+
+    fn show_memory(&mut self) -> io::Result<()> {
+        let size = self.sim.get_scratch_pad_size();
+
+        for row in (0..size).step_by(16) {
+            let values: Vec<String> = (row..(row + 16).min(size))
+                .map(|address| format!("{:02X}", self.sim.get_scratch_pad_memory(address).unwrap()))
+                .collect();
+
+            writeln!(self.output, "{:02X}: {}", row, values.join(" "))?;
+        }
+
+        Ok(())
+    }
+
+    fn show_stack(&mut self) -> io::Result<()> {
+        writeln!(
+            self.output,
+            "#0 {}",
+            describe_address(self.program, self.sim.get_program_counter())
+        )?;
+
+        for (depth, frame) in self.sim.get_call_frames().iter().enumerate() {
+            writeln!(
+                self.output,
+                "#{} {}",
+                depth + 1,
+                describe_frame(self.program, frame)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn set_register(&mut self, arguments: &[&str]) -> io::Result<()> {
+        let result = match arguments {
+            [name, value] => parse_register(self.program, name)
+                .and_then(|register| Ok((register, parse_byte(value)?))),
+            _ => Err("Usage: set <sX> <value>".to_string()),
+        };
+
+        match result {
+            Ok((register, value)) => {
+                self.sim.set_register(register, value);
+                Ok(())
+            }
+            Err(message) => writeln!(self.output, "{}", message),
+        }
+    }
+
+    /// Finds the address of a source line (`12`), an address (`0x1A3`) or a label.
+    fn find_location(&self, arguments: &[&str]) -> Result<usize, String> {
+        let location = match arguments {
+            [location] => *location,
+            _ => return Err("Expected a source line, label or address.".to_string()),
+        };
+
+        if let Some(hex) = location.strip_prefix("0x") {
+            return usize::from_str_radix(hex, 16)
+                .map_err(|_| format!("'{}' isn't a valid address", location));
+        }
+
+        if let Ok(line) = location.parse::<usize>() {
+            return self
+                .program
+                .get_source_map()
+                .find_address_from(line)
+                .map(|(address, _)| address)
+                .ok_or_else(|| format!("There are no instructions from line {} on.", line));
+        }
+
+        self.program
+            .find_label(location)
+            .map(|address| address as usize)
+            .ok_or_else(|| format!("There's no label named '{}'.", location))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kcpsm6sim::assemble_str;
+
+    fn session(source: &str, script: &str) -> String {
+        let program = assemble_str(source);
+        let options = SimulationOptions::default();
+        let mut debugger = Debugger {
+            program: &program,
+            options: &options,
+            sim: program.create_simulation(),
+            output: Vec::new(),
+        };
+
+        debugger.session(script.as_bytes()).unwrap();
+        String::from_utf8(debugger.output).unwrap()
+    }
+
+    const PROGRAM: &str = "\
+start: load s0, 01
+       call double
+       call double
+       jump end
+double: add s0, s0
+       return
+end:   load s1, s0
+";
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let output = session(
+            PROGRAM,
+            "break double\ncontinue\nbt\nfinish\nnext\ndelete all\nfinish\nset s2 AA\nregs\nquit\n",
+        );
+
+        assert!(output.contains("Breakpoint at 004 (double, line 5)"));
+        assert!(output.contains("004 (double, line 5)  [cycle 4, 40 ns]  double: add s0, s0"));
+        assert!(output.contains("#1 001 (start+1, line 2)"));
+        assert!(output.contains("002 (start+2, line 3)  [cycle 8, 80 ns]"));
+        assert!(output.contains("004 (double, line 5)  [cycle 10, 100 ns]"));
+        assert_eq!(output.matches("Breakpoint reached.").count(), 2);
+        assert!(output.contains("003 (start+3, line 4)  [cycle 14, 140 ns]"));
+        assert!(output.contains("s0=04 s1=00 s2=AA"));
+    }
+
+    #[test]
+    fn run_to_the_end() {
+        let output = session(PROGRAM, "c\nbreak 9\nbreak nowhere\nlist\nfrobnicate\n");
+
+        assert!(output.contains("The program halted, there's no instruction at 007."));
+        assert!(output.contains("There are no instructions from line 9 on."));
+        assert!(output.contains("There's no label named 'nowhere'."));
+        assert!(output.contains("      7  end:   load s1, s0"));
+        assert!(output.contains("Unknown command 'frobnicate'"));
+    }
+
+    #[test]
+    fn faults_keep_the_session_going() {
+        let output = session("return\n", "step\nstep\nquit\n");
+
+        assert_eq!(output.matches("Runtime fault at 000 (line 1)").count(), 2);
+    }
+}
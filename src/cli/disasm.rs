@@ -0,0 +1,73 @@
+use std::fs;
+
+use kcpsm6sim::{decode, read_hex};
+
+use super::{io_failure, unknown_option, Arg, Args, Failure};
+
+pub fn disasm(mut args: Args) -> Result<(), Failure> {
+    let mut path = None;
+    let mut all = false;
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) if option == "--all" => all = true,
+            Arg::Option(option) => return Err(unknown_option(&option)),
+            Arg::Positional(file) if path.is_none() => path = Some(file),
+            Arg::Positional(file) => {
+                return Err(Failure::Usage(format!("unexpected argument '{}'", file)))
+            }
+        }
+    }
+
+    let path = path.ok_or_else(|| Failure::Usage("disasm needs an image".to_string()))?;
+    let contents = fs::read_to_string(&path).map_err(|error| io_failure(&path, error))?;
+    let image = read_hex(&contents).map_err(|diagnostic| {
+        eprintln!(
+            "{}:{}: {}: {}",
+            path, diagnostic.line, diagnostic.severity, diagnostic.message
+        );
+        Failure::Assembly
+    })?;
+
+    print!("{}", disassemble(&image, all));
+
+    Ok(())
+}
+
+/// Lists the address, opcode and instruction of every word in an image. Unused memory is zero
+/// (which is `LOAD s0, s0`), so it's left out after the last instruction unless `all` is set.
+fn disassemble(image: &[u32], all: bool) -> String {
+    let end = if all {
+        image.len()
+    } else {
+        image
+            .iter()
+            .rposition(|opcode| *opcode != 0)
+            .map_or(0, |last| last + 1)
+    };
+
+    image[..end]
+        .iter()
+        .enumerate()
+        .map(|(address, opcode)| match decode(*opcode) {
+            Some(instruction) => format!("{:03X}  {:05X}  {}\n", address, opcode, instruction),
+            None => format!("{:03X}  {:05X}  ??? (invalid opcode)\n", address, opcode),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_image() {
+        let image = [0x01041, 0x15000, 0x22000, 0, 0];
+
+        assert_eq!(
+            disassemble(&image, false),
+            "000  01041  LOAD s0, 41\n001  15000  ??? (invalid opcode)\n002  22000  JUMP 000\n"
+        );
+        assert_eq!(disassemble(&image, true).lines().count(), 5);
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs;
+
+use kcpsm6sim::analysis::blocks::ControlFlowGraph;
+use kcpsm6sim::analysis::calls::CallGraph;
+use kcpsm6sim::analysis::flow::FlowGraph;
+use kcpsm6sim::DEFAULT_INTERRUPT_VECTOR;
+
+use super::{io_failure, load_program, parse_value, unknown_option, Arg, Args, Failure};
+
+pub fn graph(mut args: Args) -> Result<(), Failure> {
+    let mut path = None;
+    let mut output = None;
+    let mut calls = false;
+    let mut json = false;
+    let mut interrupt_vector = DEFAULT_INTERRUPT_VECTOR;
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) => match option.as_str() {
+                "--calls" => calls = true,
+                "--format" => match args.value(&option)?.as_str() {
+                    "dot" => json = false,
+                    "json" => json = true,
+                    format => {
+                        return Err(Failure::Usage(format!(
+                            "--format: '{}' isn't 'dot' or 'json'",
+                            format
+                        )))
+                    }
+                },
+                "-o" | "--output" => output = Some(args.value(&option)?),
+                "--interrupt-vector" => {
+                    interrupt_vector = parse_value(&args.value(&option)?)
+                        .map_err(|message| Failure::Usage(format!("{}: {}", option, message)))?
+                        as usize;
+                }
+                _ => return Err(unknown_option(&option)),
+            },
+            Arg::Positional(file) if path.is_none() => path = Some(file),
+            Arg::Positional(file) => {
+                return Err(Failure::Usage(format!("unexpected argument '{}'", file)))
+            }
+        }
+    }
+
+    let path = path.ok_or_else(|| Failure::Usage("graph needs a file".to_string()))?;
+    let program = load_program(&path, None, false)?;
+    let flow = FlowGraph::new(&program, interrupt_vector);
+
+    let text = match (calls, json) {
+        (false, false) => ControlFlowGraph::new(&flow).to_dot(&program),
+        (false, true) => format!("{}\n", ControlFlowGraph::new(&flow).to_json(&program)),
+        (true, false) => CallGraph::new(&flow).to_dot(&program),
+        (true, true) => format!("{}\n", CallGraph::new(&flow).to_json(&program)),
+    };
+
+    match output {
+        Some(output) => fs::write(&output, text).map_err(|error| io_failure(&output, error)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
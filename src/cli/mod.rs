@@ -0,0 +1,558 @@
+mod args;
+mod assemble;
+mod cosim;
+mod coverage;
+mod debug;
+mod disasm;
+mod graph;
+mod profile;
+mod run;
+mod server;
+mod stack;
+mod timing;
+mod trace;
+mod tui;
+mod uart;
+
+use std::io::Error;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use kcpsm6sim::{
+    assemble_file, lint, CallFrame, Diagnostic, Lint, LintConfig, LintLevel, Program, Severity,
+};
+
+pub use args::{Arg, Args};
+pub use kcpsm6sim::system::parse_frequency;
+
+use kcpsm6sim::interpreter::operands::parse_literal;
+
+/// Exit codes, so the tool can be used from Makefiles and CI.
+pub const EXIT_SUCCESS: u8 = 0;
+pub const EXIT_ASSEMBLY_ERRORS: u8 = 1;
+pub const EXIT_RUNTIME_FAULT: u8 = 2;
+pub const EXIT_OUTPUT_MISMATCH: u8 = 3;
+pub const EXIT_USAGE: u8 = 64;
+pub const EXIT_IO_ERROR: u8 = 74;
+
+const USAGE: &str = "\
+Usage: KCPSM6Sim <command> [options]
+
+Commands:
+  check <file.psm>...      Report the diagnostics of PSM files, including those of the lints
+      --deny-warnings      Treat warnings as errors
+  assemble <file.psm>      Write the program memory image and a listing
+      -o, --hex <path>     Image path (default: next to the source, with a .hex extension)
+      --listing <path>     Listing path (default: next to the source, with a .log extension)
+      --no-listing         Don't write a listing
+    Both take lint options:
+      -A, --allow <lint>   Don't run a lint ('all' for every lint)
+      -W, --warn <lint>    Report what a lint finds as warnings
+      -D, --deny <lint>    Report what a lint finds as errors
+      --scratch-pad <n>    Scratch pad size in bytes: 64 (default), 128 or 256
+      --interrupt-vector <addr>
+                           Address of the interrupt vector (default 3FF), so the code there
+                           isn't unreachable
+  run <file.psm>           Run a program until it halts or reaches the cycle limit
+  debug <file.psm>         Start an interactive debugging session (type 'help' in it)
+      --max-cycles <n>     Stop after n clock cycles
+      --clock <freq>       Clock frequency, e.g. 100MHz (default), 50e6 or 12.5MHz
+      --reg <sX=value>     Initial register value, can be repeated
+      --input <pp=values>  Values read from an input port, e.g. 01=00,80,FF. The last one is
+                           kept once the others are used up. Can be repeated
+      --interrupt <cycle>  Raise the interrupt input at a clock cycle, can be repeated
+      --hwbuild <value>    Value returned by HWBUILD
+      --system <path>      TOML or JSON description of the processor (memory sizes, HWBUILD,
+                           interrupt vector, clock) and the peripherals on its ports, which
+                           can be programs answering on their standard output. The options
+                           above override it, and the UART options apply to its first UART
+      --stimulus <path>    CSV or JSON file of input port values to replay from given cycles or
+                           times (e.g. 'time,port,value' then '1ms,01,2A'). Can be repeated
+      -q, --quiet          Don't print port writes (run only)
+      --vcd <path>         Write a Value Change Dump of the clock, address, instruction, port
+                           signals, flags and registers, timed by the clock frequency (run only)
+      --vcd-reg <names>    Registers of the selected bank in the dump, e.g. s0,counter. Can be
+                           repeated
+      --uart               Connect models of the uart_tx6 and uart_rx6 macros: status on input
+                           port 00, data on port 01 and the buffer resets on OUTPUTK port 1, as in
+                           PicoTerm_routines.psm (run and tui)
+      --baud <rate>        Baud rate of the UART (default 115200)
+      --uart-input <path>  Characters sent to the UART at the start of the run
+      --uart-bridge <stdio|pty|tcp:[host:]port>
+                           Connect the UART to standard input and output, a new pseudo-terminal
+                           or the first client of a TCP port (a bare port listens on 127.0.0.1).
+                           The run ends a little after the other end closes, or on Ctrl-] when
+                           standard input is a terminal
+      --realtime           Keep the simulated time from running ahead of the wall clock (run
+                           only)
+      --picoterm           Emulate PicoTerm at the other end of the UART: its escape sequences
+                           are shown with ANSI ones, its requests are answered and its virtual
+                           LEDs, 7-segment display and switches are reported
+      --switches <value>   Initial state of the 16 PicoTerm virtual switches (default 0)
+      --picoterm-time <seconds>
+                           Answer PicoTerm time and date requests with a fixed Unix time
+      --i2c                Connect the KC705 I2C bus on ports 02 and 08, with a PCA9548 switch at
+                           address 74 and an M24C08 EEPROM on its channel 3, and check its timing
+      --eeprom <path>      Keep the contents of the EEPROM in a file
+      --expect <path>      CSV or JSON file of the OUTPUTs and OUTPUTKs the program should make,
+                           in order (e.g. 'port,value' then '04,2A'). Mismatches are reported
+                           with their cycle and source line, and the exit code is 3 (run only)
+      --cosim <tcp:[host:]port|unix:path>
+                           Wait for a co-simulation client, such as an HDL testbench, on a TCP
+                           port or Unix socket and let it play the ports nothing else is
+                           connected to: every port access is sent to it as a line of text and
+                           INPUTs wait for its answer (run only)
+  trace <file.psm>         Run a program and record every instruction it executes, with the
+                           options of run
+      --format <text|jsonl|binary>
+                           Output format (default text)
+      -o, --output <path>  Output path (default: standard output)
+      --range <first>-<last>
+                           Only record these addresses (or labels), can be repeated
+      --routine <label>    Only record while a routine runs, including the routines it calls.
+                           Can be repeated
+      --read <trace.bin>   Convert a binary trace instead, with labels if a PSM file is given
+  coverage <file.psm>      Run a program and report the lines it executed and which way its
+                           conditional JUMPs, CALLs and RETURNs went, per file and per routine,
+                           with the options of run
+      --lcov <path>        Also write the coverage as an lcov tracefile
+  profile <file.psm>       Run a program and report the clock cycles spent in each routine and at
+                           each address, with and without the routines they call, the
+                           instruction mix and the deepest call stack, with the options of run
+      --top <n>            Addresses listed, those with the most cycles (default 20)
+      --folded <path>      Also write the call stacks in the folded format of flame graph tools
+  tui <file.psm>           Debug in a full-screen terminal interface, with the options of debug
+                           and the UART and I2C options of run. The console pane is the other
+                           end of the UART: what the program sends is printed, and keys typed
+                           in it (i) are sent to the program
+  disasm <file.hex>        Disassemble a program memory image
+      --all                Include the unused memory after the last instruction
+  graph <file.psm>         Write the control-flow graph of the basic blocks, in Graphviz DOT
+      --calls              Write the call graph between routines instead
+      --format <dot|json>  Output format (default dot)
+      -o, --output <path>  Output path (default: standard output)
+      --interrupt-vector <addr>
+                           Address of the interrupt vector (default 3FF)
+  stack <file.psm>         Find the worst-case call stack depth without running the program
+      --interrupt-vector <addr>
+                           Address of the interrupt vector (default 3FF)
+  timing <file.psm> <label> [<end label>]
+                           Find the best and worst case time from a label until it returns,
+                           or until it reaches the end label, without running the program
+      --clock <freq>       Clock frequency (default 100MHz)
+      --loop <label|[file:]line>=<n>
+                           Iterations of the loop starting at a label or line, or a range like
+                           1..10. Lines of included files are given as file:line. Also read
+                           from '; @loop <n>' comments on the loop's first line or on the jump
+                           back to it
+  lsp                      Run a language server for PSM files on standard input and output
+  dap                      Run a Debug Adapter Protocol server on standard input and output
+      --port <[host:]port> Serve one client over TCP instead (host defaults to 127.0.0.1)
+  help                     Show this message
+
+Lints (all warnings, except outputk-port):
+  unreachable-code     Instructions that can never run
+  unused-label         Labels that are never used
+  unused-constant      Constants that are never used
+  unused-namereg       NAMEREG names that are never used
+  fall-through         Subroutines that run into the next one without a RETURN
+  unset-flags          Conditions tested right after instructions that don't set them
+  outputk-port         OUTPUTK ports above 0xF
+  scratch-pad-address  STORE and FETCH addresses beyond the scratch pad
+
+Values are hexadecimal as in PSM files, unless written as 10'd, 00001010'b or 0x0A.
+
+Exit codes: 0 success, 1 assembly errors, 2 runtime fault, 3 outputs other than the expected ones,
+64 usage error, 74 I/O error.
+";
+
+/// Why a command failed, which decides the exit code.
+#[derive(Debug)]
+pub enum Failure {
+    Usage(String),
+    Io(String),
+    /// The diagnostics were already printed.
+    Assembly,
+    /// The fault was already printed.
+    Runtime,
+    /// The outputs didn't match the expected ones, as already printed.
+    Mismatch,
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Io(_) => EXIT_IO_ERROR,
+            Failure::Assembly => EXIT_ASSEMBLY_ERRORS,
+            Failure::Runtime => EXIT_RUNTIME_FAULT,
+            Failure::Mismatch => EXIT_OUTPUT_MISMATCH,
+        }
+    }
+}
+
+pub fn main(args: Vec<String>) -> ExitCode {
+    let result = match args.split_first() {
+        Some((command, rest)) => {
+            let rest = rest.to_vec();
+
+            match command.as_str() {
+                "check" => assemble::check(Args::new(rest)),
+                "assemble" => assemble::assemble(Args::new(rest)),
+                "run" => run::run(Args::new(rest)),
+                "dap" => server::dap(Args::new(rest)),
+                "debug" => debug::debug(Args::new(rest)),
+                "disasm" => disasm::disasm(Args::new(rest)),
+                "graph" => graph::graph(Args::new(rest)),
+                "lsp" => server::lsp(Args::new(rest)),
+                "stack" => stack::stack(Args::new(rest)),
+                "timing" => timing::timing(Args::new(rest)),
+                "trace" => trace::trace(Args::new(rest)),
+                "coverage" => coverage::coverage(Args::new(rest)),
+                "profile" => profile::profile(Args::new(rest)),
+                "tui" => tui::tui(Args::new(rest)),
+                "help" | "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    Ok(())
+                }
+                _ => Err(Failure::Usage(format!("unknown command '{}'", command))),
+            }
+        }
+        None => Err(Failure::Usage("no command given".to_string())),
+    };
+
+    match result {
+        Ok(()) => ExitCode::from(EXIT_SUCCESS),
+        Err(failure) => {
+            match &failure {
+                Failure::Usage(message) => {
+                    eprintln!("error: {}\n\n{}", message, USAGE);
+                }
+                Failure::Io(message) => eprintln!("error: {}", message),
+                Failure::Assembly | Failure::Runtime | Failure::Mismatch => {}
+            }
+
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+/// Error for options a command doesn't know about.
+pub fn unknown_option(option: &str) -> Failure {
+    Failure::Usage(format!("unknown option '{}'", option))
+}
+
+pub fn io_failure(path: &str, error: Error) -> Failure {
+    Failure::Io(format!("{}: {}", path, error))
+}
+
+/// Assembles a file and prints its diagnostics, along with those of the lints if any are given.
+/// Fails if there are errors (or warnings, when they're denied).
+pub fn load_program(
+    path: &str,
+    lints: Option<&LintConfig>,
+    deny_warnings: bool,
+) -> Result<Program, Failure> {
+    let program = assemble_file(path).map_err(|error| io_failure(path, error))?;
+    let mut diagnostics: Vec<Diagnostic> = program.get_diagnostics().clone();
+
+    if let Some(config) = lints {
+        diagnostics.extend(lint(&program, config));
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    }
+
+    print_diagnostics(path, &program, &diagnostics);
+
+    let denied = deny_warnings
+        && diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Warning);
+
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) || denied {
+        return Err(Failure::Assembly);
+    }
+
+    Ok(program)
+}
+
+/// Prints diagnostics as `path:line: severity: message`, the way compilers do. Lines of included
+/// files are given in the file they're in.
+pub fn print_diagnostics(path: &str, program: &Program, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        let (file, line) = match program.get_location(diagnostic.line) {
+            Some((Some(file), line)) if program.get_path() != Some(file) => {
+                (file.display().to_string(), line)
+            }
+            Some((_, line)) => (path.to_string(), line),
+            None => (path.to_string(), diagnostic.line),
+        };
+
+        match diagnostic.lint {
+            Some(lint) => eprintln!(
+                "{}:{}: {}: {} [{}]",
+                file, line, diagnostic.severity, diagnostic.message, lint
+            ),
+            None => eprintln!(
+                "{}:{}: {}: {}",
+                file, line, diagnostic.severity, diagnostic.message
+            ),
+        }
+    }
+}
+
+/// Reads the lint options shared by `check` and `assemble`. Returns false for any other option.
+pub fn parse_lint_option(
+    option: &str,
+    args: &mut Args,
+    config: &mut LintConfig,
+) -> Result<bool, Failure> {
+    let level = match option {
+        "-A" | "--allow" => LintLevel::Allow,
+        "-W" | "--warn" => LintLevel::Warn,
+        "-D" | "--deny" => LintLevel::Deny,
+        "--scratch-pad" => {
+            let value = args.value(option)?;
+
+            config.scratch_pad_size = match parse_count(&value) {
+                Ok(size @ (64 | 128 | 256)) => size as usize,
+                _ => {
+                    return Err(Failure::Usage(format!(
+                        "{}: the scratch pad holds 64, 128 or 256 bytes, not '{}'",
+                        option, value
+                    )))
+                }
+            };
+
+            return Ok(true);
+        }
+        "--interrupt-vector" => {
+            let value = args.value(option)?;
+
+            config.interrupt_vector = match parse_value(&value) {
+                Ok(address) if address <= 0xFFF => address as usize,
+                _ => {
+                    return Err(Failure::Usage(format!(
+                        "{}: expected an address up to FFF, not '{}'",
+                        option, value
+                    )))
+                }
+            };
+
+            return Ok(true);
+        }
+        _ => return Ok(false),
+    };
+
+    let name = args.value(option)?;
+
+    if name == "all" {
+        config.set_all_levels(level);
+        return Ok(true);
+    }
+
+    match Lint::from_name(&name) {
+        Some(lint) => config.set_level(lint, level),
+        None => {
+            return Err(Failure::Usage(format!(
+                "{}: unknown lint '{}', see 'KCPSM6Sim help'",
+                option, name
+            )))
+        }
+    }
+
+    Ok(true)
+}
+
+/// Reads a value the way PSM files write them: hexadecimal unless it ends with `'d` (decimal) or
+/// `'b` (binary). `0x` prefixes are accepted too.
+pub fn parse_value(text: &str) -> Result<u32, String> {
+    parse_literal(text).ok_or_else(|| format!("'{}' isn't a valid value", text))
+}
+
+pub fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_value(text)?;
+
+    u8::try_from(value).map_err(|_| format!("'{}' doesn't fit in 8 bits", text))
+}
+
+/// Where the simulator waits for a client, for bridges and co-simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP address to listen on.
+    Tcp(String),
+    /// The path of a Unix socket.
+    Unix(PathBuf),
+}
+
+/// Reads `tcp:[host:]port` or `unix:path`. A bare port listens on the loopback interface.
+pub fn parse_endpoint(text: &str) -> Option<Endpoint> {
+    if let Some(path) = text.strip_prefix("unix:").filter(|path| !path.is_empty()) {
+        return Some(Endpoint::Unix(PathBuf::from(path)));
+    }
+
+    match text.strip_prefix("tcp:") {
+        Some(port) if port.parse::<u16>().is_ok() => {
+            Some(Endpoint::Tcp(format!("127.0.0.1:{}", port)))
+        }
+        Some(address) if address.contains(':') => Some(Endpoint::Tcp(address.to_string())),
+        _ => None,
+    }
+}
+
+/// Reads a decimal count, such as a number of cycles.
+pub fn parse_count(text: &str) -> Result<u64, String> {
+    text.replace('_', "")
+        .parse::<u64>()
+        .map_err(|_| format!("'{}' isn't a valid count", text))
+}
+
+/// Finds a register by its default name (`s0` to `sF`) or a name given to it with NAMEREG.
+pub fn parse_register(program: &Program, name: &str) -> Result<usize, String> {
+    program
+        .find_register(name)
+        .map(usize::from)
+        .ok_or_else(|| format!("'{}' isn't a register", name))
+}
+
+/// Describes where a call stack frame was entered, e.g. `012 (loop+2, line 14)` for a CALL and
+/// `interrupt at 012 (loop+2, line 14)` for the instruction an interrupt took the place of.
+pub fn describe_frame(program: &Program, frame: &CallFrame) -> String {
+    match frame.interrupt {
+        true => format!("interrupt at {}", describe_address(program, frame.address)),
+        false => describe_address(program, frame.address),
+    }
+}
+
+/// Describes a program memory address for humans, e.g. `012 (loop+2, line 14)`.
+pub fn describe_address(program: &Program, address: usize) -> String {
+    let mut details = Vec::new();
+
+    if let Some((label, offset)) = program.find_label_before(address) {
+        if offset == 0 {
+            details.push(label.to_string());
+        } else {
+            details.push(format!("{}+{}", label, offset));
+        }
+    }
+
+    if let Some(line) = program.get_source_map().get_line(address) {
+        match program.get_location(line) {
+            Some((Some(file), line)) if program.get_path() != Some(file) => {
+                let name = file.file_name().unwrap_or(file.as_os_str());
+
+                details.push(format!("line {} of {}", line, name.to_string_lossy()));
+            }
+            _ => details.push(format!("line {}", line)),
+        }
+    }
+
+    if details.is_empty() {
+        format!("{:03X}", address)
+    } else {
+        format!("{:03X} ({})", address, details.join(", "))
+    }
+}
+
+/// Formats time in the most readable unit.
+pub fn format_time(seconds: f64) -> String {
+    if seconds >= 1.0 {
+        format!("{:.3} s", seconds)
+    } else if seconds >= 1e-3 {
+        format!("{:.3} ms", seconds * 1e3)
+    } else if seconds >= 1e-6 {
+        format!("{:.3} us", seconds * 1e6)
+    } else {
+        format!("{:.0} ns", seconds * 1e9)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kcpsm6sim::assemble_str;
+
+    #[test]
+    fn values() {
+        assert_eq!(parse_value("FF"), Ok(0xFF));
+        assert_eq!(parse_value("0x1f"), Ok(0x1F));
+        assert_eq!(parse_value("10'd"), Ok(10));
+        assert_eq!(parse_value("00001010'b"), Ok(10));
+        assert!(parse_value("xyz").is_err());
+        assert!(parse_byte("100").is_err());
+        assert_eq!(parse_count("1_000"), Ok(1000));
+    }
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(
+            parse_endpoint("tcp:5000"),
+            Some(Endpoint::Tcp("127.0.0.1:5000".to_string()))
+        );
+        assert_eq!(
+            parse_endpoint("tcp:0.0.0.0:5000"),
+            Some(Endpoint::Tcp("0.0.0.0:5000".to_string()))
+        );
+        assert_eq!(
+            parse_endpoint("unix:/tmp/sim.sock"),
+            Some(Endpoint::Unix(PathBuf::from("/tmp/sim.sock")))
+        );
+        assert_eq!(parse_endpoint("tcp:"), None);
+        assert_eq!(parse_endpoint("unix:"), None);
+        assert_eq!(parse_endpoint("5000"), None);
+    }
+
+    #[test]
+    fn frequencies() {
+        assert_eq!(parse_frequency("100MHz"), Ok(100e6));
+        assert_eq!(parse_frequency("12.5 mhz"), Ok(12.5e6));
+        assert_eq!(parse_frequency("50e6"), Ok(50e6));
+        assert!(parse_frequency("fast").is_err());
+        assert!(parse_frequency("0").is_err());
+    }
+
+    #[test]
+    fn registers_and_addresses() {
+        let program = assemble_str("namereg s3, counter\nstart: load counter, 01\njump start\n");
+
+        assert_eq!(parse_register(&program, "sA"), Ok(10));
+        assert_eq!(parse_register(&program, "counter"), Ok(3));
+        assert!(parse_register(&program, "total").is_err());
+        assert_eq!(describe_address(&program, 1), "001 (start+1, line 3)");
+        assert_eq!(format_time(20e-9), "20 ns");
+    }
+
+    #[test]
+    fn lint_options() {
+        let mut config = LintConfig::new();
+        let mut args = Args::new(
+            [
+                "-A",
+                "all",
+                "--deny=unset-flags",
+                "--scratch-pad",
+                "128",
+                "--interrupt-vector=7F0",
+                "-W",
+                "nothing",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        );
+
+        while let Some(Arg::Option(option)) = args.next().unwrap() {
+            if option == "-W" {
+                assert!(parse_lint_option(&option, &mut args, &mut config).is_err());
+                break;
+            }
+
+            assert!(parse_lint_option(&option, &mut args, &mut config).unwrap());
+        }
+
+        assert_eq!(config.get_level(Lint::UnusedLabel), LintLevel::Allow);
+        assert_eq!(config.get_level(Lint::UnsetFlags), LintLevel::Deny);
+        assert_eq!(config.scratch_pad_size, 128);
+        assert_eq!(config.interrupt_vector, 0x7F0);
+        assert!(!parse_lint_option("--frobnicate", &mut args, &mut config).unwrap());
+    }
+}
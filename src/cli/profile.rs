@@ -0,0 +1,76 @@
+use std::fs;
+
+use kcpsm6sim::profile::Profiler;
+use kcpsm6sim::StepEvent;
+
+use super::run::{report_fault, SimulationOptions};
+use super::{describe_address, io_failure, parse_count, Args, Failure};
+
+pub fn profile(args: Args) -> Result<(), Failure> {
+    let mut folded_path = None;
+    let mut top = 20;
+    let options = SimulationOptions::parse_with(args, |option, args| {
+        match option {
+            "--folded" => folded_path = Some(args.value(option)?),
+            "--top" => {
+                top = parse_count(&args.value(option)?)
+                    .map_err(|message| Failure::Usage(format!("{}: {}", option, message)))?
+                    as usize
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    })?;
+    let program = options.load_program()?;
+    let mut sim = options.create_simulation(&program)?;
+    let mut profiler = Profiler::new(&sim);
+    let mut fault = false;
+
+    let stop = loop {
+        let event = match sim.step() {
+            Ok(StepEvent::Halted) => {
+                break format!(
+                    "Halted at {}, which has no instruction,",
+                    describe_address(&program, sim.get_program_counter())
+                )
+            }
+            Ok(event) => event,
+            Err(error) => {
+                // Where the time went until the fault is still worth reporting.
+                report_fault(&program, &sim, &options, &error);
+                fault = true;
+                break "Stopped by the fault".to_string();
+            }
+        };
+
+        profiler.record(&sim, &event);
+
+        if options
+            .max_cycles
+            .is_some_and(|max| sim.get_cycles() >= max)
+        {
+            break format!(
+                "Reached the cycle limit at {}",
+                describe_address(&program, sim.get_program_counter())
+            );
+        }
+    };
+
+    eprintln!(
+        "{} after {} clock cycles ({}).",
+        stop,
+        sim.get_cycles(),
+        options.time(sim.get_cycles())
+    );
+    print!("{}", profiler.format_report(&program, top));
+
+    if let Some(path) = folded_path {
+        fs::write(&path, profiler.to_folded(&program)).map_err(|error| io_failure(&path, error))?;
+    }
+
+    match fault {
+        true => Err(Failure::Runtime),
+        false => Ok(()),
+    }
+}
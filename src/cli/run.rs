@@ -0,0 +1,927 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use kcpsm6sim::peripherals::cosim::CoSimHandle;
+use kcpsm6sim::peripherals::i2c::{I2cBus, I2cConfig, I2cHandle, M24c08, Pca9548};
+use kcpsm6sim::peripherals::picoterm::PicoTerm;
+use kcpsm6sim::peripherals::uart::{Uart, UartConfig, UartHost};
+use kcpsm6sim::stimulus::{OutputCheck, Stimulus};
+use kcpsm6sim::system::{Peripherals, SystemDescription};
+use kcpsm6sim::vcd::VcdWriter;
+use kcpsm6sim::{
+    PortAccess, PortHandler, PortState, Program, SimulationContext, StepEvent,
+    CLOCK_CYCLES_PER_INSTRUCTION,
+};
+
+use super::cosim::{self, parse_socket};
+use super::uart::{parse_bridge, Bridge, Connection};
+use super::{
+    describe_address, format_time, io_failure, load_program, parse_byte, parse_count,
+    parse_frequency, parse_register, parse_value, unknown_option, Arg, Args, Failure,
+};
+
+/// Options shared by `run` and `debug`.
+pub struct SimulationOptions {
+    pub path: Option<String>,
+    pub max_cycles: Option<u64>,
+    /// Clock frequency in Hz.
+    pub clock: f64,
+    pub quiet: bool,
+    pub system: Option<SystemDescription>,
+    clock_given: bool,
+    // Register names can only be resolved once the program is assembled.
+    registers: Vec<(String, u8)>,
+    inputs: Vec<(u8, Vec<u8>)>,
+    stimuli: Vec<Stimulus>,
+    interrupts: Vec<u64>,
+    hwbuild: Option<u8>,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        SimulationOptions {
+            path: None,
+            max_cycles: None,
+            clock: 100e6,
+            quiet: false,
+            system: None,
+            clock_given: false,
+            registers: Vec::new(),
+            inputs: Vec::new(),
+            stimuli: Vec::new(),
+            interrupts: Vec::new(),
+            hwbuild: None,
+        }
+    }
+}
+
+impl SimulationOptions {
+    pub fn parse(args: Args) -> Result<SimulationOptions, Failure> {
+        SimulationOptions::parse_with(args, |_, _| Ok(false))
+    }
+
+    /// Like `parse`, for commands with options of their own. `extra` is given each option first,
+    /// and returns false for those it doesn't know.
+    pub fn parse_with(
+        mut args: Args,
+        mut extra: impl FnMut(&str, &mut Args) -> Result<bool, Failure>,
+    ) -> Result<SimulationOptions, Failure> {
+        let mut options = SimulationOptions::default();
+
+        while let Some(arg) = args.next()? {
+            match arg {
+                Arg::Option(option) if extra(&option, &mut args)? => {}
+                Arg::Option(option) => options.parse_option(&option, &mut args)?,
+                Arg::Positional(path) if options.path.is_none() => options.path = Some(path),
+                Arg::Positional(path) => {
+                    return Err(Failure::Usage(format!("unexpected argument '{}'", path)))
+                }
+            }
+        }
+
+        // The clock of the system description, unless it's given on the command line.
+        if let (Some(system), false) = (&options.system, options.clock_given) {
+            options.clock = system.get_processor().clock.unwrap_or(options.clock);
+        }
+
+        Ok(options)
+    }
+
+    fn parse_option(&mut self, option: &str, args: &mut Args) -> Result<(), Failure> {
+        let usage = |message: String| Failure::Usage(format!("{}: {}", option, message));
+
+        match option {
+            "--max-cycles" => {
+                self.max_cycles = Some(parse_count(&args.value(option)?).map_err(usage)?)
+            }
+            "--clock" => {
+                self.clock = parse_frequency(&args.value(option)?).map_err(usage)?;
+                self.clock_given = true;
+            }
+            "--system" => {
+                self.system = Some(SystemDescription::load(args.value(option)?).map_err(usage)?)
+            }
+            "--hwbuild" => self.hwbuild = Some(parse_byte(&args.value(option)?).map_err(usage)?),
+            "--interrupt" => self
+                .interrupts
+                .push(parse_count(&args.value(option)?).map_err(usage)?),
+            "--reg" => {
+                let value = args.value(option)?;
+                let (name, value) = value
+                    .split_once('=')
+                    .ok_or_else(|| usage(format!("expected sX=value, got '{}'", value)))?;
+
+                self.registers
+                    .push((name.to_string(), parse_byte(value).map_err(usage)?));
+            }
+            "--input" => {
+                let value = args.value(option)?;
+                let (port, values) = value
+                    .split_once('=')
+                    .ok_or_else(|| usage(format!("expected port=values, got '{}'", value)))?;
+                let values = values
+                    .split(',')
+                    .map(parse_byte)
+                    .collect::<Result<Vec<u8>, String>>()
+                    .map_err(usage)?;
+
+                self.inputs.push((parse_byte(port).map_err(usage)?, values));
+            }
+            "--stimulus" => self
+                .stimuli
+                .push(Stimulus::load(args.value(option)?).map_err(usage)?),
+            "-q" | "--quiet" => self.quiet = true,
+            _ => return Err(unknown_option(option)),
+        }
+
+        Ok(())
+    }
+
+    pub fn load_program(&self) -> Result<Program, Failure> {
+        let path = self
+            .path
+            .as_ref()
+            .ok_or_else(|| Failure::Usage("a PSM file is needed".to_string()))?;
+
+        load_program(path, None, false)
+    }
+
+    /// Ports with the input values, stimulus and interrupts of the options.
+    pub fn create_ports(&self) -> PortState {
+        let mut ports = PortState::new();
+
+        for (port, values) in &self.inputs {
+            ports.queue_inputs(*port, values);
+        }
+
+        for stimulus in &self.stimuli {
+            stimulus.apply(&mut ports, self.clock);
+        }
+
+        for cycle in &self.interrupts {
+            ports.raise_interrupt_at(*cycle);
+        }
+
+        ports
+    }
+
+    /// The peripherals of the system description in front of the ports of `create_ports`, or
+    /// those alone without a description.
+    pub fn create_system_ports(&self) -> Result<(Box<dyn PortHandler>, Peripherals), Failure> {
+        self.create_system_ports_with(Box::new(self.create_ports()))
+    }
+
+    /// Like `create_system_ports`, in front of other ports.
+    pub fn create_system_ports_with(
+        &self,
+        ports: Box<dyn PortHandler>,
+    ) -> Result<(Box<dyn PortHandler>, Peripherals), Failure> {
+        match &self.system {
+            Some(system) => {
+                let (ports, peripherals) = system
+                    .create_ports(self.clock, ports)
+                    .map_err(|message| Failure::Io(format!("--system: {}", message)))?;
+
+                Ok((Box::new(ports), peripherals))
+            }
+            None => Ok((ports, Peripherals::default())),
+        }
+    }
+
+    /// Creates a simulation of the program with the initial registers and port stimulus applied.
+    pub fn create_simulation(&self, program: &Program) -> Result<SimulationContext, Failure> {
+        let (ports, _) = self.create_system_ports()?;
+
+        self.create_simulation_with(program, ports)
+    }
+
+    /// Like `create_simulation`, with other ports.
+    pub fn create_simulation_with(
+        &self,
+        program: &Program,
+        ports: Box<dyn PortHandler>,
+    ) -> Result<SimulationContext, Failure> {
+        let mut sim = program.create_simulation();
+
+        sim.set_port_handler(ports);
+
+        if let Some(system) = &self.system {
+            system
+                .configure(&mut sim)
+                .map_err(|message| Failure::Usage(format!("--system: {}", message)))?;
+        }
+
+        if let Some(hwbuild) = self.hwbuild {
+            sim.set_hwbuild(hwbuild);
+        }
+
+        for (name, value) in &self.registers {
+            let register = parse_register(program, name)
+                .map_err(|message| Failure::Usage(format!("--reg: {}", message)))?;
+
+            sim.set_register(register, *value);
+        }
+
+        Ok(sim)
+    }
+
+    /// Simulated time after the given amount of clock cycles.
+    pub fn time(&self, cycles: u64) -> String {
+        format_time(cycles as f64 / self.clock)
+    }
+}
+
+/// The UART and I2C options of `run`, which `tui` takes too.
+pub struct PeripheralOptions {
+    pub uart: bool,
+    pub baud: u32,
+    pub uart_input: Option<String>,
+    pub i2c: bool,
+    pub eeprom: Option<String>,
+}
+
+impl Default for PeripheralOptions {
+    fn default() -> Self {
+        PeripheralOptions {
+            uart: false,
+            baud: UartConfig::default().baud,
+            uart_input: None,
+            i2c: false,
+            eeprom: None,
+        }
+    }
+}
+
+impl PeripheralOptions {
+    /// Takes `option` if it's one of the peripheral options, for `SimulationOptions::parse_with`.
+    pub fn parse_option(&mut self, option: &str, args: &mut Args) -> Result<bool, Failure> {
+        let usage = |message: String| Failure::Usage(format!("{}: {}", option, message));
+
+        match option {
+            "--uart" => self.uart = true,
+            "--baud" => {
+                self.baud = match parse_count(&args.value(option)?).map_err(usage)? {
+                    0 => return Err(usage("the baud rate can't be 0".to_string())),
+                    baud => baud.min(u32::MAX as u64) as u32,
+                };
+                self.uart = true;
+            }
+            "--uart-input" => {
+                self.uart_input = Some(args.value(option)?);
+                self.uart = true;
+            }
+            "--i2c" => self.i2c = true,
+            "--eeprom" => {
+                self.eeprom = Some(args.value(option)?);
+                self.i2c = true;
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    pub fn uart_config(&self, options: &SimulationOptions) -> UartConfig {
+        UartConfig {
+            baud: self.baud,
+            clock: options.clock,
+            ..UartConfig::default()
+        }
+    }
+
+    /// The simulation with the peripherals of the system description and of these options in
+    /// front of `inner`. Also gives the host end of the UART the options are about, the first of
+    /// the system description or the `--uart` one, with the `--uart-input` characters sent, and
+    /// the `--i2c` bus.
+    pub fn create_simulation(
+        &self,
+        options: &SimulationOptions,
+        program: &Program,
+        inner: Box<dyn PortHandler>,
+    ) -> Result<
+        (
+            SimulationContext,
+            Peripherals,
+            Option<UartHost>,
+            Option<I2cHandle>,
+        ),
+        Failure,
+    > {
+        let (mut ports, peripherals) = options.create_system_ports_with(inner)?;
+        let i2c = match self.i2c {
+            true => {
+                let (bus, handle) = create_i2c_bus(options.clock, self.eeprom.as_deref(), ports)?;
+
+                ports = Box::new(bus);
+                Some(handle)
+            }
+            false => None,
+        };
+        let (sim, mut host) = match (peripherals.get_uarts().first(), self.uart) {
+            (Some((_, host)), _) => (
+                options.create_simulation_with(program, ports)?,
+                Some(host.clone()),
+            ),
+            (None, true) => {
+                let uart = Uart::new(self.uart_config(options), ports);
+                let host = uart.host();
+
+                (
+                    options.create_simulation_with(program, Box::new(uart))?,
+                    Some(host),
+                )
+            }
+            (None, false) => (options.create_simulation_with(program, ports)?, None),
+        };
+
+        if let (Some(path), Some(host)) = (&self.uart_input, &mut host) {
+            let input = fs::read(path).map_err(|error| io_failure(path, error))?;
+
+            host.write_all(&input)
+                .map_err(|error| io_failure(path, error))?;
+        }
+
+        Ok((sim, peripherals, host, i2c))
+    }
+}
+
+pub fn run(args: Args) -> Result<(), Failure> {
+    let mut vcd_path = None;
+    let mut vcd_registers = Vec::new();
+    let mut peripheral_options = PeripheralOptions::default();
+    let mut bridge = None;
+    let mut realtime = false;
+    let mut picoterm = false;
+    let mut switches = 0;
+    let mut picoterm_time = None;
+    let mut expect = None;
+    let mut cosim = None;
+    let options = SimulationOptions::parse_with(args, |option, args| {
+        let usage = |message: String| Failure::Usage(format!("{}: {}", option, message));
+
+        if peripheral_options.parse_option(option, args)? {
+            return Ok(true);
+        }
+
+        match option {
+            "--uart-bridge" => {
+                bridge = Some(parse_bridge(&args.value(option)?).map_err(usage)?);
+                peripheral_options.uart = true;
+            }
+            "--realtime" => realtime = true,
+            "--picoterm" => {
+                picoterm = true;
+                peripheral_options.uart = true;
+            }
+            "--switches" => {
+                let value = parse_value(&args.value(option)?).map_err(usage)?;
+
+                switches = u16::try_from(value)
+                    .map_err(|_| usage(format!("'{:X}' doesn't fit in 16 bits", value)))?;
+                picoterm = true;
+                peripheral_options.uart = true;
+            }
+            "--picoterm-time" => {
+                picoterm_time = Some(parse_count(&args.value(option)?).map_err(usage)?);
+                picoterm = true;
+                peripheral_options.uart = true;
+            }
+            "--cosim" => cosim = Some(parse_socket(&args.value(option)?).map_err(usage)?),
+            "--expect" => expect = Some(args.value(option)?),
+            "--vcd" => vcd_path = Some(args.value(option)?),
+            "--vcd-reg" => vcd_registers.extend(
+                args.value(option)?
+                    .split(',')
+                    .map(|name| name.trim().to_string()),
+            ),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    })?;
+    let program = options.load_program()?;
+    let mut check = match &expect {
+        Some(path) => Some(
+            OutputCheck::load(path, options.clock)
+                .map_err(|message| Failure::Usage(format!("--expect: {}", message)))?,
+        ),
+        None => None,
+    };
+    let config = peripheral_options.uart_config(&options);
+    // The co-simulation client plays the ports nothing else is connected to.
+    let (inner, cosim): (Box<dyn PortHandler>, _) = match &cosim {
+        Some(socket) => {
+            let cosim = cosim::accept(socket, options.clock)
+                .map_err(|error| Failure::Io(format!("co-simulation: {}", error)))?;
+            let handle = cosim.handle();
+
+            (Box::new(cosim), Some(handle))
+        }
+        None => (Box::new(options.create_ports()), None),
+    };
+    let (mut sim, peripherals, mut host, i2c) =
+        peripheral_options.create_simulation(&options, &program, inner)?;
+    // Everything on the other end of a co-simulation link, named for errors and for stopping.
+    let mut clients: Vec<(String, String, CoSimHandle)> = Vec::new();
+
+    if let Some(handle) = cosim {
+        let stopper = "the co-simulation client".to_string();
+
+        clients.push(("co-simulation".to_string(), stopper, handle));
+    }
+
+    for (name, handle) in peripherals.get_processes() {
+        let label = format!("peripheral {}", name);
+
+        clients.push((label.clone(), label, handle.clone()));
+    }
+
+    let mut picoterm = match (picoterm, &host) {
+        (true, Some(host)) => {
+            let mut picoterm = PicoTerm::new(host.clone());
+
+            picoterm.set_switches(switches);
+            picoterm.set_time(picoterm_time);
+            Some(picoterm)
+        }
+        _ => None,
+    };
+    let mut vcd = match &vcd_path {
+        Some(path) => {
+            let registers = vcd_registers
+                .iter()
+                .map(|name| Ok((name.clone(), parse_register(&program, name)?)))
+                .collect::<Result<Vec<(String, usize)>, String>>()
+                .map_err(|message| Failure::Usage(format!("--vcd-reg: {}", message)))?;
+            let output = File::create(path).map_err(|error| io_failure(path, error))?;
+
+            Some(
+                VcdWriter::new(BufWriter::new(output), &mut sim, options.clock, &registers)
+                    .map_err(|error| io_failure(path, error))?,
+            )
+        }
+        None => None,
+    };
+    let bridge_failure = |error: Error| Failure::Io(format!("UART bridge: {}", error));
+    let mut connection = match &bridge {
+        Some(bridge) => Some(Connection::open(bridge).map_err(bridge_failure)?),
+        None => None,
+    };
+    // Standard output is the serial line then, so everything else goes to standard error.
+    let stdio = bridge == Some(Bridge::Stdio);
+    let quiet = options.quiet || stdio;
+    let newline = match connection.as_ref().is_some_and(Connection::is_raw) {
+        true => "\r\n",
+        false => "\n",
+    };
+    // The bridge is looked at ten times per character, which is often enough not to be noticed
+    // and rarely enough not to slow the simulation down.
+    let character_cycles = host
+        .as_ref()
+        .map_or(config.character_cycles(), UartHost::get_character_cycles);
+    let exchange_cycles = (character_cycles / 10).max(1);
+    let mut next_exchange = 0;
+    let mut idle_since = None;
+    let start = Instant::now();
+
+    let stop = loop {
+        let mut event = sim.step();
+
+        if let (Some(vcd), Ok(event), Some(path)) = (&mut vcd, &event, &vcd_path) {
+            vcd.record(&mut sim, event)
+                .map_err(|error| io_failure(path, error))?;
+        }
+
+        // A peripheral that doesn't answer in time faults the run, where a broken link is an I/O
+        // error below.
+        if let Some((label, _, client)) =
+            clients.iter().find(|(_, _, client)| client.is_timed_out())
+        {
+            let message = format!("{}: {}", label, client.get_error().unwrap_or_default());
+
+            event = Err(Error::new(ErrorKind::TimedOut, message));
+        }
+
+        match event {
+            Ok(StepEvent::Executed(executed)) => {
+                if let (Some(check), Some(port)) = (&mut check, &executed.port) {
+                    check.check(executed.cycle, executed.address, port);
+                }
+
+                if let (Some(port), false) = (executed.port, quiet) {
+                    match port.access {
+                        PortAccess::Input => {}
+                        PortAccess::Output => println!(
+                            "{:>10}  OUTPUT   port {:02X} = {:02X}",
+                            executed.cycle, port.port, port.value
+                        ),
+                        PortAccess::OutputK => println!(
+                            "{:>10}  OUTPUTK  port {:X} = {:02X}",
+                            executed.cycle, port.port, port.value
+                        ),
+                    }
+                }
+            }
+            Ok(StepEvent::Interrupt { .. }) => {}
+            Ok(StepEvent::Halted) => {
+                break format!(
+                    "Halted at {}, which has no instruction,",
+                    describe_address(&program, sim.get_program_counter())
+                )
+            }
+            Err(error) => {
+                // Puts the terminal back the way it was before reporting.
+                drop(connection);
+
+                for (_, _, client) in &clients {
+                    client.finish(sim.get_cycles());
+                }
+
+                // The waveform leading up to the fault is what's needed to find it.
+                if let (Some(vcd), Some(path)) = (vcd, &vcd_path) {
+                    vcd.finish(&sim).map_err(|error| io_failure(path, error))?;
+                }
+
+                report_fault(&program, &sim, &options, &error);
+                return Err(Failure::Runtime);
+            }
+        }
+
+        for (label, _, client) in &clients {
+            if let Some(error) = client.get_error() {
+                return Err(Failure::Io(format!("{}: {}", label, error)));
+            }
+        }
+
+        if let Some((_, stopper, _)) = clients.iter().find(|(_, _, client)| client.is_stopped()) {
+            break format!(
+                "Stopped by {} at {}",
+                stopper,
+                describe_address(&program, sim.get_program_counter())
+            );
+        }
+
+        if options
+            .max_cycles
+            .is_some_and(|max| sim.get_cycles() >= max)
+        {
+            break format!(
+                "Reached the cycle limit at {}",
+                describe_address(&program, sim.get_program_counter())
+            );
+        }
+
+        if realtime {
+            // Waits for the wall clock to catch up with the simulated time, a millisecond at a
+            // time so the simulation still runs smoothly.
+            let simulated = Duration::from_secs_f64(sim.get_cycles() as f64 / options.clock);
+            let ahead = simulated.saturating_sub(start.elapsed());
+
+            if ahead > Duration::from_millis(1) {
+                thread::sleep(ahead);
+            }
+        }
+
+        let cycles = sim.get_cycles();
+
+        if cycles < next_exchange || (connection.is_none() && picoterm.is_none()) {
+            continue;
+        }
+
+        next_exchange = cycles + exchange_cycles;
+
+        // PicoTerm answers requests as it reads what the program sent, bridged or not.
+        let going = match (&mut connection, &mut picoterm, &mut host) {
+            (Some(connection), Some(picoterm), _) => connection.exchange(picoterm),
+            (Some(connection), None, Some(host)) => connection.exchange(host),
+            _ => Ok(true),
+        };
+
+        if let Some(picoterm) = &mut picoterm {
+            for event in picoterm.take_events() {
+                eprint!("{:>10}  PicoTerm {}{}", cycles, event, newline);
+            }
+        }
+
+        if !going.map_err(bridge_failure)? {
+            break format!(
+                "Stopped from the terminal at {}",
+                describe_address(&program, sim.get_program_counter())
+            );
+        }
+
+        if let (Some(connection), Some(host)) = (&connection, &host) {
+            // Once the other side is gone, the run ends when the program has dealt with what it
+            // sent and kept quiet for the time of 10 characters.
+            match connection.is_closed() && host.is_idle() {
+                true if idle_since.is_some_and(|since| cycles - since >= 10 * character_cycles) => {
+                    break format!(
+                        "The other end of the UART closed, stopped at {}",
+                        describe_address(&program, sim.get_program_counter())
+                    );
+                }
+                true => idle_since = idle_since.or(Some(cycles)),
+                false => idle_since = None,
+            }
+        }
+    };
+
+    if let Some(mut connection) = connection {
+        // What the program sent last, before the terminal is put back the way it was.
+        match (&mut picoterm, &mut host) {
+            (Some(picoterm), _) => connection.exchange(picoterm),
+            (None, Some(host)) => connection.exchange(host),
+            (None, None) => Ok(true),
+        }
+        .map_err(bridge_failure)?;
+    }
+
+    for (_, _, client) in &clients {
+        client.finish(sim.get_cycles());
+    }
+
+    if let Some(picoterm) = &mut picoterm {
+        for event in picoterm.take_events() {
+            eprintln!("{:>10}  PicoTerm {}", sim.get_cycles(), event);
+        }
+    }
+
+    if let (Some(vcd), Some(path)) = (vcd, &vcd_path) {
+        vcd.finish(&sim).map_err(|error| io_failure(path, error))?;
+    }
+
+    let mut summary = match &host {
+        Some(host) => format_uart(host, picoterm.as_mut(), bridge.is_some()),
+        None => String::new(),
+    };
+
+    for (name, bus) in peripherals.get_i2c_buses() {
+        summary.push_str(&format_i2c(&format!("I2C {}", name), bus));
+        bus.save()
+            .map_err(|error| Failure::Io(format!("I2C {}: {}", name, error)))?;
+    }
+
+    if let Some(i2c) = &i2c {
+        summary.push_str(&format_i2c("I2C", i2c));
+
+        if let Some(path) = &peripheral_options.eeprom {
+            i2c.save().map_err(|error| io_failure(path, error))?;
+        }
+    }
+
+    summary.push_str(&format!(
+        "{} after {} clock cycles ({}).\n",
+        stop,
+        sim.get_cycles(),
+        options.time(sim.get_cycles())
+    ));
+    summary.push_str(&format_state(&sim));
+
+    if let Some(check) = &mut check {
+        check.finish(sim.get_cycles());
+        summary.push_str(&format_check(&program, check));
+    }
+
+    match stdio {
+        true => eprint!("{}", summary),
+        false => print!("{}", summary),
+    }
+
+    match check.is_some_and(|check| !check.get_mismatches().is_empty()) {
+        true => Err(Failure::Mismatch),
+        false => Ok(()),
+    }
+}
+
+/// How many outputs were the expected ones, and the first mismatches with where the program made
+/// them.
+fn format_check(program: &Program, check: &OutputCheck) -> String {
+    let mismatches = check.get_mismatches();
+    let mut text = format!(
+        "Outputs: {} of {} expected matched, {} mismatches\n",
+        check.get_matched(),
+        check.get_expected().len(),
+        mismatches.len()
+    );
+
+    for mismatch in mismatches.iter().take(10) {
+        match mismatch.address {
+            Some(address) => text.push_str(&format!(
+                "Mismatch: {}, at {}\n",
+                mismatch,
+                describe_address(program, address)
+            )),
+            None => text.push_str(&format!("Mismatch: {}\n", mismatch)),
+        }
+    }
+
+    if mismatches.len() > 10 {
+        text.push_str(&format!("Mismatch: {} more\n", mismatches.len() - 10));
+    }
+
+    text
+}
+
+/// The I2C bus of the KC705 board as `kc705_i2c_devices.psm` uses it: the M24C08 EEPROM is on
+/// channel 3 of the PCA9548 switch, and keeps its contents in a file if it's given one.
+fn create_i2c_bus(
+    clock: f64,
+    eeprom: Option<&str>,
+    inner: Box<dyn PortHandler>,
+) -> Result<(I2cBus, I2cHandle), Failure> {
+    let config = I2cConfig {
+        clock,
+        ..I2cConfig::default()
+    };
+    let mut bus = I2cBus::new(config, inner);
+    let mut switch = Pca9548::new(0x74);
+    let memory = match eeprom {
+        Some(path) => M24c08::open(0x54, path).map_err(|error| io_failure(path, error))?,
+        None => M24c08::new(0x54),
+    };
+
+    switch.connect(3, Box::new(memory));
+    bus.connect(Box::new(switch));
+
+    let handle = bus.handle();
+
+    Ok((bus, handle))
+}
+
+/// How much went on on the I2C bus, and the first timing violations if there were any.
+fn format_i2c(label: &str, i2c: &I2cHandle) -> String {
+    let violations = i2c.get_violations();
+    let mut text = format!(
+        "{}: {} transactions, {} not acknowledged\n",
+        label,
+        i2c.get_transactions(),
+        i2c.get_nacks()
+    );
+
+    for violation in violations.iter().take(5) {
+        text.push_str(&format!("{} timing: {}\n", label, violation));
+    }
+
+    if violations.len() > 5 {
+        text.push_str(&format!(
+            "{} timing: {} more violations\n",
+            label,
+            violations.len() - 5
+        ));
+    }
+
+    text
+}
+
+/// What the program sent through the UART, or what PicoTerm displayed, unless it went to a
+/// bridge, the state of the PicoTerm devices and the characters the program missed.
+fn format_uart(host: &UartHost, picoterm: Option<&mut PicoTerm>, bridged: bool) -> String {
+    let mut text = String::new();
+
+    match (picoterm, bridged) {
+        (Some(picoterm), bridged) => {
+            if !bridged {
+                let mut displayed = Vec::new();
+                let _ = picoterm.read_to_end(&mut displayed);
+
+                text.push_str(&format!(
+                    "PicoTerm displayed {} characters: \"{}\"\n",
+                    displayed.len(),
+                    displayed.escape_ascii()
+                ));
+            }
+
+            text.push_str(&format!("PicoTerm {}\n", picoterm.format_devices()));
+        }
+        (None, false) => {
+            let transmitted = host.take_transmitted();
+
+            text.push_str(&format!(
+                "UART transmitted {} characters: \"{}\"\n",
+                transmitted.len(),
+                transmitted.escape_ascii()
+            ));
+        }
+        (None, true) => {}
+    }
+
+    if !host.is_rx_idle() {
+        text.push_str("UART input wasn't all received by the end of the run\n");
+    }
+
+    if host.get_overruns() > 0 {
+        text.push_str(&format!(
+            "UART receiver FIFO overrun, {} characters lost\n",
+            host.get_overruns()
+        ));
+    }
+
+    text
+}
+
+pub fn report_fault(
+    program: &Program,
+    sim: &SimulationContext,
+    options: &SimulationOptions,
+    error: &Error,
+) {
+    eprintln!(
+        "error: runtime fault at {} after {} clock cycles ({}): {}",
+        describe_address(program, sim.get_program_counter()),
+        sim.get_cycles(),
+        options.time(sim.get_cycles()),
+        error
+    );
+}
+
+/// Registers of both banks, flags and interrupt state.
+pub fn format_state(sim: &SimulationContext) -> String {
+    let mut state = String::new();
+
+    for bank in ['a', 'b'] {
+        let registers = sim.get_bank_registers(bank);
+        let selected = if sim.get_register_bank() == bank {
+            " (selected)"
+        } else {
+            ""
+        };
+
+        state.push_str(&format!(
+            "Bank {}{}:\n",
+            bank.to_ascii_uppercase(),
+            selected
+        ));
+
+        for row in registers.chunks(8).enumerate() {
+            let (index, values) = row;
+            let line: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("s{:X}={:02X}", index * 8 + i, value))
+                .collect();
+
+            state.push_str(&format!("  {}\n", line.join(" ")));
+        }
+    }
+
+    state.push_str(&format!(
+        "Z={} C={} interrupts {}, {} instructions executed\n",
+        sim.get_zero_flag() as u8,
+        sim.get_carry_flag() as u8,
+        if sim.is_interrupt_enabled() {
+            "enabled"
+        } else {
+            "disabled"
+        },
+        sim.get_cycles() / CLOCK_CYCLES_PER_INSTRUCTION
+    ));
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kcpsm6sim::assemble_str;
+
+    fn options(list: &[&str]) -> SimulationOptions {
+        SimulationOptions::parse(Args::new(list.iter().map(|s| s.to_string()).collect())).unwrap()
+    }
+
+    #[test]
+    fn stimulus_is_applied() {
+        let program =
+            assemble_str("namereg s1, count\ninput s0, 02\ninput s0, 02\nadd count, s0\n");
+        let options = options(&["--reg", "count=10", "--input=02=01,05", "--hwbuild", "3"]);
+        let mut sim = options.create_simulation(&program).unwrap();
+
+        sim.resume(None).unwrap();
+
+        assert_eq!(sim.get_register(1), Some(0x15));
+        assert_eq!(sim.get_hwbuild(), 3);
+    }
+
+    #[test]
+    fn invalid_options() {
+        let parse = |list: &[&str]| {
+            SimulationOptions::parse(Args::new(list.iter().map(|s| s.to_string()).collect()))
+        };
+
+        assert!(parse(&["--reg", "s0"]).is_err());
+        assert!(parse(&["--input", "01=1FF"]).is_err());
+        assert!(parse(&["--clock", "fast"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["a.psm", "b.psm"]).is_err());
+    }
+
+    #[test]
+    fn state() {
+        let sim = assemble_str("load s0, 01\n").create_simulation();
+        let state = format_state(&sim);
+
+        assert!(state.starts_with("Bank A (selected):\n  s0=00 s1=00"));
+        assert!(state.contains("Z=0 C=0 interrupts disabled"));
+    }
+}
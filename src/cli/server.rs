@@ -0,0 +1,42 @@
+use std::io;
+
+use kcpsm6sim::{dap, lsp};
+
+use super::{unknown_option, Arg, Args, Failure};
+
+/// Runs the language server on standard input and output.
+pub fn lsp(mut args: Args) -> Result<(), Failure> {
+    if let Some(arg) = args.next()? {
+        return Err(match arg {
+            Arg::Option(option) => unknown_option(&option),
+            Arg::Positional(value) => Failure::Usage(format!("unexpected argument '{}'", value)),
+        });
+    }
+
+    lsp::serve(io::stdin().lock(), io::stdout().lock())
+        .map_err(|error| Failure::Io(format!("language server: {}", error)))
+}
+
+/// Runs the debug adapter on standard input and output, or for one client on a TCP address.
+pub fn dap(mut args: Args) -> Result<(), Failure> {
+    let mut address = None;
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) if option == "--port" => address = Some(args.value(&option)?),
+            Arg::Option(option) => return Err(unknown_option(&option)),
+            Arg::Positional(value) => {
+                return Err(Failure::Usage(format!("unexpected argument '{}'", value)))
+            }
+        }
+    }
+
+    let result = match address {
+        // A bare port number listens on the loopback interface.
+        Some(port) if port.parse::<u16>().is_ok() => dap::serve_tcp(format!("127.0.0.1:{}", port)),
+        Some(address) => dap::serve_tcp(address),
+        None => dap::serve(io::BufReader::new(io::stdin()), io::stdout()),
+    };
+
+    result.map_err(|error| Failure::Io(format!("debug adapter: {}", error)))
+}
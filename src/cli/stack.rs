@@ -0,0 +1,59 @@
+use kcpsm6sim::analysis::stack::format_chain;
+use kcpsm6sim::{analyze_stack, CALL_STACK_SIZE, DEFAULT_INTERRUPT_VECTOR};
+
+use super::{load_program, parse_value, print_diagnostics, unknown_option, Arg, Args, Failure};
+
+pub fn stack(mut args: Args) -> Result<(), Failure> {
+    let mut path = None;
+    let mut interrupt_vector = DEFAULT_INTERRUPT_VECTOR;
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) if option == "--interrupt-vector" => {
+                interrupt_vector = parse_value(&args.value(&option)?)
+                    .map_err(|message| Failure::Usage(format!("{}: {}", option, message)))?
+                    as usize;
+            }
+            Arg::Option(option) => return Err(unknown_option(&option)),
+            Arg::Positional(file) if path.is_none() => path = Some(file),
+            Arg::Positional(file) => {
+                return Err(Failure::Usage(format!("unexpected argument '{}'", file)))
+            }
+        }
+    }
+
+    let path = path.ok_or_else(|| Failure::Usage("stack needs a file".to_string()))?;
+    let program = load_program(&path, None, false)?;
+    let report = analyze_stack(&program, interrupt_vector);
+
+    println!(
+        "Reset:      {:>2}  {}",
+        report.reset.depth,
+        format_chain(&program, &report.reset)
+    );
+
+    match &report.interrupt {
+        Some(interrupt) => println!(
+            "Interrupt:  {:>2}  (interrupt) -> {}",
+            interrupt.depth,
+            format_chain(&program, interrupt)
+        ),
+        None => println!("Interrupt:   -  no instruction at the interrupt vector"),
+    }
+
+    println!(
+        "Worst case: {:>2} of {} call stack entries",
+        report.get_worst_case(),
+        CALL_STACK_SIZE
+    );
+
+    let diagnostics = report.get_diagnostics(&program);
+
+    print_diagnostics(&path, &program, &diagnostics);
+
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        return Err(Failure::Assembly);
+    }
+
+    Ok(())
+}
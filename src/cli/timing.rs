@@ -0,0 +1,137 @@
+use kcpsm6sim::{analyze_timing, LoopBound, LoopBounds, Program};
+
+use super::{
+    format_time, load_program, parse_frequency, print_diagnostics, unknown_option, Arg, Args,
+    Failure,
+};
+
+pub fn timing(mut args: Args) -> Result<(), Failure> {
+    let mut positional = Vec::new();
+    let mut clock = 100e6;
+    let mut loops = Vec::new();
+
+    while let Some(arg) = args.next()? {
+        match arg {
+            Arg::Option(option) if option == "--clock" => {
+                clock = parse_frequency(&args.value(&option)?)
+                    .map_err(|message| Failure::Usage(format!("{}: {}", option, message)))?;
+            }
+            Arg::Option(option) if option == "--loop" => loops.push(args.value(&option)?),
+            Arg::Option(option) => return Err(unknown_option(&option)),
+            Arg::Positional(value) if positional.len() < 3 => positional.push(value),
+            Arg::Positional(value) => {
+                return Err(Failure::Usage(format!("unexpected argument '{}'", value)))
+            }
+        }
+    }
+
+    if positional.len() < 2 {
+        return Err(Failure::Usage(
+            "timing needs a file and a label".to_string(),
+        ));
+    }
+
+    let path = &positional[0];
+    let program = load_program(path, None, false)?;
+    let find = |name: &str| {
+        program
+            .find_label(name)
+            .map(|address| address as usize)
+            .ok_or_else(|| Failure::Usage(format!("'{}' isn't a label", name)))
+    };
+
+    let from = find(&positional[1])?;
+    let to = positional.get(2).map(|name| find(name)).transpose()?;
+    let mut bounds = LoopBounds::from_annotations(&program);
+
+    for text in &loops {
+        let (line, bound) = parse_loop(&program, text)
+            .map_err(|message| Failure::Usage(format!("--loop: {}", message)))?;
+
+        bounds.set(line, bound);
+    }
+
+    let timing = match analyze_timing(&program, from, to, &bounds) {
+        Ok(timing) => timing,
+        Err(diagnostic) => {
+            print_diagnostics(path, &program, &[diagnostic]);
+            return Err(Failure::Assembly);
+        }
+    };
+
+    match positional.get(2) {
+        Some(to) => println!("From {} to {}:", positional[1], to),
+        None => println!("From {} until it returns:", positional[1]),
+    }
+
+    for (name, instructions, cycles) in [
+        ("Best", timing.best, timing.get_best_cycles()),
+        ("Worst", timing.worst, timing.get_worst_cycles()),
+    ] {
+        println!(
+            "  {:<6} {:>10} instructions {:>11} clock cycles  {}",
+            name,
+            instructions,
+            cycles,
+            format_time(cycles as f64 / clock)
+        );
+    }
+
+    Ok(())
+}
+
+/// Reads `<where>=<n>` or `<where>=<min>..<max>`, where the loop is given by the label of its
+/// first instruction, by a line of the program's file or by `file:line` for included files.
+fn parse_loop(program: &Program, text: &str) -> Result<(usize, LoopBound), String> {
+    let (place, bound) = text
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' should be <label|[file:]line>=<count>", text))?;
+
+    let bound = LoopBound::parse(bound)
+        .ok_or_else(|| format!("'{}' isn't a valid count or range", bound))?;
+
+    let file_line = place
+        .rsplit_once(':')
+        .and_then(|(file, line)| Some((file, line.parse::<usize>().ok()?)));
+    let line = if let Ok(line) = place.parse::<usize>() {
+        program
+            .find_line(None, line)
+            .ok_or_else(|| format!("there's no line {}", line))?
+    } else if let Some((file, line)) = file_line {
+        program
+            .find_file_line(file, line)
+            .ok_or_else(|| format!("there's no line {} in '{}'", line, file))?
+    } else {
+        program
+            .find_label(place)
+            .and_then(|address| program.get_source_map().get_line(address as usize))
+            .ok_or_else(|| format!("'{}' isn't a label", place))?
+    };
+
+    Ok((line, bound))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kcpsm6sim::assemble_str;
+
+    #[test]
+    fn loops() {
+        let program = assemble_str("load s0, 01\nwait: sub s0, 01\njump nz, wait\n");
+
+        assert_eq!(
+            parse_loop(&program, "wait=1..4"),
+            Ok((2, LoopBound { min: 1, max: 4 }))
+        );
+        assert_eq!(
+            parse_loop(&program, "3=10"),
+            Ok((3, LoopBound { min: 10, max: 10 }))
+        );
+        assert!(parse_loop(&program, "wait").is_err());
+        assert!(parse_loop(&program, "loop=10").is_err());
+        assert!(parse_loop(&program, "wait=0").is_err());
+        assert!(parse_loop(&program, "4=10").is_err());
+        assert!(parse_loop(&program, "main.psm:3=10").is_err());
+    }
+}
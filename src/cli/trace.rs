@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+
+use kcpsm6sim::trace::{TraceFilter, TraceFormat, TraceReader, TraceWriter, Tracer};
+use kcpsm6sim::{Program, StepEvent};
+
+use super::run::{report_fault, SimulationOptions};
+use super::{describe_address, io_failure, load_program, parse_value, Args, Failure};
+
+pub fn trace(args: Args) -> Result<(), Failure> {
+    let mut format = TraceFormat::Text;
+    let mut output_path = None;
+    let mut read = None;
+    // Labels can only be resolved once the program is assembled.
+    let mut ranges = Vec::new();
+    let mut routines = Vec::new();
+    let options = SimulationOptions::parse_with(args, |option, args| {
+        match option {
+            "--format" => {
+                let name = args.value(option)?;
+
+                format = TraceFormat::parse(&name).ok_or_else(|| {
+                    Failure::Usage(format!(
+                        "--format: expected text, jsonl or binary, got '{}'",
+                        name
+                    ))
+                })?;
+            }
+            "-o" | "--output" => output_path = Some(args.value(option)?),
+            "--read" => read = Some(args.value(option)?),
+            "--range" => ranges.push(args.value(option)?),
+            "--routine" => routines.push(args.value(option)?),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    })?;
+
+    let output_name = output_path.as_deref().unwrap_or("standard output");
+
+    // A binary trace is converted, with labels when the program is given too.
+    if let Some(path) = read {
+        let program = match &options.path {
+            Some(program) => Some(load_program(program, None, false)?),
+            None => None,
+        };
+        let output = create_output(output_path.as_deref())?;
+
+        return convert(&path, program.as_ref(), output, output_name, format);
+    }
+
+    let program = options.load_program()?;
+    let mut filter = TraceFilter::new();
+
+    for range in &ranges {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+
+        filter.add_range(
+            find_location(&program, first, "--range")?,
+            find_location(&program, last, "--range")?,
+        );
+    }
+
+    for routine in &routines {
+        filter.add_routine(find_location(&program, routine, "--routine")?);
+    }
+
+    // The output is only created once the program assembled, so a failed run doesn't leave an
+    // empty trace behind.
+    let output = create_output(output_path.as_deref())?;
+    let mut sim = options.create_simulation(&program)?;
+    let mut tracer = Tracer::new(&sim, filter);
+    let mut writer = TraceWriter::new(output, format, Some(&program))
+        .map_err(|error| io_failure(output_name, error))?;
+    let mut written = 0u64;
+
+    let stop = loop {
+        let event = match sim.step() {
+            Ok(StepEvent::Halted) => {
+                break format!(
+                    "Halted at {}, which has no instruction,",
+                    describe_address(&program, sim.get_program_counter())
+                )
+            }
+            Ok(event) => event,
+            Err(error) => {
+                writer
+                    .flush()
+                    .map_err(|error| io_failure(output_name, error))?;
+                report_fault(&program, &sim, &options, &error);
+                return Err(Failure::Runtime);
+            }
+        };
+
+        if let Some(entry) = tracer.record(&sim, &event) {
+            writer
+                .write(&entry)
+                .map_err(|error| io_failure(output_name, error))?;
+            written += 1;
+        }
+
+        if options
+            .max_cycles
+            .is_some_and(|max| sim.get_cycles() >= max)
+        {
+            break format!(
+                "Reached the cycle limit at {}",
+                describe_address(&program, sim.get_program_counter())
+            );
+        }
+    };
+
+    writer
+        .flush()
+        .map_err(|error| io_failure(output_name, error))?;
+
+    eprintln!(
+        "{} after {} clock cycles ({}), {} trace entries.",
+        stop,
+        sim.get_cycles(),
+        options.time(sim.get_cycles()),
+        written
+    );
+
+    Ok(())
+}
+
+/// An address (hexadecimal as in PSM files) or a label.
+/// The file given with `-o`, or the standard output.
+fn create_output(path: Option<&str>) -> Result<BufWriter<Box<dyn Write>>, Failure> {
+    let output: Box<dyn Write> = match path {
+        Some(path) => Box::new(File::create(path).map_err(|error| io_failure(path, error))?),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    Ok(BufWriter::new(output))
+}
+
+fn find_location(program: &Program, text: &str, option: &str) -> Result<usize, Failure> {
+    match program.find_label(text) {
+        Some(address) => Ok(address as usize),
+        None => parse_value(text)
+            .map(|address| address as usize)
+            .map_err(|_| {
+                Failure::Usage(format!(
+                    "{}: '{}' is neither a label nor an address",
+                    option, text
+                ))
+            }),
+    }
+}
+
+fn convert(
+    path: &str,
+    program: Option<&Program>,
+    output: impl Write,
+    output_name: &str,
+    format: TraceFormat,
+) -> Result<(), Failure> {
+    let write_failure = |error| io_failure(output_name, error);
+    let input = File::open(path).map_err(|error| io_failure(path, error))?;
+    let reader =
+        TraceReader::new(BufReader::new(input)).map_err(|error| io_failure(path, error))?;
+    let mut writer = TraceWriter::new(output, format, program).map_err(write_failure)?;
+
+    for entry in reader {
+        let entry = entry.map_err(|error| io_failure(path, error))?;
+
+        writer.write(&entry).map_err(write_failure)?;
+    }
+
+    writer.flush().map_err(write_failure)
+}
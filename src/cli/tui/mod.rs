@@ -0,0 +1,842 @@
+mod screen;
+pub mod terminal;
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::mpsc::TryRecvError;
+use std::time::{Duration, Instant};
+
+use kcpsm6sim::peripherals::uart::UartHost;
+use kcpsm6sim::{
+    PortAccess, PortTransaction, Program, RunMode, SimulationContext, StepEvent, StopReason,
+};
+
+use super::run::{PeripheralOptions, SimulationOptions};
+use super::{describe_address, describe_frame, Args, Failure};
+use screen::{Screen, Style};
+use terminal::{get_size, read_keys, Key, RawTerminal};
+
+const KEYS: &str = "s step  n next  f finish  c continue  p pause  b breakpoint  \
+                    ↑↓ move  . current line  i console  r reset  q quit";
+
+/// Port transactions kept for the port pane.
+const PORT_LOG_SIZE: usize = 500;
+
+/// Longest time the program runs between redraws while it's running.
+const FRAME: Duration = Duration::from_millis(40);
+
+/// Width of the register, flag and call stack panes.
+const SIDE_WIDTH: usize = 36;
+
+pub fn tui(args: Args) -> Result<(), Failure> {
+    let mut peripheral_options = PeripheralOptions::default();
+    let options = SimulationOptions::parse_with(args, |option, args| {
+        peripheral_options.parse_option(option, args)
+    })?;
+
+    let program = options.load_program()?;
+    let mut tui = Tui::new(&program, &options, &peripheral_options)?;
+
+    tui.session()
+        .map_err(|error| Failure::Io(format!("terminal: {}", error)))
+}
+
+/// What the program sent through the UART, shown in the console pane. What's typed there goes
+/// the other way.
+#[derive(Default)]
+struct Console {
+    host: Option<UartHost>,
+    output: String,
+    // To take CR LF as a single line break.
+    last: u8,
+}
+
+impl Console {
+    /// Takes what the program has sent since the last time.
+    fn receive(&mut self) {
+        let Some(host) = &self.host else {
+            return;
+        };
+
+        for value in host.take_transmitted() {
+            match value {
+                b'\r' => self.output.push('\n'),
+                b'\n' if self.last != b'\r' => self.output.push('\n'),
+                // Backspace.
+                0x08 => {
+                    self.output.pop();
+                }
+                // Reads of an empty receiver FIFO give 0, which some programs echo.
+                b'\n' | 0 => {}
+                _ => self.output.push(value as char),
+            }
+
+            self.last = value;
+        }
+    }
+
+    fn send(&mut self, value: u8) {
+        if let Some(host) = &mut self.host {
+            // Writing to the host end of the line doesn't fail.
+            let _ = host.write_all(&[value]);
+        }
+    }
+}
+
+/// Registers and memory when the last command started, to highlight what it changed.
+struct Snapshot {
+    banks: [[u8; 16]; 2],
+    zero: bool,
+    carry: bool,
+    scratch_pad: Vec<u8>,
+}
+
+impl Snapshot {
+    fn new(sim: &SimulationContext) -> Snapshot {
+        Snapshot {
+            banks: [sim.get_bank_registers('a'), sim.get_bank_registers('b')],
+            zero: sim.get_zero_flag(),
+            carry: sim.get_carry_flag(),
+            scratch_pad: (0..sim.get_scratch_pad_size())
+                .map(|address| sim.get_scratch_pad_memory(address).unwrap_or(0))
+                .collect(),
+        }
+    }
+}
+
+struct Tui<'a> {
+    program: &'a Program,
+    options: &'a SimulationOptions,
+    sim: SimulationContext,
+    console: Console,
+    previous: Snapshot,
+    ports: VecDeque<(u64, PortTransaction)>,
+    running: Option<RunMode>,
+    /// Source line selected with the arrow keys, and the first line shown.
+    cursor: usize,
+    scroll: usize,
+    /// Whether keys go to the console instead of being commands.
+    typing: bool,
+    message: String,
+}
+
+impl<'a> Tui<'a> {
+    fn new(
+        program: &'a Program,
+        options: &'a SimulationOptions,
+        peripheral_options: &PeripheralOptions,
+    ) -> Result<Tui<'a>, Failure> {
+        let (sim, _, host, _) = peripheral_options.create_simulation(
+            options,
+            program,
+            Box::new(options.create_ports()),
+        )?;
+        let console = Console {
+            host,
+            ..Console::default()
+        };
+        let mut tui = Tui {
+            program,
+            options,
+            previous: Snapshot::new(&sim),
+            sim,
+            console,
+            ports: VecDeque::new(),
+            running: None,
+            cursor: 1,
+            scroll: 1,
+            typing: false,
+            message: String::new(),
+        };
+
+        tui.follow_program_counter();
+        Ok(tui)
+    }
+
+    fn session(&mut self) -> io::Result<()> {
+        let _terminal = RawTerminal::new()?;
+        let keys = read_keys();
+        let mut size = get_size()?;
+
+        loop {
+            let mut screen = Screen::new(size.0, size.1);
+
+            self.draw(&mut screen);
+            print!("{}", screen.render());
+            io::stdout().flush()?;
+
+            let key = if self.running.is_some() {
+                let started = Instant::now();
+
+                while self.running.is_some() && started.elapsed() < FRAME {
+                    self.run_slice(1000);
+                }
+
+                match keys.try_recv() {
+                    Ok(key) => Some(key),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match keys.recv() {
+                    Ok(key) => Some(key),
+                    Err(_) => break,
+                }
+            };
+
+            if let Some(key) = key {
+                if !self.handle_key(key) {
+                    break;
+                }
+
+                if self.running.is_none() {
+                    size = get_size()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a key press. Returns false when the debugger should close.
+    fn handle_key(&mut self, key: Key) -> bool {
+        if self.typing {
+            let byte = match key {
+                Key::Escape => {
+                    self.typing = false;
+                    self.message = "Left the console.".to_string();
+                    return true;
+                }
+                Key::Enter => Some(b'\r'),
+                Key::Backspace => Some(0x08),
+                Key::Char(c) if c.is_ascii() => Some(c as u8),
+                Key::Control(c) => Some(c as u8 - b'a' + 1),
+                _ => None,
+            };
+
+            if let Some(byte) = byte {
+                self.console.send(byte);
+            }
+
+            return true;
+        }
+
+        let lines = self.program.get_source().len().max(1);
+
+        match key {
+            Key::Char('q') | Key::Control('c') => return false,
+            Key::Char('p') | Key::Char(' ') | Key::Escape if self.running.is_some() => {
+                self.stop("Paused.".to_string())
+            }
+            _ if self.running.is_some() => {}
+            Key::Char('s') | Key::Function(11) => self.start(RunMode::Steps(1)),
+            Key::Char('n') | Key::Function(10) => self.start(self.sim.step_over()),
+            Key::Char('f') => match self.sim.step_out() {
+                None => self.message = "Not inside a routine.".to_string(),
+                Some(mode) => self.start(mode),
+            },
+            Key::Char('c') | Key::Function(5) => self.start(RunMode::Continue),
+            Key::Char('b') | Key::Function(9) => self.toggle_breakpoint(),
+            Key::Char('r') => {
+                self.sim.reset();
+                self.previous = Snapshot::new(&self.sim);
+                self.ports.clear();
+                self.console.output.clear();
+                self.follow_program_counter();
+                self.message = "Reset.".to_string();
+            }
+            Key::Char('i') => {
+                self.typing = true;
+                self.message = match self.console.host {
+                    Some(_) => "Typing to the UART, Esc to stop.".to_string(),
+                    None => "There's no UART, see --uart. Esc to stop.".to_string(),
+                };
+            }
+            Key::Char('.') => self.follow_program_counter(),
+            Key::Up | Key::Char('k') => self.cursor = self.cursor.saturating_sub(1).max(1),
+            Key::Down | Key::Char('j') => self.cursor = (self.cursor + 1).min(lines),
+            Key::PageUp => self.cursor = self.cursor.saturating_sub(20).max(1),
+            Key::PageDown => self.cursor = (self.cursor + 20).min(lines),
+            Key::Home => self.cursor = 1,
+            Key::End => self.cursor = lines,
+            _ => {}
+        }
+
+        true
+    }
+
+    fn start(&mut self, mode: RunMode) {
+        self.previous = Snapshot::new(&self.sim);
+        self.running = Some(mode);
+        self.message = "Running, p to pause.".to_string();
+
+        // Single steps finish right away rather than on the next frame.
+        if let RunMode::Steps(1) = mode {
+            self.run_slice(1);
+        }
+    }
+
+    fn stop(&mut self, message: String) {
+        self.running = None;
+        self.message = message;
+        self.follow_program_counter();
+    }
+
+    /// Runs up to `steps` instructions of the running program.
+    fn run_slice(&mut self, steps: usize) {
+        let Some(mode) = self.running else {
+            return;
+        };
+
+        let ports = &mut self.ports;
+        let reason = self
+            .sim
+            .run_until(mode, self.options.max_cycles, steps, |_, event| {
+                if let StepEvent::Executed(executed) = event {
+                    if let Some(port) = executed.port {
+                        if ports.len() == PORT_LOG_SIZE {
+                            ports.pop_front();
+                        }
+
+                        ports.push_back((executed.cycle, port));
+                    }
+                }
+            });
+
+        self.console.receive();
+
+        match reason {
+            Ok(Some(StopReason::Halted)) => self.stop(format!(
+                "The program halted, there's no instruction at {:03X}.",
+                self.sim.get_program_counter()
+            )),
+            Ok(Some(StopReason::Breakpoint(_))) => self.stop("Breakpoint reached.".to_string()),
+            Ok(Some(StopReason::CycleLimit)) => self.stop("Reached the cycle limit.".to_string()),
+            Ok(Some(StopReason::Done)) => self.stop(String::new()),
+            Ok(None) => {}
+            Err(error) => self.stop(format!(
+                "Runtime fault at {}: {}",
+                describe_address(self.program, self.sim.get_program_counter()),
+                error
+            )),
+        }
+    }
+
+    fn toggle_breakpoint(&mut self) {
+        let Some((address, line)) = self.program.get_source_map().find_address_from(self.cursor)
+        else {
+            self.message = format!("There are no instructions from line {} on.", self.cursor);
+            return;
+        };
+
+        if self.sim.remove_breakpoint(address) {
+            self.message = format!(
+                "Removed the breakpoint at {}.",
+                describe_address(self.program, address)
+            );
+        } else {
+            self.sim.add_breakpoint(address);
+            self.message = format!("Breakpoint at {}.", describe_address(self.program, address));
+        }
+
+        self.cursor = line;
+    }
+
+    fn current_line(&self) -> Option<usize> {
+        self.program
+            .get_source_map()
+            .get_line(self.sim.get_program_counter())
+    }
+
+    fn follow_program_counter(&mut self) {
+        if let Some(line) = self.current_line() {
+            self.cursor = line;
+        }
+    }
+
+    fn draw(&mut self, screen: &mut Screen) {
+        let (width, height) = (screen.get_width(), screen.get_height());
+        let side = SIDE_WIDTH.min(width / 2);
+        // The scratch pad takes 16 bytes per row when there's room for it, or 8.
+        let per_row = if width >= 110 { 16 } else { 8 };
+        let memory = 6 + per_row * 3;
+        let memory_rows = self.sim.get_scratch_pad_size().div_ceil(per_row);
+        let bottom = (memory_rows + 2).clamp(6, (height.saturating_sub(1) / 3).max(6));
+        let top = height.saturating_sub(1 + bottom);
+
+        self.draw_source(screen, 0, 0, width - side, top);
+        self.draw_registers(screen, width - side, 0, side);
+        self.draw_state(screen, width - side, 11, side);
+        self.draw_stack(screen, width - side, 16, side, top.saturating_sub(16));
+
+        let memory = memory.min(width / 2);
+        let ports = (width - memory) / 2;
+
+        self.draw_scratch_pad(screen, 0, top, memory, bottom, per_row);
+        self.draw_ports(screen, memory, top, ports, bottom);
+        self.draw_console(screen, memory + ports, top, width - memory - ports, bottom);
+
+        let status = match (self.typing, self.message.is_empty()) {
+            (false, true) => KEYS,
+            _ => &self.message,
+        };
+
+        screen.put(0, height.saturating_sub(1), width, status, Style::Normal);
+    }
+
+    fn draw_source(
+        &mut self,
+        screen: &mut Screen,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) {
+        let title = self
+            .program
+            .get_path()
+            .map_or("Source".to_string(), |path| path.display().to_string());
+
+        screen.frame(x, y, width, height, &title);
+
+        let rows = height.saturating_sub(2);
+        let source_map = self.program.get_source_map();
+        let current = self.current_line();
+
+        // Keep the cursor in view, with some lines around it when jumping.
+        if self.cursor < self.scroll || self.cursor >= self.scroll + rows {
+            self.scroll = self.cursor.saturating_sub(rows / 3).max(1);
+        }
+
+        for row in 0..rows {
+            let line = self.scroll + row;
+            let Some(text) = self.program.get_source_line(line) else {
+                break;
+            };
+            let address = source_map.get_address(line);
+            let breakpoint =
+                address.is_some_and(|address| self.sim.get_breakpoints().contains(&address));
+            let marker = match (Some(line) == current, breakpoint) {
+                (true, _) => "=>",
+                (false, true) => "● ",
+                (false, false) => "  ",
+            };
+            let address = address.map_or("   ".to_string(), |address| format!("{:03X}", address));
+            let text = format!("{} {:>5} {}  {}", marker, line, address, expand_tabs(text));
+            let inner = width.saturating_sub(2);
+
+            screen.put(x + 1, y + 1 + row, inner, &text, Style::Normal);
+            screen.paint(x + 4, y + 1 + row, 9, Style::Dim);
+
+            if Some(line) == current {
+                screen.paint(x + 1, y + 1 + row, inner, Style::Current);
+            } else if line == self.cursor {
+                screen.paint(x + 1, y + 1 + row, inner, Style::Cursor);
+            }
+
+            if breakpoint && Some(line) != current {
+                screen.paint(x + 1, y + 1 + row, 2, Style::Breakpoint);
+            }
+        }
+    }
+
+    fn draw_registers(&self, screen: &mut Screen, x: usize, y: usize, width: usize) {
+        let bank = self.sim.get_register_bank();
+
+        screen.frame(
+            x,
+            y,
+            width,
+            11,
+            &format!("Registers, bank {}", bank.to_ascii_uppercase()),
+        );
+        screen.put(x + 2, y + 1, width, "Bank A", Style::Title);
+        screen.put(x + 17, y + 1, width, "Bank B", Style::Title);
+
+        for (column, name) in ['a', 'b'].into_iter().enumerate() {
+            let registers = self.sim.get_bank_registers(name);
+
+            for (index, value) in registers.iter().enumerate() {
+                let (left, row) = (x + 2 + column * 15 + index / 8 * 7, y + 2 + index % 8);
+                let changed = *value != self.previous.banks[column][index];
+
+                screen.put(left, row, 3, &format!("s{:X}", index), Style::Dim);
+                screen.put(
+                    left + 3,
+                    row,
+                    2,
+                    &format!("{:02X}", value),
+                    if changed {
+                        Style::Changed
+                    } else {
+                        Style::Normal
+                    },
+                );
+            }
+        }
+
+        // Mark the selected bank.
+        screen.paint(
+            x + 2 + (bank == 'b') as usize * 15,
+            y + 1,
+            6,
+            Style::Current,
+        );
+    }
+
+    fn draw_state(&self, screen: &mut Screen, x: usize, y: usize, width: usize) {
+        let sim = &self.sim;
+        let inner = width.saturating_sub(3);
+
+        screen.frame(x, y, width, 5, "State");
+
+        let flag = |value: bool, previous: bool| match value == previous {
+            true => Style::Normal,
+            false => Style::Changed,
+        };
+
+        screen.put(x + 2, y + 1, 2, "Z", Style::Dim);
+        screen.put(
+            x + 4,
+            y + 1,
+            1,
+            &(sim.get_zero_flag() as u8).to_string(),
+            flag(sim.get_zero_flag(), self.previous.zero),
+        );
+        screen.put(x + 7, y + 1, 2, "C", Style::Dim);
+        screen.put(
+            x + 9,
+            y + 1,
+            1,
+            &(sim.get_carry_flag() as u8).to_string(),
+            flag(sim.get_carry_flag(), self.previous.carry),
+        );
+        screen.put(
+            x + 12,
+            y + 1,
+            inner.saturating_sub(10),
+            &format!(
+                "interrupts {} @{:03X}",
+                match sim.is_interrupt_enabled() {
+                    true => "on",
+                    false => "off",
+                },
+                sim.get_interrupt_vector()
+            ),
+            Style::Normal,
+        );
+        screen.put(
+            x + 2,
+            y + 2,
+            inner,
+            &format!(
+                "PC {}",
+                describe_address(self.program, sim.get_program_counter())
+            ),
+            Style::Normal,
+        );
+        screen.put(
+            x + 2,
+            y + 3,
+            inner,
+            &format!(
+                "cycle {} ({})",
+                sim.get_cycles(),
+                self.options.time(sim.get_cycles())
+            ),
+            Style::Normal,
+        );
+    }
+
+    fn draw_stack(&self, screen: &mut Screen, x: usize, y: usize, width: usize, height: usize) {
+        if height < 3 {
+            return;
+        }
+
+        screen.frame(x, y, width, height, "Call stack");
+
+        let mut frames = vec![describe_address(
+            self.program,
+            self.sim.get_program_counter(),
+        )];
+
+        for frame in self.sim.get_call_frames() {
+            frames.push(describe_frame(self.program, &frame));
+        }
+
+        for (depth, frame) in frames.iter().take(height - 2).enumerate() {
+            screen.put(
+                x + 2,
+                y + 1 + depth,
+                width.saturating_sub(3),
+                &format!("#{} {}", depth, frame),
+                Style::Normal,
+            );
+        }
+    }
+
+    fn draw_scratch_pad(
+        &self,
+        screen: &mut Screen,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        per_row: usize,
+    ) {
+        screen.frame(x, y, width, height, "Scratch pad");
+
+        let size = self.sim.get_scratch_pad_size();
+
+        for (row, start) in (0..size)
+            .step_by(per_row)
+            .take(height.saturating_sub(2))
+            .enumerate()
+        {
+            let inner = width.saturating_sub(3);
+
+            screen.put(
+                x + 2,
+                y + 1 + row,
+                inner,
+                &format!("{:02X}", start),
+                Style::Dim,
+            );
+
+            for address in start..(start + per_row).min(size) {
+                let value = self.sim.get_scratch_pad_memory(address).unwrap_or(0);
+                let column = 4 + (address - start) * 3;
+
+                if column + 2 > inner {
+                    break;
+                }
+
+                screen.put(
+                    x + 2 + column,
+                    y + 1 + row,
+                    2,
+                    &format!("{:02X}", value),
+                    match self.previous.scratch_pad.get(address) == Some(&value) {
+                        true => Style::Normal,
+                        false => Style::Changed,
+                    },
+                );
+            }
+        }
+    }
+
+    fn draw_ports(&self, screen: &mut Screen, x: usize, y: usize, width: usize, height: usize) {
+        screen.frame(x, y, width, height, "Ports");
+
+        let rows = height.saturating_sub(2);
+        let inner = width.saturating_sub(3);
+
+        for (row, (cycle, port)) in self
+            .ports
+            .iter()
+            .skip(self.ports.len().saturating_sub(rows))
+            .enumerate()
+        {
+            let access = match port.access {
+                PortAccess::Input => "IN ",
+                PortAccess::Output => "OUT",
+                PortAccess::OutputK => "OUTK",
+            };
+            let text = format!("{:<4} {:02X}={:02X}", access, port.port, port.value);
+            let text = match inner >= text.len() + 11 {
+                true => format!("{:>10} {}", cycle, text),
+                false => text,
+            };
+
+            screen.put(x + 2, y + 1 + row, inner, &text, Style::Normal);
+        }
+    }
+
+    fn draw_console(&self, screen: &mut Screen, x: usize, y: usize, width: usize, height: usize) {
+        let console = &self.console;
+        let title = match (&console.host, self.typing) {
+            (Some(_), true) => "Console, typing",
+            (Some(_), false) => "Console",
+            (None, _) => "Console, no UART",
+        };
+
+        screen.frame(x, y, width, height, title);
+
+        let inner = width.saturating_sub(3).max(1);
+        let rows = height.saturating_sub(2);
+        let mut lines: Vec<String> = Vec::new();
+
+        // Wrap long lines.
+        for line in console.output.split('\n') {
+            let chars: Vec<char> = line.chars().collect();
+
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+
+            lines.extend(chars.chunks(inner).map(|chunk| chunk.iter().collect()));
+        }
+
+        for (row, line) in lines
+            .iter()
+            .skip(lines.len().saturating_sub(rows))
+            .enumerate()
+        {
+            screen.put(x + 2, y + 1 + row, inner, line, Style::Normal);
+        }
+    }
+}
+
+fn expand_tabs(text: &str) -> String {
+    let mut expanded = String::new();
+
+    for c in text.chars() {
+        match c {
+            '\t' => expanded.push_str(&" ".repeat(8 - expanded.chars().count() % 8)),
+            c => expanded.push(c),
+        }
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kcpsm6sim::assemble_str;
+
+    const PROGRAM: &str = "\
+start: load s0, 01
+       call send
+       call send
+       jump start
+send:  output s0, 04
+       store s0, 02
+       add s0, s0
+       return
+";
+
+    fn find(screen: &Screen, text: &str) -> (usize, usize) {
+        screen
+            .to_text()
+            .lines()
+            .enumerate()
+            .find_map(|(y, line)| {
+                line.find(text)
+                    .map(|index| (line[..index].chars().count(), y))
+            })
+            .unwrap_or_else(|| panic!("'{}' isn't on the screen:\n{}", text, screen.to_text()))
+    }
+
+    fn draw(tui: &mut Tui) -> Screen {
+        let mut screen = Screen::new(100, 30);
+
+        tui.draw(&mut screen);
+        screen
+    }
+
+    #[test]
+    fn stepping_highlights_changes() {
+        let program = assemble_str(PROGRAM);
+        let options = SimulationOptions::default();
+        let mut tui = Tui::new(&program, &options, &PeripheralOptions::default()).unwrap();
+
+        tui.cursor = 5;
+        tui.handle_key(Key::Char('b'));
+        tui.handle_key(Key::Char('c'));
+
+        while tui.running.is_some() {
+            tui.run_slice(1000);
+        }
+
+        assert_eq!(tui.message, "Breakpoint reached.");
+
+        let screen = draw(&mut tui);
+        let (x, y) = find(&screen, "=>     5 004  send:  output s0, 04");
+
+        assert_eq!(screen.get_style(x, y), Some(Style::Current));
+        assert_eq!(
+            screen.get_style(x, find(&screen, "     1 000").1),
+            Some(Style::Normal)
+        );
+        assert!(screen.to_text().contains("#1 001 (start+1, line 2)"));
+        assert!(screen.to_text().contains("s0 01"));
+
+        // Run the routine: s0 and the scratch pad change, the output is in the port pane.
+        tui.handle_key(Key::Char('f'));
+
+        while tui.running.is_some() {
+            tui.run_slice(1000);
+        }
+
+        let screen = draw(&mut tui);
+        let (x, y) = find(&screen, "s0 02");
+
+        assert_eq!(screen.get_style(x + 3, y), Some(Style::Changed));
+        assert_eq!(
+            screen.get_style(find(&screen, "s1 00").0 + 3, y + 1),
+            Some(Style::Normal)
+        );
+        assert!(screen.to_text().contains("OUT  04=01"));
+
+        let (x, y) = find(&screen, "00  00 00 01");
+
+        assert_eq!(screen.get_style(x + 10, y), Some(Style::Changed));
+        assert_eq!(screen.get_style(x + 4, y), Some(Style::Normal));
+    }
+
+    #[test]
+    fn console_and_keys() {
+        // Echoes what the UART receives.
+        let program = assemble_str(
+            "loop: input s1, 00\ntest s1, 08\njump z, loop\n\
+             input s0, 01\noutput s0, 01\njump loop\n",
+        );
+        let options = SimulationOptions::default();
+        let mut tui = Tui::new(&program, &options, &PeripheralOptions::default()).unwrap();
+
+        tui.handle_key(Key::Char('i'));
+        assert_eq!(tui.message, "There's no UART, see --uart. Esc to stop.");
+        tui.handle_key(Key::Escape);
+
+        let peripheral_options = PeripheralOptions {
+            uart: true,
+            ..PeripheralOptions::default()
+        };
+        let mut tui = Tui::new(&program, &options, &peripheral_options).unwrap();
+
+        tui.handle_key(Key::Char('i'));
+
+        for c in "hi".chars() {
+            tui.handle_key(Key::Char(c));
+        }
+
+        tui.handle_key(Key::Enter);
+        tui.handle_key(Key::Escape);
+        tui.handle_key(Key::Char('c'));
+
+        // A character takes about 8700 cycles to go through the line at 115200 baud, so only the
+        // echo of the first one is back after 20000.
+        for _ in 0..10 {
+            tui.run_slice(1000);
+        }
+
+        assert!(tui.running.is_some());
+        assert_eq!(tui.console.output, "h");
+
+        for _ in 0..40 {
+            tui.run_slice(1000);
+        }
+
+        tui.handle_key(Key::Char('p'));
+        assert!(tui.running.is_none());
+
+        let screen = draw(&mut tui);
+
+        assert_eq!(tui.console.output, "hi\n");
+        assert!(screen.to_text().contains("│ hi"));
+        assert!(screen.to_text().contains("Console"));
+        assert_eq!(tui.message, "Paused.");
+        assert!(!tui.handle_key(Key::Char('q')));
+    }
+}
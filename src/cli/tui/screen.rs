@@ -0,0 +1,183 @@
+/// How a character is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Normal,
+    /// Pane titles.
+    Title,
+    /// Line numbers, addresses and other secondary text.
+    Dim,
+    /// The current instruction.
+    Current,
+    /// Values changed by the last command.
+    Changed,
+    Breakpoint,
+    /// The line selected in the source pane.
+    Cursor,
+}
+
+impl Style {
+    fn escape(self) -> &'static str {
+        match self {
+            Style::Normal => "\x1b[0m",
+            Style::Title => "\x1b[0;1;36m",
+            Style::Dim => "\x1b[0;2m",
+            Style::Current => "\x1b[0;30;42m",
+            Style::Changed => "\x1b[0;1;33m",
+            Style::Breakpoint => "\x1b[0;1;31m",
+            Style::Cursor => "\x1b[0;7m",
+        }
+    }
+}
+
+/// A grid of styled characters, drawn in one go.
+pub struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<(char, Style)>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Screen {
+        Screen {
+            width,
+            height,
+            cells: vec![(' ', Style::Normal); width * height],
+        }
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    #[cfg(test)]
+    pub fn get_style(&self, x: usize, y: usize) -> Option<Style> {
+        (x < self.width && y < self.height).then(|| self.cells[y * self.width + x].1)
+    }
+
+    /// Writes text from a position, cut at `limit` columns or the right edge. Control characters
+    /// are shown as dots.
+    pub fn put(&mut self, x: usize, y: usize, limit: usize, text: &str, style: Style) {
+        if y >= self.height {
+            return;
+        }
+
+        let end = x.saturating_add(limit).min(self.width);
+
+        for (column, c) in (x..end).zip(text.chars()) {
+            let c = if c.is_control() { '.' } else { c };
+
+            self.cells[y * self.width + column] = (c, style);
+        }
+    }
+
+    /// Changes the style of part of a row, keeping its text.
+    pub fn paint(&mut self, x: usize, y: usize, length: usize, style: Style) {
+        if y >= self.height {
+            return;
+        }
+
+        for column in x..x.saturating_add(length).min(self.width) {
+            self.cells[y * self.width + column].1 = style;
+        }
+    }
+
+    /// A box with a title on its top edge.
+    pub fn frame(&mut self, x: usize, y: usize, width: usize, height: usize, title: &str) {
+        if width < 2 || height < 2 {
+            return;
+        }
+
+        let (right, bottom) = (x + width - 1, y + height - 1);
+
+        for column in x + 1..right {
+            self.put(column, y, 1, "─", Style::Dim);
+            self.put(column, bottom, 1, "─", Style::Dim);
+        }
+
+        for row in y + 1..bottom {
+            self.put(x, row, 1, "│", Style::Dim);
+            self.put(right, row, 1, "│", Style::Dim);
+        }
+
+        self.put(x, y, 1, "┌", Style::Dim);
+        self.put(right, y, 1, "┐", Style::Dim);
+        self.put(x, bottom, 1, "└", Style::Dim);
+        self.put(right, bottom, 1, "┘", Style::Dim);
+        self.put(
+            x + 2,
+            y,
+            width.saturating_sub(4),
+            &format!(" {} ", title),
+            Style::Title,
+        );
+    }
+
+    /// The whole screen as terminal output, starting from the top left corner.
+    pub fn render(&self) -> String {
+        let mut output = String::from("\x1b[H");
+        let mut style = None;
+
+        for (row, cells) in self.cells.chunks(self.width.max(1)).enumerate() {
+            if row > 0 {
+                output.push_str("\r\n");
+            }
+
+            for (c, cell_style) in cells {
+                if style != Some(*cell_style) {
+                    output.push_str(cell_style.escape());
+                    style = Some(*cell_style);
+                }
+
+                output.push(*c);
+            }
+        }
+
+        output.push_str(Style::Normal.escape());
+        output
+    }
+
+    /// The text of each row, without styles.
+    #[cfg(test)]
+    pub fn to_text(&self) -> String {
+        self.cells
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .map(|(c, _)| c)
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drawing() {
+        let mut screen = Screen::new(12, 4);
+
+        screen.frame(0, 0, 12, 4, "Regs");
+        screen.put(1, 1, 5, "s0 01 s1 02", Style::Normal);
+        screen.put(1, 2, 10, "a\tb", Style::Changed);
+        screen.paint(1, 1, 2, Style::Current);
+
+        assert_eq!(
+            screen.to_text(),
+            "┌─ Regs ───┐\n│s0 01     │\n│a.b       │\n└──────────┘"
+        );
+        assert_eq!(screen.get_style(1, 1), Some(Style::Current));
+        assert_eq!(screen.get_style(3, 1), Some(Style::Normal));
+        assert!(screen
+            .render()
+            .starts_with("\x1b[H\x1b[0;2m┌─\x1b[0;1;36m Regs "));
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// A key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    /// F1 to F12.
+    Function(u8),
+    /// Ctrl with a letter, such as Ctrl-C.
+    Control(char),
+}
+
+/// Splits what the terminal sent into keys. Sequences that aren't understood are dropped.
+pub fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut chars = text.chars().peekable();
+    let mut keys = Vec::new();
+
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' => match chars.peek() {
+                Some('[') | Some('O') => {
+                    chars.next();
+
+                    // Parameters, then a final letter or '~'.
+                    let mut parameters = String::new();
+
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == ';') {
+                        parameters.push(c);
+                    }
+
+                    let code = parameters.split(';').next().unwrap_or("");
+
+                    match chars.next() {
+                        Some('A') => Some(Key::Up),
+                        Some('B') => Some(Key::Down),
+                        Some('H') => Some(Key::Home),
+                        Some('F') => Some(Key::End),
+                        Some(c @ 'P'..='S') => Some(Key::Function(c as u8 - b'P' + 1)),
+                        Some('~') => match code {
+                            "1" | "7" => Some(Key::Home),
+                            "4" | "8" => Some(Key::End),
+                            "5" => Some(Key::PageUp),
+                            "6" => Some(Key::PageDown),
+                            "15" => Some(Key::Function(5)),
+                            "17" => Some(Key::Function(6)),
+                            "18" => Some(Key::Function(7)),
+                            "19" => Some(Key::Function(8)),
+                            "20" => Some(Key::Function(9)),
+                            "21" => Some(Key::Function(10)),
+                            "23" => Some(Key::Function(11)),
+                            "24" => Some(Key::Function(12)),
+                            _ => None,
+                        },
+                        _ => None,
+                    }
+                }
+                _ => Some(Key::Escape),
+            },
+            '\r' | '\n' => Some(Key::Enter),
+            '\x7f' | '\x08' => Some(Key::Backspace),
+            '\x01'..='\x1a' => Some(Key::Control((c as u8 - 1 + b'a') as char)),
+            c if c.is_control() => None,
+            c => Some(Key::Char(c)),
+        };
+
+        keys.extend(key);
+    }
+
+    keys
+}
+
+/// The settings of a terminal, as `tcgetattr` fills them on Linux: four flag words, the line
+/// discipline, 32 control characters and the two speeds.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Termios([u32; 15]);
+
+#[cfg(target_os = "linux")]
+mod ffi {
+    use std::os::raw::{c_int, c_ulong};
+
+    use super::Termios;
+
+    pub const TCSANOW: c_int = 0;
+    pub const TIOCGWINSZ: c_ulong = 0x5413;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct WinSize {
+        pub rows: u16,
+        pub columns: u16,
+        pub width: u16,
+        pub height: u16,
+    }
+
+    extern "C" {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, action: c_int, termios: *const Termios) -> c_int;
+        pub fn cfmakeraw(termios: *mut Termios);
+        pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+}
+
+/// Puts a terminal in raw mode, where characters go through as they're typed without being
+/// echoed, and returns the settings it had.
+#[cfg(target_os = "linux")]
+pub fn set_raw(fd: RawFd) -> io::Result<Termios> {
+    let mut saved = Termios([0; 15]);
+
+    // SAFETY: `Termios` has the size and alignment of the C library's `struct termios`, which
+    // these calls only read and write.
+    unsafe {
+        if ffi::tcgetattr(fd, &mut saved) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = saved;
+
+        ffi::cfmakeraw(&mut raw);
+        set_attributes(fd, &raw)?;
+    }
+
+    Ok(saved)
+}
+
+/// Puts back the settings [`set_raw`] returned.
+#[cfg(target_os = "linux")]
+pub fn set_attributes(fd: RawFd, termios: &Termios) -> io::Result<()> {
+    // SAFETY: as in `set_raw`.
+    match unsafe { ffi::tcsetattr(fd, ffi::TCSANOW, termios) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Columns and rows of the terminal.
+#[cfg(target_os = "linux")]
+pub fn get_size() -> io::Result<(usize, usize)> {
+    let tty = File::open("/dev/tty")?;
+    let mut size = ffi::WinSize::default();
+
+    // SAFETY: TIOCGWINSZ writes a `struct winsize`, which `WinSize` has the layout of.
+    if unsafe {
+        ffi::ioctl(
+            tty.as_raw_fd(),
+            ffi::TIOCGWINSZ,
+            &mut size as *mut ffi::WinSize,
+        )
+    } != 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((size.columns as usize, size.rows as usize))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_raw(_: RawFd) -> io::Result<Termios> {
+    Err(io::Error::other("terminals are only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_attributes(_: RawFd, _: &Termios) -> io::Result<()> {
+    Err(io::Error::other("terminals are only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_size() -> io::Result<(usize, usize)> {
+    Err(io::Error::other("terminals are only supported on Linux"))
+}
+
+/// Puts the terminal in raw mode, where keys are sent as they're typed without being echoed, and
+/// restores it when dropped.
+pub struct RawMode {
+    tty: File,
+    saved: Termios,
+}
+
+impl RawMode {
+    pub fn new() -> io::Result<RawMode> {
+        let tty = File::open("/dev/tty")?;
+        let saved = set_raw(tty.as_raw_fd())?;
+
+        Ok(RawMode { tty, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = set_attributes(self.tty.as_raw_fd(), &self.saved);
+    }
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and restores it when dropped.
+pub struct RawTerminal {
+    // Dropped after the screen is restored.
+    _mode: RawMode,
+}
+
+impl RawTerminal {
+    pub fn new() -> io::Result<RawTerminal> {
+        let mode = RawMode::new()?;
+
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+        io::stdout().flush()?;
+
+        Ok(RawTerminal { _mode: mode })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Reads keys on a thread of their own, so the program can keep running between key presses.
+pub fn read_keys() -> Receiver<Key> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut input = io::stdin();
+        let mut buffer = [0; 64];
+
+        // A key sends its whole sequence at once, so each read holds whole keys.
+        while let Ok(count @ 1..) = input.read(&mut buffer) {
+            for key in parse_keys(&buffer[..count]) {
+                if sender.send(key).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            parse_keys(b"s\x1b[A\x1b[6~\x1bOP\x1b[21~\r\x7f\x03\x1b"),
+            vec![
+                Key::Char('s'),
+                Key::Up,
+                Key::PageDown,
+                Key::Function(1),
+                Key::Function(10),
+                Key::Enter,
+                Key::Backspace,
+                Key::Control('c'),
+                Key::Escape,
+            ]
+        );
+        assert_eq!(
+            parse_keys("é\x1b[1;5A".as_bytes()),
+            vec![Key::Char('é'), Key::Up]
+        );
+    }
+}
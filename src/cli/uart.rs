@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{self, IsTerminal, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use super::tui::terminal::RawMode;
+use super::{parse_endpoint, Endpoint};
+
+/// Ctrl-], which ends the run when typed on a terminal in raw mode, as in telnet.
+const ESCAPE: u8 = 0x1D;
+
+/// Where the host end of the simulated UART is connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bridge {
+    /// Standard input and output.
+    Stdio,
+    /// A new pseudo-terminal, for serial terminal programs to open.
+    Pty,
+    /// The first client to connect to a TCP address.
+    Tcp(String),
+}
+
+/// Reads `stdio`, `pty` or `tcp:[host:]port`. A bare port listens on the loopback interface.
+pub fn parse_bridge(text: &str) -> Result<Bridge, String> {
+    match (text, parse_endpoint(text)) {
+        ("stdio", _) => Ok(Bridge::Stdio),
+        ("pty", _) => Ok(Bridge::Pty),
+        (_, Some(Endpoint::Tcp(address))) => Ok(Bridge::Tcp(address)),
+        _ => Err(format!(
+            "expected stdio, pty or tcp:[host:]port, got '{}'",
+            text
+        )),
+    }
+}
+
+/// A bridge once connected. What comes from the other side is read on a thread of its own, so the
+/// simulation never waits for it.
+pub struct Connection {
+    incoming: Receiver<Vec<u8>>,
+    output: Box<dyn Write>,
+    // Whether the other side is gone: the end of standard input or the client disconnecting.
+    closed: bool,
+    // Whether Ctrl-] ends the run, when standard input is a terminal.
+    escape: bool,
+    raw: Option<RawMode>,
+}
+
+impl Connection {
+    /// Connects a bridge, waiting for the client of a TCP bridge. Where to find a pseudo-terminal
+    /// or TCP port is printed on standard error.
+    pub fn open(bridge: &Bridge) -> io::Result<Connection> {
+        match bridge {
+            Bridge::Stdio => {
+                let stdin = io::stdin();
+                let raw = match stdin.is_terminal() {
+                    true => {
+                        eprintln!("Connected to the UART, type Ctrl-] to end the run.");
+                        Some(RawMode::new()?)
+                    }
+                    false => None,
+                };
+
+                Ok(Connection {
+                    incoming: read_on_thread(stdin, false),
+                    output: Box::new(io::stdout()),
+                    closed: false,
+                    escape: raw.is_some(),
+                    raw,
+                })
+            }
+            Bridge::Pty => {
+                let (master, path) = open_pty()?;
+
+                eprintln!("UART on {}", path);
+
+                Ok(Connection {
+                    // Reads fail while no terminal program has the other end open.
+                    incoming: read_on_thread(master.try_clone()?, true),
+                    output: Box::new(master),
+                    closed: false,
+                    escape: false,
+                    raw: None,
+                })
+            }
+            Bridge::Tcp(address) => {
+                let listener = TcpListener::bind(address)?;
+
+                eprintln!(
+                    "UART waiting for a connection on {}",
+                    listener.local_addr()?
+                );
+
+                let (stream, _) = listener.accept()?;
+
+                stream.set_nodelay(true)?;
+
+                Ok(Connection {
+                    incoming: read_on_thread(stream.try_clone()?, false),
+                    output: Box::new(stream),
+                    closed: false,
+                    escape: false,
+                    raw: None,
+                })
+            }
+        }
+    }
+
+    /// Sends what came from the other side to the host end of the UART, and what was read from it
+    /// to the other side. Returns false once Ctrl-] was typed.
+    pub fn exchange(&mut self, host: &mut (impl Read + Write)) -> io::Result<bool> {
+        loop {
+            match self.incoming.try_recv() {
+                Ok(bytes) if self.escape && bytes.contains(&ESCAPE) => return Ok(false),
+                Ok(bytes) => host.write_all(&bytes)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        let mut transmitted = Vec::new();
+
+        // Reading the host end only fails when there's nothing more to read.
+        let _ = host.read_to_end(&mut transmitted);
+
+        if !transmitted.is_empty() {
+            // Writes to a pseudo-terminal fail while nothing has it open, so what the program
+            // sends then is lost like on a disconnected serial line.
+            if self.output.write_all(&transmitted).is_ok() {
+                self.output.flush()?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Whether the terminal on standard input is in raw mode, so lines need a carriage return.
+    pub fn is_raw(&self) -> bool {
+        self.raw.is_some()
+    }
+
+    /// Whether the other side is gone. Nothing more will come from it.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+/// Reads on a thread, until the end of the input. With `retry`, errors are retried after a moment
+/// instead of ending it.
+fn read_on_thread(mut input: impl Read + Send + 'static, retry: bool) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut buffer = [0; 256];
+
+        loop {
+            match input.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(count) => {
+                    if sender.send(buffer[..count].to_vec()).is_err() {
+                        return;
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) if retry => thread::sleep(Duration::from_millis(50)),
+                Err(_) => return,
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Opens a new pseudo-terminal: its master side, and the path of the side for terminal programs.
+#[cfg(target_os = "linux")]
+fn open_pty() -> io::Result<(File, String)> {
+    use std::ffi::CStr;
+    use std::fs::OpenOptions;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::raw::{c_char, c_int};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    use super::tui::terminal::set_raw;
+
+    const O_RDWR: c_int = 0o2;
+    const O_NOCTTY: c_int = 0o400;
+
+    extern "C" {
+        fn posix_openpt(flags: c_int) -> c_int;
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname_r(fd: c_int, buffer: *mut c_char, length: usize) -> c_int;
+    }
+
+    let mut name = [0 as c_char; 128];
+
+    // SAFETY: these are the C library calls documented for opening a pseudo-terminal. The file
+    // descriptor is owned by the returned file from then on (closing it on failure), and
+    // `ptsname_r` writes a null-terminated path of at most `name.len()` bytes.
+    let (master, path) = unsafe {
+        let fd = posix_openpt(O_RDWR | O_NOCTTY);
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let master = File::from_raw_fd(fd);
+
+        if grantpt(fd) != 0
+            || unlockpt(fd) != 0
+            || ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        let path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+        (master, path)
+    };
+
+    // Characters go through as they are, like on a serial line, until a terminal program sets
+    // the modes it wants.
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(O_NOCTTY)
+        .open(&path)?;
+
+    set_raw(slave.as_raw_fd())?;
+
+    Ok((master, path))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_pty() -> io::Result<(File, String)> {
+    Err(io::Error::other(
+        "pseudo-terminals are only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bridges() {
+        assert_eq!(parse_bridge("stdio"), Ok(Bridge::Stdio));
+        assert_eq!(parse_bridge("pty"), Ok(Bridge::Pty));
+        assert_eq!(
+            parse_bridge("tcp:5000"),
+            Ok(Bridge::Tcp("127.0.0.1:5000".to_string()))
+        );
+        assert_eq!(
+            parse_bridge("tcp:0.0.0.0:5000"),
+            Ok(Bridge::Tcp("0.0.0.0:5000".to_string()))
+        );
+        assert!(parse_bridge("tcp:").is_err());
+        assert!(parse_bridge("serial").is_err());
+    }
+}
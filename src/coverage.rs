@@ -0,0 +1,577 @@
+use std::collections::BTreeMap;
+
+use crate::analysis::calls::CallGraph;
+use crate::analysis::flow::FlowGraph;
+use crate::analysis::stack::describe_routine;
+use crate::{Instruction, Program, SimulationContext, StepEvent};
+
+/// How often a conditional JUMP, CALL or RETURN went each way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCount {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Lines and branches of part of a program, and how many of them ran.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub lines: usize,
+    pub lines_hit: usize,
+    /// Each conditional instruction counts as two branches, taken and not taken.
+    pub branches: usize,
+    pub branches_hit: usize,
+}
+
+impl Counts {
+    fn add_line(&mut self, hits: u64) {
+        self.lines += 1;
+        self.lines_hit += (hits > 0) as usize;
+    }
+
+    fn add_branch(&mut self, branch: BranchCount) {
+        self.branches += 2;
+        self.branches_hit += (branch.taken > 0) as usize + (branch.not_taken > 0) as usize;
+    }
+}
+
+/// Coverage per source file and per routine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageSummary {
+    pub files: Vec<(String, Counts)>,
+    /// Routines by entry address, with their names.
+    pub routines: Vec<(usize, String, Counts)>,
+}
+
+/// Records which instructions a simulation executed and which way its conditional JUMPs, CALLs
+/// and RETURNs went, for line and branch coverage of the source.
+///
+/// ```
+/// use kcpsm6sim::{assemble_str, coverage::Coverage};
+///
+/// let program = assemble_str("load s0, 01\nsub s0, 01\njump nz, 000\nload s1, 02\n");
+/// let mut sim = program.create_simulation();
+/// let mut coverage = Coverage::new(&sim);
+///
+/// for _ in 0..4 {
+///     let event = sim.step().unwrap();
+///
+///     coverage.record(&sim, &event);
+/// }
+///
+/// let summary = coverage.summarize(&program);
+///
+/// assert_eq!(summary.files[0].1.lines_hit, 4);
+/// assert_eq!(summary.files[0].1.branches_hit, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// Times each instruction ran, by address. Every instruction of the program is there.
+    hits: BTreeMap<usize, u64>,
+    branches: BTreeMap<usize, BranchCount>,
+    interrupt_vector: usize,
+    /// Call stack depth before the next step, to tell whether a conditional CALL or RETURN
+    /// was taken.
+    depth: usize,
+}
+
+impl Coverage {
+    pub fn new(sim: &SimulationContext) -> Coverage {
+        let mut hits = BTreeMap::new();
+        let mut branches = BTreeMap::new();
+
+        for address in 0..sim.get_program_memory_size() {
+            if let Some(instruction) = sim.get_instruction(address) {
+                hits.insert(address, 0);
+
+                if is_conditional(instruction) {
+                    branches.insert(address, BranchCount::default());
+                }
+            }
+        }
+
+        Coverage {
+            hits,
+            branches,
+            interrupt_vector: sim.get_interrupt_vector(),
+            depth: sim.get_call_stack().len(),
+        }
+    }
+
+    /// Records a step of the simulation, right after it happened.
+    pub fn record(&mut self, sim: &SimulationContext, event: &StepEvent) {
+        let depth = sim.get_call_stack().len();
+
+        if let StepEvent::Executed(executed) = event {
+            *self.hits.entry(executed.address).or_default() += 1;
+
+            let taken = match executed.instruction {
+                Instruction::JumpConditional { address, .. } => {
+                    Some(sim.get_program_counter() == address as usize)
+                }
+                Instruction::CallConditional { .. } => Some(depth > self.depth),
+                Instruction::ReturnCondition { .. } => Some(depth < self.depth),
+                _ => None,
+            };
+
+            if let Some(taken) = taken {
+                let branch = self.branches.entry(executed.address).or_default();
+
+                match taken {
+                    true => branch.taken += 1,
+                    false => branch.not_taken += 1,
+                }
+            }
+        }
+
+        self.depth = depth;
+    }
+
+    /// Times the instruction at an address ran, `None` if there's no instruction there.
+    pub fn get_hits(&self, address: usize) -> Option<u64> {
+        self.hits.get(&address).copied()
+    }
+
+    /// Which way the conditional instruction at an address went.
+    pub fn get_branch(&self, address: usize) -> Option<BranchCount> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Hits of each source line with instructions, by file and line. A line with several
+    /// instructions counts the most any of them ran.
+    fn lines(
+        &self,
+        program: &Program,
+        addresses: impl Iterator<Item = usize>,
+    ) -> BTreeMap<(String, usize), u64> {
+        let mut lines = BTreeMap::new();
+
+        for address in addresses {
+            if let (Some(location), Some(hits)) =
+                (location(program, address), self.get_hits(address))
+            {
+                let line = lines.entry(location).or_insert(0);
+
+                *line = hits.max(*line);
+            }
+        }
+
+        lines
+    }
+
+    fn count(&self, program: &Program, addresses: &[usize]) -> Counts {
+        let mut counts = Counts::default();
+
+        for hits in self.lines(program, addresses.iter().copied()).values() {
+            counts.add_line(*hits);
+        }
+
+        for branch in addresses
+            .iter()
+            .filter_map(|address| self.get_branch(*address))
+        {
+            counts.add_branch(branch);
+        }
+
+        counts
+    }
+
+    /// Routines of the program, found from the reset address, the interrupt vector and the calls.
+    fn routines(&self, program: &Program) -> Vec<(usize, String, Vec<usize>)> {
+        let calls = CallGraph::new(&FlowGraph::new(program, self.interrupt_vector));
+
+        calls
+            .get_routines()
+            .values()
+            .map(|routine| {
+                (
+                    routine.entry,
+                    describe_routine(program, routine.entry),
+                    routine.body.iter().copied().collect(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn summarize(&self, program: &Program) -> CoverageSummary {
+        let mut files: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+        for address in self.hits.keys() {
+            if let Some((file, _)) = location(program, *address) {
+                files.entry(file).or_default().push(*address);
+            }
+        }
+
+        CoverageSummary {
+            files: files
+                .into_iter()
+                .map(|(file, addresses)| {
+                    let counts = self.count(program, &addresses);
+
+                    (file, counts)
+                })
+                .collect(),
+            routines: self
+                .routines(program)
+                .into_iter()
+                .map(|(entry, name, body)| (entry, name, self.count(program, &body)))
+                .collect(),
+        }
+    }
+
+    /// The coverage as an lcov tracefile, which coverage viewers such as genhtml read.
+    pub fn to_lcov(&self, program: &Program, test_name: &str) -> String {
+        let mut lcov = String::new();
+        let routines = self.routines(program);
+        let mut files: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+        for address in self.hits.keys() {
+            if let Some((file, _)) = location(program, *address) {
+                files.entry(file).or_default().push(*address);
+            }
+        }
+
+        for (file, addresses) in files {
+            lcov.push_str(&format!("TN:{}\nSF:{}\n", test_name, file));
+
+            let functions: Vec<(usize, &str, u64)> = routines
+                .iter()
+                .filter_map(|(entry, name, _)| match location(program, *entry) {
+                    Some((routine_file, line)) if routine_file == file => {
+                        Some((line, name.as_str(), self.get_hits(*entry).unwrap_or(0)))
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            for (line, name, _) in &functions {
+                lcov.push_str(&format!("FN:{},{}\n", line, name));
+            }
+
+            for (_, name, hits) in &functions {
+                lcov.push_str(&format!("FNDA:{},{}\n", hits, name));
+            }
+
+            lcov.push_str(&format!(
+                "FNF:{}\nFNH:{}\n",
+                functions.len(),
+                functions.iter().filter(|(_, _, hits)| *hits > 0).count()
+            ));
+
+            let mut counts = Counts::default();
+
+            for address in &addresses {
+                let (Some(branch), Some((_, line))) =
+                    (self.get_branch(*address), location(program, *address))
+                else {
+                    continue;
+                };
+                // Branches of instructions that never ran are written as '-'.
+                let count = |count: u64| match self.get_hits(*address) {
+                    Some(0) | None => "-".to_string(),
+                    _ => count.to_string(),
+                };
+
+                lcov.push_str(&format!(
+                    "BRDA:{},{},0,{}\nBRDA:{},{},1,{}\n",
+                    line,
+                    address,
+                    count(branch.taken),
+                    line,
+                    address,
+                    count(branch.not_taken)
+                ));
+                counts.add_branch(branch);
+            }
+
+            lcov.push_str(&format!(
+                "BRF:{}\nBRH:{}\n",
+                counts.branches, counts.branches_hit
+            ));
+
+            for ((_, line), hits) in self.lines(program, addresses.into_iter()) {
+                lcov.push_str(&format!("DA:{},{}\n", line, hits));
+                counts.add_line(hits);
+            }
+
+            lcov.push_str(&format!(
+                "LF:{}\nLH:{}\nend_of_record\n",
+                counts.lines, counts.lines_hit
+            ));
+        }
+
+        lcov
+    }
+
+    /// Coverage per file and per routine, then the lines that never ran and the branches that
+    /// only went one way.
+    pub fn format_report(&self, program: &Program) -> String {
+        let summary = self.summarize(program);
+        let width = summary
+            .files
+            .iter()
+            .map(|(file, _)| file.len())
+            .chain(summary.routines.iter().map(|(_, name, _)| name.len()))
+            .max()
+            .unwrap_or(0)
+            .max(8);
+        let mut report = format!(
+            "{:width$}  {:<18}  {}\n",
+            "File",
+            "Lines",
+            "Branches",
+            width = width
+        );
+
+        for (file, counts) in &summary.files {
+            report.push_str(&format_counts(file, counts, width));
+        }
+
+        report.push_str(&format!(
+            "\n{:width$}  {:<18}  {}\n",
+            "Routine",
+            "Lines",
+            "Branches",
+            width = width
+        ));
+
+        for (_, name, counts) in &summary.routines {
+            report.push_str(&format_counts(name, counts, width));
+        }
+
+        // Runs of instructions that never ran, by address.
+        let mut missed: Vec<Vec<usize>> = Vec::new();
+
+        for (address, hits) in &self.hits {
+            match missed.last_mut() {
+                _ if *hits > 0 => continue,
+                Some(run) if run.last() == Some(&(address - 1)) => run.push(*address),
+                _ => missed.push(vec![*address]),
+            }
+        }
+
+        if !missed.is_empty() {
+            report.push_str("\nNever executed:\n");
+        }
+
+        for run in &missed {
+            let (first, last) = (run[0], run[run.len() - 1]);
+            let lines = match (location(program, first), location(program, last)) {
+                (Some((file, first)), Some((other, last))) if file == other && first != last => {
+                    format!("{}:{}-{}", file, first, last)
+                }
+                (Some((file, line)), _) => format!("{}:{}", file, line),
+                _ => format!("0x{:03X}", first),
+            };
+
+            report.push_str(&format!("  {:24}  {}\n", lines, describe(program, first)));
+        }
+
+        let one_way: Vec<(usize, &str)> = self
+            .branches
+            .iter()
+            .filter_map(|(address, branch)| match (branch.taken, branch.not_taken) {
+                (0, 0) => None,
+                (0, _) => Some((*address, "never taken")),
+                (_, 0) => Some((*address, "always taken")),
+                _ => None,
+            })
+            .collect();
+
+        if !one_way.is_empty() {
+            report.push_str("\nBranches that only went one way:\n");
+        }
+
+        for (address, direction) in one_way {
+            let line = match location(program, address) {
+                Some((file, line)) => format!("{}:{}", file, line),
+                None => format!("0x{:03X}", address),
+            };
+            let instruction = program
+                .get_instruction(address)
+                .map(|instruction| instruction.to_string())
+                .unwrap_or_default();
+
+            report.push_str(&format!(
+                "  {:24}  {:20}  {}, {}\n",
+                line,
+                describe(program, address),
+                instruction,
+                direction
+            ));
+        }
+
+        report
+    }
+}
+
+fn is_conditional(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::JumpConditional { .. }
+            | Instruction::CallConditional { .. }
+            | Instruction::ReturnCondition { .. }
+    )
+}
+
+/// Source file and line of the instruction at an address.
+fn location(program: &Program, address: usize) -> Option<(String, usize)> {
+    let line = program.get_source_map().get_line(address)?;
+    let (file, line) = match program.get_location(line)? {
+        (Some(path), line) => (path.display().to_string(), line),
+        (None, line) => ("<source>".to_string(), line),
+    };
+
+    Some((file, line))
+}
+
+/// An address relative to the label before it, e.g. `loop+2`.
+fn describe(program: &Program, address: usize) -> String {
+    match program.find_label_before(address) {
+        Some((label, 0)) => label.to_string(),
+        Some((label, offset)) => format!("{}+{}", label, offset),
+        None => format!("0x{:03X}", address),
+    }
+}
+
+fn format_counts(name: &str, counts: &Counts, width: usize) -> String {
+    let ratio = |hit: usize, total: usize| match total {
+        0 => format!("{:>5}/{:<5} {:>6}", hit, total, "-"),
+        _ => format!(
+            "{:>5}/{:<5} {:5.1}%",
+            hit,
+            total,
+            hit as f64 * 100.0 / total as f64
+        ),
+    };
+
+    format!(
+        "{:width$}  {}  {}\n",
+        name,
+        ratio(counts.lines_hit, counts.lines),
+        ratio(counts.branches_hit, counts.branches),
+        width = width
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+    const SOURCE: &str = "\
+start: load s0, 03
+loop: call z, never
+      sub s0, 01
+      jump nz, loop
+      call check
+done: jump done
+never: load s1, 01
+       return
+check: compare s0, 00
+       return z
+       load s2, 02
+       return
+";
+
+    fn run(cycles: u64) -> (Program, Coverage) {
+        let program = assemble_str(SOURCE);
+        let mut sim = program.create_simulation();
+        let mut coverage = Coverage::new(&sim);
+
+        while sim.get_cycles() < cycles {
+            let event = sim.step().unwrap();
+
+            coverage.record(&sim, &event);
+        }
+
+        (program, coverage)
+    }
+
+    #[test]
+    fn hits_and_branches() {
+        let (_, coverage) = run(40);
+
+        assert_eq!(coverage.get_hits(1), Some(3));
+        assert_eq!(coverage.get_hits(6), Some(0));
+        assert_eq!(coverage.get_hits(0x10), None);
+        assert_eq!(
+            coverage.get_branch(1),
+            Some(BranchCount {
+                taken: 0,
+                not_taken: 3
+            })
+        );
+        assert_eq!(
+            coverage.get_branch(3),
+            Some(BranchCount {
+                taken: 2,
+                not_taken: 1
+            })
+        );
+        assert_eq!(
+            coverage.get_branch(9),
+            Some(BranchCount {
+                taken: 1,
+                not_taken: 0
+            })
+        );
+        assert_eq!(coverage.get_branch(5), None);
+    }
+
+    #[test]
+    fn summary() {
+        let (program, coverage) = run(40);
+        let summary = coverage.summarize(&program);
+
+        assert_eq!(
+            summary.files,
+            vec![(
+                "<source>".to_string(),
+                Counts {
+                    lines: 12,
+                    lines_hit: 8,
+                    branches: 6,
+                    branches_hit: 4
+                }
+            )]
+        );
+
+        let routines: Vec<(&str, usize, usize)> = summary
+            .routines
+            .iter()
+            .map(|(_, name, counts)| (name.as_str(), counts.lines_hit, counts.lines))
+            .collect();
+
+        assert_eq!(
+            routines,
+            vec![("start", 6, 6), ("never", 0, 2), ("check", 2, 4)]
+        );
+    }
+
+    #[test]
+    fn lcov() {
+        let (program, coverage) = run(40);
+        let lcov = coverage.to_lcov(&program, "test");
+
+        assert!(lcov.starts_with("TN:test\nSF:<source>\nFN:1,start\nFN:7,never\nFN:9,check\n"));
+        assert!(lcov.contains("FNDA:1,start\nFNDA:0,never\nFNDA:1,check\nFNF:3\nFNH:2\n"));
+        assert!(lcov.contains("BRDA:2,1,0,0\nBRDA:2,1,1,3\nBRDA:4,3,0,2\nBRDA:4,3,1,1\n"));
+        assert!(lcov.contains("BRF:6\nBRH:4\nDA:1,1\nDA:2,3\n"));
+        assert!(lcov.ends_with("DA:12,0\nLF:12\nLH:8\nend_of_record\n"));
+    }
+
+    #[test]
+    fn report() {
+        let (program, coverage) = run(40);
+        let report = coverage.format_report(&program);
+
+        assert!(report.contains("<source>      8/12     66.7%      4/6      66.7%\n"));
+        assert!(report.contains("never         0/2       0.0%      0/0          -\n"));
+        assert!(report.contains("Never executed:\n  <source>:7-8"));
+        assert!(report.contains("  <source>:11-12            check+2\n"));
+        assert!(report.contains(
+            "  <source>:2                loop                  CALL Z, 006, never taken\n"
+        ));
+        assert!(report.contains("RETURN Z, always taken\n"));
+    }
+}
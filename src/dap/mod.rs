@@ -0,0 +1,75 @@
+mod session;
+
+use std::io::{self, BufRead, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use crate::protocol::read_message;
+use session::Session;
+
+/// Runs a Debug Adapter Protocol server on the given streams (standard input and output in
+/// practice) until the client disconnects or closes the input.
+///
+/// Requests are read on a thread of their own, so a running program can be paused.
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut input = input;
+
+        loop {
+            let message = read_message(&mut input).transpose();
+            let end = !matches!(message, Some(Ok(_)));
+
+            if let Some(message) = message {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+
+            if end {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(output);
+
+    loop {
+        let message = if session.is_running() {
+            match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            }
+        };
+
+        if let Some(message) = message {
+            if !session.handle(&message?)? {
+                break;
+            }
+        }
+
+        session.run_slice()?;
+    }
+
+    Ok(())
+}
+
+/// Waits for a single client on a TCP address, such as `127.0.0.1:4711`, and serves it.
+pub fn serve_tcp(address: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+
+    serve(io::BufReader::new(stream.try_clone()?), stream)
+}
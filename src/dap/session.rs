@@ -0,0 +1,1034 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::interpreter::operands::parse_literal;
+use crate::json::Json;
+use crate::protocol::write_message;
+use crate::{
+    assemble_file, Alias, CallFrame, Instruction, PortAccess, PortState, Program, RunMode,
+    SimulationContext, StepEvent, StopReason,
+};
+
+/// Instructions executed between checks for new requests while the program runs.
+const SLICE: usize = 10_000;
+
+// The only thread: the processor.
+const THREAD_ID: u32 = 1;
+
+// Variable references of the scopes.
+const BANK_A: u32 = 1;
+const BANK_B: u32 = 2;
+const STATE: u32 = 3;
+const SCRATCH_PAD: u32 = 4;
+const PORTS: u32 = 5;
+
+/// The program being debugged.
+struct Debuggee {
+    program: Program,
+    path: String,
+    sim: SimulationContext,
+    stop_on_entry: bool,
+    max_cycles: Option<u64>,
+    /// Last value seen on each port, by access and port.
+    ports: BTreeMap<(u8, u8), u8>,
+    /// Addresses of the breakpoints set in each source file, by the path the client gave.
+    breakpoints: BTreeMap<String, Vec<usize>>,
+}
+
+pub struct Session<W: Write> {
+    output: W,
+    seq: u64,
+    debuggee: Option<Debuggee>,
+    running: Option<RunMode>,
+}
+
+fn format_byte(value: u8) -> String {
+    format!("0x{:02X} ({})", value, value)
+}
+
+impl Debuggee {
+    fn launch(arguments: &Json) -> Result<Debuggee, String> {
+        let path = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch needs a 'program' with the path of a PSM file")?;
+        let program = assemble_file(path).map_err(|error| format!("{}: {}", path, error))?;
+
+        if program.has_errors() {
+            let errors: Vec<String> = program
+                .get_errors()
+                .map(|error| match program.get_location(error.line) {
+                    Some((Some(file), line)) => {
+                        format!("{}:{}: {}", file.display(), line, error.message)
+                    }
+                    _ => format!("{}:{}: {}", path, error.line, error.message),
+                })
+                .collect();
+
+            return Err(errors.join("\n"));
+        }
+
+        let mut sim = program.create_simulation();
+
+        sim.set_port_handler(Box::new(PortState::new()));
+
+        if let Some(hwbuild) = arguments.get("hwbuild").and_then(Json::as_u64) {
+            sim.set_hwbuild(hwbuild as u8);
+        }
+
+        Ok(Debuggee {
+            path: path.to_string(),
+            sim,
+            stop_on_entry: arguments
+                .get("stopOnEntry")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            max_cycles: arguments.get("maxCycles").and_then(Json::as_u64),
+            ports: BTreeMap::new(),
+            breakpoints: BTreeMap::new(),
+            program,
+        })
+    }
+
+    /// The file and line of the instruction at an address, which may be in an included file.
+    fn location_of(&self, address: usize) -> (String, usize) {
+        let location = self
+            .program
+            .get_source_map()
+            .get_line(address)
+            .and_then(|line| self.program.get_location(line));
+
+        match location {
+            Some((Some(file), line)) => (file.display().to_string(), line),
+            Some((None, line)) => (self.path.clone(), line),
+            None => (self.path.clone(), 0),
+        }
+    }
+
+    fn describe_routine(&self, entry: usize) -> String {
+        match self.program.get_label_at(entry) {
+            Some(label) => label.to_string(),
+            None if entry == self.sim.get_interrupt_vector() => "interrupt".to_string(),
+            None => format!("0x{:03X}", entry),
+        }
+    }
+
+    /// Routine a call stack frame is in: the interrupt vector for an interrupt, or the target of
+    /// the CALL that entered it.
+    fn find_routine(&self, frame: &CallFrame) -> Option<usize> {
+        if frame.interrupt {
+            return Some(self.sim.get_interrupt_vector());
+        }
+
+        match self.sim.get_instruction(frame.address) {
+            Some(Instruction::Call { address })
+            | Some(Instruction::CallConditional { address, .. }) => Some(*address as usize),
+            _ => None,
+        }
+    }
+
+    /// Frames from the innermost, as (routine name, address) pairs.
+    fn get_frames(&self) -> Vec<(String, usize)> {
+        let mut frames = Vec::new();
+        let mut address = self.sim.get_program_counter();
+
+        for frame in self.sim.get_call_frames() {
+            let name = match self.find_routine(&frame) {
+                Some(entry) => self.describe_routine(entry),
+                None => self
+                    .program
+                    .find_label_before(address)
+                    .map_or("?".to_string(), |(label, _)| label.to_string()),
+            };
+
+            frames.push((name, address));
+            address = frame.address;
+        }
+
+        frames.push((self.describe_routine(0), address));
+        frames
+    }
+
+    fn get_variables(&self, reference: u32) -> Vec<(String, String)> {
+        let sim = &self.sim;
+
+        match reference {
+            BANK_A | BANK_B => {
+                let bank = if reference == BANK_A { 'a' } else { 'b' };
+                let registers = sim.get_bank_registers(bank);
+
+                (0..16)
+                    .map(|index| {
+                        let names: Vec<&str> = self
+                            .program
+                            .get_aliases()
+                            .iter()
+                            .filter(|Alias(_, register)| *register as usize == index)
+                            .map(|Alias(name, _)| name.as_str())
+                            .collect();
+                        let name = match names.is_empty() {
+                            true => format!("s{:X}", index),
+                            false => format!("s{:X} ({})", index, names.join(", ")),
+                        };
+
+                        (name, format_byte(registers[index]))
+                    })
+                    .collect()
+            }
+            STATE => vec![
+                ("Z".to_string(), (sim.get_zero_flag() as u8).to_string()),
+                ("C".to_string(), (sim.get_carry_flag() as u8).to_string()),
+                (
+                    "interrupts".to_string(),
+                    match sim.is_interrupt_enabled() {
+                        true => "enabled".to_string(),
+                        false => "disabled".to_string(),
+                    },
+                ),
+                (
+                    "bank".to_string(),
+                    sim.get_register_bank().to_ascii_uppercase().to_string(),
+                ),
+                (
+                    "PC".to_string(),
+                    format!("0x{:03X}", sim.get_program_counter()),
+                ),
+                ("cycles".to_string(), sim.get_cycles().to_string()),
+            ],
+            SCRATCH_PAD => (0..sim.get_scratch_pad_size())
+                .map(|address| {
+                    (
+                        format!("{:02X}", address),
+                        format_byte(sim.get_scratch_pad_memory(address).unwrap_or(0)),
+                    )
+                })
+                .collect(),
+            PORTS => self
+                .ports
+                .iter()
+                .map(|((access, port), value)| {
+                    let name = match *access {
+                        0 => format!("INPUT {:02X}", port),
+                        1 => format!("OUTPUT {:02X}", port),
+                        _ => format!("OUTPUTK {:X}", port),
+                    };
+
+                    (name, format_byte(*value))
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// A register, constant, label, flag or number.
+    fn evaluate_value(&self, text: &str) -> Option<u32> {
+        let program = &self.program;
+
+        match text {
+            "Z" | "z" => return Some(self.sim.get_zero_flag() as u32),
+            "C" | "c" => return Some(self.sim.get_carry_flag() as u32),
+            _ => {}
+        }
+
+        program
+            .find_register(text)
+            .and_then(|register| self.sim.get_register(register.into()))
+            .map(u32::from)
+            .or_else(|| program.find_constant(text))
+            .or_else(|| program.find_label(text))
+            .or_else(|| parse_literal(text))
+    }
+
+    /// Evaluates `name` or `name = value`, where the name is a register or a flag.
+    fn evaluate(&mut self, expression: &str) -> Result<String, String> {
+        let Some((target, value)) = expression.split_once('=') else {
+            let text = expression.trim();
+
+            return self
+                .evaluate_value(text)
+                .map(|value| match value {
+                    0..=0xFF => format_byte(value as u8),
+                    _ => format!("0x{:03X} ({})", value, value),
+                })
+                .ok_or_else(|| format!("'{}' isn't a register, symbol or number", text));
+        };
+
+        let (target, text) = (target.trim(), value.trim());
+        let value = self
+            .evaluate_value(text)
+            .ok_or_else(|| format!("'{}' isn't a register, symbol or number", text))?;
+
+        match target {
+            "Z" | "z" => self.sim.set_zero_flag(value != 0),
+            "C" | "c" => self.sim.set_carry_flag(value != 0),
+            _ => {
+                let register = self
+                    .program
+                    .find_register(target)
+                    .map(usize::from)
+                    .ok_or_else(|| format!("'{}' isn't a register or a flag", target))?;
+                let value =
+                    u8::try_from(value).map_err(|_| format!("'{}' doesn't fit in 8 bits", text))?;
+
+                self.sim.set_register(register, value);
+            }
+        }
+
+        Ok(format_byte(value as u8))
+    }
+
+    fn set_variable(&mut self, reference: u32, name: &str, value: &str) -> Result<String, String> {
+        let byte = self
+            .evaluate_value(value.trim())
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| format!("'{}' isn't an 8-bit value", value))?;
+        // Registers are shown as `s5 (counter)`.
+        let key = name.split_whitespace().next().unwrap_or(name);
+
+        match reference {
+            BANK_A | BANK_B => {
+                let bank = if reference == BANK_A { 'a' } else { 'b' };
+                let register = self
+                    .program
+                    .find_register(key)
+                    .map(usize::from)
+                    .ok_or_else(|| format!("'{}' isn't a register", name))?;
+
+                self.sim.set_bank_register(bank, register, byte);
+            }
+            STATE if key == "Z" => self.sim.set_zero_flag(byte != 0),
+            STATE if key == "C" => self.sim.set_carry_flag(byte != 0),
+            SCRATCH_PAD => {
+                let address = usize::from_str_radix(key, 16)
+                    .map_err(|_| format!("'{}' isn't a scratch pad address", name))?;
+
+                self.sim.set_scratch_pad_memory(address, byte);
+            }
+            _ => return Err(format!("'{}' can't be changed", name)),
+        }
+
+        Ok(match reference {
+            STATE => byte.min(1).to_string(),
+            _ => format_byte(byte),
+        })
+    }
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(output: W) -> Session<W> {
+        Session {
+            output,
+            seq: 0,
+            debuggee: None,
+            running: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    fn send(&mut self, mut members: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        members.insert(0, ("seq", Json::from(self.seq)));
+
+        write_message(&mut self.output, &Json::object(members))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(vec![
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ])
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        self.running = None;
+        self.event(
+            "stopped",
+            Json::object([
+                ("reason", Json::from(reason)),
+                ("threadId", Json::from(THREAD_ID)),
+                ("allThreadsStopped", Json::from(true)),
+                ("text", Json::from(text)),
+            ]),
+        )
+    }
+
+    /// Handles a request, `false` once the session is over.
+    pub fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        if message.get("type").and_then(Json::as_str) != Some("request") {
+            return Ok(true);
+        }
+
+        let command = message
+            .get("command")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        let arguments = message.get("arguments").cloned().unwrap_or(Json::Null);
+        let result = self.request(&command, &arguments);
+        let mut response = vec![
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                message.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("command", Json::from(command.as_str())),
+        ];
+
+        match result {
+            Ok(body) => {
+                response.push(("success", Json::from(true)));
+                response.push(("body", body));
+            }
+            Err(error) => {
+                response.push(("success", Json::from(false)));
+                response.push(("message", Json::from(error)));
+            }
+        }
+
+        self.send(response)?;
+
+        match command.as_str() {
+            "launch" if self.debuggee.is_some() => {
+                self.event("initialized", Json::object(Vec::<(String, Json)>::new()))?
+            }
+            "configurationDone" => match self.debuggee.as_ref().map(|d| d.stop_on_entry) {
+                Some(true) => self.stopped("entry", None)?,
+                Some(false) => self.running = Some(RunMode::Continue),
+                None => {}
+            },
+            "pause" if self.running.is_some() => self.stopped("pause", None)?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    fn debuggee(&mut self) -> Result<&mut Debuggee, String> {
+        self.debuggee
+            .as_mut()
+            .ok_or_else(|| "no program was launched".to_string())
+    }
+
+    fn request(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        let empty = || Json::object(Vec::<(String, Json)>::new());
+
+        match command {
+            "initialize" => Ok(Json::object([
+                ("supportsConfigurationDoneRequest", Json::from(true)),
+                ("supportsSetVariable", Json::from(true)),
+                ("supportsTerminateRequest", Json::from(true)),
+            ])),
+            "launch" => {
+                self.debuggee = Some(Debuggee::launch(arguments)?);
+                Ok(empty())
+            }
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setExceptionBreakpoints" => {
+                Ok(Json::object([("breakpoints", Json::Array(Vec::new()))]))
+            }
+            "configurationDone" | "pause" | "disconnect" | "terminate" => Ok(empty()),
+            "threads" => Ok(Json::object([(
+                "threads",
+                Json::array([Json::object([
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("KCPSM6")),
+                ])]),
+            )])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => {
+                let bank = self.debuggee()?.sim.get_register_bank();
+                let scope = |name: String, reference: u32, count: usize| {
+                    Json::object([
+                        ("name", Json::from(name)),
+                        ("variablesReference", Json::from(reference)),
+                        ("namedVariables", Json::from(count)),
+                        ("expensive", Json::from(false)),
+                    ])
+                };
+                let selected = |this: char| match this == bank {
+                    true => " (selected)",
+                    false => "",
+                };
+                let scratch_pad = self.debuggee()?.sim.get_scratch_pad_size();
+
+                Ok(Json::object([(
+                    "scopes",
+                    Json::array([
+                        scope(format!("Bank A{}", selected('a')), BANK_A, 16),
+                        scope(format!("Bank B{}", selected('b')), BANK_B, 16),
+                        scope("Flags".to_string(), STATE, 6),
+                        scope("Scratch pad".to_string(), SCRATCH_PAD, scratch_pad),
+                        scope("Ports".to_string(), PORTS, self.debuggee()?.ports.len()),
+                    ]),
+                )]))
+            }
+            "variables" => {
+                let reference = arguments
+                    .get("variablesReference")
+                    .and_then(Json::as_u64)
+                    .unwrap_or(0) as u32;
+                let variables = self.debuggee()?.get_variables(reference);
+
+                Ok(Json::object([(
+                    "variables",
+                    Json::Array(
+                        variables
+                            .into_iter()
+                            .map(|(name, value)| {
+                                Json::object([
+                                    ("name", Json::from(name)),
+                                    ("value", Json::from(value)),
+                                    ("variablesReference", Json::from(0u32)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                )]))
+            }
+            "setVariable" => {
+                let text = |name: &str| {
+                    arguments
+                        .get(name)
+                        .and_then(Json::as_str)
+                        .unwrap_or("")
+                        .to_string()
+                };
+                let reference = arguments
+                    .get("variablesReference")
+                    .and_then(Json::as_u64)
+                    .unwrap_or(0) as u32;
+                let value =
+                    self.debuggee()?
+                        .set_variable(reference, &text("name"), &text("value"))?;
+
+                Ok(Json::object([("value", Json::from(value))]))
+            }
+            "evaluate" => {
+                let expression = arguments
+                    .get("expression")
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                let result = self.debuggee()?.evaluate(expression)?;
+
+                Ok(Json::object([
+                    ("result", Json::from(result)),
+                    ("variablesReference", Json::from(0u32)),
+                ]))
+            }
+            "continue" => {
+                self.debuggee()?;
+                self.running = Some(RunMode::Continue);
+                Ok(Json::object([("allThreadsContinued", Json::from(true))]))
+            }
+            "stepIn" => {
+                self.debuggee()?;
+                self.running = Some(RunMode::Steps(1));
+                Ok(empty())
+            }
+            "next" => {
+                self.running = Some(self.debuggee()?.sim.step_over());
+                Ok(empty())
+            }
+            "stepOut" => {
+                let mode = self.debuggee()?.sim.step_out();
+
+                self.running = Some(mode.unwrap_or(RunMode::Continue));
+                Ok(empty())
+            }
+            _ => Err(format!("unsupported request '{}'", command)),
+        }
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let debuggee = self.debuggee()?;
+        let path = arguments
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let file = Path::new(path);
+        let lines: Vec<usize> = arguments
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_u64))
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Breakpoints are set a file at a time, so only this file's are replaced.
+        for address in debuggee.breakpoints.remove(path).unwrap_or_default() {
+            debuggee.sim.remove_breakpoint(address);
+        }
+
+        let mut addresses = Vec::new();
+        let breakpoints: Vec<Json> = lines
+            .iter()
+            .map(|line| {
+                let program = &debuggee.program;
+                // Breakpoints on comments or directives move to the next instruction of the file.
+                let found = program
+                    .find_line(Some(file), *line)
+                    .and_then(|line| program.get_source_map().find_address_from(line))
+                    .and_then(|(address, found)| {
+                        let (_, line) = program.get_location(found)?;
+
+                        // The next instruction may be past the end of the file, in another one.
+                        (program.find_line(Some(file), line) == Some(found))
+                            .then_some((address, line))
+                    });
+
+                match found {
+                    Some((address, line)) => {
+                        debuggee.sim.add_breakpoint(address);
+                        addresses.push(address);
+
+                        Json::object([("verified", Json::from(true)), ("line", Json::from(line))])
+                    }
+                    None => Json::object([
+                        ("verified", Json::from(false)),
+                        ("line", Json::from(*line)),
+                        (
+                            "message",
+                            Json::from("no instruction at or after this line"),
+                        ),
+                    ]),
+                }
+            })
+            .collect();
+
+        debuggee.breakpoints.insert(path.to_string(), addresses);
+
+        Ok(Json::object([("breakpoints", Json::Array(breakpoints))]))
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let debuggee = self.debuggee()?;
+        let source = |path: &str| {
+            Json::object([
+                (
+                    "name",
+                    Json::from(
+                        Path::new(path)
+                            .file_name()
+                            .map_or(path.to_string(), |name| name.to_string_lossy().into_owned()),
+                    ),
+                ),
+                ("path", Json::from(path)),
+            ])
+        };
+        let frames: Vec<Json> = debuggee
+            .get_frames()
+            .into_iter()
+            .enumerate()
+            .map(|(id, (name, address))| {
+                let (path, line) = debuggee.location_of(address);
+
+                Json::object([
+                    ("id", Json::from(id)),
+                    ("name", Json::from(name)),
+                    ("source", source(&path)),
+                    ("line", Json::from(line)),
+                    ("column", Json::from(1u32)),
+                    (
+                        "instructionPointerReference",
+                        Json::from(format!("0x{:03X}", address)),
+                    ),
+                ])
+            })
+            .collect();
+
+        Ok(Json::object([
+            ("totalFrames", Json::from(frames.len())),
+            ("stackFrames", Json::Array(frames)),
+        ]))
+    }
+
+    /// Runs the program for a while if it's running, and reports why it stopped if it did.
+    pub fn run_slice(&mut self) -> io::Result<()> {
+        let (Some(mode), Some(debuggee)) = (self.running, self.debuggee.as_mut()) else {
+            return Ok(());
+        };
+
+        let ports = &mut debuggee.ports;
+        let reason =
+            debuggee
+                .sim
+                .run_until(mode, debuggee.max_cycles, SLICE, |_, event| match event {
+                    StepEvent::Executed(executed) => {
+                        if let Some(port) = executed.port {
+                            let access = match port.access {
+                                PortAccess::Input => 0,
+                                PortAccess::Output => 1,
+                                PortAccess::OutputK => 2,
+                            };
+
+                            ports.insert((access, port.port), port.value);
+                        }
+                    }
+                    StepEvent::Interrupt { .. } | StepEvent::Halted => {}
+                });
+
+        let stop = match reason {
+            Ok(Some(StopReason::Halted)) => Some(("halted", None)),
+            Ok(Some(StopReason::Done)) => Some(("step", None)),
+            Ok(Some(StopReason::CycleLimit)) => {
+                Some(("pause", Some("Reached the cycle limit.".to_string())))
+            }
+            Ok(Some(StopReason::Breakpoint(_))) => Some(("breakpoint", None)),
+            Ok(None) => None,
+            Err(error) => Some(("exception", Some(error.to_string()))),
+        };
+
+        match stop {
+            Some(("halted", _)) => {
+                self.running = None;
+                self.event("terminated", Json::object(Vec::<(String, Json)>::new()))
+            }
+            Some((reason, text)) => self.stopped(reason, text),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(seq: u32, command: &str, arguments: Json) -> Json {
+        Json::object([
+            ("seq", Json::from(seq)),
+            ("type", Json::from("request")),
+            ("command", Json::from(command)),
+            ("arguments", arguments),
+        ])
+    }
+
+    fn write_program(name: &str, source: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("kcpsm6sim-dap-{}", std::process::id()));
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name);
+
+        std::fs::write(&path, source).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    struct Client {
+        session: Session<Vec<u8>>,
+        seq: u32,
+    }
+
+    impl Client {
+        fn launch(path: &str, stop_on_entry: bool) -> Client {
+            let mut client = Client {
+                session: Session::new(Vec::new()),
+                seq: 0,
+            };
+
+            client.send("initialize", Json::Null);
+            client.send(
+                "launch",
+                Json::object([
+                    ("program", Json::from(path)),
+                    ("stopOnEntry", Json::from(stop_on_entry)),
+                ]),
+            );
+            client
+        }
+
+        /// Sends a request, lets the program run until it stops, returns what the adapter sent.
+        fn send(&mut self, command: &str, arguments: Json) -> Vec<Json> {
+            self.seq += 1;
+            self.session
+                .handle(&request(self.seq, command, arguments))
+                .unwrap();
+
+            while self.session.is_running() {
+                self.session.run_slice().unwrap();
+            }
+
+            let output = std::mem::take(&mut self.session.output);
+            let mut input = &output[..];
+            let mut messages = Vec::new();
+
+            while let Some(message) = crate::protocol::read_message(&mut input).unwrap() {
+                messages.push(message);
+            }
+
+            messages
+        }
+
+        fn body(&mut self, command: &str, arguments: Json) -> Json {
+            let messages = self.send(command, arguments);
+            let response = &messages[0];
+
+            assert_eq!(
+                response.get("success"),
+                Some(&Json::Bool(true)),
+                "{}",
+                response
+            );
+            response.get("body").unwrap().clone()
+        }
+
+        fn variables(&mut self, reference: u32) -> Vec<(String, String)> {
+            let body = self.body(
+                "variables",
+                Json::object([("variablesReference", Json::from(reference))]),
+            );
+
+            body.get("variables")
+                .and_then(Json::as_array)
+                .unwrap()
+                .iter()
+                .map(|variable| {
+                    (
+                        variable
+                            .get("name")
+                            .and_then(Json::as_str)
+                            .unwrap()
+                            .to_string(),
+                        variable
+                            .get("value")
+                            .and_then(Json::as_str)
+                            .unwrap()
+                            .to_string(),
+                    )
+                })
+                .collect()
+        }
+    }
+
+    fn event_reason(messages: &[Json]) -> Option<&str> {
+        messages
+            .iter()
+            .find(|message| message.get("event").and_then(Json::as_str) == Some("stopped"))
+            .and_then(|message| message.get("body"))
+            .and_then(|body| body.get("reason"))
+            .and_then(Json::as_str)
+    }
+
+    const PROGRAM: &str = "\
+namereg s1, count
+start: load count, 03
+loop:  call send
+       sub count, 01
+       jump nz, loop
+       jump done
+send:  output count, 04
+       store count, 02
+       return
+done:  load s0, 01
+";
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let path = write_program("stepping.psm", PROGRAM);
+        let mut client = Client::launch(&path, false);
+        let breakpoints = client.body(
+            "setBreakpoints",
+            Json::object([
+                (
+                    "source",
+                    Json::object([("path", Json::from(path.as_str()))]),
+                ),
+                (
+                    "breakpoints",
+                    Json::array([Json::object([("line", Json::from(7u32))])]),
+                ),
+            ]),
+        );
+
+        assert_eq!(
+            breakpoints.get("breakpoints").unwrap().as_array().unwrap()[0].get("line"),
+            Some(&Json::from(7u32))
+        );
+
+        let messages = client.send("configurationDone", Json::Null);
+
+        assert_eq!(event_reason(&messages), Some("breakpoint"));
+
+        let trace = client.body("stackTrace", Json::object([("threadId", Json::from(1u32))]));
+        let frames = trace.get("stackFrames").and_then(Json::as_array).unwrap();
+        let frame = |index: usize, field: &str| frames[index].get(field).cloned().unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frame(0, "name"), Json::from("send"));
+        assert_eq!(frame(0, "line"), Json::from(7u32));
+        assert_eq!(frame(1, "name"), Json::from("start"));
+        assert_eq!(frame(1, "line"), Json::from(3u32));
+
+        // Step out of send, then over the SUB.
+        assert_eq!(
+            event_reason(&client.send("stepOut", Json::Null)),
+            Some("step")
+        );
+        assert_eq!(event_reason(&client.send("next", Json::Null)), Some("step"));
+
+        let trace = client.body("stackTrace", Json::Null);
+
+        assert_eq!(
+            trace.get("stackFrames").unwrap().as_array().unwrap()[0].get("line"),
+            Some(&Json::from(5u32))
+        );
+        assert_eq!(
+            client.variables(BANK_A)[1],
+            ("s1 (count)".to_string(), "0x02 (2)".to_string())
+        );
+        assert_eq!(client.variables(SCRATCH_PAD)[2].1, "0x03 (3)");
+        assert_eq!(
+            client.variables(PORTS),
+            vec![("OUTPUT 04".to_string(), "0x03 (3)".to_string())]
+        );
+
+        // Stepping over a call runs the whole routine.
+        client.send("next", Json::Null);
+        assert_eq!(
+            event_reason(&client.send("next", Json::Null)),
+            Some("breakpoint")
+        );
+
+        client.body(
+            "setBreakpoints",
+            Json::object([
+                (
+                    "source",
+                    Json::object([("path", Json::from(path.as_str()))]),
+                ),
+                ("breakpoints", Json::Array(Vec::new())),
+            ]),
+        );
+
+        let messages = client.send("continue", Json::Null);
+
+        assert!(messages
+            .iter()
+            .any(|message| message.get("event") == Some(&Json::from("terminated"))));
+    }
+
+    #[test]
+    fn evaluate_and_set_variables() {
+        let path = write_program("evaluate.psm", PROGRAM);
+        let mut client = Client::launch(&path, true);
+
+        assert_eq!(
+            event_reason(&client.send("configurationDone", Json::Null)),
+            Some("entry")
+        );
+
+        let evaluate = |client: &mut Client, expression: &str| {
+            client
+                .send(
+                    "evaluate",
+                    Json::object([("expression", Json::from(expression))]),
+                )
+                .remove(0)
+        };
+        let result = |response: Json| {
+            response
+                .get("body")
+                .and_then(|body| body.get("result"))
+                .and_then(Json::as_str)
+                .map(str::to_string)
+        };
+
+        assert_eq!(
+            result(evaluate(&mut client, "count = 10'd")).as_deref(),
+            Some("0x0A (10)")
+        );
+        assert_eq!(
+            result(evaluate(&mut client, "s1")).as_deref(),
+            Some("0x0A (10)")
+        );
+        assert_eq!(
+            result(evaluate(&mut client, "s2 = count")).as_deref(),
+            Some("0x0A (10)")
+        );
+        assert_eq!(
+            result(evaluate(&mut client, "C = 1")).as_deref(),
+            Some("0x01 (1)")
+        );
+        assert_eq!(
+            result(evaluate(&mut client, "done")).as_deref(),
+            Some("0x08 (8)")
+        );
+        assert_eq!(
+            evaluate(&mut client, "s3 = 100'd").get("success"),
+            Some(&Json::Bool(true))
+        );
+        assert_eq!(
+            evaluate(&mut client, "s3 = 300'd").get("success"),
+            Some(&Json::Bool(false))
+        );
+
+        let body = client.body(
+            "setVariable",
+            Json::object([
+                ("variablesReference", Json::from(BANK_B)),
+                ("name", Json::from("s4")),
+                ("value", Json::from("7F")),
+            ]),
+        );
+
+        assert_eq!(body.get("value"), Some(&Json::from("0x7F (127)")));
+        assert_eq!(client.variables(BANK_B)[4].1, "0x7F (127)");
+        assert_eq!(
+            client.variables(STATE)[1],
+            ("C".to_string(), "1".to_string())
+        );
+
+        let scopes = client.body("scopes", Json::object([("frameId", Json::from(0u32))]));
+
+        assert_eq!(
+            scopes.get("scopes").unwrap().as_array().unwrap()[0].get("name"),
+            Some(&Json::from("Bank A (selected)"))
+        );
+    }
+
+    #[test]
+    fn pause_and_errors() {
+        let path = write_program("forever.psm", "start: add s0, 01\njump start\n");
+        let mut client = Client::launch(&path, false);
+
+        client
+            .session
+            .handle(&request(10, "configurationDone", Json::Null))
+            .unwrap();
+        client.session.run_slice().unwrap();
+        assert!(client.session.is_running());
+
+        let messages = client.send("pause", Json::Null);
+
+        assert_eq!(event_reason(&messages), Some("pause"));
+        assert!(!client.session.is_running());
+
+        let bad = write_program("bad.psm", "jump nowhere\n");
+        let mut client = Client {
+            session: Session::new(Vec::new()),
+            seq: 0,
+        };
+        let messages = client.send(
+            "launch",
+            Json::object([("program", Json::from(bad.as_str()))]),
+        );
+
+        assert_eq!(messages[0].get("success"), Some(&Json::Bool(false)));
+        assert!(messages[0]
+            .get("message")
+            .and_then(Json::as_str)
+            .unwrap()
+            .contains("bad.psm:1: Unable to find"));
+        assert_eq!(messages.len(), 1);
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found while assembling a program, pointing at the source line it was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Source line, starting from 1.
+    pub line: usize,
+    pub message: String,
+    /// Name of the lint that found the problem, if it was found by one.
+    pub lint: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn error(line: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            line,
+            message,
+            lint: None,
+        }
+    }
+
+    pub fn warning(line: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            line,
+            message,
+            lint: None,
+        }
+    }
+
+    pub fn with_lint(mut self, lint: &'static str) -> Diagnostic {
+        self.lint = Some(lint);
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (line {}): {}",
+            self.severity, self.line, self.message
+        )?;
+
+        match self.lint {
+            Some(lint) => write!(f, " [{}]", lint),
+            None => Ok(()),
+        }
+    }
+}
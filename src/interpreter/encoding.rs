@@ -0,0 +1,431 @@
+use std::fmt;
+
+use crate::{ConditionType, Instruction};
+
+/// Opcodes of the instructions that take either two registers or a register and a constant. The
+/// second one is always the first plus one.
+const ALU_OPCODES: [(&str, u32); 16] = [
+    ("LOAD", 0x00),
+    ("AND", 0x02),
+    ("OR", 0x04),
+    ("XOR", 0x06),
+    ("INPUT", 0x08),
+    ("FETCH", 0x0A),
+    ("TEST", 0x0C),
+    ("TESTCY", 0x0E),
+    ("ADD", 0x10),
+    ("ADDCY", 0x12),
+    ("STAR", 0x16),
+    ("SUB", 0x18),
+    ("SUBCY", 0x1A),
+    ("COMPARE", 0x1C),
+    ("COMPARECY", 0x1E),
+    ("OUTPUT", 0x2C),
+];
+
+/// Last 8 bits of the shift and rotate instructions, which all share opcode 0x14.
+const SHIFT_OPCODES: [(&str, u32); 11] = [
+    ("SLA", 0x00),
+    ("RL", 0x02),
+    ("SLX", 0x04),
+    ("SL0", 0x06),
+    ("SL1", 0x07),
+    ("SRA", 0x08),
+    ("SRX", 0x0A),
+    ("RR", 0x0C),
+    ("SR0", 0x0E),
+    ("SR1", 0x0F),
+    ("HWBUILD", 0x80),
+];
+
+fn condition_bits(condition: &ConditionType) -> u32 {
+    match condition {
+        ConditionType::IfZero => 0x10,
+        ConditionType::IfNonZero => 0x14,
+        ConditionType::IfCarry => 0x18,
+        ConditionType::IfNonCarry => 0x1C,
+    }
+}
+
+fn condition_from_bits(bits: u32) -> Option<ConditionType> {
+    match bits {
+        0x10 => Some(ConditionType::IfZero),
+        0x14 => Some(ConditionType::IfNonZero),
+        0x18 => Some(ConditionType::IfCarry),
+        0x1C => Some(ConditionType::IfNonCarry),
+        _ => None,
+    }
+}
+
+fn opcode_of(table: &[(&str, u32)], mnemonic: &str) -> u32 {
+    table
+        .iter()
+        .find(|(name, _)| *name == mnemonic)
+        .map(|(_, opcode)| *opcode)
+        .unwrap()
+}
+
+fn reg_reg(opcode: u32, x: u8, y: u8) -> u32 {
+    (opcode << 12) | ((x as u32 & 0xF) << 8) | ((y as u32 & 0xF) << 4)
+}
+
+fn reg_const(opcode: u32, x: u8, k: u32) -> u32 {
+    (opcode << 12) | ((x as u32 & 0xF) << 8) | (k & 0xFF)
+}
+
+fn shift(x: u8, mnemonic: &str) -> u32 {
+    reg_const(0x14, x, opcode_of(&SHIFT_OPCODES, mnemonic))
+}
+
+/// Mnemonic and operands of instructions that have the `sX, sY` / `sX, kk` forms, along with
+/// whether the second operand is a constant.
+fn alu_operands(instruction: &Instruction) -> Option<(&'static str, u8, u32, bool)> {
+    let operands = match *instruction {
+        Instruction::Load { lhs, rhs } => ("LOAD", lhs, rhs as u32, false),
+        Instruction::LoadConstant { lhs, rhs } => ("LOAD", lhs, rhs, true),
+        Instruction::And { lhs, rhs } => ("AND", lhs, rhs as u32, false),
+        Instruction::AndConstant { lhs, rhs } => ("AND", lhs, rhs, true),
+        Instruction::Or { lhs, rhs } => ("OR", lhs, rhs as u32, false),
+        Instruction::OrConstant { lhs, rhs } => ("OR", lhs, rhs, true),
+        Instruction::Xor { lhs, rhs } => ("XOR", lhs, rhs as u32, false),
+        Instruction::XorConstant { lhs, rhs } => ("XOR", lhs, rhs, true),
+        Instruction::InputDeref { lhs, rhs } => ("INPUT", lhs, rhs as u32, false),
+        Instruction::InputConstant { lhs, rhs } => ("INPUT", lhs, rhs, true),
+        Instruction::FetchDeref { lhs, rhs } => ("FETCH", lhs, rhs as u32, false),
+        Instruction::FetchConstant { lhs, rhs } => ("FETCH", lhs, rhs, true),
+        Instruction::Test { lhs, rhs } => ("TEST", lhs, rhs as u32, false),
+        Instruction::TestConstant { lhs, rhs } => ("TEST", lhs, rhs, true),
+        Instruction::TestCarry { lhs, rhs } => ("TESTCY", lhs, rhs as u32, false),
+        Instruction::TestCarryConstant { lhs, rhs } => ("TESTCY", lhs, rhs, true),
+        Instruction::Add { lhs, rhs } => ("ADD", lhs, rhs as u32, false),
+        Instruction::AddConstant { lhs, rhs } => ("ADD", lhs, rhs, true),
+        Instruction::AddCarry { lhs, rhs } => ("ADDCY", lhs, rhs as u32, false),
+        Instruction::AddCarryConstant { lhs, rhs } => ("ADDCY", lhs, rhs, true),
+        Instruction::Star { lhs, rhs } => ("STAR", lhs, rhs as u32, false),
+        Instruction::StarConstant { lhs, rhs } => ("STAR", lhs, rhs, true),
+        Instruction::Subtract { lhs, rhs } => ("SUB", lhs, rhs as u32, false),
+        Instruction::SubtractConstant { lhs, rhs } => ("SUB", lhs, rhs, true),
+        Instruction::SubtractCarry { lhs, rhs } => ("SUBCY", lhs, rhs as u32, false),
+        Instruction::SubtractCarryConstant { lhs, rhs } => ("SUBCY", lhs, rhs, true),
+        Instruction::Compare { lhs, rhs } => ("COMPARE", lhs, rhs as u32, false),
+        Instruction::CompareConstant { lhs, rhs } => ("COMPARE", lhs, rhs, true),
+        Instruction::CompareCarry { lhs, rhs } => ("COMPARECY", lhs, rhs as u32, false),
+        Instruction::CompareCarryConstant { lhs, rhs } => ("COMPARECY", lhs, rhs, true),
+        Instruction::OutputDeref { lhs, rhs } => ("OUTPUT", lhs, rhs as u32, false),
+        Instruction::OutputConstant { lhs, rhs } => ("OUTPUT", lhs, rhs, true),
+        _ => return None,
+    };
+
+    Some(operands)
+}
+
+fn shift_operands(instruction: &Instruction) -> Option<(&'static str, u8)> {
+    let operands = match *instruction {
+        Instruction::ShiftLeftCarry { register } => ("SLA", register),
+        Instruction::RotateLeft { register } => ("RL", register),
+        Instruction::ShiftLeftArth { register } => ("SLX", register),
+        Instruction::ShiftLeftZero { register } => ("SL0", register),
+        Instruction::ShiftLeftOne { register } => ("SL1", register),
+        Instruction::ShiftRightCarry { register } => ("SRA", register),
+        Instruction::ShiftRightArth { register } => ("SRX", register),
+        Instruction::RotateRight { register } => ("RR", register),
+        Instruction::ShiftRightZero { register } => ("SR0", register),
+        Instruction::ShiftRightOne { register } => ("SR1", register),
+        Instruction::HardwareBuild { register } => ("HWBUILD", register),
+        _ => return None,
+    };
+
+    Some(operands)
+}
+
+/// Encodes an instruction into its 18-bit KCPSM6 opcode.
+pub fn encode(instruction: &Instruction) -> Option<u32> {
+    if let Some((mnemonic, x, y, constant)) = alu_operands(instruction) {
+        let opcode = opcode_of(&ALU_OPCODES, mnemonic);
+
+        return Some(if constant {
+            reg_const(opcode + 1, x, y)
+        } else {
+            reg_reg(opcode, x, y as u8)
+        });
+    }
+
+    if let Some((mnemonic, register)) = shift_operands(instruction) {
+        return Some(shift(register, mnemonic));
+    }
+
+    let opcode = match *instruction {
+        Instruction::StoreDeref { lhs, rhs } => reg_reg(0x2E, lhs, rhs),
+        Instruction::StoreConstant { lhs, rhs } => reg_const(0x2F, lhs, rhs),
+        Instruction::OutputDoubleConstant { lhs, rhs } => {
+            (0x2B << 12) | ((lhs & 0xFF) << 4) | (rhs & 0xF)
+        }
+        Instruction::Regbank { selection } => (0x37 << 12) | (selection == 'b') as u32,
+        Instruction::Interrupt { state } => (0x28 << 12) | state as u32,
+        Instruction::ReturnInterrupt { state } => (0x29 << 12) | state as u32,
+        Instruction::Jump { address } => (0x22 << 12) | (address & 0xFFF),
+        Instruction::JumpConditional {
+            ref condition,
+            address,
+        } => ((condition_bits(condition) | 0x22) << 12) | (address & 0xFFF),
+        Instruction::JumpAt { first, second } => reg_reg(0x26, first, second),
+        Instruction::Call { address } => (0x20 << 12) | (address & 0xFFF),
+        Instruction::CallConditional {
+            ref condition,
+            address,
+        } => ((condition_bits(condition) | 0x20) << 12) | (address & 0xFFF),
+        Instruction::CallAt { first, second } => reg_reg(0x24, first, second),
+        Instruction::Return => 0x25 << 12,
+        Instruction::ReturnCondition { ref condition } => (condition_bits(condition) | 0x21) << 12,
+        Instruction::LoadAndReturn { lhs, rhs } => reg_const(0x21, lhs, rhs),
+        _ => return None,
+    };
+
+    Some(opcode)
+}
+
+/// Decodes an 18-bit KCPSM6 opcode. Returns `None` for opcodes that don't belong to any
+/// instruction.
+pub fn decode(opcode: u32) -> Option<Instruction> {
+    let op = (opcode >> 12) & 0x3F;
+    let x = ((opcode >> 8) & 0xF) as u8;
+    let y = ((opcode >> 4) & 0xF) as u8;
+    let k = opcode & 0xFF;
+    let address = opcode & 0xFFF;
+
+    if opcode > 0x3FFFF {
+        return None;
+    }
+
+    // Instructions with a `sX, sY` form need the lowest 4 bits to be zero.
+    let deref = opcode & 0xF == 0;
+
+    let instruction = match op {
+        0x00 if deref => Instruction::Load { lhs: x, rhs: y },
+        0x01 => Instruction::LoadConstant { lhs: x, rhs: k },
+        0x02 if deref => Instruction::And { lhs: x, rhs: y },
+        0x03 => Instruction::AndConstant { lhs: x, rhs: k },
+        0x04 if deref => Instruction::Or { lhs: x, rhs: y },
+        0x05 => Instruction::OrConstant { lhs: x, rhs: k },
+        0x06 if deref => Instruction::Xor { lhs: x, rhs: y },
+        0x07 => Instruction::XorConstant { lhs: x, rhs: k },
+        0x08 if deref => Instruction::InputDeref { lhs: x, rhs: y },
+        0x09 => Instruction::InputConstant { lhs: x, rhs: k },
+        0x0A if deref => Instruction::FetchDeref { lhs: x, rhs: y },
+        0x0B => Instruction::FetchConstant { lhs: x, rhs: k },
+        0x0C if deref => Instruction::Test { lhs: x, rhs: y },
+        0x0D => Instruction::TestConstant { lhs: x, rhs: k },
+        0x0E if deref => Instruction::TestCarry { lhs: x, rhs: y },
+        0x0F => Instruction::TestCarryConstant { lhs: x, rhs: k },
+        0x10 if deref => Instruction::Add { lhs: x, rhs: y },
+        0x11 => Instruction::AddConstant { lhs: x, rhs: k },
+        0x12 if deref => Instruction::AddCarry { lhs: x, rhs: y },
+        0x13 => Instruction::AddCarryConstant { lhs: x, rhs: k },
+        0x14 => {
+            let register = x;
+
+            match k {
+                0x00 => Instruction::ShiftLeftCarry { register },
+                0x02 => Instruction::RotateLeft { register },
+                0x04 => Instruction::ShiftLeftArth { register },
+                0x06 => Instruction::ShiftLeftZero { register },
+                0x07 => Instruction::ShiftLeftOne { register },
+                0x08 => Instruction::ShiftRightCarry { register },
+                0x0A => Instruction::ShiftRightArth { register },
+                0x0C => Instruction::RotateRight { register },
+                0x0E => Instruction::ShiftRightZero { register },
+                0x0F => Instruction::ShiftRightOne { register },
+                0x80 => Instruction::HardwareBuild { register },
+                _ => return None,
+            }
+        }
+        0x16 if deref => Instruction::Star { lhs: x, rhs: y },
+        0x17 => Instruction::StarConstant { lhs: x, rhs: k },
+        0x18 if deref => Instruction::Subtract { lhs: x, rhs: y },
+        0x19 => Instruction::SubtractConstant { lhs: x, rhs: k },
+        0x1A if deref => Instruction::SubtractCarry { lhs: x, rhs: y },
+        0x1B => Instruction::SubtractCarryConstant { lhs: x, rhs: k },
+        0x1C if deref => Instruction::Compare { lhs: x, rhs: y },
+        0x1D => Instruction::CompareConstant { lhs: x, rhs: k },
+        0x1E if deref => Instruction::CompareCarry { lhs: x, rhs: y },
+        0x1F => Instruction::CompareCarryConstant { lhs: x, rhs: k },
+        0x20 => Instruction::Call { address },
+        0x21 => Instruction::LoadAndReturn { lhs: x, rhs: k },
+        0x22 => Instruction::Jump { address },
+        0x24 if deref => Instruction::CallAt {
+            first: x,
+            second: y,
+        },
+        0x25 if address == 0 => Instruction::Return,
+        0x26 if deref => Instruction::JumpAt {
+            first: x,
+            second: y,
+        },
+        0x28 if address <= 1 => Instruction::Interrupt {
+            state: address == 1,
+        },
+        0x29 if address <= 1 => Instruction::ReturnInterrupt {
+            state: address == 1,
+        },
+        0x2B => Instruction::OutputDoubleConstant {
+            lhs: (opcode >> 4) & 0xFF,
+            rhs: opcode & 0xF,
+        },
+        0x2C if deref => Instruction::OutputDeref { lhs: x, rhs: y },
+        0x2D => Instruction::OutputConstant { lhs: x, rhs: k },
+        0x2E if deref => Instruction::StoreDeref { lhs: x, rhs: y },
+        0x2F => Instruction::StoreConstant { lhs: x, rhs: k },
+        0x37 if address <= 1 => Instruction::Regbank {
+            selection: if address == 1 { 'b' } else { 'a' },
+        },
+        _ => {
+            let condition = condition_from_bits(op & 0x1C)?;
+
+            match op & 0x23 {
+                0x22 => Instruction::JumpConditional { condition, address },
+                0x20 => Instruction::CallConditional { condition, address },
+                0x21 if address == 0 => Instruction::ReturnCondition { condition },
+                _ => return None,
+            }
+        }
+    };
+
+    Some(instruction)
+}
+
+impl fmt::Display for ConditionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionType::IfZero => write!(f, "Z"),
+            ConditionType::IfNonZero => write!(f, "NZ"),
+            ConditionType::IfCarry => write!(f, "C"),
+            ConditionType::IfNonCarry => write!(f, "NC"),
+        }
+    }
+}
+
+/// Disassembles the instruction, using the syntax of the KCPSM6 assembler.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((mnemonic, x, y, constant)) = alu_operands(self) {
+            let deref = matches!(mnemonic, "INPUT" | "OUTPUT" | "FETCH");
+
+            return match (constant, deref) {
+                (true, _) => write!(f, "{} s{:X}, {:02X}", mnemonic, x, y),
+                (false, true) => write!(f, "{} s{:X}, (s{:X})", mnemonic, x, y),
+                (false, false) => write!(f, "{} s{:X}, s{:X}", mnemonic, x, y),
+            };
+        }
+
+        if let Some((mnemonic, register)) = shift_operands(self) {
+            return write!(f, "{} s{:X}", mnemonic, register);
+        }
+
+        match self {
+            Instruction::None => write!(f, "<none>"),
+            Instruction::StoreDeref { lhs, rhs } => write!(f, "STORE s{:X}, (s{:X})", lhs, rhs),
+            Instruction::StoreConstant { lhs, rhs } => write!(f, "STORE s{:X}, {:02X}", lhs, rhs),
+            Instruction::OutputDoubleConstant { lhs, rhs } => {
+                write!(f, "OUTPUTK {:02X}, {:X}", lhs, rhs)
+            }
+            Instruction::Regbank { selection } => {
+                write!(f, "REGBANK {}", selection.to_ascii_uppercase())
+            }
+            Instruction::Interrupt { state: true } => write!(f, "ENABLE INTERRUPT"),
+            Instruction::Interrupt { state: false } => write!(f, "DISABLE INTERRUPT"),
+            Instruction::ReturnInterrupt { state: true } => write!(f, "RETURNI ENABLE"),
+            Instruction::ReturnInterrupt { state: false } => write!(f, "RETURNI DISABLE"),
+            Instruction::Jump { address } => write!(f, "JUMP {:03X}", address),
+            Instruction::JumpConditional { condition, address } => {
+                write!(f, "JUMP {}, {:03X}", condition, address)
+            }
+            Instruction::JumpAt { first, second } => {
+                write!(f, "JUMP@ (s{:X}, s{:X})", first, second)
+            }
+            Instruction::Call { address } => write!(f, "CALL {:03X}", address),
+            Instruction::CallConditional { condition, address } => {
+                write!(f, "CALL {}, {:03X}", condition, address)
+            }
+            Instruction::CallAt { first, second } => {
+                write!(f, "CALL@ (s{:X}, s{:X})", first, second)
+            }
+            Instruction::Return => write!(f, "RETURN"),
+            Instruction::ReturnCondition { condition } => write!(f, "RETURN {}", condition),
+            Instruction::LoadAndReturn { lhs, rhs } => {
+                write!(f, "LOAD&RETURN s{:X}, {:02X}", lhs, rhs)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+    #[test]
+    fn encode_instructions() {
+        let cases = [
+            ("load s1, s2", 0x00120),
+            ("load s1, 41", 0x01141),
+            ("input s0, (s3)", 0x08030),
+            ("output sA, 04", 0x2DA04),
+            ("outputk 41, 3", 0x2B413),
+            ("sl0 s5", 0x14506),
+            ("hwbuild s0", 0x14080),
+            ("regbank b", 0x37001),
+            ("enable interrupt", 0x28001),
+            ("returni disable", 0x29000),
+            ("start: jump start", 0x22000),
+            ("jump nz, 123", 0x36123),
+            ("call c, 3FF", 0x383FF),
+            ("return nc", 0x3D000),
+            ("return", 0x25000),
+            ("jump@ (s1, s2)", 0x26120),
+            ("load&return s4, 20", 0x21420),
+            ("store s2, 3F", 0x2F23F),
+            ("star s1, s0", 0x16100),
+        ];
+
+        for (source, opcode) in cases {
+            let program = assemble_str(source);
+            let (_, instruction) = &program.get_instructions()[0];
+
+            assert_eq!(encode(instruction), Some(opcode), "{}", source);
+            assert_eq!(decode(opcode).as_ref(), Some(instruction), "{}", source);
+        }
+    }
+
+    #[test]
+    fn decode_invalid_opcodes() {
+        assert!(decode(0x00121).is_none());
+        assert!(decode(0x14010).is_none());
+        assert!(decode(0x15000).is_none());
+        assert!(decode(0x40000).is_none());
+    }
+
+    #[test]
+    fn disassemble() {
+        let program = assemble_str(
+            "load s0, \"A\"\nfetch s1, (s0)\noutputk 01, A\njump z, 010\nregbank a\nsrx sF\n",
+        );
+        let text: Vec<String> = program
+            .get_instructions()
+            .iter()
+            .map(|(_, instruction)| instruction.to_string())
+            .collect();
+
+        assert_eq!(
+            text,
+            vec![
+                "LOAD s0, 41",
+                "FETCH s1, (s0)",
+                "OUTPUTK 01, A",
+                "JUMP Z, 010",
+                "REGBANK A",
+                "SRX sF",
+            ]
+        );
+    }
+}
@@ -13,10 +13,10 @@ pub fn bit_oddity(n: u8) -> bool {
 
     while n > 0 {
         if n & 1 == 1 {
-            amount_of_ones = amount_of_ones + 1;
+            amount_of_ones += 1;
         }
 
-        n = n >> 1;
+        n >>= 1;
     }
 
     amount_of_ones % 2 == 0
@@ -0,0 +1,118 @@
+use std::io::{Error, ErrorKind};
+
+use super::diagnostics::Diagnostic;
+use super::encoding::encode;
+use crate::Instruction;
+
+/// The KCPSM6 assembler always writes a full 4K image, no matter how large the program memory is.
+pub const HEX_IMAGE_SIZE: usize = 4096;
+
+/// Encodes instructions into a program memory image. Unused addresses are zero, and instructions
+/// past the end of the 4K image are an error.
+pub fn create_image(instructions: &[(usize, Instruction)]) -> Result<Vec<u32>, Error> {
+    let mut image = vec![0u32; HEX_IMAGE_SIZE];
+
+    for (address, instruction) in instructions {
+        let Some(word) = image.get_mut(*address) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The instruction at address 0x{:03X} is past the end of the program memory.",
+                    address
+                ),
+            ));
+        };
+
+        if let Some(opcode) = encode(instruction) {
+            *word = opcode;
+        }
+    }
+
+    Ok(image)
+}
+
+/// Writes an image in the `.hex` format of the KCPSM6 assembler: one 5 digit opcode per line.
+pub fn write_hex(image: &[u32]) -> String {
+    image
+        .iter()
+        .map(|opcode| format!("{:05X}\n", opcode))
+        .collect()
+}
+
+/// Reads a `.hex` image. Blank lines are ignored. A malformed image gives the error on its first
+/// bad line.
+pub fn read_hex(contents: &str) -> Result<Vec<u32>, Diagnostic> {
+    let mut image = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if image.len() == HEX_IMAGE_SIZE {
+            return Err(Diagnostic::error(
+                index + 1,
+                format!(
+                    "The program memory holds at most {} opcodes.",
+                    HEX_IMAGE_SIZE
+                ),
+            ));
+        }
+
+        match u32::from_str_radix(line, 16) {
+            Ok(opcode) if opcode <= 0x3FFFF => image.push(opcode),
+            _ => {
+                return Err(Diagnostic::error(
+                    index + 1,
+                    format!("'{}' isn't an 18-bit hexadecimal opcode.", line),
+                ))
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+    #[test]
+    fn hex_round_trip() {
+        let program = assemble_str("load s0, 01\naddress 010\njump 000\n");
+        let image = create_image(program.get_instructions()).unwrap();
+        let hex = write_hex(&image);
+
+        assert_eq!(hex.lines().count(), HEX_IMAGE_SIZE);
+        assert_eq!(&hex[..12], "01001\n00000\n");
+        assert_eq!(read_hex(&hex).unwrap(), image);
+        assert_eq!(image[0x10], 0x22000);
+    }
+
+    #[test]
+    fn instructions_past_the_end() {
+        let instructions = [(0xFFF, Instruction::Return), (0x1000, Instruction::Return)];
+
+        assert!(create_image(&instructions[..1]).is_ok());
+        assert!(create_image(&instructions).is_err());
+    }
+
+    #[test]
+    fn invalid_hex() {
+        assert_eq!(
+            read_hex("01001\n\n4ZZZZ\n"),
+            Err(Diagnostic::error(
+                3,
+                "'4ZZZZ' isn't an 18-bit hexadecimal opcode.".to_string()
+            ))
+        );
+        assert!(read_hex("40000\n").is_err());
+        assert_eq!(
+            read_hex(&"00000\n".repeat(HEX_IMAGE_SIZE + 1)).map_err(|error| error.line),
+            Err(HEX_IMAGE_SIZE + 1)
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::diagnostics::Diagnostic;
+
+/// The source of a program with its INCLUDE directives expanded: the lines of each included file
+/// follow the directive that includes it, so the program is assembled as a single source.
+///
+/// Lines are numbered through the expanded source, and `origins` maps them back to the file and
+/// line they were written in.
+#[derive(Debug, Default)]
+pub struct Expansion {
+    /// Every line of the expanded source, as written.
+    pub lines: Vec<String>,
+    /// The same lines with the INCLUDE directives blanked out, for the assembler.
+    pub code: Vec<String>,
+    /// File (an index into `files`) and line, starting from 1, of every expanded line.
+    pub origins: Vec<(usize, usize)>,
+    /// The files of the program. The first one is the program's own source.
+    pub files: Vec<Option<PathBuf>>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Expansion {
+    /// Expands the INCLUDE directives of a source. Included files are found relative to the file
+    /// including them, or to the current directory for a source that isn't from a file.
+    pub fn new(source: &str, path: Option<&Path>) -> Expansion {
+        let mut expansion = Expansion::default();
+
+        expansion.files.push(path.map(Path::to_path_buf));
+        expansion.expand(source, 0, &mut Vec::new());
+        expansion
+    }
+
+    fn expand(&mut self, source: &str, file: usize, including: &mut Vec<PathBuf>) {
+        let directory = self.files[file]
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        if let Some(path) = &self.files[file] {
+            including.push(fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+        }
+
+        for (index, text) in source.lines().enumerate() {
+            let name = parse_include(text);
+
+            self.lines.push(text.to_string());
+            self.code.push(match name {
+                Some(_) => String::new(),
+                None => text.to_string(),
+            });
+            self.origins.push((file, index + 1));
+
+            let line = self.lines.len();
+
+            match name {
+                Some(Ok(name)) => self.include(&directory.join(name), line, including),
+                Some(Err(message)) => self.diagnostics.push(Diagnostic::error(line, message)),
+                None => {}
+            }
+        }
+
+        if self.files[file].is_some() {
+            including.pop();
+        }
+    }
+
+    fn include(&mut self, path: &Path, line: usize, including: &mut Vec<PathBuf>) {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        if including.contains(&canonical) {
+            return self.diagnostics.push(Diagnostic::error(
+                line,
+                format!("'{}' is already being included.", path.display()),
+            ));
+        }
+
+        // Like the program itself, included files may be in Windows-1252.
+        match fs::read(path) {
+            Ok(bytes) => {
+                self.files.push(Some(path.to_path_buf()));
+                self.expand(
+                    &String::from_utf8_lossy(&bytes),
+                    self.files.len() - 1,
+                    including,
+                );
+            }
+            Err(error) => self.diagnostics.push(Diagnostic::error(
+                line,
+                format!("Unable to include '{}': {}.", path.display(), error),
+            )),
+        }
+    }
+}
+
+/// The file name of an INCLUDE directive, or an error if the directive doesn't have one. None for
+/// any other line.
+fn parse_include(text: &str) -> Option<Result<&str, String>> {
+    let trimmed = text.trim_start();
+    let keyword = trimmed.get(..7)?;
+
+    if !keyword.eq_ignore_ascii_case("include")
+        || !trimmed[7..].starts_with(|c: char| c.is_whitespace() || c == '"')
+    {
+        return None;
+    }
+
+    let rest = trimmed[7..].trim_start();
+    let name = rest
+        .strip_prefix('"')
+        .and_then(|rest| rest.split_once('"'))
+        .map(|(name, _)| name)
+        .filter(|name| !name.is_empty());
+
+    Some(name.ok_or_else(|| "INCLUDE needs a file name in double quotes.".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("kcpsm6sim-include-{}", std::process::id()));
+
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("lib/a.psm"),
+            "a: RETURN\n  include \"b.psm\" ; from lib\n",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/b.psm"), "b: RETURN\nINCLUDE \"a.psm\"\n").unwrap();
+
+        let main = dir.join("main.psm");
+        let expansion = Expansion::new(
+            "CALL a\nINCLUDE \"lib/a.psm\"\nINCLUDE \"missing.psm\"\nINCLUDE missing\nJUMP 000",
+            Some(&main),
+        );
+
+        assert_eq!(
+            expansion.lines,
+            [
+                "CALL a",
+                "INCLUDE \"lib/a.psm\"",
+                "a: RETURN",
+                "  include \"b.psm\" ; from lib",
+                "b: RETURN",
+                "INCLUDE \"a.psm\"",
+                "INCLUDE \"missing.psm\"",
+                "INCLUDE missing",
+                "JUMP 000",
+            ]
+        );
+        assert_eq!(expansion.code[1], "");
+        assert_eq!(expansion.code[2], "a: RETURN");
+        assert_eq!(
+            expansion.origins,
+            [
+                (0, 1),
+                (0, 2),
+                (1, 1),
+                (1, 2),
+                (2, 1),
+                (2, 2),
+                (0, 3),
+                (0, 4),
+                (0, 5)
+            ]
+        );
+        assert_eq!(
+            expansion.files,
+            [
+                Some(main),
+                Some(dir.join("lib/a.psm")),
+                Some(dir.join("lib/b.psm"))
+            ]
+        );
+
+        let errors: Vec<(usize, &str)> = expansion
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.line, diagnostic.message.as_str()))
+            .collect();
+
+        // b.psm includes a.psm back, which is left out.
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].0, 6);
+        assert!(errors[0].1.ends_with("a.psm' is already being included."));
+        assert_eq!(errors[1].0, 7);
+        assert!(errors[1].1.starts_with("Unable to include '"));
+        assert_eq!(
+            errors[2],
+            (8, "INCLUDE needs a file name in double quotes.")
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{ConditionType, SimulationContext, SimulationUpdate};
+use crate::{ConditionType, SimulationContext, SimulationUpdate, CALL_STACK_SIZE};
 use std::io::{Error, ErrorKind};
 
 pub fn address(
@@ -9,17 +9,21 @@ pub fn address(
     let mut update = SimulationUpdate::new(ctx);
     let call_stack = ctx.get_call_stack();
 
-    if call_stack.len() >= 30 {
+    if call_stack.len() >= CALL_STACK_SIZE {
         return Err(Error::new(
             ErrorKind::InvalidInput,
             "CALL: The call stack is full!",
         ));
     }
 
-    if address > 0x3FF {
+    if address as usize >= ctx.get_program_memory_size() {
         return Err(Error::new(
             ErrorKind::AddrNotAvailable,
-            format!("JUMP: The user tried to jump to an address outside of the program ROM ({}, max is 0x3FF)!", address)
+            format!(
+                "CALL: The user tried to jump to an address outside of the program ROM (0x{:03X}, max is 0x{:03X})!",
+                address,
+                ctx.get_program_memory_size() - 1
+            ),
         ));
     }
 
@@ -43,6 +47,14 @@ pub fn address(
     Ok(update)
 }
 
+/// CALL@ (sX, sY) calls the address made of the lower 4 bits of sX and the 8 bits of sY.
+pub fn at(ctx: &SimulationContext, first: u8, second: u8) -> Result<SimulationUpdate, Error> {
+    let upper = (ctx.get_register(first as usize).unwrap() & 0x0F) as u32;
+    let lower = ctx.get_register(second as usize).unwrap() as u32;
+
+    address(ctx, (upper << 8) | lower, None)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -217,4 +229,26 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn call_at() {
+        let mut registers = [0u8; 16];
+
+        registers[1] = 0x02;
+        registers[2] = 0x10;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            at(&context, 1, 2).unwrap(),
+            SimulationUpdate {
+                registers,
+                carry: false,
+                zero: false,
+                pc: 0x210,
+                call_addr: Some(1),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
 }
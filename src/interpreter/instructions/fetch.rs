@@ -1,4 +1,4 @@
-use crate::{interpreter::interpreter::MemoryOperation, SimulationContext, SimulationUpdate, SCRATCH_PAD_MEMORY_SIZE};
+use crate::{interpreter::interpreter::MemoryOperation, SimulationContext, SimulationUpdate};
 use std::io::{Error, ErrorKind};
 
 pub fn register_constant(
@@ -9,10 +9,10 @@ pub fn register_constant(
     let rhs = rhs as usize;
     let mut update = SimulationUpdate::new(ctx);
 
-    if rhs >= SCRATCH_PAD_MEMORY_SIZE {
+    if rhs >= ctx.get_scratch_pad_size() {
         return Err(
             Error::new(ErrorKind::AddrNotAvailable, 
-            format!("Unable to fetch value from address as it is out of bounds! (address was {}, max is {}!", rhs, SCRATCH_PAD_MEMORY_SIZE))
+            format!("Unable to fetch value from address as it is out of bounds! (address was {}, max is {}!", rhs, ctx.get_scratch_pad_size()))
         );
     }
 
@@ -29,10 +29,10 @@ pub fn register_deref(
     let value = ctx.get_register(rhs as usize).unwrap() as usize;
     let mut update = SimulationUpdate::new(ctx);
 
-    if value >= SCRATCH_PAD_MEMORY_SIZE {
+    if value >= ctx.get_scratch_pad_size() {
         return Err(
             Error::new(ErrorKind::AddrNotAvailable, 
-            format!("Unable to fetch value from address as it is out of bounds! (address was {}, max is {}!", rhs, SCRATCH_PAD_MEMORY_SIZE))
+            format!("Unable to fetch value from address as it is out of bounds! (address was {}, max is {}!", value, ctx.get_scratch_pad_size()))
         );
     }
 
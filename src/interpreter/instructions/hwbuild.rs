@@ -0,0 +1,56 @@
+use crate::{SimulationContext, SimulationUpdate};
+use std::io::Error;
+
+pub fn register(ctx: &SimulationContext, register: u8) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+    let value = ctx.get_hwbuild();
+
+    // HWBUILD always sets the carry flag, so a program can tell it apart from other instructions.
+    update.registers[register as usize] = value;
+    update.zero = value == 0u8;
+    update.carry = true;
+
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hwbuild_value() {
+        let mut context = SimulationContext::new_with_params([0u8; 16], false, false);
+        let mut end_registers = [0u8; 16];
+
+        context.set_hwbuild(0x41);
+
+        end_registers[4] = 0x41;
+
+        assert_eq!(
+            register(&context, 4).unwrap(),
+            SimulationUpdate {
+                registers: end_registers,
+                carry: true,
+                zero: false,
+                pc: 1,
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn hwbuild_zero() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        assert_eq!(
+            register(&context, 0).unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: true,
+                zero: true,
+                pc: 1,
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+}
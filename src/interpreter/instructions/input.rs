@@ -0,0 +1,78 @@
+use crate::{interpreter::interpreter::PortOperation, SimulationContext, SimulationUpdate};
+use std::io::{Error, ErrorKind};
+
+pub fn register_constant(
+    ctx: &SimulationContext,
+    lhs: u8,
+    rhs: u32,
+) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+
+    if rhs > 255 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("INPUT: The port specified was too large ({})!", rhs),
+        ));
+    }
+
+    // The value is only known once the port has been read, which is up to the simulation.
+    update.port_op = Some(PortOperation::Input(rhs as u8, lhs));
+
+    Ok(update)
+}
+
+pub fn register_deref(
+    ctx: &SimulationContext,
+    lhs: u8,
+    rhs: u8,
+) -> Result<SimulationUpdate, Error> {
+    let port = ctx.get_register(rhs as usize).unwrap();
+    let mut update = SimulationUpdate::new(ctx);
+
+    update.port_op = Some(PortOperation::Input(port, lhs));
+
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_constant() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        assert_eq!(
+            register_constant(&context, 3, 0x20).unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: false,
+                zero: false,
+                pc: 1,
+                port_op: Some(PortOperation::Input(0x20, 3)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn input_deref_register() {
+        let mut registers = [0u8; 16];
+
+        registers[1] = 0x42;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            register_deref(&context, 0, 1).unwrap(),
+            SimulationUpdate {
+                registers,
+                carry: false,
+                zero: false,
+                pc: 1,
+                port_op: Some(PortOperation::Input(0x42, 0)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{SimulationContext, SimulationUpdate};
+use std::io::{Error, ErrorKind};
+
+pub fn enable(ctx: &SimulationContext, state: bool) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+
+    update.interrupt_enable = Some(state);
+
+    Ok(update)
+}
+
+/// RETURNI returns from the interrupt routine, restoring the flags and the register bank that were
+/// preserved when the interrupt happened.
+pub fn ret(ctx: &SimulationContext, state: bool) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+
+    if ctx.get_call_stack().is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "RETURNI: Unable to return as call stack is empty!",
+        ));
+    }
+
+    update.ret_interrupt = true;
+    update.interrupt_enable = Some(state);
+
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enable_interrupt() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        assert_eq!(
+            enable(&context, true).unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: false,
+                zero: false,
+                pc: 1,
+                interrupt_enable: Some(true),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn return_from_interrupt() {
+        let mut context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        context.add_to_call_stack_unrestricted(5);
+
+        assert_eq!(
+            ret(&context, false).unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: false,
+                zero: false,
+                pc: 1,
+                ret_interrupt: true,
+                interrupt_enable: Some(false),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn return_from_interrupt_with_empty_stack() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        ret(&context, true).unwrap();
+    }
+}
@@ -8,10 +8,14 @@ pub fn address(
 ) -> Result<SimulationUpdate, Error> {
     let mut update = SimulationUpdate::new(ctx);
 
-    if address > 0x3FF {
+    if address as usize >= ctx.get_program_memory_size() {
         return Err(Error::new(
             ErrorKind::AddrNotAvailable,
-            format!("JUMP: The user tried to jump to an address outside of the program ROM ({}, max is 0x3FF)!", address)
+            format!(
+                "JUMP: The user tried to jump to an address outside of the program ROM (0x{:03X}, max is 0x{:03X})!",
+                address,
+                ctx.get_program_memory_size() - 1
+            ),
         ));
     }
 
@@ -33,6 +37,14 @@ pub fn address(
     Ok(update)
 }
 
+/// JUMP@ (sX, sY) jumps to the address made of the lower 4 bits of sX and the 8 bits of sY.
+pub fn at(ctx: &SimulationContext, first: u8, second: u8) -> Result<SimulationUpdate, Error> {
+    let upper = (ctx.get_register(first as usize).unwrap() & 0x0F) as u32;
+    let lower = ctx.get_register(second as usize).unwrap() as u32;
+
+    address(ctx, (upper << 8) | lower, None)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -197,4 +209,25 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn jump_at() {
+        let mut registers = [0u8; 16];
+
+        registers[1] = 0xF1;
+        registers[2] = 0x23;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            at(&context, 1, 2).unwrap(),
+            SimulationUpdate {
+                registers,
+                carry: false,
+                zero: false,
+                pc: 0x123,
+                ..SimulationUpdate::default()
+            }
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use super::ret;
 use crate::{SimulationContext, SimulationUpdate};
 use std::io::{Error, ErrorKind};
 
@@ -32,6 +33,17 @@ pub fn register_constant(
     Ok(update)
 }
 
+/// LOAD&RETURN loads a constant and returns, which is how KCPSM6 reads constant tables from
+/// program memory.
+pub fn and_return(ctx: &SimulationContext, lhs: u8, rhs: u32) -> Result<SimulationUpdate, Error> {
+    let mut update = register_constant(ctx, lhs, rhs)?;
+    let ret = ret::default(ctx, None)?;
+
+    update.ret_addr = ret.ret_addr;
+
+    Ok(update)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -102,4 +114,34 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn load_and_return() {
+        let mut context = SimulationContext::new_with_params([0u8; 16], false, false);
+        let mut end_registers = [0u8; 16];
+
+        context.add_to_call_stack_unrestricted(7);
+
+        end_registers[2] = 0x41;
+
+        assert_eq!(
+            and_return(&context, 2, 0x41).unwrap(),
+            SimulationUpdate {
+                registers: end_registers,
+                carry: false,
+                zero: false,
+                ret_addr: true,
+                pc: 1,
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn load_and_return_with_empty_stack() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        and_return(&context, 2, 0x41).unwrap();
+    }
 }
@@ -5,9 +5,14 @@ pub mod call;
 pub mod compare;
 pub mod compare_carry;
 pub mod fetch;
+pub mod hwbuild;
+pub mod input;
+pub mod interrupt;
 pub mod jump;
 pub mod load;
 pub mod or;
+pub mod output;
+pub mod regbank;
 pub mod ret;
 pub mod rotate_left;
 pub mod rotate_right;
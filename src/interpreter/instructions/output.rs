@@ -0,0 +1,139 @@
+use crate::{interpreter::interpreter::PortOperation, SimulationContext, SimulationUpdate};
+use std::io::{Error, ErrorKind};
+
+pub fn register_constant(
+    ctx: &SimulationContext,
+    lhs: u8,
+    rhs: u32,
+) -> Result<SimulationUpdate, Error> {
+    let value = ctx.get_register(lhs as usize).unwrap();
+    let mut update = SimulationUpdate::new(ctx);
+
+    if rhs > 255 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("OUTPUT: The port specified was too large ({})!", rhs),
+        ));
+    }
+
+    update.port_op = Some(PortOperation::Output(rhs as u8, value));
+
+    Ok(update)
+}
+
+pub fn register_deref(
+    ctx: &SimulationContext,
+    lhs: u8,
+    rhs: u8,
+) -> Result<SimulationUpdate, Error> {
+    let value = ctx.get_register(lhs as usize).unwrap();
+    let port = ctx.get_register(rhs as usize).unwrap();
+    let mut update = SimulationUpdate::new(ctx);
+
+    update.port_op = Some(PortOperation::Output(port, value));
+
+    Ok(update)
+}
+
+pub fn constant_constant(
+    ctx: &SimulationContext,
+    lhs: u32,
+    rhs: u32,
+) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+
+    if lhs > 255 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("OUTPUTK: The constant specified was too large ({})!", lhs),
+        ));
+    }
+
+    // OUTPUTK only drives the lower 4 bits of PORT_ID.
+    if rhs > 15 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "OUTPUTK: The port specified was too large ({}, max is 0xF)!",
+                rhs
+            ),
+        ));
+    }
+
+    update.port_op = Some(PortOperation::OutputK(rhs as u8, lhs as u8));
+
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_constant() {
+        let mut registers = [0u8; 16];
+
+        registers[2] = 0xAA;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            register_constant(&context, 2, 0x04).unwrap(),
+            SimulationUpdate {
+                registers,
+                carry: false,
+                zero: false,
+                pc: 1,
+                port_op: Some(PortOperation::Output(0x04, 0xAA)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn output_deref_register() {
+        let mut registers = [0u8; 16];
+
+        registers[0] = 0x12;
+        registers[1] = 0x80;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            register_deref(&context, 0, 1).unwrap(),
+            SimulationUpdate {
+                registers,
+                carry: false,
+                zero: false,
+                pc: 1,
+                port_op: Some(PortOperation::Output(0x80, 0x12)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn output_double_constant() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        assert_eq!(
+            constant_constant(&context, 0x41, 0x1).unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: false,
+                zero: false,
+                pc: 1,
+                port_op: Some(PortOperation::OutputK(0x1, 0x41)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn output_double_constant_invalid_port() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        constant_constant(&context, 0x41, 0x10).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{interpreter::interpreter::BankOperation, SimulationContext, SimulationUpdate};
+use std::io::{Error, ErrorKind};
+
+pub fn select(ctx: &SimulationContext, selection: char) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+
+    if selection != 'a' && selection != 'b' {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("REGBANK: There's no register bank named {}!", selection),
+        ));
+    }
+
+    update.bank_op = Some(BankOperation::Select(selection));
+
+    Ok(update)
+}
+
+/// STAR copies a value into a register of the bank that is *not* selected.
+pub fn star_register(ctx: &SimulationContext, lhs: u8, rhs: u8) -> Result<SimulationUpdate, Error> {
+    let value = ctx.get_register(rhs as usize).unwrap();
+    let mut update = SimulationUpdate::new(ctx);
+
+    update.bank_op = Some(BankOperation::Star(lhs, value));
+
+    Ok(update)
+}
+
+pub fn star_constant(
+    ctx: &SimulationContext,
+    lhs: u8,
+    rhs: u32,
+) -> Result<SimulationUpdate, Error> {
+    let mut update = SimulationUpdate::new(ctx);
+
+    if rhs > 255 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("STAR: The constant specified was too large ({})!", rhs),
+        ));
+    }
+
+    update.bank_op = Some(BankOperation::Star(lhs, rhs as u8));
+
+    Ok(update)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regbank_select() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        assert_eq!(
+            select(&context, 'b').unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: false,
+                zero: false,
+                pc: 1,
+                bank_op: Some(BankOperation::Select('b')),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn star_between_banks() {
+        let mut registers = [0u8; 16];
+
+        registers[3] = 0x5A;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            star_register(&context, 7, 3).unwrap(),
+            SimulationUpdate {
+                registers,
+                carry: false,
+                zero: false,
+                pc: 1,
+                bank_op: Some(BankOperation::Star(7, 0x5A)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+
+    #[test]
+    fn star_a_constant() {
+        let context = SimulationContext::new_with_params([0u8; 16], false, false);
+
+        assert_eq!(
+            star_constant(&context, 0, 0x10).unwrap(),
+            SimulationUpdate {
+                registers: [0u8; 16],
+                carry: false,
+                zero: false,
+                pc: 1,
+                bank_op: Some(BankOperation::Star(0, 0x10)),
+                ..SimulationUpdate::default()
+            }
+        );
+    }
+}
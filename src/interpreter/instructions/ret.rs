@@ -8,7 +8,7 @@ pub fn default(
     let mut update = SimulationUpdate::new(ctx);
     let call_stack = ctx.get_call_stack();
 
-    if call_stack.len() <= 0 {
+    if call_stack.is_empty() {
         return Err(Error::new(
             ErrorKind::InvalidInput,
             "RETURN: Unable to return as call stack is empty!",
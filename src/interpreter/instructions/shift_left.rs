@@ -33,7 +33,7 @@ pub fn register(
     // To shift the value, just use the left shift operator then add the new bit to the right.
     register_value = (register_value << 1).wrapping_add(shift_value);
 
-    update.carry = if carry_value == 1 { true } else { false };
+    update.carry = carry_value != 0;
     update.zero = register_value == 0u8;
     update.registers[register as usize] = register_value;
 
@@ -135,4 +135,25 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn shift_left_into_carry() {
+        let mut registers = [0u8; 16];
+        let end_registers = [0u8; 16];
+
+        registers[0] = 0b10000000;
+
+        let context = SimulationContext::new_with_params(registers, false, false);
+
+        assert_eq!(
+            register(&context, 0, ShiftMode::Number(0)).unwrap(),
+            SimulationUpdate {
+                registers: end_registers,
+                carry: true,
+                zero: true,
+                pc: 1,
+                ..SimulationUpdate::default()
+            }
+        );
+    }
 }
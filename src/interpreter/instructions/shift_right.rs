@@ -33,7 +33,7 @@ pub fn register(
     // To shift the value, just use the right shift operator then add the new bit to the left.
     register_value = (register_value >> 1).wrapping_add(shift_value);
 
-    update.carry = if carry_value == 1 { true } else { false };
+    update.carry = carry_value == 1;
     update.zero = register_value == 0u8;
     update.registers[register as usize] = register_value;
 
@@ -1,4 +1,4 @@
-use crate::{interpreter::interpreter::MemoryOperation, SimulationContext, SimulationUpdate, SCRATCH_PAD_MEMORY_SIZE};
+use crate::{interpreter::interpreter::MemoryOperation, SimulationContext, SimulationUpdate};
 use std::io::{Error, ErrorKind};
 
 pub fn register_constant(
@@ -10,10 +10,10 @@ pub fn register_constant(
     let value = ctx.get_register(lhs as usize).unwrap();
     let mut update = SimulationUpdate::new(ctx);
 
-    if rhs > SCRATCH_PAD_MEMORY_SIZE {
+    if rhs >= ctx.get_scratch_pad_size() {
         return Err(
             Error::new(ErrorKind::AddrNotAvailable, 
-            format!("Unable to store value into address as it is out of bounds! (address was {}, max is {}!", rhs, SCRATCH_PAD_MEMORY_SIZE))
+            format!("Unable to store value into address as it is out of bounds! (address was {}, max is {}!", rhs, ctx.get_scratch_pad_size()))
         );
     }
 
@@ -33,10 +33,10 @@ pub fn register_deref(
 
     let mut update = SimulationUpdate::new(ctx);
 
-    if addr > SCRATCH_PAD_MEMORY_SIZE {
+    if addr >= ctx.get_scratch_pad_size() {
         return Err(
             Error::new(ErrorKind::AddrNotAvailable, 
-            format!("Unable to store value into address as it is out of bounds! (address was {}, max is {}!", rhs, SCRATCH_PAD_MEMORY_SIZE))
+            format!("Unable to store value into address as it is out of bounds! (address was {}, max is {}!", addr, ctx.get_scratch_pad_size()))
         );
     }
 
@@ -1,19 +1,51 @@
-use crate::{instructions::*, Instruction};
+use super::instructions::*;
+use super::ports::{PortAccess, PortHandler, PortState, PortTransaction};
+use crate::Instruction;
 
+use std::collections::BTreeSet;
 use std::io::{Error, ErrorKind};
 
 use super::helpers::ShiftMode;
 
 pub(crate) const PROGRAM_MEMORY_SIZE: usize = 1024usize;
+/// The largest program memory a KCPSM6 can address, 4K instructions.
+pub(crate) const MAX_PROGRAM_MEMORY_SIZE: usize = 4096usize;
 pub(crate) const SCRATCH_PAD_MEMORY_SIZE: usize = 64usize;
 
+/// The call stack holds 30 return addresses, shared by CALL and interrupts.
+pub const CALL_STACK_SIZE: usize = 30usize;
+
+/// Every instruction takes two clock cycles to execute.
+pub const CLOCK_CYCLES_PER_INSTRUCTION: u64 = 2;
+
+/// Address the processor calls when an interrupt happens, the last address of a 1K memory.
+pub const DEFAULT_INTERRUPT_VECTOR: usize = 0x3FF;
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum MemoryOperation {
     Fetch(usize, u8),
     Store(usize, u8),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PortOperation {
+    /// Read a port (first) into a register (second).
+    Input(u8, u8),
+    /// Write a value (second) to a port (first).
+    Output(u8, u8),
+    /// Write a constant (second) to a 4-bit port (first).
+    OutputK(u8, u8),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BankOperation {
+    /// Select register bank `a` or `b`.
+    Select(char),
+    /// Write a value (second) to a register (first) of the bank that isn't selected.
+    Star(u8, u8),
+}
+
+#[derive(Debug, PartialEq, Default)]
 pub struct SimulationUpdate {
     pub registers: [u8; 16],
     pub zero: bool,
@@ -21,20 +53,16 @@ pub struct SimulationUpdate {
     pub pc: usize,
     pub call_addr: Option<usize>,
     pub ret_addr: bool,
-    pub memory_op: Option<MemoryOperation>
+    pub memory_op: Option<MemoryOperation>,
+    pub port_op: Option<PortOperation>,
+    pub bank_op: Option<BankOperation>,
+    pub interrupt_enable: Option<bool>,
+    pub ret_interrupt: bool,
 }
 
 impl SimulationUpdate {
     pub fn new(ctx: &SimulationContext) -> SimulationUpdate {
-        SimulationUpdate {
-            registers: ctx.get_registers(),
-            zero: ctx.get_zero_flag(),
-            carry: ctx.get_carry_flag(),
-            pc: ctx.get_program_counter() + 1,
-            call_addr: None,
-            ret_addr: false,
-            memory_op: None
-        }
+        SimulationUpdate::new_with_pc(ctx, ctx.get_program_counter() + 1)
     }
 
     pub fn new_with_pc(ctx: &SimulationContext, pc: usize) -> SimulationUpdate {
@@ -45,48 +73,102 @@ impl SimulationUpdate {
             call_addr: None,
             ret_addr: false,
             memory_op: None,
+            port_op: None,
+            bank_op: None,
+            interrupt_enable: None,
+            ret_interrupt: false,
             pc,
         }
     }
 }
 
-impl Default for SimulationUpdate {
-    fn default() -> Self {
-        Self {
-            registers: [0u8; 16],
-            zero: false,
-            carry: false,
-            pc: 0usize,
-            call_addr: None,
-            ret_addr: false,
-            memory_op: None
-        }
-    }
+/// An instruction that was executed by [`SimulationContext::step`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutedInstruction {
+    /// Clock cycle the instruction started in.
+    pub cycle: u64,
+    pub address: usize,
+    pub instruction: Instruction,
+    pub memory_op: Option<MemoryOperation>,
+    pub port: Option<PortTransaction>,
+}
+
+/// What happened during a single [`SimulationContext::step`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepEvent {
+    Executed(ExecutedInstruction),
+    /// The interrupt input was active, so the processor called the interrupt vector instead of
+    /// executing the instruction at `address`.
+    Interrupt {
+        cycle: u64,
+        address: usize,
+    },
+    /// The program counter points at an address without an instruction.
+    Halted,
+}
+
+/// Why [`SimulationContext::resume`] or [`SimulationContext::run_until`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Halted,
+    Breakpoint(usize),
+    CycleLimit,
+    /// The [`RunMode`] was done.
+    Done,
+}
+
+/// How far [`SimulationContext::run_until`] runs the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    /// This many instructions, counted from the start of each call.
+    Steps(u64),
+    /// Until the call stack is at most this deep.
+    Depth(usize),
+    /// Until the program halts, reaches a breakpoint or the cycle limit.
+    Continue,
+}
+
+/// A frame of the call stack, as [`SimulationContext::get_call_frames`] gives them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    /// The CALL that entered the frame, or the instruction an interrupt took the place of.
+    pub address: usize,
+    pub interrupt: bool,
 }
 
 pub struct SimulationContext {
     //instructions_: Vec<(usize, Instruction)>,
     instructions: Vec<Option<Instruction>>,
     registers: [u8; 16],
-    scratch_memory: [u8; SCRATCH_PAD_MEMORY_SIZE],
+    // Registers of the bank that isn't selected, only reachable through STAR and REGBANK.
+    inactive_registers: [u8; 16],
+    bank: char,
+    scratch_memory: Vec<u8>,
     pc: usize,
     zero: bool,
     carry: bool,
     call_stack: Vec<usize>,
+    // Call stack depths right after entering interrupts, to tell their frames from calls.
+    interrupt_frames: Vec<usize>,
+    interrupt_enabled: bool,
+    // Flags and register bank at the time of the last interrupt, restored by RETURNI.
+    preserved: (bool, bool, char),
+    interrupt_vector: usize,
+    hwbuild: u8,
+    cycles: u64,
+    ports: Box<dyn PortHandler>,
+    breakpoints: BTreeSet<usize>,
+}
+
+impl Default for SimulationContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SimulationContext {
     pub fn new() -> SimulationContext {
-        SimulationContext {
-            //instructions_: Vec::new(),
-            instructions: vec![None; PROGRAM_MEMORY_SIZE],
-            registers: [0u8; 16],
-            scratch_memory: [0u8; SCRATCH_PAD_MEMORY_SIZE],
-            pc: 0,
-            zero: false,
-            carry: false,
-            call_stack: vec![],
-        }
+        SimulationContext::new_with_params([0u8; 16], false, false)
     }
 
     pub fn new_with_params(registers: [u8; 16], zero: bool, carry: bool) -> SimulationContext {
@@ -95,53 +177,80 @@ impl SimulationContext {
             instructions: vec![None; PROGRAM_MEMORY_SIZE],
             pc: 0,
             registers,
-            scratch_memory: [0u8; SCRATCH_PAD_MEMORY_SIZE],
+            inactive_registers: [0u8; 16],
+            bank: 'a',
+            scratch_memory: vec![0u8; SCRATCH_PAD_MEMORY_SIZE],
             zero,
             carry,
             call_stack: vec![],
+            interrupt_frames: vec![],
+            interrupt_enabled: false,
+            preserved: (false, false, 'a'),
+            interrupt_vector: DEFAULT_INTERRUPT_VECTOR,
+            hwbuild: 0,
+            cycles: 0,
+            ports: Box::new(PortState::new()),
+            breakpoints: BTreeSet::new(),
         }
     }
 
-    pub fn new_with_instructions(instructions: Vec<(usize, Instruction)>) -> SimulationContext {
-        let mut instr_list: Vec<Option<Instruction>> = vec![None; PROGRAM_MEMORY_SIZE];
-
-        for (addr, i) in instructions {
-            instr_list[addr] = Some(i);
-        }
+    pub fn new_with_instructions(
+        instructions: Vec<(usize, Instruction)>,
+    ) -> Result<SimulationContext, Error> {
+        let mut ctx = SimulationContext::new();
 
-        SimulationContext {
-            //instructions_: instructions,
-            instructions: instr_list,
-            registers: [0u8; 16],
-            scratch_memory: [0u8; SCRATCH_PAD_MEMORY_SIZE],
-            pc: 0,
-            zero: false,
-            carry: false,
-            call_stack: vec![],
-        }
+        ctx.initialize_instructions(instructions)?;
+        Ok(ctx)
     }
 
+    /// Loads instructions into program memory. Fails if one of them is past the end of the 4K
+    /// program memory, which the Parser reports for instructions that come from source.
     pub fn initialize_instructions(
         &mut self,
         instructions: Vec<(usize, Instruction)>,
-    ) -> &mut SimulationContext {
-        let mut instr_list: Vec<Option<Instruction>> = vec![None; PROGRAM_MEMORY_SIZE];
+    ) -> Result<&mut SimulationContext, Error> {
+        if let Some((addr, _)) = instructions
+            .iter()
+            .find(|(addr, _)| *addr >= MAX_PROGRAM_MEMORY_SIZE)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Instruction at address 0x{:X} is past the end of the 4K program memory!",
+                    addr
+                ),
+            ));
+        }
+
+        // KCPSM6 comes with 1K, 2K or 4K of program memory, so use the smallest one that fits.
+        let size = instructions
+            .iter()
+            .map(|(addr, _)| (addr + 1).next_power_of_two())
+            .fold(PROGRAM_MEMORY_SIZE, usize::max);
+
+        let mut instr_list: Vec<Option<Instruction>> = vec![None; size];
 
         for (addr, i) in instructions {
             instr_list[addr] = Some(i);
         }
 
         self.instructions = instr_list;
-        self
+        Ok(self)
     }
 
     pub fn reset(&mut self) -> &mut SimulationContext {
         self.registers = [0u8; 16];
-        self.scratch_memory = [0u8; SCRATCH_PAD_MEMORY_SIZE];
+        self.inactive_registers = [0u8; 16];
+        self.bank = 'a';
+        self.scratch_memory.fill(0);
         self.zero = false;
         self.carry = false;
         self.pc = 0;
         self.call_stack = vec![];
+        self.interrupt_frames = vec![];
+        self.interrupt_enabled = false;
+        self.preserved = (false, false, 'a');
+        self.cycles = 0;
         self
     }
 
@@ -149,47 +258,233 @@ impl SimulationContext {
         // Ensure that all processor flags and registers are reset.
         self.reset();
 
-        loop {
-            let i = &self.instructions[self.pc];
+        while self.step()? != StepEvent::Halted {}
+
+        Ok(())
+    }
+
+    /// Executes instructions until the program halts, reaches a breakpoint or `cycle_limit` clock
+    /// cycles have passed since reset. At least one instruction is executed, so resuming from a
+    /// breakpoint doesn't stop right away.
+    pub fn resume(&mut self, cycle_limit: Option<u64>) -> Result<StopReason, Error> {
+        let reason = self.run_until(RunMode::Continue, cycle_limit, usize::MAX, |_, _| {})?;
+
+        Ok(reason.unwrap_or(StopReason::CycleLimit))
+    }
+
+    /// Executes at most `max_steps` instructions until `mode` is done, the program halts, reaches
+    /// a breakpoint or `cycle_limit` clock cycles have passed since reset. `observe` sees every
+    /// event right after it happened. Returns `None` if `max_steps` ran out first, so front ends
+    /// can run the program in slices and call again with the same mode.
+    pub fn run_until<F>(
+        &mut self,
+        mode: RunMode,
+        cycle_limit: Option<u64>,
+        max_steps: usize,
+        mut observe: F,
+    ) -> Result<Option<StopReason>, Error>
+    where
+        F: FnMut(&SimulationContext, &StepEvent),
+    {
+        let mut steps = 0u64;
+
+        for _ in 0..max_steps {
+            let event = self.step()?;
+
+            observe(self, &event);
+
+            if event == StepEvent::Halted {
+                return Ok(Some(StopReason::Halted));
+            }
+
+            steps += 1;
+
+            let done = match mode {
+                RunMode::Steps(count) => steps >= count,
+                RunMode::Depth(depth) => self.call_stack.len() <= depth,
+                RunMode::Continue => false,
+            };
+
+            if done {
+                return Ok(Some(StopReason::Done));
+            }
+
+            if cycle_limit.is_some_and(|limit| self.cycles >= limit) {
+                return Ok(Some(StopReason::CycleLimit));
+            }
+
+            if self.breakpoints.contains(&self.pc) {
+                return Ok(Some(StopReason::Breakpoint(self.pc)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The mode that executes the next instruction, running a called routine until it returns.
+    pub fn step_over(&self) -> RunMode {
+        match self.instructions.get(self.pc) {
+            Some(Some(
+                Instruction::Call { .. }
+                | Instruction::CallConditional { .. }
+                | Instruction::CallAt { .. },
+            )) => RunMode::Depth(self.call_stack.len()),
+            _ => RunMode::Steps(1),
+        }
+    }
+
+    /// The mode that runs until the current routine returns, or `None` outside of routines.
+    pub fn step_out(&self) -> Option<RunMode> {
+        self.call_stack.len().checked_sub(1).map(RunMode::Depth)
+    }
+
+    /// Executes a single instruction, or enters the interrupt routine if an interrupt is pending.
+    pub fn step(&mut self) -> Result<StepEvent, Error> {
+        let cycle = self.cycles;
+
+        self.ports.tick(cycle);
 
-            if i.is_none() {
-                eprintln!("The program ended because it reached an invalid address.");
-                break;
+        if self.interrupt_enabled && self.ports.interrupt(cycle) {
+            return self.enter_interrupt();
+        }
+
+        let instruction = match self.instructions.get(self.pc) {
+            Some(Some(instruction)) => instruction.clone(),
+            _ => return Ok(StepEvent::Halted),
+        };
+        let address = self.pc;
+
+        let update = self.execute_instruction(instruction.clone())?;
+
+        self.registers = update.registers;
+        self.zero = update.zero;
+        self.carry = update.carry;
+        self.pc = update.pc;
+
+        // We just returned.
+        if update.ret_addr || update.ret_interrupt {
+            if let Some(ret_addr) = self.call_stack.pop() {
+                self.pc = ret_addr;
             }
 
-            let update = self.execute_instruction(i.clone().unwrap())?;
+            let depth = self.call_stack.len();
+
+            self.interrupt_frames.retain(|frame| *frame <= depth);
+        }
+
+        // We just returned from an interrupt, which restores the state from before it.
+        if update.ret_interrupt {
+            let (zero, carry, bank) = self.preserved;
+
+            self.zero = zero;
+            self.carry = carry;
+            self.select_register_bank(bank);
+        }
 
-            self.registers = update.registers;
-            self.zero = update.zero;
-            self.carry = update.carry;
-            self.pc = update.pc;
+        // We just called to another routine.
+        if let Some(addr) = update.call_addr {
+            self.call_stack.push(addr);
+        }
 
-            // We just returned.
-            if update.ret_addr {
-                if let Some(ret_addr) = self.call_stack.pop() {
-                    self.pc = ret_addr;
+        // We just fetched or stored a value from/in memory.
+        if let Some(mem_op) = update.memory_op {
+            match mem_op {
+                MemoryOperation::Store(addr, value) => {
+                    self.scratch_memory[addr] = value;
+                }
+                MemoryOperation::Fetch(addr, register) => {
+                    self.registers[register as usize] = self.scratch_memory[addr];
                 }
             }
+        }
+
+        // We just read from or wrote to a port.
+        let port = update.port_op.map(|port_op| match port_op {
+            PortOperation::Input(port, register) => {
+                let value = self.ports.input(port, cycle);
 
-            // We just called to another routine.
-            if let Some(addr) = update.call_addr {
-                self.call_stack.push(addr);
+                self.registers[register as usize] = value;
+                PortTransaction {
+                    access: PortAccess::Input,
+                    port,
+                    value,
+                }
+            }
+            PortOperation::Output(port, value) => {
+                self.ports.output(port, value, cycle);
+                PortTransaction {
+                    access: PortAccess::Output,
+                    port,
+                    value,
+                }
+            }
+            PortOperation::OutputK(port, value) => {
+                self.ports.output_k(port, value, cycle);
+                PortTransaction {
+                    access: PortAccess::OutputK,
+                    port,
+                    value,
+                }
             }
+        });
 
-            // We just fetched or stored a value from/in memory.
-            if let Some(mem_op) = update.memory_op {
-                match mem_op {
-                    MemoryOperation::Store(addr, value) => {
-                        self.scratch_memory[addr] = value;
-                    },
-                    MemoryOperation::Fetch(addr, register) => {
-                        self.registers[register as usize] = self.scratch_memory[addr];
-                    }
+        if let Some(bank_op) = update.bank_op {
+            match bank_op {
+                BankOperation::Select(bank) => self.select_register_bank(bank),
+                BankOperation::Star(register, value) => {
+                    self.inactive_registers[register as usize] = value;
                 }
             }
         }
 
-        Ok(())
+        if let Some(enable) = update.interrupt_enable {
+            self.interrupt_enabled = enable;
+        }
+
+        // The program counter wraps around at the end of the program memory.
+        self.pc %= self.instructions.len();
+        self.cycles += CLOCK_CYCLES_PER_INSTRUCTION;
+
+        Ok(StepEvent::Executed(ExecutedInstruction {
+            cycle,
+            address,
+            instruction,
+            memory_op: update.memory_op,
+            port,
+        }))
+    }
+
+    fn enter_interrupt(&mut self) -> Result<StepEvent, Error> {
+        let cycle = self.cycles;
+
+        if self.call_stack.len() >= CALL_STACK_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "INTERRUPT: The call stack is full!",
+            ));
+        }
+
+        // The interrupt behaves like a CALL to the interrupt vector, replacing the instruction
+        // that would have been executed. Flags and the register bank are preserved for RETURNI.
+        self.call_stack.push(self.pc);
+        self.interrupt_frames.push(self.call_stack.len());
+        self.preserved = (self.zero, self.carry, self.bank);
+        self.interrupt_enabled = false;
+        self.ports.interrupt_ack(cycle);
+
+        let address = self.pc;
+
+        self.pc = self.interrupt_vector;
+        self.cycles += CLOCK_CYCLES_PER_INSTRUCTION;
+
+        Ok(StepEvent::Interrupt { cycle, address })
+    }
+
+    fn select_register_bank(&mut self, bank: char) {
+        if bank != self.bank {
+            std::mem::swap(&mut self.registers, &mut self.inactive_registers);
+            self.bank = bank;
+        }
     }
 
     pub fn get_zero_flag(&self) -> bool {
@@ -220,6 +515,30 @@ impl SimulationContext {
         &self.call_stack
     }
 
+    /// The frames of the call stack from the innermost. The call stack holds return addresses:
+    /// the instruction after a CALL, but the interrupted instruction itself for an interrupt.
+    pub fn get_call_frames(&self) -> Vec<CallFrame> {
+        let size = self.get_program_memory_size();
+
+        self.call_stack
+            .iter()
+            .enumerate()
+            .rev()
+            .map(
+                |(index, address)| match self.interrupt_frames.contains(&(index + 1)) {
+                    true => CallFrame {
+                        address: *address,
+                        interrupt: true,
+                    },
+                    false => CallFrame {
+                        address: (address + size - 1) % size,
+                        interrupt: false,
+                    },
+                },
+            )
+            .collect()
+    }
+
     pub fn add_to_call_stack_unrestricted(&mut self, addr: usize) {
         self.call_stack.push(addr);
     }
@@ -229,7 +548,7 @@ impl SimulationContext {
     }
 
     pub fn get_register(&self, index: usize) -> Option<u8> {
-        if index > 16 {
+        if index >= 16 {
             return None;
         }
 
@@ -237,15 +556,41 @@ impl SimulationContext {
     }
 
     pub fn set_register(&mut self, index: usize, value: u8) {
-        if index > 16 {
+        if index >= 16 {
             return;
         }
 
         self.registers[index] = value;
     }
 
+    /// The selected register bank, `a` or `b`.
+    pub fn get_register_bank(&self) -> char {
+        self.bank
+    }
+
+    /// Registers of either bank, no matter which one is selected.
+    pub fn get_bank_registers(&self, bank: char) -> [u8; 16] {
+        if bank == self.bank {
+            self.registers
+        } else {
+            self.inactive_registers
+        }
+    }
+
+    pub fn set_bank_register(&mut self, bank: char, index: usize, value: u8) {
+        if index >= 16 {
+            return;
+        }
+
+        if bank == self.bank {
+            self.registers[index] = value;
+        } else {
+            self.inactive_registers[index] = value;
+        }
+    }
+
     pub fn get_scratch_pad_memory(&self, addr: usize) -> Option<u8> {
-        if addr > SCRATCH_PAD_MEMORY_SIZE {
+        if addr >= self.scratch_memory.len() {
             return None;
         }
 
@@ -253,20 +598,101 @@ impl SimulationContext {
     }
 
     pub fn set_scratch_pad_memory(&mut self, addr: usize, value: u8) {
-        if addr > SCRATCH_PAD_MEMORY_SIZE {
+        if addr >= self.scratch_memory.len() {
             return;
         }
 
         self.scratch_memory[addr] = value;
     }
 
+    pub fn get_scratch_pad_size(&self) -> usize {
+        self.scratch_memory.len()
+    }
+
+    /// Sets the size of the scratch pad memory, given by the `scratch_pad_memory_size` generic of
+    /// the processor: 64, 128 or 256 bytes. Its contents are cleared.
+    pub fn set_scratch_pad_size(&mut self, size: usize) {
+        self.scratch_memory = vec![0u8; size];
+    }
+
+    pub fn get_program_memory_size(&self) -> usize {
+        self.instructions.len()
+    }
+
+    /// Sets the size of the program memory, 1K, 2K or 4K instructions on KCPSM6. Instructions past
+    /// the end are dropped.
+    pub fn set_program_memory_size(&mut self, size: usize) {
+        self.instructions.resize(size, None);
+    }
+
+    pub fn get_instruction(&self, addr: usize) -> Option<&Instruction> {
+        self.instructions.get(addr)?.as_ref()
+    }
+
+    pub fn is_interrupt_enabled(&self) -> bool {
+        self.interrupt_enabled
+    }
+
+    pub fn get_interrupt_vector(&self) -> usize {
+        self.interrupt_vector
+    }
+
+    pub fn set_interrupt_vector(&mut self, addr: usize) {
+        self.interrupt_vector = addr;
+    }
+
+    /// Value returned by HWBUILD, set by the `hwbuild` generic of the processor.
+    pub fn get_hwbuild(&self) -> u8 {
+        self.hwbuild
+    }
+
+    pub fn set_hwbuild(&mut self, hwbuild: u8) {
+        self.hwbuild = hwbuild;
+    }
+
+    /// Clock cycles since the last reset.
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn get_port_handler(&self) -> &dyn PortHandler {
+        self.ports.as_ref()
+    }
+
+    pub fn get_port_handler_mut(&mut self) -> &mut dyn PortHandler {
+        self.ports.as_mut()
+    }
+
+    /// Connects something else to the ports, replacing the default [`PortState`].
+    pub fn set_port_handler(&mut self, ports: Box<dyn PortHandler>) {
+        self.ports = ports;
+    }
+
+    pub fn get_breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
     fn execute_instruction(&self, instruction: Instruction) -> Result<SimulationUpdate, Error> {
         match instruction {
             Instruction::Load { lhs, rhs } => load::register_register(self, lhs, rhs),
             Instruction::LoadConstant { lhs, rhs } => load::register_constant(self, lhs, rhs),
+            Instruction::LoadAndReturn { lhs, rhs } => load::and_return(self, lhs, rhs),
             Instruction::And { lhs, rhs } => and::register_register(self, lhs, rhs),
             Instruction::AndConstant { lhs, rhs } => and::register_constant(self, lhs, rhs),
             Instruction::Call { address } => call::address(self, address, None),
+            Instruction::CallAt { first, second } => call::at(self, first, second),
             Instruction::CallConditional { condition, address } => {
                 call::address(self, address, Some(condition))
             }
@@ -277,13 +703,23 @@ impl SimulationContext {
             }
             Instruction::CompareCarryConstant { lhs, rhs } => {
                 compare_carry::register_constant(self, lhs, rhs)
-            },
-            Instruction::FetchConstant { lhs, rhs } => {
-                fetch::register_constant(self, lhs, rhs)
-            },
+            }
+            Instruction::FetchConstant { lhs, rhs } => fetch::register_constant(self, lhs, rhs),
             Instruction::FetchDeref { lhs, rhs } => fetch::register_deref(self, lhs, rhs),
+            Instruction::HardwareBuild { register } => hwbuild::register(self, register),
+            Instruction::InputConstant { lhs, rhs } => input::register_constant(self, lhs, rhs),
+            Instruction::InputDeref { lhs, rhs } => input::register_deref(self, lhs, rhs),
+            Instruction::Interrupt { state } => interrupt::enable(self, state),
             Instruction::Or { lhs, rhs } => or::register_register(self, lhs, rhs),
             Instruction::OrConstant { lhs, rhs } => or::register_constant(self, lhs, rhs),
+            Instruction::OutputConstant { lhs, rhs } => output::register_constant(self, lhs, rhs),
+            Instruction::OutputDoubleConstant { lhs, rhs } => {
+                output::constant_constant(self, lhs, rhs)
+            }
+            Instruction::OutputDeref { lhs, rhs } => output::register_deref(self, lhs, rhs),
+            Instruction::Regbank { selection } => regbank::select(self, selection),
+            Instruction::Star { lhs, rhs } => regbank::star_register(self, lhs, rhs),
+            Instruction::StarConstant { lhs, rhs } => regbank::star_constant(self, lhs, rhs),
             Instruction::Xor { lhs, rhs } => xor::register_register(self, lhs, rhs),
             Instruction::XorConstant { lhs, rhs } => xor::register_constant(self, lhs, rhs),
             Instruction::Add { lhs, rhs } => add::register_register(self, lhs, rhs),
@@ -293,11 +729,13 @@ impl SimulationContext {
                 add_carry::register_constant(self, lhs, rhs)
             }
             Instruction::Jump { address } => jump::address(self, address, None),
+            Instruction::JumpAt { first, second } => jump::at(self, first, second),
             Instruction::JumpConditional { condition, address } => {
                 jump::address(self, address, Some(condition))
             }
             Instruction::Return => ret::default(self, None),
             Instruction::ReturnCondition { condition } => ret::default(self, Some(condition)),
+            Instruction::ReturnInterrupt { state } => interrupt::ret(self, state),
             Instruction::ShiftLeftZero { register } => {
                 shift_left::register(self, register, ShiftMode::Number(0))
             }
@@ -343,10 +781,238 @@ impl SimulationContext {
             Instruction::RotateLeft { register } => rotate_left::register(self, register),
             Instruction::RotateRight { register } => rotate_right::register(self, register),
 
-            _ => Err(Error::new(
+            Instruction::None => Err(Error::new(
                 ErrorKind::Unsupported,
                 "Unable to run instruction as there's no behavior defined for it.",
             )),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assemble_str;
+
+    fn simulate(source: &str) -> SimulationContext {
+        let program = assemble_str(source);
+
+        assert!(!program.has_errors(), "{:?}", program.get_diagnostics());
+        program.create_simulation()
+    }
+
+    #[test]
+    fn ports_are_read_and_written() {
+        let mut sim = simulate("input s0, 01\nadd s0, 01\noutput s0, 02\noutputk 41, 3\n");
+        let mut ports = PortState::new();
+
+        ports.set_input(1, 0x10);
+        sim.set_port_handler(Box::new(ports));
+
+        let events: Vec<StepEvent> = (0..5).map(|_| sim.step().unwrap()).collect();
+
+        assert_eq!(sim.get_register(0), Some(0x11));
+        assert_eq!(sim.get_cycles(), 8);
+        assert_eq!(events[4], StepEvent::Halted);
+        assert!(matches!(
+            &events[2],
+            StepEvent::Executed(ExecutedInstruction {
+                cycle: 4,
+                address: 2,
+                port: Some(PortTransaction {
+                    access: PortAccess::Output,
+                    port: 2,
+                    value: 0x11
+                }),
+                ..
+            })
+        ));
+        assert!(matches!(
+            &events[3],
+            StepEvent::Executed(ExecutedInstruction {
+                port: Some(PortTransaction {
+                    access: PortAccess::OutputK,
+                    port: 3,
+                    value: 0x41
+                }),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn steps_over_and_out_of_routines() {
+        let mut sim = simulate(
+            "call outer\nload s0, 01\njump 002\n\
+             outer: call inner\nload s1, 01\nreturn\ninner: load s2, 01\nreturn\n",
+        );
+
+        assert_eq!(sim.step_out(), None);
+        assert_eq!(sim.step_over(), RunMode::Depth(0));
+        assert_eq!(
+            sim.run_until(RunMode::Steps(1), None, usize::MAX, |_, _| {})
+                .unwrap(),
+            Some(StopReason::Done)
+        );
+        assert_eq!(sim.get_program_counter(), 3);
+        assert_eq!(sim.step_out(), Some(RunMode::Depth(0)));
+
+        let mut events = 0;
+        let mode = sim.step_over();
+
+        assert_eq!(
+            sim.run_until(mode, None, usize::MAX, |_, _| events += 1)
+                .unwrap(),
+            Some(StopReason::Done)
+        );
+        assert_eq!((sim.get_program_counter(), events), (4, 3));
+        assert_eq!(sim.get_register(2), Some(1));
+
+        let mode = sim.step_out().unwrap();
+
+        assert_eq!(sim.run_until(mode, None, 1, |_, _| {}).unwrap(), None);
+        assert_eq!(
+            sim.run_until(mode, None, 1, |_, _| {}).unwrap(),
+            Some(StopReason::Done)
+        );
+        assert_eq!(sim.get_program_counter(), 1);
+        assert_eq!(sim.step_over(), RunMode::Steps(1));
+
+        sim.add_breakpoint(2);
+
+        assert_eq!(
+            sim.run_until(RunMode::Continue, Some(100), usize::MAX, |_, _| {})
+                .unwrap(),
+            Some(StopReason::Breakpoint(2))
+        );
+        assert_eq!(
+            sim.run_until(RunMode::Continue, Some(100), usize::MAX, |_, _| {})
+                .unwrap(),
+            Some(StopReason::Breakpoint(2))
+        );
+
+        sim.clear_breakpoints();
+
+        assert_eq!(
+            sim.run_until(RunMode::Continue, Some(100), usize::MAX, |_, _| {})
+                .unwrap(),
+            Some(StopReason::CycleLimit)
+        );
+    }
+
+    #[test]
+    fn register_banks_are_separate() {
+        let mut sim = simulate("load s0, 01\nstar s1, s0\nregbank b\nadd s1, 01\nregbank a\n");
+
+        sim.run().unwrap();
+
+        assert_eq!(sim.get_register_bank(), 'a');
+        assert_eq!(sim.get_register(1), Some(0));
+        assert_eq!(sim.get_bank_registers('b')[1], 2);
+    }
+
+    #[test]
+    fn interrupts_preserve_flags_and_bank() {
+        let mut sim = simulate(
+            "enable interrupt\nloop: load s0, 00\njump loop\n\
+             isr: regbank b\nload s1, 01\nreturni disable\naddress 3FF\njump isr\n",
+        );
+        let mut ports = PortState::new();
+
+        ports.raise_interrupt_at(6);
+        sim.set_port_handler(Box::new(ports));
+        sim.add_breakpoint(0x3FF);
+
+        assert_eq!(
+            sim.resume(Some(100)).unwrap(),
+            StopReason::Breakpoint(0x3FF)
+        );
+        assert_eq!(sim.get_call_stack(), &vec![1]);
+        assert!(!sim.is_interrupt_enabled());
+
+        sim.clear_breakpoints();
+
+        assert_eq!(sim.resume(Some(100)).unwrap(), StopReason::CycleLimit);
+        assert_eq!(sim.get_register_bank(), 'a');
+        assert_eq!(sim.get_bank_registers('b')[1], 1);
+        assert!(sim.get_call_stack().is_empty());
+    }
+
+    #[test]
+    fn call_frames_tell_interrupts_from_calls() {
+        let mut sim = simulate(
+            "enable interrupt\ncall routine\nend: jump end\nroutine: load s0, 01\n\
+             loop: jump loop\nisr: returni enable\naddress 3FF\njump isr\n",
+        );
+        let mut ports = PortState::new();
+
+        ports.raise_interrupt_at(10);
+        sim.set_port_handler(Box::new(ports));
+        sim.add_breakpoint(0x3FF);
+
+        assert_eq!(
+            sim.resume(Some(100)).unwrap(),
+            StopReason::Breakpoint(0x3FF)
+        );
+
+        let interrupted = sim.get_call_stack()[1];
+        let call = CallFrame {
+            address: 1,
+            interrupt: false,
+        };
+
+        assert_eq!(
+            sim.get_call_frames(),
+            vec![
+                CallFrame {
+                    address: interrupted,
+                    interrupt: true
+                },
+                call
+            ]
+        );
+
+        sim.step().unwrap();
+        sim.step().unwrap();
+
+        assert_eq!(sim.get_program_counter(), interrupted);
+        assert_eq!(sim.get_call_frames(), vec![call]);
+    }
+
+    #[test]
+    fn instructions_past_program_memory() {
+        let mut sim = SimulationContext::new();
+
+        assert!(sim
+            .initialize_instructions(vec![(0xFFF, Instruction::Return)])
+            .is_ok());
+        assert_eq!(sim.get_program_memory_size(), 4096);
+        assert!(sim
+            .initialize_instructions(vec![(0x1000, Instruction::Return)])
+            .is_err());
+    }
+
+    #[test]
+    fn jump_tables() {
+        let mut sim = simulate(
+            "load s0, table'upper\nload s1, table'lower\nadd s1, 01\ncall@ (s0, s1)\njump end\n\
+             table: load&return s2, 10\nload&return s2, 20\nend: load s3, 01\n",
+        );
+
+        sim.run().unwrap();
+
+        assert_eq!(sim.get_register(2), Some(0x20));
+        assert_eq!(sim.get_register(3), Some(1));
+    }
+
+    #[test]
+    fn runtime_faults() {
+        let mut sim = simulate("return\n");
+
+        assert!(sim.run().is_err());
+
+        let mut sim = simulate("load s0, 40\nstore s0, (s0)\n");
+
+        assert!(sim.run().is_err());
+    }
+}
@@ -1,5 +1,16 @@
+pub mod diagnostics;
+pub mod encoding;
+pub mod helpers;
+pub mod image;
+pub mod include;
+pub mod instructions;
+#[allow(clippy::module_inception)]
 pub mod interpreter;
+pub mod operands;
 pub mod parser;
+pub mod predefined;
+pub mod ports;
+pub mod program;
 pub mod reader;
+pub mod source_map;
 pub mod tokenizer;
-pub mod helpers;
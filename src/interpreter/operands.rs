@@ -0,0 +1,262 @@
+use crate::ConditionType;
+
+/// What an operand is expected to be, depending on the instruction and its position.
+///
+/// Words such as `abc` or `de` can either be identifiers or hexadecimal literals, which the
+/// Tokenizer can't tell apart on its own. The Parser uses the operand kind to decide: `abc` is an
+/// address in `JUMP abc`, but just an identifier in `CONSTANT abc, 10'd`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    /// A register, by its default name or a NAMEREG name.
+    Register,
+    /// A jump, call or return condition (`Z`, `NZ`, `C`, `NC`).
+    Condition,
+    /// An 8-bit constant (`kk`). Port IDs and scratch pad addresses are also 8 bits wide.
+    Constant,
+    /// A 4-bit port ID, only used by OUTPUTK (`p`).
+    Port,
+    /// A 12-bit program memory address (`aaa`).
+    Address,
+}
+
+impl OperandKind {
+    /// Finds the kind of the operand at `index` (counting from zero, operands are separated by
+    /// commas) of an instruction. `operand_count` is needed to tell `JUMP aaa` and `JUMP c, aaa`
+    /// apart.
+    pub fn for_instruction(instruction: &str, index: usize, operand_count: usize) -> OperandKind {
+        match instruction {
+            "jump" | "call" if operand_count > 1 && index == 0 => OperandKind::Condition,
+            "jump" | "call" => OperandKind::Address,
+            "return" => OperandKind::Condition,
+            "outputk" if index == 0 => OperandKind::Constant,
+            "outputk" => OperandKind::Port,
+            _ if index == 0 => OperandKind::Register,
+            _ => OperandKind::Constant,
+        }
+    }
+
+    /// Amount of digits a hexadecimal literal has in this position, if any.
+    fn hex_digits(&self) -> Option<usize> {
+        match self {
+            OperandKind::Constant => Some(2),
+            OperandKind::Port => Some(1),
+            OperandKind::Address => Some(3),
+            _ => None,
+        }
+    }
+
+    pub fn max_value(&self) -> u32 {
+        match self {
+            OperandKind::Constant => 0xFF,
+            OperandKind::Port => 0xF,
+            OperandKind::Address => 0xFFF,
+            _ => 0,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            OperandKind::Register => "register",
+            OperandKind::Condition => "condition",
+            OperandKind::Constant => "8-bit constant",
+            OperandKind::Port => "4-bit port",
+            OperandKind::Address => "12-bit address",
+        }
+    }
+
+    /// Reads a word as a hexadecimal literal, as long as it has exactly the amount of digits
+    /// expected in this position (e.g. `FF` for constants, `3FF` for addresses).
+    pub fn parse_hex_literal(&self, word: &str) -> Option<u32> {
+        let digits = self.hex_digits()?;
+
+        if word.len() != digits || !word.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        u32::from_str_radix(word, 16).ok()
+    }
+
+    /// Reads a word as a binary literal the Tokenizer left alone, as it only knows the 8- and
+    /// 12-bit ones: the 4 digits of an OUTPUTK port (e.g. `1010'b`).
+    pub fn parse_binary_literal(&self, word: &str) -> Option<u32> {
+        let digits = word
+            .strip_suffix("'b")
+            .or_else(|| word.strip_suffix("'B"))?;
+
+        if *self != OperandKind::Port || digits.len() != 4 {
+            return None;
+        }
+
+        u32::from_str_radix(digits, 2).ok()
+    }
+
+    /// Explains what's wrong with a word that looks like a literal but doesn't have the form
+    /// expected in this position, e.g. `1F` where a 3-digit address goes.
+    pub fn describe_literal_error(&self, word: &str) -> Option<String> {
+        if word.len() > 1 && word.starts_with('"') && word.ends_with('"') {
+            return Some(format!(
+                "'{}' isn't a valid character, which is a single ASCII character between double quotes (e.g. \"A\"). Text goes in a STRING directive.",
+                word
+            ));
+        }
+
+        let digits = self.hex_digits()?;
+        let max_value = self.max_value();
+        let describe = self.describe();
+
+        if let Some(binary) = word
+            .strip_suffix("'b")
+            .or_else(|| word.strip_suffix("'B"))
+            .filter(|binary| !binary.is_empty() && binary.chars().all(|c| c.is_digit(2)))
+        {
+            let bits = self.max_value().count_ones() as usize;
+
+            return Some(match u32::from_str_radix(binary, 2) {
+                Ok(value) if value <= max_value => format!(
+                    "'{}' isn't a valid {}, which has {} binary digits. Did you mean {:0bits$b}'b?",
+                    word, describe, bits, value
+                ),
+                _ => format!(
+                    "'{}' is too large for a {} (max is 0x{:X}).",
+                    word, describe, max_value
+                ),
+            });
+        }
+
+        // Identifiers can't start with a digit, so this was meant as a number.
+        if !word.starts_with(|c: char| c.is_ascii_digit())
+            || !word.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return None;
+        }
+
+        let hexadecimal = u32::from_str_radix(word, 16).ok();
+        let decimal = word.parse::<u32>().ok();
+
+        Some(match (hexadecimal, decimal) {
+            (Some(value), _) if value <= max_value => format!(
+                "'{}' isn't a valid {}, which has {} hexadecimal digits. Did you mean {:0digits$X}?",
+                word, describe, digits, value
+            ),
+            (_, Some(value)) if value <= max_value => format!(
+                "'{}' is too large in hexadecimal, {}s go up to 0x{:X}. Did you mean the decimal {}'d?",
+                word, describe, max_value, word
+            ),
+            _ => format!(
+                "'{}' is too large for a {} (max is 0x{:X}).",
+                word, describe, max_value
+            ),
+        })
+    }
+}
+
+pub fn parse_condition(word: &str) -> Option<ConditionType> {
+    match word.to_lowercase().as_str() {
+        "z" => Some(ConditionType::IfZero),
+        "nz" => Some(ConditionType::IfNonZero),
+        "c" => Some(ConditionType::IfCarry),
+        "nc" => Some(ConditionType::IfNonCarry),
+        _ => None,
+    }
+}
+
+/// Reads a value typed outside of a PSM file, e.g. in a debugger or on the command line, the way
+/// PSM files write them: hexadecimal unless it ends with `'d` (decimal) or `'b` (binary), or starts
+/// with `0x`. Unlike operands, it can have any amount of digits.
+pub fn parse_literal(text: &str) -> Option<u32> {
+    if let Some(decimal) = text.strip_suffix("'d") {
+        decimal.parse().ok()
+    } else if let Some(binary) = text.strip_suffix("'b") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        let hex = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix("0X"))
+            .unwrap_or(text);
+
+        u32::from_str_radix(hex, 16).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_literal_depends_on_position() {
+        assert_eq!(OperandKind::Constant.parse_hex_literal("aB"), Some(0xAB));
+        assert_eq!(OperandKind::Constant.parse_hex_literal("abc"), None);
+        assert_eq!(OperandKind::Address.parse_hex_literal("abc"), Some(0xABC));
+        assert_eq!(OperandKind::Address.parse_hex_literal("de"), None);
+        assert_eq!(OperandKind::Port.parse_hex_literal("c"), Some(0xC));
+        assert_eq!(OperandKind::Register.parse_hex_literal("ab"), None);
+        assert_eq!(OperandKind::Address.parse_hex_literal("xyz"), None);
+    }
+
+    #[test]
+    fn typed_literals() {
+        assert_eq!(parse_literal("1F"), Some(0x1F));
+        assert_eq!(parse_literal("0x3ff"), Some(0x3FF));
+        assert_eq!(parse_literal("200'd"), Some(200));
+        assert_eq!(parse_literal("101'b"), Some(5));
+        assert_eq!(parse_literal("12'b"), None);
+        assert_eq!(parse_literal("loop"), None);
+    }
+
+    #[test]
+    fn binary_ports() {
+        assert_eq!(OperandKind::Port.parse_binary_literal("1010'b"), Some(0xA));
+        assert_eq!(OperandKind::Port.parse_binary_literal("10100'b"), None);
+        assert_eq!(OperandKind::Constant.parse_binary_literal("1010'b"), None);
+    }
+
+    #[test]
+    fn literal_errors() {
+        assert_eq!(
+            OperandKind::Address.describe_literal_error("1F").unwrap(),
+            "'1F' isn't a valid 12-bit address, which has 3 hexadecimal digits. Did you mean 01F?"
+        );
+        assert_eq!(
+            OperandKind::Constant.describe_literal_error("123").unwrap(),
+            "'123' is too large in hexadecimal, 8-bit constants go up to 0xFF. Did you mean the decimal 123'd?"
+        );
+        assert_eq!(
+            OperandKind::Constant.describe_literal_error("1010'b").unwrap(),
+            "'1010'b' isn't a valid 8-bit constant, which has 8 binary digits. Did you mean 00001010'b?"
+        );
+        assert!(OperandKind::Constant
+            .describe_literal_error("\"AB\"")
+            .unwrap()
+            .starts_with("'\"AB\"' isn't a valid character"));
+        assert_eq!(OperandKind::Constant.describe_literal_error("abc"), None);
+        assert_eq!(OperandKind::Register.describe_literal_error("1F"), None);
+    }
+
+    #[test]
+    fn operand_kinds() {
+        assert_eq!(
+            OperandKind::for_instruction("jump", 0, 1),
+            OperandKind::Address
+        );
+        assert_eq!(
+            OperandKind::for_instruction("jump", 0, 2),
+            OperandKind::Condition
+        );
+        assert_eq!(
+            OperandKind::for_instruction("call", 1, 2),
+            OperandKind::Address
+        );
+        assert_eq!(
+            OperandKind::for_instruction("outputk", 1, 2),
+            OperandKind::Port
+        );
+        assert_eq!(
+            OperandKind::for_instruction("load", 0, 2),
+            OperandKind::Register
+        );
+        assert_eq!(
+            OperandKind::for_instruction("load", 1, 2),
+            OperandKind::Constant
+        );
+    }
+}
@@ -1,17 +1,45 @@
-use crate::{ConditionType, Token};
+use super::diagnostics::Diagnostic;
+use super::interpreter::MAX_PROGRAM_MEMORY_SIZE;
+use super::operands::{parse_condition, OperandKind};
+use super::predefined;
+use super::source_map::SourceMap;
+use super::tokenizer::is_reserved_word;
+use crate::{ConditionType, NumberType, Token};
 
 #[derive(Debug, Clone)]
-pub struct Label(String, u32);
+pub struct Label(pub String, pub u32);
 
 #[derive(Debug, Clone)]
-pub struct Constant(String, u32);
+pub struct Constant(pub String, pub u32);
 
 #[derive(Debug, Clone)]
-pub struct Alias(String, u8);
+pub struct Alias(pub String, pub u8);
+
+/// The characters of a STRING (whose name ends in `$`) or the values of a TABLE (`#`), which
+/// LOAD&RETURN and OUTPUTK expand into one instruction each.
+#[derive(Debug, Clone)]
+pub struct Table(pub String, pub Vec<u8>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Label,
+    Constant,
+    /// A name given to a register by NAMEREG.
+    RegisterName,
+}
+
+/// A line where a label, constant or register name is defined or used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    /// Source line, starting from 1.
+    pub line: usize,
+}
 
 // @TODO: Use the Register enum type instead of u8 for registers. Update
 // the entire code base accordingly :smiley:.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[rustfmt::skip]
 pub enum Instruction {
     None,
@@ -75,12 +103,26 @@ pub enum Instruction {
     XorConstant { lhs: u8, rhs: u32 },
 }
 
+#[derive(Default)]
 pub struct Parser {
     instructions: Vec<(usize, Instruction)>,
     addresses: Vec<usize>,
     labels: Vec<Label>,
     constants: Vec<Constant>,
+    tables: Vec<Table>,
     aliases: Vec<Alias>,
+    // Name each register currently goes by, following the NAMEREG directives in source order.
+    // `None` means the register still has its default name (`s0` to `sF`).
+    register_names: [Option<String>; 16],
+    // Every NAMEREG that took effect, in source order: its line, the register and the name the
+    // register goes by from there on (`None` once it's renamed back to its default name).
+    renames: Vec<(usize, u8, Option<String>)>,
+    definitions: Vec<Symbol>,
+    references: Vec<Symbol>,
+    source_map: SourceMap,
+    diagnostics: Vec<Diagnostic>,
+    // Source line being parsed, starting from 1.
+    line: usize,
 }
 
 fn convert_tokens_into_string(token_list: &Vec<Token>) -> String {
@@ -95,6 +137,7 @@ fn convert_tokens_into_string(token_list: &Vec<Token>) -> String {
             Token::Condition(_) => 'c',
             Token::Comma => 'C',
             Token::Parentheses => 'p',
+            Token::Table(_) => 's',
             Token::Tilda => 't',
             _ => '.',
         };
@@ -105,205 +148,242 @@ fn convert_tokens_into_string(token_list: &Vec<Token>) -> String {
     res
 }
 
-fn instr_only(token_list: &Vec<Token>) -> Instruction {
+fn instr_only(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr)] => match instr.as_str() {
-            "return" => Instruction::Return,
-            _ => panic!("Unable to parse line!"),
+            "return" => Some(Instruction::Return),
+            _ => None,
         },
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn instr_condition(token_list: &Vec<Token>) -> Instruction {
+fn instr_condition(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Condition(condition)] => {
             let condition = *condition;
             match instr.as_str() {
-                "return" => Instruction::ReturnCondition { condition },
-                _ => panic!("Unable to parse line!"),
+                "return" => Some(Instruction::ReturnCondition { condition }),
+                _ => None,
             }
         }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn instr_reg_reg(token_list: &Vec<Token>) -> Instruction {
+fn instr_reg_reg(token_list: &Vec<Token>) -> Option<Instruction> {
     let match_instruction = |instr: &str, lhs: u8, rhs: u8| match instr {
-        "add" => Instruction::Add { lhs, rhs },
-        "addcy" => Instruction::AddCarry { lhs, rhs },
-        "and" => Instruction::And { lhs, rhs },
-        "compare" => Instruction::Compare { lhs, rhs },
-        "comparecy" => Instruction::CompareCarry { lhs, rhs },
-        "load" => Instruction::Load { lhs, rhs },
-        "or" => Instruction::Or { lhs, rhs },
-        "star" => Instruction::Star { lhs, rhs },
-        "sub" => Instruction::Subtract { lhs, rhs },
-        "subcy" => Instruction::SubtractCarry { lhs, rhs },
-        "test" => Instruction::Test { lhs, rhs },
-        "testcy" => Instruction::TestCarry { lhs, rhs },
-        "xor" => Instruction::Xor { lhs, rhs },
-        _ => panic!("Unable to parse line!"),
+        "add" => Some(Instruction::Add { lhs, rhs }),
+        "addcy" => Some(Instruction::AddCarry { lhs, rhs }),
+        "and" => Some(Instruction::And { lhs, rhs }),
+        "compare" => Some(Instruction::Compare { lhs, rhs }),
+        "comparecy" => Some(Instruction::CompareCarry { lhs, rhs }),
+        "load" => Some(Instruction::Load { lhs, rhs }),
+        "or" => Some(Instruction::Or { lhs, rhs }),
+        "star" => Some(Instruction::Star { lhs, rhs }),
+        "sub" => Some(Instruction::Subtract { lhs, rhs }),
+        "subcy" => Some(Instruction::SubtractCarry { lhs, rhs }),
+        "test" => Some(Instruction::Test { lhs, rhs }),
+        "testcy" => Some(Instruction::TestCarry { lhs, rhs }),
+        "xor" => Some(Instruction::Xor { lhs, rhs }),
+        _ => None,
     };
 
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Register(lhs), _, Token::Register(rhs)] => {
             match_instruction(instr.as_str(), *lhs, *rhs)
         }
-        [Token::Instruction(instr), Token::Register(lhs), _, Token::Tilda, Token::Register(rhs)] => {
-            match_instruction(instr.as_str(), *lhs, *rhs)
-        }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn instr_reg_num(token_list: &Vec<Token>) -> Instruction {
+fn instr_reg_num(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Register(lhs), _, Token::Number(rhs, _)] => {
             let lhs = *lhs;
             let rhs = *rhs;
             match instr.as_str() {
-                "add" => Instruction::AddConstant { lhs, rhs },
-                "addcy" => Instruction::AddCarryConstant { lhs, rhs },
-                "and" => Instruction::AndConstant { lhs, rhs },
-                "compare" => Instruction::CompareConstant { lhs, rhs },
-                "comparecy" => Instruction::CompareCarryConstant { lhs, rhs },
-                "fetch" => Instruction::FetchConstant { lhs, rhs },
-                "input" => Instruction::InputConstant { lhs, rhs },
-                "load" => Instruction::LoadConstant { lhs, rhs },
-                "load&return" => Instruction::LoadAndReturn { lhs, rhs },
-                "or" => Instruction::OrConstant { lhs, rhs },
-                "output" => Instruction::OutputConstant { lhs, rhs },
-                "store" => Instruction::StoreConstant { lhs, rhs },
-                "star" => Instruction::StarConstant { lhs, rhs },
-                "sub" => Instruction::SubtractConstant { lhs, rhs },
-                "subcy" => Instruction::SubtractCarryConstant { lhs, rhs },
-                "test" => Instruction::TestConstant { lhs, rhs },
-                "testcy" => Instruction::TestCarryConstant { lhs, rhs },
-                "xor" => Instruction::XorConstant { lhs, rhs },
-                _ => panic!("Unable to parse line!"),
-            }
-        }
-        _ => panic!("Unable to parse line!"),
+                "add" => Some(Instruction::AddConstant { lhs, rhs }),
+                "addcy" => Some(Instruction::AddCarryConstant { lhs, rhs }),
+                "and" => Some(Instruction::AndConstant { lhs, rhs }),
+                "compare" => Some(Instruction::CompareConstant { lhs, rhs }),
+                "comparecy" => Some(Instruction::CompareCarryConstant { lhs, rhs }),
+                "fetch" => Some(Instruction::FetchConstant { lhs, rhs }),
+                "input" => Some(Instruction::InputConstant { lhs, rhs }),
+                "load" => Some(Instruction::LoadConstant { lhs, rhs }),
+                "load&return" => Some(Instruction::LoadAndReturn { lhs, rhs }),
+                "or" => Some(Instruction::OrConstant { lhs, rhs }),
+                "output" => Some(Instruction::OutputConstant { lhs, rhs }),
+                "store" => Some(Instruction::StoreConstant { lhs, rhs }),
+                "star" => Some(Instruction::StarConstant { lhs, rhs }),
+                "sub" => Some(Instruction::SubtractConstant { lhs, rhs }),
+                "subcy" => Some(Instruction::SubtractCarryConstant { lhs, rhs }),
+                "test" => Some(Instruction::TestConstant { lhs, rhs }),
+                "testcy" => Some(Instruction::TestCarryConstant { lhs, rhs }),
+                "xor" => Some(Instruction::XorConstant { lhs, rhs }),
+                _ => None,
+            }
+        }
+        _ => None,
     }
 }
 
-fn instr_reg(token_list: &Vec<Token>) -> Instruction {
+fn instr_reg(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Register(register)] => {
             let register = *register;
 
             match instr.as_str() {
-                "sl0" => Instruction::ShiftLeftZero { register },
-                "sl1" => Instruction::ShiftLeftOne { register },
-                "sla" => Instruction::ShiftLeftCarry { register },
-                "slx" => Instruction::ShiftLeftArth { register },
-                "sr0" => Instruction::ShiftRightZero { register },
-                "sr1" => Instruction::ShiftRightOne { register },
-                "sra" => Instruction::ShiftRightCarry { register },
-                "srx" => Instruction::ShiftRightArth { register },
-                "rl" => Instruction::RotateLeft { register },
-                "rr" => Instruction::RotateRight { register },
-                "hwbuild" => Instruction::HardwareBuild { register },
-                _ => panic!("Unable to parse line!"),
-            }
-        }
-        _ => panic!("Unable to parse line!"),
+                "sl0" => Some(Instruction::ShiftLeftZero { register }),
+                "sl1" => Some(Instruction::ShiftLeftOne { register }),
+                "sla" => Some(Instruction::ShiftLeftCarry { register }),
+                "slx" => Some(Instruction::ShiftLeftArth { register }),
+                "sr0" => Some(Instruction::ShiftRightZero { register }),
+                "sr1" => Some(Instruction::ShiftRightOne { register }),
+                "sra" => Some(Instruction::ShiftRightCarry { register }),
+                "srx" => Some(Instruction::ShiftRightArth { register }),
+                "rl" => Some(Instruction::RotateLeft { register }),
+                "rr" => Some(Instruction::RotateRight { register }),
+                "hwbuild" => Some(Instruction::HardwareBuild { register }),
+                _ => None,
+            }
+        }
+        _ => None,
     }
 }
 
-fn instr_reg_deref(token_list: &Vec<Token>) -> Instruction {
+fn instr_reg_deref(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Register(lhs), _, _, Token::Register(rhs), _] => {
             let lhs = *lhs;
             let rhs = *rhs;
 
             match instr.as_str() {
-                "input" => Instruction::InputDeref { lhs, rhs },
-                "output" => Instruction::OutputDeref { lhs, rhs },
-                "fetch" => Instruction::FetchDeref { lhs, rhs },
-                "store" => Instruction::StoreDeref { lhs, rhs },
-                _ => panic!("Unable to parse line!"),
+                "input" => Some(Instruction::InputDeref { lhs, rhs }),
+                "output" => Some(Instruction::OutputDeref { lhs, rhs }),
+                "fetch" => Some(Instruction::FetchDeref { lhs, rhs }),
+                "store" => Some(Instruction::StoreDeref { lhs, rhs }),
+                _ => None,
             }
         }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn instr_num_num(token_list: &Vec<Token>) -> Instruction {
+fn instr_num_num(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Number(lhs, _), _, Token::Number(rhs, _)] => {
             let lhs = *lhs;
             let rhs = *rhs;
 
             match instr.as_str() {
-                "outputk" => Instruction::OutputDoubleConstant { lhs, rhs },
-                _ => panic!("Unable to parse line!"),
+                "outputk" => Some(Instruction::OutputDoubleConstant { lhs, rhs }),
+                _ => None,
             }
         }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
+    }
+}
+
+/// `LOAD&RETURN sX, name$`, one instruction per character or value.
+fn instr_reg_table(token_list: &Vec<Token>) -> Option<Vec<Instruction>> {
+    match token_list.as_slice() {
+        [Token::Instruction(instr), Token::Register(lhs), _, Token::Table(values)]
+            if instr == "load&return" =>
+        {
+            Some(
+                values
+                    .iter()
+                    .map(|value| Instruction::LoadAndReturn {
+                        lhs: *lhs,
+                        rhs: *value as u32,
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// `OUTPUTK name$, p`, one instruction per character or value.
+fn instr_table_num(token_list: &Vec<Token>) -> Option<Vec<Instruction>> {
+    match token_list.as_slice() {
+        [Token::Instruction(instr), Token::Table(values), _, Token::Number(port, _)]
+            if instr == "outputk" =>
+        {
+            Some(
+                values
+                    .iter()
+                    .map(|value| Instruction::OutputDoubleConstant {
+                        lhs: *value as u32,
+                        rhs: *port,
+                    })
+                    .collect(),
+            )
+        }
+        _ => None,
     }
 }
 
-fn instr_double_deref(token_list: &Vec<Token>) -> Instruction {
+fn instr_double_deref(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), _, Token::Register(first), _, Token::Register(second), _] => {
             let first = *first;
             let second = *second;
 
             match instr.as_str() {
-                "jump@" => Instruction::JumpAt { first, second },
-                "call@" => Instruction::CallAt { first, second },
-                _ => panic!("Unable to parse line!"),
+                "jump@" => Some(Instruction::JumpAt { first, second }),
+                "call@" => Some(Instruction::CallAt { first, second }),
+                _ => None,
             }
         }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn instr_addr(token_list: &Vec<Token>) -> Instruction {
+fn instr_addr(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Address(address)] => {
             let address = *address;
 
             match instr.as_str() {
-                "jump" => Instruction::Jump { address },
-                "call" => Instruction::Call { address },
-                _ => panic!("Unable to parse line!"),
+                "jump" => Some(Instruction::Jump { address }),
+                "call" => Some(Instruction::Call { address }),
+                _ => None,
             }
         }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn instr_condition_addr(token_list: &Vec<Token>) -> Instruction {
+fn instr_condition_addr(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Instruction(instr), Token::Condition(condition), _, Token::Address(address)] => {
             let condition = *condition;
             let address = *address;
 
             match instr.as_str() {
-                "jump" => Instruction::JumpConditional { condition, address },
-                "call" => Instruction::CallConditional { condition, address },
-                _ => panic!("Unable to parse line!"),
+                "jump" => Some(Instruction::JumpConditional { condition, address }),
+                "call" => Some(Instruction::CallConditional { condition, address }),
+                _ => None,
             }
         }
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
-fn word_word(token_list: &Vec<Token>) -> Instruction {
+fn word_word(token_list: &Vec<Token>) -> Option<Instruction> {
     match token_list.as_slice() {
         [Token::Word(w1), Token::Word(w2)] => match w1.to_lowercase().as_str() {
             "regbank" => {
                 let w2 = w2.to_lowercase();
 
                 if w2 == "a" {
-                    return Instruction::Regbank { selection: 'a' };
+                    Some(Instruction::Regbank { selection: 'a' })
                 } else if w2 == "b" {
-                    return Instruction::Regbank { selection: 'b' };
+                    Some(Instruction::Regbank { selection: 'b' })
                 } else {
-                    panic!("Unable to parse line!")
+                    None
                 }
             }
 
@@ -311,11 +391,11 @@ fn word_word(token_list: &Vec<Token>) -> Instruction {
                 let w2 = w2.to_lowercase();
 
                 if w2 == "disable" {
-                    return Instruction::ReturnInterrupt { state: false };
+                    Some(Instruction::ReturnInterrupt { state: false })
                 } else if w2 == "enable" {
-                    return Instruction::ReturnInterrupt { state: true };
+                    Some(Instruction::ReturnInterrupt { state: true })
                 } else {
-                    panic!("Unable to parse line!")
+                    None
                 }
             }
 
@@ -325,19 +405,19 @@ fn word_word(token_list: &Vec<Token>) -> Instruction {
 
                 if w2 == "interrupt" {
                     if w1 == "enable" {
-                        return Instruction::Interrupt { state: true };
+                        Some(Instruction::Interrupt { state: true })
                     } else if w1 == "disable" {
-                        return Instruction::Interrupt { state: false };
+                        Some(Instruction::Interrupt { state: false })
                     } else {
-                        panic!("Unable to parse line!");
+                        None
                     }
                 } else {
-                    panic!("Unable to parse line!");
+                    None
                 }
             }
-            _ => panic!("Unable to parse line!"),
+            _ => None,
         },
-        _ => panic!("Unable to parse line!"),
+        _ => None,
     }
 }
 
@@ -348,61 +428,80 @@ impl Parser {
             addresses: Vec::new(),
             labels: Vec::new(),
             constants: Vec::new(),
+            tables: Vec::new(),
             aliases: Vec::new(),
+            register_names: Default::default(),
+            renames: Vec::new(),
+            definitions: Vec::new(),
+            references: Vec::new(),
+            source_map: SourceMap::new(),
+            diagnostics: Vec::new(),
+            line: 0,
         }
     }
 
     pub fn parse(&mut self, tokens: Vec<Token>) -> &mut Parser {
-        // Split the tokens by line.
+        // Split the tokens by line. The Reader keeps one entry per source line (even empty
+        // ones), so the index of each line here matches its line number in the source.
         let tokens_per_line: Vec<Vec<Token>> = tokens
-            .split(|token| match token {
-                Token::EndOfLine => true,
-                _ => false,
-            })
+            .split(|token| matches!(token, Token::EndOfLine))
             .map(|list| list.to_vec())
             .collect();
 
+        // STRING and TABLE directives are read first, as the instructions using them take as many
+        // addresses as there are characters or values, even when they're defined further down.
+        for (index, line) in tokens_per_line.iter().enumerate() {
+            self.line = index + 1;
+
+            if line
+                .iter()
+                .any(|token| matches!(token, Token::StringDirective | Token::TableDirective))
+            {
+                self.add_table(line);
+            }
+        }
+
         let mut instruction_address = 0;
 
         // TODO: We could build a possibly smaller token matrix here already ignoring the
         // directives. This could save some time when parsing for instructions.
         //
         // Run through the tokens once to find assembler directive.
-        for line in tokens_per_line.clone() {
-            if self.addresses.contains(&instruction_address) {
-                panic!(
-                    "Attempted to add instruction at address that's already occupied ({}).",
-                    instruction_address
-                );
-            }
+        for (index, line) in tokens_per_line.iter().enumerate() {
+            self.line = index + 1;
 
-            let (should_increment, new_address) = self.parse_directives(&line, instruction_address);
+            // Lines with only directives don't take any address, since they don't technically
+            // make part of the code.
+            let (size, new_address) = self.parse_directives(line, instruction_address);
 
-            // Check if we should increment the current address. This makes sure that
-            // lines with only directives aren't incrementing the address since they don't
-            // technically make part of the code.
-            if should_increment {
-                instruction_address = new_address + 1;
-            } else {
-                instruction_address = new_address;
-            }
+            instruction_address = new_address + size;
         }
 
         instruction_address = 0;
 
         // Then parse the tokens for instructions.
-        for line in tokens_per_line {
-            let (new_address, instr) = self.parse_line(&line, instruction_address);
+        for (index, line) in tokens_per_line.iter().enumerate() {
+            self.line = index + 1;
 
-            match instr {
-                Instruction::None => instruction_address = new_address,
-                _ => {
-                    self.instructions.push((new_address, instr));
-                    instruction_address = new_address + 1;
+            let (new_address, instructions) = self.parse_line(line, instruction_address);
+
+            instruction_address = new_address;
+
+            for instruction in instructions {
+                // Already reported in the first pass.
+                if instruction_address >= MAX_PROGRAM_MEMORY_SIZE {
+                    break;
                 }
+
+                self.instructions.push((instruction_address, instruction));
+                self.source_map.insert(instruction_address, self.line);
+                instruction_address += 1;
             }
         }
 
+        // Labels and constants are defined in the first pass, register names in the second.
+        self.definitions.sort_by_key(|symbol| symbol.line);
+
         // Make sure that our instructions are sorted so we execute them in order.
         self.instructions.sort_by(|a, b| {
             let (addr_a, _) = a;
@@ -414,16 +513,70 @@ impl Parser {
         self
     }
 
+    fn error(&mut self, message: String) {
+        self.add_diagnostic(Diagnostic::error(self.line, message));
+    }
+
+    fn warning(&mut self, message: String) {
+        self.add_diagnostic(Diagnostic::warning(self.line, message));
+    }
+
+    fn add_diagnostic(&mut self, diagnostic: Diagnostic) {
+        // Some directives (e.g. ADDRESS) are looked at in both passes, make sure their problems
+        // are only reported once.
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
     fn parse_line(
         &mut self,
         token_list: &Vec<Token>,
         instruction_address: usize,
-    ) -> (usize, Instruction) {
+    ) -> (usize, Vec<Instruction>) {
         let (updated_addr, token_list) =
             self.ignore_directives_and_update_tokens(token_list, instruction_address);
 
         if token_list.is_empty() {
-            return (updated_addr, Instruction::None);
+            return (updated_addr, Vec::new());
+        }
+
+        // Any word left in an instruction couldn't be resolved into a register, label, constant
+        // or literal.
+        if let Some(Token::Instruction(_)) = token_list.first() {
+            let unknown_words: Vec<&String> = token_list
+                .iter()
+                .filter_map(|token| match token {
+                    Token::Word(word) => Some(word),
+                    _ => None,
+                })
+                .collect();
+
+            if !unknown_words.is_empty() {
+                for word in unknown_words {
+                    let message = self.unknown_symbol_message(word);
+                    self.error(message);
+                }
+
+                return (updated_addr, Vec::new());
+            }
+        }
+
+        // Mnemonics are read as such wherever they're written, so one in the operands is most
+        // likely meant as the name of a label, constant or register.
+        if let [Token::Instruction(_), operands @ ..] = token_list.as_slice() {
+            let reserved = operands.iter().find_map(|token| match token {
+                Token::Instruction(word) => Some(word.clone()),
+                _ => None,
+            });
+
+            if let Some(word) = reserved {
+                self.error(format!(
+                    "'{}' is a mnemonic or a reserved word, so it can't be used as an operand.",
+                    word
+                ));
+                return (updated_addr, Vec::new());
+            }
         }
 
         let syntax_pattern = convert_tokens_into_string(&token_list);
@@ -431,102 +584,484 @@ impl Parser {
         // I'm so not proud of this, but we ball.
         // Picoblaze assembly is very simple, so we don't need a super
         // sofisticated parser and this will suffice.
-        //
-        // TODO: Implement support for call@ (sx, sy) and jump@ (sx, sy)
-        match syntax_pattern.as_str() {
-            "i" => (updated_addr, instr_only(&token_list)),
-            "ic" => (updated_addr, instr_condition(&token_list)),
-            "ir" => (updated_addr, instr_reg(&token_list)),
-            "irCr" => (updated_addr, instr_reg_reg(&token_list)),
-            "irCn" => (updated_addr, instr_reg_num(&token_list)),
-            "irCprp" => (updated_addr, instr_reg_deref(&token_list)), // Update
-            "inCn" => (updated_addr, instr_num_num(&token_list)),
-            "ia" => (updated_addr, instr_addr(&token_list)),
-            "icCa" => (updated_addr, instr_condition_addr(&token_list)),
-            "iprCrp" => (updated_addr, instr_double_deref(&token_list)), // Update
-            "ww" => (updated_addr, word_word(&token_list)),
-            _ => {
-                eprintln!(
-                    "Failed to parse line {} (pattern {})",
-                    updated_addr, syntax_pattern
-                );
+        let instructions = match syntax_pattern.as_str() {
+            "irCs" => instr_reg_table(&token_list),
+            "isCn" => instr_table_num(&token_list),
+            _ => self
+                .parse_single(&syntax_pattern, &token_list)
+                .map(|i| vec![i]),
+        };
+
+        match instructions {
+            Some(instructions) => (updated_addr, instructions),
+            None => {
+                match token_list.first() {
+                    Some(Token::Instruction(instr)) => self.error(format!(
+                        "Invalid operands for '{}' (syntax pattern '{}').",
+                        instr.to_uppercase(),
+                        syntax_pattern
+                    )),
+                    _ => self.error(format!(
+                        "Unable to parse line (syntax pattern '{}').",
+                        syntax_pattern
+                    )),
+                }
 
-                (updated_addr, Instruction::None)
+                (updated_addr, Vec::new())
             }
         }
     }
 
+    fn parse_single(&self, syntax_pattern: &str, token_list: &Vec<Token>) -> Option<Instruction> {
+        match syntax_pattern {
+            "i" => instr_only(token_list),
+            "ic" => instr_condition(token_list),
+            "ir" => instr_reg(token_list),
+            "irCr" => instr_reg_reg(token_list),
+            "irCn" => instr_reg_num(token_list),
+            "irCprp" => instr_reg_deref(token_list),
+            "inCn" => instr_num_num(token_list),
+            "ia" => instr_addr(token_list),
+            "icCa" => instr_condition_addr(token_list),
+            "iprCrp" => instr_double_deref(token_list),
+            "ww" => word_word(token_list),
+            _ => None,
+        }
+    }
+
     fn add_label(&mut self, token: &Token, instruction_address: usize) {
         if let Token::Label(label) = token {
-            if self
-                .labels
-                .iter()
-                .find(|l| {
-                    let Label(name, _) = l;
-                    label == name
-                })
-                .is_some()
-            {
-                panic!(
-                    "There is already a label called '{}' (line {})!",
-                    label, instruction_address
-                );
+            if self.find_label(label).is_some() {
+                self.error(format!("There is already a label called '{}'.", label));
+                return;
+            }
+
+            if is_reserved_word(label) {
+                self.error(format!(
+                    "'{}' is a mnemonic or a reserved word, so it can't be used as a label.",
+                    label
+                ));
+                return;
             }
 
+            self.warn_about_case_only_match(label);
             self.labels
                 .push(Label(label.clone(), instruction_address as u32));
+            self.define(SymbolKind::Label, label);
         }
     }
 
     fn add_constant(&mut self, tokens: &Vec<Token>) {
         match tokens.as_slice() {
-            [Token::ConstantDirective, Token::Word(constant_name), _, Token::Number(value, _)] => {
-                self.constants.push(Constant(constant_name.clone(), *value));
+            [Token::ConstantDirective, Token::Word(constant_name), Token::Comma, value] => {
+                if self.find_constant(constant_name).is_some() {
+                    self.error(format!(
+                        "There is already a constant called '{}'.",
+                        constant_name
+                    ));
+                    return;
+                }
+
+                self.warn_about_case_only_match(constant_name);
+
+                match self.resolve_operand(value, OperandKind::Constant) {
+                    Token::Number(value, _) => {
+                        self.constants.push(Constant(constant_name.clone(), value));
+                        self.define(SymbolKind::Constant, constant_name);
+                    }
+                    Token::Word(word) => {
+                        let message = self.unknown_symbol_message(&word);
+                        self.error(message);
+                    }
+                    _ => self.error(format!(
+                        "Unable to parse the value of constant '{}'.",
+                        constant_name
+                    )),
+                }
             }
+            _ => self.error("Unable to parse CONSTANT directive.".to_string()),
+        }
+    }
 
-            [Token::ConstantDirective, Token::Word(constant_name), _, Token::Word(word)] => {
-                if let Some(Constant(_, value)) = self.find_constant(word) {
-                    self.constants.push(Constant(constant_name.clone(), value));
-                } else {
-                    panic!("Unable to parse constant.");
+    fn add_table(&mut self, tokens: &Vec<Token>) {
+        let (name, values) = match tokens.as_slice() {
+            [Token::StringDirective, Token::Word(name), Token::Comma, text] => {
+                (name, self.parse_string_text(name, text))
+            }
+            [Token::TableDirective, Token::Word(name), Token::Comma, values @ ..] => {
+                (name, self.parse_table_values(name, values))
+            }
+            [Token::StringDirective, ..] => {
+                self.error("Unable to parse STRING directive.".to_string());
+                return;
+            }
+            _ => {
+                self.error("Unable to parse TABLE directive.".to_string());
+                return;
+            }
+        };
+
+        let Some(values) = values else {
+            return;
+        };
+
+        if self.find_table(name).is_some() {
+            self.error(format!(
+                "There is already a string or table called '{}'.",
+                name
+            ));
+            return;
+        }
+
+        self.warn_about_case_only_match(name);
+        self.tables.push(Table(name.clone(), values));
+        self.define(SymbolKind::Constant, name);
+    }
+
+    /// Characters of a STRING, written between double quotes (`"Hello"`).
+    fn parse_string_text(&mut self, name: &String, text: &Token) -> Option<Vec<u8>> {
+        if !name.ends_with('$') {
+            self.error(format!(
+                "The name of a STRING must end with '$' ('{}').",
+                name
+            ));
+            return None;
+        }
+
+        let text = match text {
+            Token::Word(text)
+                if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') =>
+            {
+                text[1..text.len() - 1].to_string()
+            }
+            // A single character reads as a character literal.
+            Token::Number(value, NumberType::Character) => char::from(*value as u8).to_string(),
+            _ => {
+                self.error(format!(
+                    "The text of STRING '{}' must be between double quotes.",
+                    name
+                ));
+                return None;
+            }
+        };
+
+        if text.is_empty() || !text.bytes().all(|c| (32..=126).contains(&c)) {
+            self.error(format!(
+                "The text of STRING '{}' must have at least one character, and only printable ASCII characters.",
+                name
+            ));
+            return None;
+        }
+
+        Some(text.into_bytes())
+    }
+
+    /// Values of a TABLE, written as a list in brackets: `[3F,06]` in hexadecimal, or with a `'d`
+    /// or `'b` suffix for decimal or binary values (`[10,20]'d`).
+    fn parse_table_values(&mut self, name: &String, tokens: &[Token]) -> Option<Vec<u8>> {
+        if !name.ends_with('#') {
+            self.error(format!(
+                "The name of a TABLE must end with '#' ('{}').",
+                name
+            ));
+            return None;
+        }
+
+        let mut text = String::new();
+
+        for token in tokens {
+            match token {
+                Token::Word(word) => text.push_str(word),
+                Token::Comma => text.push(','),
+                _ => {
+                    self.error(format!("Unable to parse the values of TABLE '{}'.", name));
+                    return None;
                 }
             }
-            _ => unreachable!("Constant"),
         }
+
+        let (list, radix) = match text.rsplit_once('\'') {
+            Some((list, "d")) | Some((list, "D")) => (list, 10),
+            Some((list, "b")) | Some((list, "B")) => (list, 2),
+            Some(_) => ("", 16),
+            None => (text.as_str(), 16),
+        };
+
+        let Some(list) = list
+            .strip_prefix('[')
+            .and_then(|list| list.strip_suffix(']'))
+        else {
+            self.error(format!(
+                "The values of TABLE '{}' must be a list in brackets, e.g. [3F,06] or [10,20]'d.",
+                name
+            ));
+            return None;
+        };
+
+        let mut values = Vec::new();
+
+        for value in list.split(',') {
+            match u32::from_str_radix(value.trim(), radix) {
+                Ok(value) if value <= 0xFF => values.push(value as u8),
+                _ => {
+                    self.error(format!(
+                        "'{}' in TABLE '{}' is not a valid 8-bit value.",
+                        value, name
+                    ));
+                    return None;
+                }
+            }
+        }
+
+        Some(values)
     }
 
     fn add_alias(&mut self, tokens: &Vec<Token>) {
-        // TODO: It turns out namereg directives are not creating aliases, but instead RENAMING a
-        // register. For example, if you do `namereg s1, first`, then `s1` is not longer "in the
-        // scope". Right now we're hoping that the user won't try to access a register by its
-        // original name after the namereg.
+        // NAMEREG directives RENAME a register instead of creating an alias: after
+        // `namereg s1, first`, the name `s1` is no longer in scope until another NAMEREG gives
+        // it back (`namereg first, s1`). This is called while parsing instructions, so every
+        // line sees the names that are in effect at that point of the source.
         match tokens.as_slice() {
-            [Token::NameregDirective, Token::Register(register), _, Token::Word(alias_name)] => {
-                self.aliases.push(Alias(alias_name.clone(), *register));
+            [Token::NameregDirective, Token::Register(register), _, Token::Word(new_name)] => {
+                self.check_default_register_name(*register);
+                self.rename_register(*register, new_name);
             }
 
-            [Token::NameregDirective, Token::Word(other_alias), _, Token::Word(alias_name)] => {
-                if let Some(Alias(_, register)) = self.find_alias(other_alias) {
-                    self.aliases.push(Alias(alias_name.clone(), register));
-                } else {
-                    panic!("Unable to parse alias.");
+            [Token::NameregDirective, Token::Word(current_name), _, Token::Word(new_name)] => {
+                if let Some(register) = self.find_register(current_name) {
+                    self.reference(SymbolKind::RegisterName, current_name);
+                    self.rename_register(register, new_name);
+                }
+            }
+
+            [Token::NameregDirective, Token::Word(current_name), _, Token::Register(register)] => {
+                let Some(current_register) = self.find_register(current_name) else {
+                    return;
+                };
+
+                self.reference(SymbolKind::RegisterName, current_name);
+
+                if current_register != *register {
+                    self.error(format!(
+                        "Unable to rename '{}' to 's{:X}', it can only be renamed back to 's{:X}'.",
+                        current_name, register, current_register
+                    ));
+                    return;
                 }
+
+                self.register_names[current_register as usize] = None;
+                self.renames.push((self.line, current_register, None));
             }
-            _ => unreachable!("Alias"),
+            _ => self.error("Unable to parse NAMEREG directive.".to_string()),
         }
     }
 
-    fn update_address(&mut self, tokens: &Vec<Token>) -> usize {
+    fn rename_register(&mut self, register: u8, new_name: &String) {
+        if let Some(other) = self.find_register_name(new_name) {
+            self.error(format!(
+                "Unable to rename register 's{:X}' to '{}', as that is already the name of 's{:X}'.",
+                register, new_name, other
+            ));
+            return;
+        }
+
+        self.warn_about_case_only_match(new_name);
+        self.register_names[register as usize] = Some(new_name.clone());
+        self.aliases.push(Alias(new_name.clone(), register));
+        self.renames
+            .push((self.line, register, Some(new_name.clone())));
+        self.define(SymbolKind::RegisterName, new_name);
+    }
+
+    fn define(&mut self, kind: SymbolKind, name: &str) {
+        self.definitions.push(Symbol {
+            kind,
+            name: name.to_string(),
+            line: self.line,
+        });
+    }
+
+    fn reference(&mut self, kind: SymbolKind, name: &str) {
+        let symbol = Symbol {
+            kind,
+            name: name.to_string(),
+            line: self.line,
+        };
+
+        // Like diagnostics, directives looked at in both passes would otherwise count twice.
+        if !self.references.contains(&symbol) {
+            self.references.push(symbol);
+        }
+    }
+
+    /// Makes sure a register referenced by its default name (e.g. `sF`) hasn't been renamed.
+    fn check_default_register_name(&mut self, register: u8) {
+        if let Some(name) = self.register_names[register as usize].clone() {
+            self.error(format!(
+                "Register 's{:X}' was renamed to '{}' by a NAMEREG directive, use '{}' instead.",
+                register, name, name
+            ));
+        }
+    }
+
+    /// Finds the register that currently goes by the given name. Reports an error if no register
+    /// has that name at this point of the source, including names that were renamed away.
+    fn find_register(&mut self, name: &String) -> Option<u8> {
+        if let Some(register) = self.find_register_name(name) {
+            return Some(register);
+        }
+
+        let message = match self.find_alias(name) {
+            Some(Alias(_, register)) => match &self.register_names[register as usize] {
+                Some(current_name) => format!(
+                    "'{}' no longer names a register, 's{:X}' was renamed to '{}'.",
+                    name, register, current_name
+                ),
+                None => format!(
+                    "'{}' no longer names a register, 's{:X}' was renamed back to 's{:X}'.",
+                    name, register, register
+                ),
+            },
+            None => format!("Unable to find a register called '{}'.", name),
+        };
+
+        self.error(message);
+        None
+    }
+
+    /// Finds a label, constant or NAMEREG name that only differs from the given name in case.
+    /// These are case sensitive, so it's most likely a typo when two of them are this similar.
+    fn find_case_only_match(&self, name: &str) -> Option<String> {
+        let labels = self.labels.iter().map(|Label(n, _)| n);
+        let constants = self.constants.iter().map(|Constant(n, _)| n);
+        let aliases = self.aliases.iter().map(|Alias(n, _)| n);
+
+        labels
+            .chain(constants)
+            .chain(aliases)
+            .find(|n| n.as_str() != name && n.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    fn warn_about_case_only_match(&mut self, name: &str) {
+        if let Some(other) = self.find_case_only_match(name) {
+            self.warning(format!(
+                "'{}' only differs from '{}' in case. Labels, constants and register names are case sensitive, did you mean '{}'?",
+                name, other, other
+            ));
+        }
+    }
+
+    fn unknown_symbol_message(&self, word: &str) -> String {
+        match self.find_case_only_match(word) {
+            Some(other) => format!(
+                "Unable to find '{}', labels, constants and register names are case sensitive. Did you mean '{}'?",
+                word, other
+            ),
+            None => format!(
+                "Unable to find a label, constant or register called '{}'.",
+                word
+            ),
+        }
+    }
+
+    fn update_address(&mut self, tokens: &Vec<Token>, instruction_address: usize) -> usize {
         match tokens.as_slice() {
-            [Token::AddressDirective, Token::Address(addr)] => *addr as usize,
-            [Token::AddressDirective, Token::Word(word)] => {
-                if let Some(Constant(_, addr)) = self.find_constant(word) {
-                    return addr as usize;
+            [Token::AddressDirective, address] => {
+                match self.resolve_operand(address, OperandKind::Address) {
+                    Token::Address(address) => address as usize,
+                    Token::Word(word) => {
+                        let message = self.unknown_symbol_message(&word);
+                        self.error(message);
+                        instruction_address
+                    }
+                    _ => {
+                        self.error("Unable to parse address.".to_string());
+                        instruction_address
+                    }
+                }
+            }
+            _ => {
+                self.error("Unable to parse ADDRESS directive.".to_string());
+                instruction_address
+            }
+        }
+    }
+
+    fn is_symbol(&self, word: &String) -> bool {
+        self.find_label(word).is_some()
+            || self.find_constant(word).is_some()
+            || self.find_table(word).is_some()
+            || self.find_alias(word).is_some()
+            || predefined::find_constant(word).is_some()
+    }
+
+    /// Converts a word or number into the token expected at an operand position. Labels,
+    /// constants and register names always take precedence, so that a constant called `abc`
+    /// isn't mistaken for the address 0xABC. Numbers are checked to fit the operand.
+    fn resolve_operand(&mut self, token: &Token, kind: OperandKind) -> Token {
+        // OUTPUTK ports given through a CONSTANT are checked by the `outputk-port` lint instead,
+        // as those constants are usually 8-bit OUTPUT ports that happen to be reused.
+        let max_value = match token {
+            Token::Word(word)
+                if kind == OperandKind::Port && self.find_constant(word).is_some() =>
+            {
+                OperandKind::Constant.max_value()
+            }
+            _ => kind.max_value(),
+        };
+
+        let token = match token {
+            Token::Word(word) if self.is_symbol(word) => self.try_to_convert_word_into_token(word),
+            Token::Word(word) if kind == OperandKind::Condition => match parse_condition(word) {
+                Some(condition) => Token::Condition(condition),
+                None => {
+                    self.error(format!(
+                        "'{}' is not a valid condition (Z, NZ, C or NC).",
+                        word
+                    ));
+                    Token::Condition(ConditionType::IfZero)
+                }
+            },
+            Token::Word(word) => match kind.parse_hex_literal(word) {
+                Some(value) => Token::Number(value, NumberType::Hexadecimal),
+                None => match kind.parse_binary_literal(word) {
+                    Some(value) => Token::Number(value, NumberType::Binary),
+                    None => match self.try_to_convert_word_into_token(word) {
+                        Token::Word(word) => match kind.describe_literal_error(&word) {
+                            Some(message) => {
+                                // Keep going with some value to avoid more errors.
+                                self.error(message);
+                                Token::Number(0, NumberType::Hexadecimal)
+                            }
+                            None => Token::Word(word),
+                        },
+                        token => token,
+                    },
+                },
+            },
+            _ => token.clone(),
+        };
+
+        match token {
+            Token::Number(value, number_type) if kind != OperandKind::Register => {
+                if value > max_value {
+                    self.error(format!(
+                        "Value {} (0x{:X}) is out of range for a {} (max is 0x{:X}).",
+                        value,
+                        value,
+                        kind.describe(),
+                        max_value
+                    ));
+                }
+
+                if kind == OperandKind::Address {
+                    Token::Address(value)
                 } else {
-                    panic!("Unable to parse address.");
+                    Token::Number(value, number_type)
                 }
             }
-            _ => unreachable!("Address"),
+            _ => token,
         }
     }
 
@@ -534,9 +1069,9 @@ impl Parser {
         &mut self,
         token_list: &Vec<Token>,
         instruction_address: usize,
-    ) -> (bool, usize) {
+    ) -> (usize, usize) {
         if token_list.is_empty() {
-            return (false, instruction_address);
+            return (0, instruction_address);
         }
 
         let mut updated_addr = instruction_address;
@@ -557,13 +1092,18 @@ impl Parser {
                     break;
                 }
                 Token::NameregDirective => {
-                    self.add_alias(token_list);
-
+                    // Register names are scoped in source order, so they're handled while
+                    // parsing the instructions instead.
+                    is_valid_instruction = false;
+                    break;
+                }
+                Token::StringDirective | Token::TableDirective => {
+                    // Already read before the first pass.
                     is_valid_instruction = false;
                     break;
                 }
                 Token::AddressDirective => {
-                    updated_addr = self.update_address(token_list);
+                    updated_addr = self.update_address(token_list, instruction_address);
 
                     is_valid_instruction = false;
                     break;
@@ -574,84 +1114,102 @@ impl Parser {
             }
         }
 
-        if is_valid_instruction {
-            self.addresses.push(instruction_address);
+        if !is_valid_instruction {
+            return (0, updated_addr);
+        }
+
+        // LOAD&RETURN and OUTPUTK with a STRING or TABLE take one address per character or value.
+        let size = token_list
+            .iter()
+            .find_map(|token| match token {
+                Token::Word(word) => self.find_table(word).map(|Table(_, values)| values.len()),
+                _ => None,
+            })
+            .unwrap_or(1);
+
+        if instruction_address + size > MAX_PROGRAM_MEMORY_SIZE {
+            self.error(format!(
+                "Program memory ends at address 0x{:03X}, there's no room for this instruction (0x{:03X}).",
+                MAX_PROGRAM_MEMORY_SIZE - 1,
+                instruction_address.max(MAX_PROGRAM_MEMORY_SIZE)
+            ));
         }
 
-        (is_valid_instruction, updated_addr)
+        for address in instruction_address..instruction_address + size {
+            if self.addresses.contains(&address) {
+                self.error(format!(
+                    "Attempted to add instruction at address that's already occupied (0x{:03X}).",
+                    address
+                ));
+            }
+
+            self.addresses.push(address);
+        }
+
+        (size, updated_addr)
     }
 
-    fn try_to_convert_word_into_token(&self, word: &String) -> Token {
+    fn try_to_convert_word_into_token(&mut self, word: &String) -> Token {
         if let Some(Label(_, addr)) = self.find_label(word) {
+            self.reference(SymbolKind::Label, word);
             return Token::Address(addr);
         }
 
         if let Some(Constant(_, value)) = self.find_constant(word) {
+            self.reference(SymbolKind::Constant, word);
             return Token::Number(value, crate::NumberType::Decimal);
         }
 
-        if let Some(Alias(_, reg)) = self.find_alias(word) {
-            return Token::Register(reg);
+        if let Some(Table(_, values)) = self.find_table(word) {
+            self.reference(SymbolKind::Constant, word);
+            return Token::Table(values);
         }
 
-        // Remove trailing and leading parentheses to make sure DerefRegister's with an alias work.
-        let word = word
-            .clone()
-            .chars()
-            .filter(|c| *c != '(' && *c != ')')
-            .collect::<String>();
+        if let Some(value) = predefined::find_constant(word) {
+            return Token::Number(value, NumberType::Hexadecimal);
+        }
 
-        // TODO: Deref registers don't exist anymore, woohoo!
-        //if let Some(Alias(_, reg)) = self.find_alias(&word) {
-        //return Token::DerefRegister(reg);
-        //}
-
-        // TODO: This is such a painful way of doing this. It works, but it scratches that part of
-        // my brain that tells me I'm being stupid.
-        /*
-        if word.contains(",") {
-            let is_register_like = |s: &String| -> bool {
-                let mut chars = s.chars();
-
-                return s.len() == 2
-                    && chars.next().unwrap_or(' ') == 's'
-                    && chars.nth(0).unwrap_or(' ').is_digit(16);
-            };
-
-            let words: Vec<&str> = word.split(",").collect();
-
-            if words.len() == 2 {
-                let w1 = words[0].to_string();
-                let w2 = words[1].to_string();
-                println!("{} {}", w1, w2);
-                let mut first = 0u8;
-                let mut second = 0u8;
-
-                if !is_register_like(&w1) {
-                    if let Some(Alias(_, register)) = self.find_alias(&w1) {
-                        first = register;
-                    } else {
-                        panic!("Unable to parse register at DerefDoubleRegister.");
-                    }
-                } else {
-                    first = u8::from_str_radix(&w1[1..2], 16)
-                        .expect("Unable to parse register at DerefDoubleRegister.");
+        if self.find_alias(word).is_some() {
+            self.reference(SymbolKind::RegisterName, word);
+
+            // Report stale names, but keep going with some register to avoid more errors.
+            return Token::Register(self.find_register(word).unwrap_or(0));
+        }
+
+        // `label'upper` and `label'lower` split a 12-bit address into two bytes, which is how
+        // addresses are loaded into registers for JUMP@ and CALL@.
+        if let Some((label, operator)) = word.rsplit_once('\'') {
+            let operator = operator.to_lowercase();
+
+            if operator == "upper" || operator == "lower" {
+                if self.find_label(&label.to_string()).is_some() {
+                    self.reference(SymbolKind::Label, label);
                 }
 
-                if !is_register_like(&w2) {
-                    if let Some(Alias(_, register)) = self.find_alias(&w2) {
-                        second = register;
-                    } else {
-                        panic!("Unable to parse register at DerefDoubleRegister.");
+                match self.find_label(&label.to_string()) {
+                    Some(Label(_, addr)) if operator == "upper" => {
+                        return Token::Number((addr >> 8) & 0xF, NumberType::Hexadecimal);
+                    }
+                    Some(Label(_, addr)) => {
+                        return Token::Number(addr & 0xFF, NumberType::Hexadecimal);
+                    }
+                    None => {
+                        self.error(format!(
+                            "Unable to find a label called '{}' (used in '{}').",
+                            label, word
+                        ));
+                        return Token::Number(0, NumberType::Hexadecimal);
                     }
-                } else {
-                    second = u8::from_str_radix(&w2[1..2], 16)
-                        .expect("Unable to parse register at DerefDoubleRegister.");
                 }
-
-                return Token::DoubleDerefRegister(first, second);
             }
-        }*/
+        }
+
+        // Remove trailing and leading parentheses to make sure DerefRegister's with an alias work.
+        let word = word
+            .clone()
+            .chars()
+            .filter(|c| *c != '(' && *c != ')')
+            .collect::<String>();
 
         Token::Word(word.clone())
     }
@@ -664,19 +1222,95 @@ impl Parser {
         let mut updated_tokens: Vec<Token> = Vec::new();
         let mut updated_addr = instruction_address;
 
+        // Words and numbers are read depending on the operand they're in, so keep track of the
+        // instruction and which operand we're at.
+        let mut instruction: Option<String> = None;
+        let mut operand_index = 0;
+        let operand_count = token_list
+            .iter()
+            .filter(|token| matches!(token, Token::Comma))
+            .count()
+            + 1;
+        // Whether the last token was `~`, which inverts the constant after it.
+        let mut invert = false;
+
         for token in token_list {
+            if invert && !matches!(token, Token::Word(_) | Token::Number(_, _)) {
+                self.error("'~' can only be used before a constant.".to_string());
+                invert = false;
+            }
+
             match token {
                 Token::Label(_) => continue,
-                Token::ConstantDirective | Token::NameregDirective => {
+                Token::ConstantDirective | Token::StringDirective | Token::TableDirective => {
+                    break;
+                }
+                Token::Tilda => invert = true,
+                Token::NameregDirective => {
+                    self.add_alias(token_list);
                     break;
                 }
                 Token::AddressDirective => {
-                    updated_addr = self.update_address(token_list);
+                    updated_addr = self.update_address(token_list, instruction_address);
                     break;
                 }
+                Token::Instruction(instr) => {
+                    instruction = Some(instr.clone());
+                    updated_tokens.push(token.clone());
+                }
+                Token::Comma => {
+                    operand_index += 1;
+                    updated_tokens.push(token.clone());
+                }
+                Token::Word(_) | Token::Number(_, _) if instruction.is_some() => {
+                    let kind = OperandKind::for_instruction(
+                        instruction.as_ref().unwrap(),
+                        operand_index,
+                        operand_count,
+                    );
+
+                    // Constants can't stand in for labels, even when their value fits an address.
+                    if let Token::Word(word) = token {
+                        if kind == OperandKind::Address
+                            && self.find_label(word).is_none()
+                            && self.find_constant(word).is_some()
+                        {
+                            self.error(format!("'{}' is a constant, not a label.", word));
+                        }
+                    }
+
+                    let final_token = match self.resolve_operand(token, kind) {
+                        Token::Number(value, number_type) if invert => {
+                            let mask = match kind.max_value() {
+                                max if max > 0 => max,
+                                _ => 0xFF,
+                            };
+
+                            Token::Number(!value & mask, number_type)
+                        }
+                        final_token if invert => {
+                            self.error("'~' can only be used before a constant.".to_string());
+                            final_token
+                        }
+                        final_token => final_token,
+                    };
+
+                    invert = false;
+                    updated_tokens.push(final_token);
+                }
                 _ => {
                     let mut final_token = token.clone();
 
+                    // The first operand of STAR is a register of the other bank, which is
+                    // always written with its default name whatever the active bank calls it.
+                    let other_bank = instruction.as_deref() == Some("star") && operand_index == 0;
+
+                    if let Token::Register(register) = final_token {
+                        if !other_bank {
+                            self.check_default_register_name(register);
+                        }
+                    }
+
                     if let Token::Word(word) = final_token {
                         final_token = self.try_to_convert_word_into_token(&word);
                     }
@@ -693,10 +1327,28 @@ impl Parser {
         &self.instructions
     }
 
+    pub fn get_source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+
     pub fn get_labels(&self) -> &Vec<Label> {
         &self.labels
     }
 
+    /// Where every label, constant and register name is defined, in source order.
+    pub fn get_definitions(&self) -> &Vec<Symbol> {
+        &self.definitions
+    }
+
+    /// Every line a label, constant or register name is used on.
+    pub fn get_references(&self) -> &Vec<Symbol> {
+        &self.references
+    }
+
     pub fn find_label(&self, label: &String) -> Option<Label> {
         self.labels
             .iter()
@@ -721,10 +1373,29 @@ impl Parser {
             .cloned()
     }
 
+    pub fn get_tables(&self) -> &Vec<Table> {
+        &self.tables
+    }
+
+    /// Finds a STRING or TABLE, including the predefined strings (`timestamp$`, `datestamp$` and
+    /// `KCPSM6_version$`).
+    pub fn find_table(&self, table: &String) -> Option<Table> {
+        self.tables
+            .iter()
+            .find(|Table(name, _)| name == table)
+            .cloned()
+            .or_else(|| predefined::find_string(table).map(|values| Table(table.clone(), values)))
+    }
+
     pub fn get_aliases(&self) -> &Vec<Alias> {
         &self.aliases
     }
 
+    pub fn get_renames(&self) -> &Vec<(usize, u8, Option<String>)> {
+        &self.renames
+    }
+
+    /// Finds any NAMEREG name ever given to a register, whether or not it's still in scope.
     pub fn find_alias(&self, alias: &String) -> Option<Alias> {
         self.aliases
             .iter()
@@ -734,4 +1405,500 @@ impl Parser {
             })
             .cloned()
     }
+
+    /// Finds the register that goes by the given NAMEREG name at the current point of the source.
+    pub fn find_register_name(&self, name: &String) -> Option<u8> {
+        self.register_names
+            .iter()
+            .position(|n| n.as_ref() == Some(name))
+            .map(|register| register as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Reader, Tokenizer};
+
+    fn parse(source: &str) -> Parser {
+        let mut reader = Reader::new();
+        let mut tokenizer = Tokenizer::new();
+        let mut parser = Parser::new();
+
+        tokenizer.tokenize(
+            reader
+                .read_buffer_and_split(source.to_string())
+                .get_contents()
+                .clone(),
+        );
+        parser.parse(tokenizer.get_tokens().clone());
+
+        parser
+    }
+
+    /// Lines of every error reported while parsing the source.
+    fn error_lines(source: &str) -> Vec<usize> {
+        parse(source)
+            .get_diagnostics()
+            .iter()
+            .filter(|d| d.is_error())
+            .map(|d| d.line)
+            .collect()
+    }
+
+    #[test]
+    fn namereg_renames_register() {
+        let parser = parse("namereg s1, first\nload first, 05");
+
+        assert!(matches!(
+            parser.get_instructions()[0],
+            (0, Instruction::LoadConstant { lhs: 1, rhs: 5 })
+        ));
+    }
+
+    #[test]
+    fn namereg_is_scoped_in_source_order() {
+        let parser = parse(
+            "load sF, 01\nnamereg sF, status\nload status, 02\nnamereg status, sF\nload sF, 03",
+        );
+        let instructions = parser.get_instructions();
+
+        assert!(matches!(
+            instructions[0],
+            (0, Instruction::LoadConstant { lhs: 15, rhs: 1 })
+        ));
+        assert!(matches!(
+            instructions[1],
+            (1, Instruction::LoadConstant { lhs: 15, rhs: 2 })
+        ));
+        assert!(matches!(
+            instructions[2],
+            (2, Instruction::LoadConstant { lhs: 15, rhs: 3 })
+        ));
+    }
+
+    #[test]
+    fn namereg_chained_rename() {
+        let parser = parse("namereg s1, first\nnamereg first, second\nxor second, second");
+
+        assert!(matches!(
+            parser.get_instructions()[0],
+            (0, Instruction::Xor { lhs: 1, rhs: 1 })
+        ));
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        let parser = parse("NAMEREG SF, Status\nLOAD Status, 0A\nJump NZ, 000");
+        let instructions = parser.get_instructions();
+
+        assert!(matches!(
+            instructions[0],
+            (0, Instruction::LoadConstant { lhs: 15, rhs: 10 })
+        ));
+        assert!(matches!(
+            instructions[1],
+            (
+                1,
+                Instruction::JumpConditional {
+                    condition: ConditionType::IfNonZero,
+                    address: 0
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn labels_are_case_sensitive() {
+        let parser = parse("Loop:\nload s0, 01\nloop:\nload s0, 02\njump Loop\njump loop");
+        let instructions = parser.get_instructions();
+
+        assert!(matches!(
+            instructions[2],
+            (2, Instruction::Jump { address: 0 })
+        ));
+        assert!(matches!(
+            instructions[3],
+            (3, Instruction::Jump { address: 1 })
+        ));
+    }
+
+    #[test]
+    fn case_only_match() {
+        let parser = parse("constant Delay, 10'd\nnamereg s1, Counter\nLoop:\nload Counter, Delay");
+
+        assert_eq!(
+            parser.find_case_only_match("loop"),
+            Some("Loop".to_string())
+        );
+        assert_eq!(
+            parser.find_case_only_match("DELAY"),
+            Some("Delay".to_string())
+        );
+        assert_eq!(
+            parser.find_case_only_match("counter"),
+            Some("Counter".to_string())
+        );
+        assert_eq!(parser.find_case_only_match("Loop"), None);
+    }
+
+    #[test]
+    fn symbols_take_precedence_over_hex_literals() {
+        let parser =
+            parse("constant abc, 100'd\nconstant def, abc\naddress abc\nmain:\nxor s0, def");
+
+        assert!(matches!(
+            parser.get_instructions()[0],
+            (100, Instruction::XorConstant { lhs: 0, rhs: 100 })
+        ));
+    }
+
+    #[test]
+    fn literals_depend_on_operand_position() {
+        let parser =
+            parse("jump abc\njump 000100100011'b\noutputk ab, c\nload s0, 1F\ncall c, 3ff");
+        let instructions = parser.get_instructions();
+
+        assert!(matches!(
+            instructions[0],
+            (0, Instruction::Jump { address: 0xABC })
+        ));
+        assert!(matches!(
+            instructions[1],
+            (1, Instruction::Jump { address: 0x123 })
+        ));
+        assert!(matches!(
+            instructions[2],
+            (
+                2,
+                Instruction::OutputDoubleConstant {
+                    lhs: 0xAB,
+                    rhs: 0xC
+                }
+            )
+        ));
+        assert!(matches!(
+            instructions[3],
+            (3, Instruction::LoadConstant { lhs: 0, rhs: 0x1F })
+        ));
+        assert!(matches!(
+            instructions[4],
+            (
+                4,
+                Instruction::CallConditional {
+                    condition: ConditionType::IfCarry,
+                    address: 0x3FF
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn character_literals() {
+        let parser =
+            parse("load s0, \"A\"\ncompare s0, \";\" ; Comment\nload s1, \" \"\nload s2, \",\"");
+        let instructions = parser.get_instructions();
+
+        assert!(matches!(
+            instructions[0],
+            (0, Instruction::LoadConstant { lhs: 0, rhs: 65 })
+        ));
+        assert!(matches!(
+            instructions[1],
+            (1, Instruction::CompareConstant { lhs: 0, rhs: 59 })
+        ));
+        assert!(matches!(
+            instructions[2],
+            (2, Instruction::LoadConstant { lhs: 1, rhs: 32 })
+        ));
+        assert!(matches!(
+            instructions[3],
+            (3, Instruction::LoadConstant { lhs: 2, rhs: 44 })
+        ));
+    }
+
+    #[test]
+    fn label_upper_and_lower() {
+        let parser = parse(
+            "load s5, routine'upper\nload s4, routine'LOWER\njump@ (s5, s4)\naddress 123\nroutine:\nreturn",
+        );
+        let instructions = parser.get_instructions();
+
+        assert!(matches!(
+            instructions[0],
+            (0, Instruction::LoadConstant { lhs: 5, rhs: 0x1 })
+        ));
+        assert!(matches!(
+            instructions[1],
+            (1, Instruction::LoadConstant { lhs: 4, rhs: 0x23 })
+        ));
+        assert!(matches!(
+            instructions[2],
+            (
+                2,
+                Instruction::JumpAt {
+                    first: 5,
+                    second: 4
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn label_upper_of_unknown_label() {
+        assert_eq!(error_lines("load s5, nowhere'upper"), vec![1]);
+    }
+
+    #[test]
+    fn hex_word_is_not_a_constant_literal() {
+        // `abc` has three digits, so it can't be an 8-bit constant.
+        let parser = parse("load s0, abc");
+
+        assert!(parser.get_instructions().is_empty());
+        assert_eq!(error_lines("load s0, abc"), vec![1]);
+    }
+
+    #[test]
+    fn constant_out_of_range() {
+        assert_eq!(error_lines("load s0, 300'd"), vec![1]);
+    }
+
+    #[test]
+    fn port_out_of_range() {
+        assert_eq!(error_lines("outputk 01, 16'd"), vec![1]);
+        assert!(error_lines("constant port, 10\noutputk 01, port").is_empty());
+        assert_eq!(
+            error_lines("outputk A5, 1010'b\noutputk A5, 00010000'b"),
+            vec![2]
+        );
+        assert!(matches!(
+            parse("outputk A5, 1010'b").get_instructions()[0],
+            (
+                0,
+                Instruction::OutputDoubleConstant {
+                    lhs: 0xA5,
+                    rhs: 0xA
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn literals_in_the_wrong_form() {
+        let parser = parse("jump 1F\nload s0, \"AB\"\nload s1, 123");
+        let messages: Vec<&str> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+
+        assert_eq!(messages.len(), 3);
+        assert!(messages[0].ends_with("Did you mean 01F?"));
+        assert!(messages[1].contains("single ASCII character"));
+        assert!(messages[2].ends_with("Did you mean the decimal 123'd?"));
+    }
+
+    #[test]
+    fn reserved_words_as_names() {
+        let parser = parse("call sub\nsub: return");
+        let messages: Vec<&str> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "'sub' is a mnemonic or a reserved word, so it can't be used as a label.",
+                "'sub' is a mnemonic or a reserved word, so it can't be used as an operand."
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_are_not_labels() {
+        let parser = parse("constant abc, 10\njump abc\ncall c, abc\nload s0, abc");
+        let messages: Vec<(usize, &str)> = parser
+            .get_diagnostics()
+            .iter()
+            .map(|d| (d.line, d.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                (2, "'abc' is a constant, not a label."),
+                (3, "'abc' is a constant, not a label.")
+            ]
+        );
+    }
+
+    #[test]
+    fn address_out_of_range() {
+        assert_eq!(error_lines("jump 4096'd"), vec![1]);
+    }
+
+    #[test]
+    fn program_memory_overflow() {
+        let source = "address FFF\nload s0, 01\nload s1, 02\nload s2, 03";
+        let parser = parse(source);
+
+        assert_eq!(error_lines(source), vec![3, 4]);
+        assert_eq!(parser.get_instructions().len(), 1);
+        assert_eq!(
+            error_lines("STRING hi$, \"Hi\"\naddress FFF\nload&return s0, hi$"),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn namereg_stale_default_name() {
+        assert_eq!(error_lines("namereg sF, status\nload sF, 01"), vec![2]);
+    }
+
+    #[test]
+    fn namereg_stale_alias() {
+        assert_eq!(
+            error_lines("namereg s1, first\nnamereg first, second\nload first, 01"),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn namereg_restore_to_other_register() {
+        assert_eq!(error_lines("namereg s1, first\nnamereg first, s2"), vec![2]);
+    }
+
+    #[test]
+    fn namereg_leaves_star_alone() {
+        let source = "namereg sF, status\nstar sF, status\nstar sF, \"F\"";
+        let parser = parse(source);
+        let instructions = parser.get_instructions();
+
+        assert_eq!(error_lines(source), Vec::<usize>::new());
+        assert!(matches!(
+            instructions[0],
+            (0, Instruction::Star { lhs: 15, rhs: 15 })
+        ));
+        assert!(matches!(
+            instructions[1],
+            (1, Instruction::StarConstant { lhs: 15, rhs: 0x46 })
+        ));
+        assert_eq!(error_lines("namereg sF, status\nstar s0, sF"), vec![2]);
+    }
+
+    #[test]
+    fn namereg_duplicate_name() {
+        assert_eq!(error_lines("namereg s1, first\nnamereg s2, first"), vec![2]);
+    }
+
+    #[test]
+    fn strings_expand_into_one_instruction_per_character() {
+        let parser = parse(
+            "jump done\nSTRING hi$, \"Hi, \"\"you\"\"\"\nload&return s1, hi$\ndone: outputk hi$, A",
+        );
+        let instructions = parser.get_instructions();
+        let text = b"Hi, \"\"you\"\"";
+
+        assert!(parser.get_diagnostics().is_empty());
+        assert_eq!(instructions.len(), 1 + 2 * text.len());
+        assert_eq!(parser.find_label(&"done".to_string()).unwrap().1, 12);
+
+        for (index, character) in text.iter().enumerate() {
+            assert_eq!(
+                instructions[1 + index],
+                (
+                    1 + index,
+                    Instruction::LoadAndReturn {
+                        lhs: 1,
+                        rhs: *character as u32
+                    }
+                )
+            );
+            assert_eq!(
+                instructions[1 + text.len() + index],
+                (
+                    12 + index,
+                    Instruction::OutputDoubleConstant {
+                        lhs: *character as u32,
+                        rhs: 0xA
+                    }
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn tables() {
+        let parser = parse(
+            "load&return s0, hex#\nload&return s0, dec#\nload&return s0, bin#\nTABLE hex#, [3F,06]\nTABLE dec#, [10, 255]'d\nTABLE bin#, [00000011]'b",
+        );
+        let values: Vec<u32> = parser
+            .get_instructions()
+            .iter()
+            .map(|(_, instruction)| match instruction {
+                Instruction::LoadAndReturn { rhs, .. } => *rhs,
+                _ => panic!("unexpected instruction {:?}", instruction),
+            })
+            .collect();
+
+        assert!(parser.get_diagnostics().is_empty());
+        assert_eq!(values, vec![0x3F, 0x06, 10, 255, 3]);
+    }
+
+    #[test]
+    fn invalid_strings_and_tables() {
+        assert_eq!(error_lines("STRING hi, \"Hi\""), vec![1]);
+        assert_eq!(error_lines("STRING hi$, Hi"), vec![1]);
+        assert_eq!(error_lines("TABLE t, [01]"), vec![1]);
+        assert_eq!(error_lines("TABLE t#, [100]"), vec![1]);
+        assert_eq!(error_lines("TABLE t#, [10]'x"), vec![1]);
+        assert_eq!(error_lines("TABLE t#, 01,02"), vec![1]);
+        assert_eq!(error_lines("TABLE t#, [01]\nTABLE t#, [02]"), vec![2]);
+    }
+
+    #[test]
+    fn not_operator() {
+        let parser = parse("CONSTANT red, 00000100'b\nand s0, ~red\noutputk ~01, ~E");
+        let instructions = parser.get_instructions();
+
+        assert!(parser.get_diagnostics().is_empty());
+        assert_eq!(
+            instructions[0],
+            (0, Instruction::AndConstant { lhs: 0, rhs: 0xFB })
+        );
+        assert_eq!(
+            instructions[1],
+            (1, Instruction::OutputDoubleConstant { lhs: 0xFE, rhs: 1 })
+        );
+        assert_eq!(error_lines("load s0, ~s1"), vec![1]);
+        assert_eq!(error_lines("jump ~010"), vec![1]);
+    }
+
+    #[test]
+    fn predefined_constants_and_strings() {
+        let parser = parse("load s0, CR\nload s1, DCS\noutputk KCPSM6_version$, 0");
+        let instructions = parser.get_instructions();
+
+        assert!(parser.get_diagnostics().is_empty());
+        assert_eq!(
+            instructions[0],
+            (0, Instruction::LoadConstant { lhs: 0, rhs: 0x0D })
+        );
+        assert_eq!(
+            instructions[1],
+            (1, Instruction::LoadConstant { lhs: 1, rhs: 0x90 })
+        );
+        assert_eq!(
+            instructions[2],
+            (
+                2,
+                Instruction::OutputDoubleConstant {
+                    lhs: 'v' as u32,
+                    rhs: 0
+                }
+            )
+        );
+    }
 }
@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+/// Which strobe a port transaction was made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortAccess {
+    /// INPUT, with READ_STROBE.
+    Input,
+    /// OUTPUT, with WRITE_STROBE.
+    Output,
+    /// OUTPUTK, with K_WRITE_STROBE. Only the lower 4 bits of the port ID are used.
+    OutputK,
+}
+
+/// A value read from or written to a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortTransaction {
+    pub access: PortAccess,
+    pub port: u8,
+    pub value: u8,
+}
+
+/// Whatever is connected to the ports and the interrupt input of the processor.
+///
+/// `cycle` is the amount of clock cycles since reset, so peripherals can keep track of time.
+pub trait PortHandler {
+    /// Value on IN_PORT when the processor reads from `port`.
+    fn input(&mut self, port: u8, cycle: u64) -> u8;
+
+    /// Called when the processor writes `value` to `port` through OUTPUT.
+    fn output(&mut self, port: u8, value: u8, cycle: u64);
+
+    /// Called when the processor writes `value` to `port` through OUTPUTK.
+    fn output_k(&mut self, port: u8, value: u8, cycle: u64) {
+        self.output(port, value, cycle);
+    }
+
+    /// Level of the INTERRUPT input.
+    fn interrupt(&mut self, _cycle: u64) -> bool {
+        false
+    }
+
+    /// Called when the processor acknowledges an interrupt (INTERRUPT_ACK).
+    fn interrupt_ack(&mut self, _cycle: u64) {}
+
+    /// Called before every instruction, so peripherals can keep running between port accesses.
+    fn tick(&mut self, _cycle: u64) {}
+}
+
+/// The default port handler: input ports return queued or fixed values, interrupts are raised at
+/// given cycles and every transaction is logged.
+#[derive(Debug, Clone, Default)]
+pub struct PortState {
+    // Values returned by consecutive reads of a port. The last one is kept once the others are
+    // used up, so a single value behaves like a fixed input.
+    inputs: HashMap<u8, VecDeque<u8>>,
+    interrupts: BTreeSet<u64>,
+    // Values ports take from a clock cycle on, by cycle.
+    changes: BTreeMap<u64, Vec<(u8, u8)>>,
+    log: Vec<(u64, PortTransaction)>,
+}
+
+impl PortState {
+    pub fn new() -> PortState {
+        PortState::default()
+    }
+
+    /// Makes every read of `port` return `value`.
+    pub fn set_input(&mut self, port: u8, value: u8) {
+        self.inputs.insert(port, VecDeque::from(vec![value]));
+    }
+
+    /// Queues values for consecutive reads of `port`.
+    pub fn queue_inputs(&mut self, port: u8, values: &[u8]) {
+        self.inputs.entry(port).or_default().extend(values);
+    }
+
+    /// Makes reads of `port` return `value` from `cycle` on, replacing what was queued for it.
+    pub fn set_input_at(&mut self, cycle: u64, port: u8, value: u8) {
+        self.changes.entry(cycle).or_default().push((port, value));
+    }
+
+    /// Raises the interrupt input at `cycle`. It stays active until the processor acknowledges it.
+    pub fn raise_interrupt_at(&mut self, cycle: u64) {
+        self.interrupts.insert(cycle);
+    }
+
+    /// Every transaction so far, along with the cycle it happened in.
+    pub fn get_log(&self) -> &Vec<(u64, PortTransaction)> {
+        &self.log
+    }
+
+    fn record(&mut self, access: PortAccess, port: u8, value: u8, cycle: u64) {
+        self.log.push((
+            cycle,
+            PortTransaction {
+                access,
+                port,
+                value,
+            },
+        ));
+    }
+}
+
+impl PortHandler for PortState {
+    fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        while let Some(entry) = self.changes.first_entry() {
+            if *entry.key() > cycle {
+                break;
+            }
+
+            for (port, value) in entry.remove() {
+                self.set_input(port, value);
+            }
+        }
+
+        let value = match self.inputs.get_mut(&port) {
+            Some(values) if values.len() > 1 => values.pop_front().unwrap(),
+            Some(values) => values.front().copied().unwrap_or(0),
+            None => 0,
+        };
+
+        self.record(PortAccess::Input, port, value, cycle);
+        value
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        self.record(PortAccess::Output, port, value, cycle);
+    }
+
+    fn output_k(&mut self, port: u8, value: u8, cycle: u64) {
+        self.record(PortAccess::OutputK, port, value, cycle);
+    }
+
+    fn interrupt(&mut self, cycle: u64) -> bool {
+        self.interrupts
+            .first()
+            .is_some_and(|raised| *raised <= cycle)
+    }
+
+    fn interrupt_ack(&mut self, _cycle: u64) {
+        self.interrupts.pop_first();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_inputs_keep_the_last_value() {
+        let mut ports = PortState::new();
+
+        ports.queue_inputs(1, &[1, 2, 3]);
+
+        assert_eq!(ports.input(1, 0), 1);
+        assert_eq!(ports.input(1, 2), 2);
+        assert_eq!(ports.input(1, 4), 3);
+        assert_eq!(ports.input(1, 6), 3);
+        assert_eq!(ports.input(2, 8), 0);
+        assert_eq!(ports.get_log().len(), 5);
+    }
+
+    #[test]
+    fn interrupts_stay_active_until_acknowledged() {
+        let mut ports = PortState::new();
+
+        ports.raise_interrupt_at(10);
+
+        assert!(!ports.interrupt(8));
+        assert!(ports.interrupt(10));
+        assert!(ports.interrupt(12));
+
+        ports.interrupt_ack(12);
+
+        assert!(!ports.interrupt(14));
+    }
+
+    #[test]
+    fn inputs_change_at_their_cycle() {
+        let mut ports = PortState::new();
+
+        ports.queue_inputs(1, &[1, 2]);
+        ports.set_input_at(10, 1, 5);
+        ports.set_input_at(20, 1, 6);
+
+        assert_eq!(ports.input(1, 0), 1);
+        assert_eq!(ports.input(1, 8), 2);
+        assert_eq!(ports.input(1, 10), 5);
+        assert_eq!(ports.input(1, 30), 6);
+    }
+}
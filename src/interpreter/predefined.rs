@@ -0,0 +1,163 @@
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Names of the ASCII control characters every program can use, as in the KCPSM6 assembler.
+const CONTROL_CHARACTERS: [(&str, u32); 11] = [
+    ("NUL", 0x00),
+    ("BEL", 0x07),
+    ("BS", 0x08),
+    ("HT", 0x09),
+    ("LF", 0x0A),
+    ("VT", 0x0B),
+    ("CR", 0x0D),
+    ("ESC", 0x1B),
+    ("DEL", 0x7F),
+    ("DCS", 0x90),
+    ("ST", 0x9C),
+];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A date and time in UTC, such as when the program was assembled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Timestamp {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+}
+
+impl Timestamp {
+    pub fn from_unix(seconds: u64) -> Timestamp {
+        let days = (seconds / 86400) as i64;
+        let time = seconds % 86400;
+
+        // Civil date from days since 1970-01-01, from Howard Hinnant's date algorithms.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Timestamp {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hours: (time / 3600) as u32,
+            minutes: (time / 60 % 60) as u32,
+            seconds: (time % 60) as u32,
+        }
+    }
+
+    /// As in `timestamp$`: `14:17:58`.
+    pub fn format_time(&self) -> String {
+        format!("{:02}:{:02}:{:02}", self.hours, self.minutes, self.seconds)
+    }
+
+    /// As in `datestamp$`: `31 Jul 2012`.
+    pub fn format_date(&self) -> String {
+        format!(
+            "{:02} {} {}",
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year
+        )
+    }
+
+    /// The time of assembly. `SOURCE_DATE_EPOCH` overrides it, for reproducible builds.
+    fn now() -> Timestamp {
+        static NOW: OnceLock<Timestamp> = OnceLock::new();
+
+        *NOW.get_or_init(|| {
+            let seconds = std::env::var("SOURCE_DATE_EPOCH")
+                .ok()
+                .and_then(|epoch| epoch.trim().parse().ok())
+                .unwrap_or_else(|| {
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |duration| duration.as_secs())
+                });
+
+            Timestamp::from_unix(seconds)
+        })
+    }
+}
+
+/// Value of a predefined constant: the control characters (`CR`, `ESC`, `DCS`...) and the time of
+/// assembly (`timestamp_hours`, `datestamp_year`...). The year only has its last two digits.
+pub fn find_constant(name: &str) -> Option<u32> {
+    if let Some((_, value)) = CONTROL_CHARACTERS.iter().find(|(n, _)| *n == name) {
+        return Some(*value);
+    }
+
+    let now = Timestamp::now();
+
+    match name {
+        "timestamp_hours" => Some(now.hours),
+        "timestamp_minutes" => Some(now.minutes),
+        "timestamp_seconds" => Some(now.seconds),
+        "datestamp_year" => Some(now.year % 100),
+        "datestamp_month" => Some(now.month),
+        "datestamp_day" => Some(now.day),
+        _ => None,
+    }
+}
+
+/// Characters of a predefined string: `timestamp$` (`14:17:58`), `datestamp$` (`31 Jul 2012`) and
+/// `KCPSM6_version$`.
+pub fn find_string(name: &str) -> Option<Vec<u8>> {
+    let now = Timestamp::now();
+    let text = match name {
+        "timestamp$" => now.format_time(),
+        "datestamp$" => now.format_date(),
+        "KCPSM6_version$" => format!("v{}", env!("CARGO_PKG_VERSION")),
+        _ => return None,
+    };
+
+    Some(text.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        // 14:17:58 on the 31st July 2012, as in the KCPSM6 documentation.
+        assert_eq!(
+            Timestamp::from_unix(1343744278),
+            Timestamp {
+                year: 2012,
+                month: 7,
+                day: 31,
+                hours: 14,
+                minutes: 17,
+                seconds: 58
+            }
+        );
+        assert_eq!(Timestamp::from_unix(951782400).day, 29);
+        assert_eq!(Timestamp::from_unix(0).year, 1970);
+    }
+
+    #[test]
+    fn predefined_names() {
+        assert_eq!(find_constant("DCS"), Some(0x90));
+        assert_eq!(find_constant("cr"), None);
+        assert!(find_constant("timestamp_hours").is_some_and(|hours| hours < 24));
+        assert_eq!(find_string("timestamp$").map(|text| text.len()), Some(8));
+        assert_eq!(
+            find_string("KCPSM6_version$").map(|text| text[0]),
+            Some(b'v')
+        );
+        assert_eq!(find_string("other$"), None);
+    }
+}
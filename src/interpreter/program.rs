@@ -0,0 +1,540 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::diagnostics::Diagnostic;
+use super::encoding::encode;
+use super::image::create_image;
+use super::include::Expansion;
+use super::source_map::SourceMap;
+use crate::{
+    Alias, Constant, Instruction, Label, Parser, Reader, SimulationContext, Symbol, SymbolKind,
+    Table, Tokenizer,
+};
+
+/// An assembled program: its instructions along with everything known about the source they came
+/// from (symbols, source lines and the problems found along the way).
+#[derive(Debug, Clone)]
+pub struct Program {
+    path: Option<PathBuf>,
+    included: Vec<PathBuf>,
+    source: Vec<String>,
+    // File (0 for the program's own, then the included ones) and line of every source line.
+    origins: Vec<(usize, usize)>,
+    instructions: Vec<(usize, Instruction)>,
+    labels: Vec<Label>,
+    constants: Vec<Constant>,
+    tables: Vec<Table>,
+    aliases: Vec<Alias>,
+    renames: Vec<(usize, u8, Option<String>)>,
+    definitions: Vec<Symbol>,
+    references: Vec<Symbol>,
+    source_map: SourceMap,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Assembles a PSM file. Only failing to read the file is an error, problems in the source itself
+/// are reported through the program's diagnostics.
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> io::Result<Program> {
+    // PSM files are often written in Windows-1252 (e.g. the copyright headers of the Xilinx
+    // examples), so anything that isn't valid UTF-8 is replaced rather than rejected.
+    let bytes = fs::read(path.as_ref())?;
+    let source = String::from_utf8_lossy(&bytes);
+
+    Ok(assemble_source(&source, Some(path.as_ref())))
+}
+
+/// Assembles PSM source code. Check [`Program::has_errors`] before running the result. Files it
+/// includes are found relative to the current directory.
+pub fn assemble_str(source: &str) -> Program {
+    assemble_source(source, None)
+}
+
+/// Assembles PSM source code as if it were read from a file, which is where the files it includes
+/// are found. Editors use it for files that haven't been saved.
+///
+/// The included files are assembled along with the program, as one source whose lines follow each
+/// other: line numbers, in diagnostics and the source map, count through all of it.
+pub fn assemble_source(source: &str, path: Option<&Path>) -> Program {
+    let expansion = Expansion::new(source, path);
+    let mut reader = Reader::new();
+    let mut tokenizer = Tokenizer::new();
+    let mut parser = Parser::new();
+
+    tokenizer.tokenize(
+        reader
+            .read_buffer_and_split(expansion.code.join("\n"))
+            .get_contents()
+            .clone(),
+    );
+    parser.parse(tokenizer.get_tokens().clone());
+
+    let mut diagnostics = expansion.diagnostics;
+    diagnostics.extend(tokenizer.get_diagnostics().iter().cloned());
+    diagnostics.extend(parser.get_diagnostics().iter().cloned());
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+    Program {
+        path: path.map(Path::to_path_buf),
+        included: expansion.files.into_iter().skip(1).flatten().collect(),
+        source: expansion.lines,
+        origins: expansion.origins,
+        instructions: parser.get_instructions().clone(),
+        labels: parser.get_labels().clone(),
+        constants: parser.get_constants().clone(),
+        tables: parser.get_tables().clone(),
+        aliases: parser.get_aliases().clone(),
+        renames: parser.get_renames().clone(),
+        definitions: parser.get_definitions().clone(),
+        references: parser.get_references().clone(),
+        source_map: parser.get_source_map().clone(),
+        diagnostics,
+    }
+}
+
+impl Program {
+    /// Creates a simulation with this program loaded into its program memory.
+    pub fn create_simulation(&self) -> SimulationContext {
+        // The Parser leaves out instructions past the end of program memory, reporting them.
+        SimulationContext::new_with_instructions(self.instructions.clone())
+            .expect("assembled instructions fit in program memory")
+    }
+
+    /// Encodes the program into a 4K program memory image, as written to `.hex` files.
+    pub fn create_image(&self) -> io::Result<Vec<u32>> {
+        create_image(&self.instructions)
+    }
+
+    /// Creates a listing: every source line along with the address and opcode of the instruction
+    /// on it, followed by the labels and constants.
+    pub fn create_listing(&self) -> String {
+        let mut listing = String::from("Addr  Opcode   Line  Source\n");
+
+        for (index, text) in self.source.iter().enumerate() {
+            let line = index + 1;
+            let instructions: Vec<(usize, u32)> = self
+                .source_map
+                .get_addresses(line)
+                .into_iter()
+                .filter_map(|address| Some((address, encode(self.get_instruction(address)?)?)))
+                .collect();
+
+            match instructions.split_first() {
+                Some(((address, opcode), rest)) => {
+                    listing.push_str(&format!(
+                        "{:03X}   {:05X}  {:>5}  {}\n",
+                        address, opcode, line, text
+                    ));
+
+                    // The other instructions of a STRING or TABLE expansion.
+                    for (address, opcode) in rest {
+                        listing.push_str(&format!("{:03X}   {:05X}\n", address, opcode));
+                    }
+                }
+                None => listing.push_str(&format!("{:13}{:>5}  {}\n", "", line, text)),
+            }
+        }
+
+        if !self.labels.is_empty() {
+            listing.push_str("\nLabels\n");
+
+            for Label(name, address) in &self.labels {
+                listing.push_str(&format!("  {:03X}  {}\n", address, name));
+            }
+        }
+
+        if !self.constants.is_empty() {
+            listing.push_str("\nConstants\n");
+
+            for Constant(name, value) in &self.constants {
+                listing.push_str(&format!("  {:02X}   {}\n", value, name));
+            }
+        }
+
+        listing
+    }
+
+    pub fn get_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Files included through INCLUDE, in the order they're included.
+    pub fn get_included_files(&self) -> &Vec<PathBuf> {
+        &self.included
+    }
+
+    /// The file and line a source line was written in: the program's own file (`None` when it was
+    /// assembled from a string) or a file it includes. Without INCLUDE, lines are the same.
+    pub fn get_location(&self, line: usize) -> Option<(Option<&Path>, usize)> {
+        let (file, line) = *self.origins.get(line.checked_sub(1)?)?;
+
+        match file {
+            0 => Some((self.path.as_deref(), line)),
+            _ => Some((Some(self.included[file - 1].as_path()), line)),
+        }
+    }
+
+    /// A source line for reports, as `file:line` with the name of the file it was written in, or as
+    /// `line <n>` for programs assembled from a string.
+    pub fn describe_line(&self, line: usize) -> String {
+        match self.get_location(line) {
+            Some((Some(file), line)) => format!("{}:{}", file_name(file), line),
+            Some((None, line)) => format!("line {}", line),
+            None => format!("line {}", line),
+        }
+    }
+
+    /// The source line `file:line` is, with the file given by its path or by its name as
+    /// [`describe_line`](Program::describe_line) writes it.
+    pub fn find_file_line(&self, file: &str, line: usize) -> Option<usize> {
+        let path = self
+            .path
+            .iter()
+            .chain(&self.included)
+            .find(|path| same_file(path, Path::new(file)) || file_name(path) == file)?;
+
+        self.find_line(Some(path), line)
+    }
+
+    /// The source line a line of a file is, the other way around from
+    /// [`get_location`](Program::get_location). `None` is the program's own file.
+    pub fn find_line(&self, file: Option<&Path>, line: usize) -> Option<usize> {
+        let index = match file {
+            None => 0,
+            Some(file)
+                if self
+                    .path
+                    .as_deref()
+                    .is_some_and(|path| same_file(path, file)) =>
+            {
+                0
+            }
+            Some(file) => {
+                self.included
+                    .iter()
+                    .position(|included| same_file(included, file))?
+                    + 1
+            }
+        };
+
+        self.origins
+            .iter()
+            .position(|origin| *origin == (index, line))
+            .map(|position| position + 1)
+    }
+
+    pub fn get_source(&self) -> &Vec<String> {
+        &self.source
+    }
+
+    /// Text of a source line, starting from 1.
+    pub fn get_source_line(&self, line: usize) -> Option<&str> {
+        self.source.get(line.checked_sub(1)?).map(|l| l.as_str())
+    }
+
+    pub fn get_instructions(&self) -> &Vec<(usize, Instruction)> {
+        &self.instructions
+    }
+
+    pub fn get_instruction(&self, address: usize) -> Option<&Instruction> {
+        self.instructions
+            .iter()
+            .find(|(addr, _)| *addr == address)
+            .map(|(_, instruction)| instruction)
+    }
+
+    pub fn get_labels(&self) -> &Vec<Label> {
+        &self.labels
+    }
+
+    pub fn find_label(&self, name: &str) -> Option<u32> {
+        self.labels
+            .iter()
+            .find(|Label(label, _)| label == name)
+            .map(|Label(_, address)| *address)
+    }
+
+    /// Name of the label pointing at the given address, if any.
+    pub fn get_label_at(&self, address: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|Label(_, addr)| *addr as usize == address)
+            .map(|Label(name, _)| name.as_str())
+    }
+
+    /// Closest label at or before the given address, along with the offset from it. Used to show
+    /// addresses as `label+offset`.
+    pub fn find_label_before(&self, address: usize) -> Option<(&str, usize)> {
+        self.labels
+            .iter()
+            .filter(|Label(_, addr)| *addr as usize <= address)
+            .max_by_key(|Label(_, addr)| *addr)
+            .map(|Label(name, addr)| (name.as_str(), address - *addr as usize))
+    }
+
+    pub fn get_constants(&self) -> &Vec<Constant> {
+        &self.constants
+    }
+
+    pub fn find_constant(&self, name: &str) -> Option<u32> {
+        self.constants
+            .iter()
+            .find(|Constant(constant, _)| constant == name)
+            .map(|Constant(_, value)| *value)
+    }
+
+    /// Every STRING and TABLE, in source order.
+    pub fn get_tables(&self) -> &Vec<Table> {
+        &self.tables
+    }
+
+    /// Characters of a STRING or values of a TABLE, without the predefined strings.
+    pub fn find_table(&self, name: &str) -> Option<&Vec<u8>> {
+        self.tables
+            .iter()
+            .find(|Table(table, _)| table == name)
+            .map(|Table(_, values)| values)
+    }
+
+    /// Every name given to a register through NAMEREG, in source order.
+    pub fn get_aliases(&self) -> &Vec<Alias> {
+        &self.aliases
+    }
+
+    /// The name a register goes by on a line, following the NAMEREG directives in force there.
+    /// `None` while it has its default name (`s0` to `sF`).
+    pub fn find_register_name(&self, register: u8, line: usize) -> Option<&str> {
+        self.renames
+            .iter()
+            .filter(|(_, r, _)| *r == register)
+            .rfind(|(l, _, _)| *l <= line)
+            .and_then(|(_, _, name)| name.as_deref())
+    }
+
+    /// Where every label, constant and register name is defined, in source order.
+    pub fn get_definitions(&self) -> &Vec<Symbol> {
+        &self.definitions
+    }
+
+    /// Every line a label, constant or register name is used on.
+    pub fn get_references(&self) -> &Vec<Symbol> {
+        &self.references
+    }
+
+    /// Lines the given symbol is used on.
+    pub fn find_references(&self, kind: SymbolKind, name: &str) -> Vec<usize> {
+        self.references
+            .iter()
+            .filter(|symbol| symbol.kind == kind && symbol.name == name)
+            .map(|symbol| symbol.line)
+            .collect()
+    }
+
+    /// The definition a name written on a line refers to. NAMEREG can give a name to a register
+    /// again after it was renamed away, and each of these definitions is in force until the next
+    /// one. Labels and constants are only defined once, and can be used before that.
+    pub fn find_definition(&self, name: &str, line: usize) -> Option<&Symbol> {
+        let mut definitions = self.definitions.iter().filter(|symbol| symbol.name == name);
+        let first = definitions.clone().next();
+
+        definitions.rfind(|symbol| symbol.line <= line).or(first)
+    }
+
+    /// Lines a definition is used on, leaving out those of other definitions of the same name.
+    pub fn find_uses(&self, definition: &Symbol) -> Vec<usize> {
+        self.find_references(definition.kind, &definition.name)
+            .into_iter()
+            .filter(|line| self.find_definition(&definition.name, *line) == Some(definition))
+            .collect()
+    }
+
+    /// Finds a register by its default name (`s0` to `sF`) or a name given to it with NAMEREG,
+    /// the last one if the name was given to several registers.
+    pub fn find_register(&self, name: &str) -> Option<u8> {
+        let lower = name.to_lowercase();
+
+        if lower.len() == 2 && lower.starts_with('s') {
+            if let Ok(register) = u8::from_str_radix(&lower[1..], 16) {
+                return Some(register);
+            }
+        }
+
+        self.aliases
+            .iter()
+            .rev()
+            .find(|Alias(alias, _)| alias == name)
+            .map(|Alias(_, register)| *register)
+    }
+
+    /// The register a NAMEREG definition names.
+    pub fn find_named_register(&self, definition: &Symbol) -> Option<u8> {
+        // Every NAMEREG adds an alias and a definition, both in source order.
+        let index = self
+            .definitions
+            .iter()
+            .filter(|symbol| symbol.kind == SymbolKind::RegisterName)
+            .filter(|symbol| symbol.name == definition.name)
+            .position(|symbol| symbol == definition)?;
+
+        self.aliases
+            .iter()
+            .filter(|Alias(alias, _)| *alias == definition.name)
+            .nth(index)
+            .map(|Alias(_, register)| *register)
+    }
+
+    pub fn get_source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+
+    pub fn get_errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.get_errors().next().is_some()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .to_string()
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || fs::canonicalize(a).is_ok_and(|a| fs::canonicalize(b).is_ok_and(|b| a == b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_source() {
+        let program = assemble_str(
+            "constant led, 04\n\nstart:\n  load s0, 01\n  output s0, led\n  jump start\n",
+        );
+
+        assert!(!program.has_errors());
+        assert_eq!(program.get_instructions().len(), 3);
+        assert_eq!(program.find_label("start"), Some(0));
+        assert_eq!(program.find_constant("led"), Some(4));
+        assert_eq!(program.get_label_at(0), Some("start"));
+        assert_eq!(program.find_label_before(2), Some(("start", 2)));
+        assert_eq!(program.get_source_map().get_line(1), Some(5));
+        assert_eq!(program.get_source_map().get_address(6), Some(2));
+        assert_eq!(program.get_source_map().find_address_from(2), Some((0, 4)));
+        assert_eq!(program.get_source_line(5), Some("  output s0, led"));
+        assert_eq!(
+            program.find_references(SymbolKind::Constant, "led"),
+            vec![5]
+        );
+        assert_eq!(program.find_references(SymbolKind::Label, "start"), vec![6]);
+    }
+
+    #[test]
+    fn symbols() {
+        let program = assemble_str(
+            "\
+namereg s1, count
+constant max, 10
+loop: add count, 01
+compare count, max
+jump nz, loop
+load s0, loop'upper
+fetch s0, (count)
+",
+        );
+        let definitions: Vec<(SymbolKind, &str, usize)> = program
+            .get_definitions()
+            .iter()
+            .map(|symbol| (symbol.kind, symbol.name.as_str(), symbol.line))
+            .collect();
+
+        assert_eq!(
+            definitions,
+            vec![
+                (SymbolKind::RegisterName, "count", 1),
+                (SymbolKind::Constant, "max", 2),
+                (SymbolKind::Label, "loop", 3),
+            ]
+        );
+        assert_eq!(
+            program.find_references(SymbolKind::RegisterName, "count"),
+            vec![3, 4, 7]
+        );
+        assert_eq!(
+            program.find_references(SymbolKind::Label, "loop"),
+            vec![5, 6]
+        );
+    }
+
+    #[test]
+    fn listing() {
+        let program = assemble_str("constant led, 04\nstart: load s0, 01\n");
+        let listing = program.create_listing();
+
+        assert!(listing.contains("                1  constant led, 04\n"));
+        assert!(listing.contains("000   01001      2  start: load s0, 01\n"));
+        assert!(listing.contains("  000  start\n"));
+        assert!(listing.contains("  04   led\n"));
+
+        let program = assemble_str("STRING hi$, \"Hi\"\nload&return s0, hi$\nreturn\n");
+        let listing = program.create_listing();
+
+        assert!(listing.contains(
+            "000   21048      2  load&return s0, hi$\n001   21069\n002   25000      3  return\n"
+        ));
+    }
+
+    #[test]
+    fn errors_point_at_source_lines() {
+        let program = assemble_str("; Comment\n\nnamereg sF, status\nload sF, 01\njump nowhere\n");
+        let errors: Vec<&Diagnostic> = program.get_errors().collect();
+
+        assert!(program.has_errors());
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, 4);
+        assert_eq!(errors[1].line, 5);
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = std::env::temp_dir().join(format!("kcpsm6sim-program-{}", std::process::id()));
+        let main = dir.join("main.psm");
+        let routines = dir.join("routines.psm");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &main,
+            "start: CALL twice\nJUMP start\nINCLUDE \"routines.psm\"\n",
+        )
+        .unwrap();
+        fs::write(&routines, "; Routines\ntwice: ADD s0, s0\nRETURN\n").unwrap();
+
+        let program = assemble_file(&main).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(!program.has_errors());
+        assert_eq!(program.get_included_files(), &vec![routines.clone()]);
+        assert_eq!(program.get_source_map().get_line(2), Some(5));
+        assert_eq!(program.get_location(5), Some((Some(routines.as_path()), 2)));
+        assert_eq!(program.get_location(2), Some((Some(main.as_path()), 2)));
+        assert_eq!(program.find_line(Some(&routines), 3), Some(6));
+        assert_eq!(program.find_line(None, 1), Some(1));
+        assert_eq!(program.find_line(Some(&routines), 4), None);
+        assert_eq!(program.describe_line(5), "routines.psm:2");
+        assert_eq!(program.describe_line(2), "main.psm:2");
+        assert_eq!(program.find_file_line("routines.psm", 3), Some(6));
+        assert_eq!(program.find_file_line(main.to_str().unwrap(), 1), Some(1));
+        assert_eq!(program.find_file_line("other.psm", 1), None);
+        assert_eq!(assemble_str("load s0, 01\n").describe_line(1), "line 1");
+    }
+}
@@ -3,12 +3,19 @@ use std::{
     io::{BufRead, BufReader},
 };
 
+// Character literals (e.g. `","` or `";"`) may contain delimiters, comment markers or whitespace,
+// so everything between double quotes is left untouched when splitting a line.
+const QUOTE: char = '"';
+
 fn split_inclusive(input: &str, delimiter: &str) -> Vec<String> {
     let mut tokens = Vec::new();
     let mut start = 0;
+    let mut is_quoted = false;
 
     for (i, c) in input.char_indices() {
-        if delimiter.contains(c) {
+        if c == QUOTE {
+            is_quoted = !is_quoted;
+        } else if !is_quoted && delimiter.contains(c) {
             // Push the substring before the delimiter
             if start < i {
                 tokens.push(input[start..i].to_string());
@@ -29,32 +36,70 @@ fn split_inclusive(input: &str, delimiter: &str) -> Vec<String> {
 }
 
 fn remove_after_delimiter(input: String, delimiter: char) -> String {
-    if let Some(pos) = input.find(delimiter) {
-        input[..pos].to_string() // Return the substring before the delimiter
-    } else {
-        input // If the delimiter is not found, return the original string
+    let mut is_quoted = false;
+
+    for (pos, c) in input.char_indices() {
+        if c == QUOTE {
+            is_quoted = !is_quoted;
+        } else if !is_quoted && c == delimiter {
+            return input[..pos].to_string(); // Return the substring before the delimiter
+        }
+    }
+
+    input // If the delimiter is not found, return the original string
+}
+
+fn split_whitespace(input: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut is_quoted = false;
+
+    for c in input.chars() {
+        if c == QUOTE {
+            is_quoted = !is_quoted;
+        }
+
+        if !is_quoted && c.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+        } else {
+            word.push(c);
+        }
     }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
 }
 
 fn squish_between_delimiters(input: String) -> String {
     // In theory, there are only parentheses in Picoblaze assembly.
     // Add other delimiters just in case.
-    let opening_delimiter = vec!['(', '[', '{'];
-    let closing_delimiter = vec![')', ']', '}'];
+    let opening_delimiter = ['(', '[', '{'];
+    let closing_delimiter = [')', ']', '}'];
 
     let mut result = String::new();
 
     let mut is_inside = false;
+    let mut is_quoted = false;
     for c in input.chars() {
+        if c == QUOTE {
+            is_quoted = !is_quoted;
+        } else if is_quoted {
+            result.push(c);
+            continue;
+        }
+
         if opening_delimiter.contains(&c) {
             is_inside = true;
         } else if closing_delimiter.contains(&c) {
             is_inside = false;
         }
 
-        if !is_inside {
-            result.push(c);
-        } else if !c.is_whitespace() {
+        if !is_inside || !c.is_whitespace() {
             result.push(c);
         }
     }
@@ -62,7 +107,35 @@ fn squish_between_delimiters(input: String) -> String {
     result
 }
 
-#[derive(Debug)]
+/// Splits a STRING directive, keeping its text whole: everything from the first double quote to the
+/// last, so the text can have quotes of its own (`STRING q$, ""quoted""`).
+fn split_string_directive(line: &str) -> Option<Vec<String>> {
+    let trimmed = line.trim_start();
+    let keyword = trimmed.get(..6)?;
+
+    if !keyword.eq_ignore_ascii_case("string")
+        || !trimmed[6..].starts_with(|c: char| c.is_whitespace())
+    {
+        return None;
+    }
+
+    let (first, last) = (line.find(QUOTE)?, line.rfind(QUOTE)?);
+
+    if first == last {
+        return None;
+    }
+
+    let mut tokens = Vec::new();
+
+    for word in split_whitespace(&line[..first]) {
+        tokens.extend(split_inclusive(&word, ","));
+    }
+
+    tokens.push(line[first..=last].to_string());
+    Some(tokens)
+}
+
+#[derive(Debug, Default)]
 pub struct Reader {
     contents: Vec<Vec<String>>,
 }
@@ -100,21 +173,28 @@ impl Reader {
     }
 
     fn read_lines(&mut self, lines: &Vec<String>) {
+        // Every line is kept, even empty ones, so that the index of each line matches its line
+        // number in the source. This is what diagnostics and the source map rely on.
         for line in lines {
             if line.is_empty() || line.chars().all(|c| c.is_whitespace()) {
+                self.contents.push(Vec::new());
                 continue;
             }
 
             // Remove all comments from the code and squish tokens.
             let line = squish_between_delimiters(remove_after_delimiter(line.clone(), ';'));
 
+            if let Some(tokens) = split_string_directive(&line) {
+                self.contents.push(tokens);
+                continue;
+            }
+
             let mut tokens: Vec<String> = Vec::new();
 
             // Split each line by whitespace, convert them into strings and collect them into another string Vector.
-            let words: Vec<String> = line
-                .split_whitespace()
-                .map(|word| word.to_lowercase().into())
-                .collect();
+            // Words keep their original case, since labels, constants and NAMEREG names are case
+            // sensitive. It's up to the Tokenizer to match keywords case insensitively.
+            let words: Vec<String> = split_whitespace(&line);
 
             // Split each word into tokens now using a comma as delimiter, and keep the comma, using the 'split_inclusive' method.
             for word in words {
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+/// Maps program memory addresses to the source lines their instructions were written in.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    lines: BTreeMap<usize, usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap {
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, address: usize, line: usize) {
+        self.lines.insert(address, line);
+    }
+
+    /// Source line (starting from 1) of the instruction at the given address.
+    pub fn get_line(&self, address: usize) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// Address of the instruction written in the given source line, if there is one.
+    pub fn get_address(&self, line: usize) -> Option<usize> {
+        self.lines
+            .iter()
+            .find(|(_, l)| **l == line)
+            .map(|(address, _)| *address)
+    }
+
+    /// Addresses of every instruction written in the given source line, in order. Lines using a
+    /// STRING or TABLE hold one instruction per character or value.
+    pub fn get_addresses(&self, line: usize) -> Vec<usize> {
+        self.lines
+            .iter()
+            .filter(|(_, l)| **l == line)
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    /// First instruction written in the given source line or after it, as an (address, line) pair.
+    /// Useful to put breakpoints on lines that only hold comments or directives.
+    pub fn find_address_from(&self, line: usize) -> Option<(usize, usize)> {
+        self.lines
+            .iter()
+            .filter(|(_, l)| **l >= line)
+            .min_by_key(|(address, l)| (**l, **address))
+            .map(|(address, l)| (*address, *l))
+    }
+
+    /// Iterates over every (address, line) pair, sorted by address.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.lines.iter().map(|(address, line)| (*address, *line))
+    }
+}
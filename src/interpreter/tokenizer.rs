@@ -1,13 +1,14 @@
-// TODO: This enum is, at the moment, useless, since we treat any number literal as a decimal.
-// Remove at some point?
+use super::diagnostics::Diagnostic;
+
 #[derive(Debug, Clone, Copy)]
 pub enum NumberType {
     Decimal,
     Hexadecimal,
     Binary,
+    Character,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConditionType {
     IfZero,
     IfNonZero,
@@ -24,17 +25,25 @@ pub enum Token {
     Number(u32, NumberType),
     Address(u32),
     Condition(ConditionType),
+    /// The characters of a STRING or the values of a TABLE, once the Parser has resolved its
+    /// name.
+    Table(Vec<u8>),
     ConstantDirective,
     AddressDirective,
     NameregDirective,
+    StringDirective,
+    TableDirective,
     Comma,
-    Tilda, // @TODO: Add support for the NOT operator.
+    /// The NOT operator, which inverts the constant after it.
+    Tilda,
     Parentheses,
     EndOfLine,
 }
 
+#[derive(Default)]
 pub struct Tokenizer {
     tokens: Vec<Token>,
+    diagnostics: Vec<Diagnostic>,
 }
 
 // Found Char::is_digit to be a better solution.
@@ -46,11 +55,11 @@ pub struct Tokenizer {
 //     }
 // }
 
-// fn is_str_begin_of_comment(word: &String) -> bool {
+// fn is_str_begin_of_comment(word: &str) -> bool {
 //     word.contains(";")
 // }
 
-fn is_str_instruction(word: &String) -> bool {
+fn is_str_instruction(word: &str) -> bool {
     let instructions: Vec<&str> = vec![
         "add",
         "addcy",
@@ -92,35 +101,23 @@ fn is_str_instruction(word: &String) -> bool {
         "xor",
     ];
 
-    instructions.contains(&word.as_str())
+    instructions.contains(&word)
 }
 
-fn is_str_label(word: &String) -> bool {
-    word.ends_with(":")
+/// Whether a word is read as a mnemonic (or the directives among them) wherever it's written, so it
+/// can't name a label, constant or register.
+pub(crate) fn is_reserved_word(word: &str) -> bool {
+    is_str_instruction(&word.to_lowercase())
 }
 
-fn is_str_hex_number(word: &String) -> bool {
-    if word.len() == 2 {
-        return word.chars().all(|c| c.is_digit(16));
-    }
-
-    false
-}
-
-fn is_str_hex_address(word: &String) -> bool {
-    // TODO: This can cause issues with identifiers that are three letters long and
-    // characters range from 'a' to 'f'. E.g.: 'abc', 'def' etc.
-    if word.len() == 3 {
-        return word.chars().all(|c| c.is_digit(16));
-    }
-
-    false
+fn is_str_label(word: &str) -> bool {
+    word.ends_with(":")
 }
 
-fn is_str_binary_number(word: &String) -> bool {
-    // Now checking for binary literals
+fn is_str_binary_number(word: &str) -> bool {
+    // Binary literals are either 8 bits (constants) or 12 bits (addresses) long.
     if word.ends_with("'b") {
-        return word.len() == 10
+        return (word.len() == 10 || word.len() == 14)
             && word
                 .chars()
                 .take(word.len() - 2) // Make sure that the last two characters are not included.
@@ -130,68 +127,33 @@ fn is_str_binary_number(word: &String) -> bool {
     false
 }
 
-fn is_str_decimal_number(word: &String) -> bool {
+fn is_str_decimal_number(word: &str) -> bool {
     // Finally, decimal literals.
     if word.ends_with("'d") {
         return word
             .chars()
             .take(word.len() - 2) // Make sure that the last two characters are not included.
-            .all(|c| c.is_digit(10));
+            .all(|c| c.is_ascii_digit());
     }
 
     false
 }
 
-fn is_str_register(word: &String) -> bool {
-    if word.len() != 2 {
-        return false;
-    }
-
-    if word.chars().next().unwrap() != 's' {
-        return false;
-    }
-
-    if !word.chars().nth(1).unwrap().is_digit(16) {
-        return false;
-    }
-
-    true
-}
-
-fn is_str_deref_register(word: &String) -> bool {
-    // NOTE: This only parses unaliased registers, e.g. "(s1)".
-    // Aliases are handled by the Tokenizer.
-    if word.len() != 4 {
-        return false;
-    }
-
-    if !word.starts_with("(") {
-        return false;
-    }
-
-    if !word.ends_with(")") {
-        return false;
-    }
-
-    true
+fn is_str_character(word: &str) -> bool {
+    // Character literals are a single ASCII character between double quotes, e.g. "A".
+    word.chars().count() == 3 && word.starts_with('"') && word.ends_with('"')
 }
 
-fn is_str_double_deref_register(word: &String) -> bool {
-    // NOTE: This only parses unaliased registers, e.g. "(s1,s2)".
-    // Aliases are handled by the Tokenizer.
-    if word.len() != 7 {
-        return false;
-    }
-
-    if !word.contains(',') {
+fn is_str_register(word: &str) -> bool {
+    if word.len() != 2 {
         return false;
     }
 
-    if !word.starts_with("(") {
+    if !word.starts_with('s') {
         return false;
     }
 
-    if !word.ends_with(")") {
+    if !word.chars().nth(1).unwrap().is_ascii_hexdigit() {
         return false;
     }
 
@@ -200,122 +162,98 @@ fn is_str_double_deref_register(word: &String) -> bool {
 
 impl Tokenizer {
     pub fn new() -> Tokenizer {
-        Tokenizer { tokens: Vec::new() }
+        Tokenizer {
+            tokens: Vec::new(),
+            diagnostics: Vec::new(),
+        }
     }
 
     pub fn tokenize(&mut self, file_contents: Vec<Vec<String>>) -> &mut Tokenizer {
-        for (line_number, line) in file_contents.iter().enumerate() {
-            for (_word_number, word) in line.iter().enumerate() {
+        for (index, line) in file_contents.iter().enumerate() {
+            let line_number = index + 1;
+
+            for word in line.iter() {
+                // Mnemonics, directives and register names are case insensitive,
+                // while labels, constants and NAMEREG names keep the case they were written in.
+                let keyword = word.to_lowercase();
+
                 if word == "," {
                     self.tokens.push(Token::Comma);
                 } else if word == "~" {
                     self.tokens.push(Token::Tilda);
                 } else if word == "(" || word == ")" {
                     self.tokens.push(Token::Parentheses);
-                } else if word == "c" {
-                    self.tokens.push(Token::Condition(ConditionType::IfCarry));
-                } else if word == "nc" {
-                    self.tokens
-                        .push(Token::Condition(ConditionType::IfNonCarry));
-                } else if word == "z" {
-                    self.tokens.push(Token::Condition(ConditionType::IfZero));
-                } else if word == "nz" {
-                    self.tokens.push(Token::Condition(ConditionType::IfNonZero));
-                } else if word == "constant" {
+                } else if keyword == "constant" {
                     self.tokens.push(Token::ConstantDirective)
-                } else if word == "address" {
+                } else if keyword == "address" {
                     self.tokens.push(Token::AddressDirective);
-                } else if word == "namereg" {
+                } else if keyword == "namereg" {
                     self.tokens.push(Token::NameregDirective);
-                } else if is_str_instruction(&word) {
-                    self.tokens.push(Token::Instruction(word.clone()));
-                } else if is_str_label(&word) {
+                } else if keyword == "string" {
+                    self.tokens.push(Token::StringDirective);
+                } else if keyword == "table" {
+                    self.tokens.push(Token::TableDirective);
+                } else if is_str_instruction(&keyword) {
+                    self.tokens.push(Token::Instruction(keyword.clone()));
+                } else if is_str_label(word) {
                     self.tokens
                         .push(Token::Label(word[0..word.len() - 1].to_string()));
-                } else if is_str_hex_number(&word) {
-                    let number = u32::from_str_radix(word.as_str(), 16);
-
-                    match number {
-                        Ok(number) => self
-                            .tokens
-                            .push(Token::Number(number, NumberType::Hexadecimal)),
-                        Err(_) => {
-                            panic!("Unable to parse {} number, at line {}!", word, line_number)
-                        }
-                    }
-                } else if is_str_hex_address(&word) {
-                    let number = u32::from_str_radix(word.as_str(), 16);
-
-                    match number {
-                        Ok(number) => {
-                            // Only accept numbers lower than 1023 (3FF).
-                            if number <= 1023 {
-                                self.tokens.push(Token::Address(number));
-                            }
-                            // Otherwise, this must be an identifier of sorts (?).
-                            else {
-                                self.tokens.push(Token::Word(word.clone()));
-                            }
-                        }
-                        Err(_) => {
-                            panic!("Unable to parse {} number, at line {}!", word, line_number)
-                        }
-                    }
-                } else if is_str_binary_number(&word) {
+                } else if is_str_binary_number(&keyword) {
                     // Remove the last two characters of literal
                     // E.g. "00010001'b" becomes "00010001"
                     let literal: &str = &word[..word.len() - 2];
                     let number = u32::from_str_radix(literal, 2);
 
+                    // Whether the number fits is up to the Parser, as that depends on where it's used.
                     match number {
-                        Ok(number) => {
-                            if number > 255 {
-                                println!(
-                                    "WARNING: Possible overflow on {} ({}), at line {}!",
-                                    number, literal, line_number
-                                );
-                            }
-
-                            self.tokens.push(Token::Number(number, NumberType::Binary))
-                        }
-                        Err(_) => {
-                            panic!("Unable to parse {} number, at line {}!", word, line_number)
-                        }
+                        Ok(number) => self.tokens.push(Token::Number(number, NumberType::Binary)),
+                        Err(_) => self.number_error(word, line_number, NumberType::Binary),
                     }
-                } else if is_str_decimal_number(&word) {
+                } else if is_str_decimal_number(&keyword) {
                     // Remove the last two characters of literal
                     // E.g. "1234'd" becomes "1234"
                     let literal: &str = &word[..word.len() - 2];
-                    let number = u32::from_str_radix(literal, 10);
+                    let number = literal.parse::<u32>();
 
                     match number {
-                        Ok(number) => {
-                            if number > 255 {
-                                println!(
-                                    "WARNING: Possible overflow on {}, at line {}!",
-                                    number, line_number
-                                );
-                            }
-
-                            self.tokens.push(Token::Number(number, NumberType::Decimal))
-                        }
-                        Err(_) => {
-                            panic!("Unable to parse {} number, at line {}!", word, line_number)
-                        }
+                        Ok(number) => self.tokens.push(Token::Number(number, NumberType::Decimal)),
+                        Err(_) => self.number_error(word, line_number, NumberType::Decimal),
                     }
-                } else if is_str_register(&word) {
+                } else if is_str_character(word) {
+                    let character = word.chars().nth(1).unwrap();
+
+                    if character.is_ascii() {
+                        self.tokens
+                            .push(Token::Number(character as u32, NumberType::Character));
+                    } else {
+                        self.diagnostics.push(Diagnostic::error(
+                            line_number,
+                            format!(
+                                "Unable to parse {} character, only ASCII characters are supported.",
+                                word
+                            ),
+                        ));
+                        self.tokens.push(Token::Number(0, NumberType::Character));
+                    }
+                } else if is_str_register(&keyword) {
                     // Remove the first letter 's' from the register to access the number.
                     // E.g. 's3' reffers to the 4th (starting from 0) register.
                     let number = u8::from_str_radix(&word[1..], 16);
 
                     match number {
                         Ok(number) => self.tokens.push(Token::Register(number)),
-                        Err(_) => panic!(
-                            "Unable to parse {} register, at line {}!",
-                            word, line_number
-                        ),
+                        Err(_) => {
+                            self.diagnostics.push(Diagnostic::error(
+                                line_number,
+                                format!("Unable to parse {} register.", word),
+                            ));
+                            self.tokens.push(Token::Register(0));
+                        }
                     }
                 } else {
+                    // Hexadecimal literals (e.g. `FF` or `3FF`) and conditions end up here too,
+                    // since they can't be told apart from identifiers without knowing where
+                    // they're used. The Parser takes care of them.
                     self.tokens.push(Token::Word(word.clone()));
                 }
             }
@@ -326,7 +264,21 @@ impl Tokenizer {
         self
     }
 
+    fn number_error(&mut self, word: &str, line_number: usize, number_type: NumberType) {
+        self.diagnostics.push(Diagnostic::error(
+            line_number,
+            format!("Unable to parse {} number.", word),
+        ));
+
+        // Keep a placeholder so the rest of the line can still be parsed.
+        self.tokens.push(Token::Number(0, number_type));
+    }
+
     pub fn get_tokens(&self) -> &Vec<Token> {
         &self.tokens
     }
+
+    pub fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
 }
@@ -0,0 +1,453 @@
+use std::fmt;
+
+/// A JSON value, as written by the graph, trace and report exports and read by the language
+/// servers. Object members keep the order they were given in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from `(name, value)` pairs.
+    pub fn object<I, K>(members: I) -> Json
+    where
+        I: IntoIterator<Item = (K, Json)>,
+        K: Into<String>,
+    {
+        Json::Object(
+            members
+                .into_iter()
+                .map(|(name, value)| (name.into(), value))
+                .collect(),
+        )
+    }
+
+    /// Builds an array from anything that converts to JSON values.
+    pub fn array<I, T>(values: I) -> Json
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Json>,
+    {
+        Json::Array(values.into_iter().map(Into::into).collect())
+    }
+
+    /// Reads a JSON document, which can't have anything but whitespace after the value.
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = JsonParser {
+            chars: text.chars().collect(),
+            position: 0,
+        };
+        let value = parser.value()?;
+
+        parser.skip_whitespace();
+
+        match parser.position < parser.chars.len() {
+            true => Err(parser.error("unexpected text after the value")),
+            false => Ok(value),
+        }
+    }
+
+    /// Member of an object, `None` for anything else or a missing member.
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(member, _)| member == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Numbers that are whole and not negative.
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_f64()
+            .filter(|value| *value >= 0.0 && value.fract() == 0.0 && *value <= u64::MAX as f64)
+            .map(|value| value as u64)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Json::Null)
+    }
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl JsonParser {
+    fn error(&self, message: &str) -> String {
+        format!("{} at character {}", message, self.position + 1)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        let end = self.position + word.len();
+
+        if end <= self.chars.len()
+            && self.chars[self.position..end]
+                .iter()
+                .copied()
+                .eq(word.chars())
+        {
+            self.position = end;
+            Ok(value)
+        } else {
+            Err(self.error("unknown value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.position += 1;
+
+                let mut values = Vec::new();
+
+                self.skip_whitespace();
+
+                if self.peek() == Some(']') {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+
+                    match self.peek() {
+                        Some(',') => self.position += 1,
+                        Some(']') => {
+                            self.position += 1;
+                            return Ok(Json::Array(values));
+                        }
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.position += 1;
+
+                let mut members = Vec::new();
+
+                self.skip_whitespace();
+
+                if self.peek() == Some('}') {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+
+                loop {
+                    self.skip_whitespace();
+
+                    if self.peek() != Some('"') {
+                        return Err(self.error("expected a member name"));
+                    }
+
+                    let name = self.string()?;
+
+                    self.expect(':')?;
+                    members.push((name, self.value()?));
+                    self.skip_whitespace();
+
+                    match self.peek() {
+                        Some(',') => self.position += 1,
+                        Some('}') => {
+                            self.position += 1;
+                            return Ok(Json::Object(members));
+                        }
+                        _ => return Err(self.error("expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.position += 1;
+        }
+
+        let text: String = self.chars[start..self.position].iter().collect();
+
+        text.parse::<f64>()
+            .map(Json::Number)
+            .map_err(|_| self.error(&format!("'{}' isn't a valid number", text)))
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let digits: String = self
+            .chars
+            .get(self.position..self.position + 4)
+            .ok_or_else(|| self.error("incomplete escape"))?
+            .iter()
+            .collect();
+
+        self.position += 4;
+        u32::from_str_radix(&digits, 16).map_err(|_| self.error("invalid escape"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // Skip the opening quote.
+        self.position += 1;
+
+        let mut text = String::new();
+
+        loop {
+            let c = self
+                .peek()
+                .ok_or_else(|| self.error("unterminated string"))?;
+
+            self.position += 1;
+
+            match c {
+                '"' => return Ok(text),
+                '\\' => {
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| self.error("unterminated string"))?;
+
+                    self.position += 1;
+
+                    match escape {
+                        '"' | '\\' | '/' => text.push(escape),
+                        'b' => text.push('\u{8}'),
+                        'f' => text.push('\u{c}'),
+                        'n' => text.push('\n'),
+                        'r' => text.push('\r'),
+                        't' => text.push('\t'),
+                        'u' => {
+                            let mut code = self.hex_escape()?;
+
+                            // Characters outside the BMP are written as UTF-16 surrogate pairs.
+                            if (0xD800..0xDC00).contains(&code)
+                                && self.chars.get(self.position..self.position + 2)
+                                    == Some(&['\\', 'u'])
+                            {
+                                self.position += 2;
+
+                                let low = self.hex_escape()?;
+
+                                code = 0x10000
+                                    + ((code - 0xD800) << 10)
+                                    + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+
+                            text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Json {
+        Json::Bool(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Json {
+        Json::String(value)
+    }
+}
+
+macro_rules! json_from_number {
+    ($($number:ty),*) => {
+        $(impl From<$number> for Json {
+            fn from(value: $number) -> Json {
+                Json::Number(value as f64)
+            }
+        })*
+    };
+}
+
+json_from_number!(u8, u16, u32, u64, usize, i32, i64, f64);
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    write!(f, "\"")
+}
+
+/// Writes compact JSON, on a single line.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) if !value.is_finite() => write!(f, "null"),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                write!(f, "[")?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write!(f, "{}", value)?;
+                }
+
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let value = Json::object([
+            ("name", Json::from("a \"b\"\n")),
+            ("address", Json::from(0x3FFusize)),
+            ("time", Json::from(0.5)),
+            ("label", Json::from(None::<&str>)),
+            ("lines", Json::array([1u32, 2])),
+            ("empty", Json::object(Vec::<(String, Json)>::new())),
+        ]);
+
+        assert_eq!(
+            value.to_string(),
+            r#"{"name":"a \"b\"\n","address":1023,"time":0.5,"label":null,"lines":[1,2],"empty":{}}"#
+        );
+        assert_eq!(Json::parse(&value.to_string()), Ok(value));
+    }
+
+    #[test]
+    fn parse() {
+        let value = Json::parse(
+            r#" {"id": 3, "params": {"text": "\u00e9\ud83d\ude00\t", "ok": true,
+                "list": [-1.5e2, null, []]}} "#,
+        )
+        .unwrap();
+        let params = value.get("params").unwrap();
+
+        assert_eq!(value.get("id").and_then(Json::as_u64), Some(3));
+        assert_eq!(
+            params.get("text").and_then(Json::as_str),
+            Some("\u{e9}\u{1F600}\t")
+        );
+        assert_eq!(params.get("ok").and_then(Json::as_bool), Some(true));
+        assert_eq!(
+            params.get("list").and_then(Json::as_array).unwrap()[0].as_f64(),
+            Some(-150.0)
+        );
+        assert!(params.get("missing").is_none());
+
+        assert!(Json::parse("{\"a\": }").is_err());
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("\"open").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+}
@@ -0,0 +1,26 @@
+pub mod analysis;
+pub mod coverage;
+pub mod dap;
+pub mod interpreter;
+pub mod json;
+pub mod lsp;
+pub mod peripherals;
+pub mod profile;
+pub mod protocol;
+pub mod stimulus;
+pub mod system;
+pub mod toml;
+pub mod trace;
+pub mod vcd;
+
+pub use interpreter::{interpreter::*, parser::*, reader::*, tokenizer::*};
+
+pub use analysis::lints::{lint, Lint, LintConfig, LintLevel};
+pub use analysis::stack::{analyze_stack, StackReport};
+pub use analysis::timing::{analyze_timing, LoopBound, LoopBounds, Timing};
+pub use interpreter::diagnostics::{Diagnostic, Severity};
+pub use interpreter::encoding::{decode, encode};
+pub use interpreter::image::{read_hex, write_hex};
+pub use interpreter::ports::{PortAccess, PortHandler, PortState, PortTransaction};
+pub use interpreter::program::{assemble_file, assemble_source, assemble_str, Program};
+pub use interpreter::source_map::SourceMap;
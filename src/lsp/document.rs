@@ -0,0 +1,304 @@
+use std::path::Path;
+
+use crate::json::Json;
+use crate::{assemble_source, lint, Constant, Diagnostic, LintConfig, Program, Symbol};
+
+/// Part of a line, in the UTF-16 columns LSP counts in. Lines start from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Range {
+    pub fn to_json(self) -> Json {
+        let position = |character: usize| {
+            Json::object([
+                ("line", Json::from(self.line)),
+                ("character", Json::from(character)),
+            ])
+        };
+
+        Json::object([("start", position(self.start)), ("end", position(self.end))])
+    }
+}
+
+/// A word of a line, with its range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    pub text: String,
+    pub range: Range,
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "_&@$#".contains(c)
+}
+
+/// Splits the code of a line (comments and character literals left out) into words.
+fn split_words(line: usize, text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut column = 0;
+    let mut quoted = false;
+
+    for c in text.chars() {
+        if !quoted && c == ';' {
+            break;
+        }
+
+        if !quoted && is_word_char(c) {
+            current.get_or_insert((column, String::new())).1.push(c);
+        } else if let Some((start, text)) = current.take() {
+            words.push(Word {
+                text,
+                range: Range {
+                    line,
+                    start,
+                    end: column,
+                },
+            });
+        }
+
+        if c == '"' {
+            quoted = !quoted;
+        }
+
+        column += c.len_utf16();
+    }
+
+    if let Some((start, text)) = current {
+        words.push(Word {
+            text,
+            range: Range {
+                line,
+                start,
+                end: column,
+            },
+        });
+    }
+
+    words
+}
+
+/// An open PSM file and what the assembler made of it.
+pub struct Document {
+    lines: Vec<String>,
+    program: Program,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    /// Assembles the text of a document, finding the files it includes next to its path if it has
+    /// one.
+    pub fn new(text: &str, path: Option<&Path>) -> Document {
+        let program = assemble_source(text, path);
+        let mut diagnostics = program.get_diagnostics().clone();
+
+        diagnostics.extend(lint(&program, &LintConfig::new()));
+
+        // Problems in included files are shown on the INCLUDE that brings them in.
+        for diagnostic in &mut diagnostics {
+            diagnostic.line = (1..=diagnostic.line)
+                .rev()
+                .find_map(|line| own_line(&program, line))
+                .unwrap_or(1);
+        }
+
+        Document {
+            lines: text.lines().map(str::to_string).collect(),
+            program,
+            diagnostics,
+        }
+    }
+
+    pub fn get_program(&self) -> &Program {
+        &self.program
+    }
+
+    /// The line of the document a line of the program is on, `None` for lines of included files.
+    pub fn own_line(&self, line: usize) -> Option<usize> {
+        own_line(&self.program, line)
+    }
+
+    /// Assembler and lint diagnostics.
+    pub fn get_diagnostics(&self) -> &Vec<Diagnostic> {
+        &self.diagnostics
+    }
+
+    /// Range of a whole line (from 0), leading whitespace left out.
+    pub fn line_range(&self, line: usize) -> Range {
+        let text = self.lines.get(line).map_or("", String::as_str);
+        let indent = text.len() - text.trim_start().len();
+
+        Range {
+            line,
+            start: text[..indent].encode_utf16().count(),
+            end: text.encode_utf16().count(),
+        }
+    }
+
+    pub fn get_words(&self, line: usize) -> Vec<Word> {
+        self.lines
+            .get(line)
+            .map_or_else(Vec::new, |text| split_words(line, text))
+    }
+
+    /// The word at a position, if it's on one.
+    pub fn word_at(&self, line: usize, character: usize) -> Option<Word> {
+        self.get_words(line)
+            .into_iter()
+            .find(|word| word.range.start <= character && character <= word.range.end)
+    }
+
+    /// Where a label, constant or register name written on a line (from 0) of the document is
+    /// defined, following the NAMEREG names in force on that line.
+    pub fn find_definition(&self, name: &str, line: usize) -> Option<&Symbol> {
+        let program = &self.program;
+        let line = program.find_line(program.get_path(), line + 1)?;
+
+        program.find_definition(name, line)
+    }
+
+    /// The NAMEREG name a register goes by on a line (from 0) of the document.
+    pub fn find_register_name(&self, register: u8, line: usize) -> Option<&str> {
+        let program = &self.program;
+        let line = program.find_line(program.get_path(), line + 1)?;
+
+        program.find_register_name(register, line)
+    }
+
+    /// Every place a symbol is written, its definition first if `declaration` is set. Other
+    /// definitions of a NAMEREG name and their uses are left out.
+    pub fn find_occurrences(&self, symbol: &Symbol, declaration: bool) -> Vec<Range> {
+        let mut lines = Vec::new();
+
+        if declaration {
+            lines.push(symbol.line);
+        }
+
+        for line in self.program.find_uses(symbol) {
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+
+        lines
+            .into_iter()
+            .filter_map(|line| self.own_line(line))
+            .flat_map(|line| self.get_words(line - 1))
+            .filter(|word| word.text == symbol.name)
+            .map(|word| word.range)
+            .collect()
+    }
+
+    /// What a symbol stands for, e.g. `0x0F (15)` for a constant.
+    pub fn describe_value(&self, symbol: &Symbol) -> Option<String> {
+        let program = &self.program;
+        let name = symbol.name.as_str();
+
+        if let Some(address) = program.find_label(name) {
+            return Some(format!("label at address 0x{:03X}", address));
+        }
+
+        if let Some(Constant(_, value)) = program
+            .get_constants()
+            .iter()
+            .find(|Constant(constant, _)| constant == name)
+        {
+            return Some(format!(
+                "constant 0x{:02X} ({}'d, {:08b}'b)",
+                value, value, value
+            ));
+        }
+
+        program
+            .find_named_register(symbol)
+            .map(|register| format!("register s{:X}", register))
+    }
+}
+
+fn own_line(program: &Program, line: usize) -> Option<usize> {
+    match program.get_location(line)? {
+        (file, line) if file == program.get_path() => Some(line),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SymbolKind;
+
+    #[test]
+    fn words() {
+        let words = split_words(3, "start: load&return s0, \"; \" ; jump@ here");
+        let texts: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+
+        assert_eq!(texts, vec!["start", "load&return", "s0"]);
+        assert_eq!(
+            words[1].range,
+            Range {
+                line: 3,
+                start: 7,
+                end: 18
+            }
+        );
+    }
+
+    #[test]
+    fn occurrences() {
+        let document = Document::new(
+            "constant max, 0A\nstart: load s0, max\n  compare s0, max\n  jump nz, start\n",
+            None,
+        );
+        let symbol = document.find_definition("max", 2).unwrap();
+
+        assert_eq!(symbol.kind, SymbolKind::Constant);
+        assert_eq!(document.find_occurrences(symbol, true).len(), 3);
+        assert_eq!(
+            document.find_occurrences(symbol, false)[1],
+            Range {
+                line: 2,
+                start: 14,
+                end: 17
+            }
+        );
+        assert_eq!(document.word_at(3, 11).unwrap().text, "start");
+        assert_eq!(
+            document.describe_value(symbol).as_deref(),
+            Some("constant 0x0A (10'd, 00001010'b)")
+        );
+        assert_eq!(document.line_range(2).start, 2);
+    }
+
+    #[test]
+    fn redefined_register_names() {
+        let document = Document::new(
+            "NAMEREG s1, cnt\nLOAD cnt, 01\nNAMEREG cnt, s1\nNAMEREG s2, cnt\nLOAD cnt, 02\n",
+            None,
+        );
+        let first = document.find_definition("cnt", 1).unwrap();
+        let second = document.find_definition("cnt", 4).unwrap();
+        let lines = |symbol| -> Vec<usize> {
+            document
+                .find_occurrences(symbol, true)
+                .iter()
+                .map(|range| range.line)
+                .collect()
+        };
+
+        assert_eq!((first.line, second.line), (1, 4));
+        assert_eq!(document.find_definition("cnt", 2).unwrap().line, 1);
+        assert_eq!(lines(first), vec![0, 1, 2]);
+        assert_eq!(lines(second), vec![3, 4]);
+        assert_eq!(
+            document.describe_value(first).as_deref(),
+            Some("register s1")
+        );
+        assert_eq!(
+            document.describe_value(second).as_deref(),
+            Some("register s2")
+        );
+    }
+}
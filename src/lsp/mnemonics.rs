@@ -0,0 +1,243 @@
+/// An instruction or directive, for hovers and completion.
+pub struct Mnemonic {
+    pub name: &'static str,
+    pub syntax: &'static str,
+    pub description: &'static str,
+}
+
+const fn mnemonic(name: &'static str, syntax: &'static str, description: &'static str) -> Mnemonic {
+    Mnemonic {
+        name,
+        syntax,
+        description,
+    }
+}
+
+pub const MNEMONICS: &[Mnemonic] = &[
+    mnemonic(
+        "ADD",
+        "ADD sX, sY | ADD sX, kk",
+        "Adds to sX. Sets C on overflow and Z when the result is 0.",
+    ),
+    mnemonic(
+        "ADDCY",
+        "ADDCY sX, sY | ADDCY sX, kk",
+        "Adds to sX with the carry. Sets C on overflow; Z is only kept set when the result is 0 \
+         and Z was already set, for multi-byte additions.",
+    ),
+    mnemonic(
+        "AND",
+        "AND sX, sY | AND sX, kk",
+        "Bitwise AND into sX. Clears C, sets Z when the result is 0.",
+    ),
+    mnemonic(
+        "CALL",
+        "CALL aaa | CALL Z|NZ|C|NC, aaa",
+        "Pushes the address of the next instruction on the call stack and jumps to aaa, if the \
+         condition holds.",
+    ),
+    mnemonic(
+        "CALL@",
+        "CALL@ (sX, sY)",
+        "Calls the address in sX (upper 4 bits) and sY (lower 8 bits).",
+    ),
+    mnemonic(
+        "COMPARE",
+        "COMPARE sX, sY | COMPARE sX, kk",
+        "Subtracts from sX without storing the result. Sets C when the operand is larger than \
+         sX and Z when they're equal.",
+    ),
+    mnemonic(
+        "COMPARECY",
+        "COMPARECY sX, sY | COMPARECY sX, kk",
+        "COMPARE with the carry, for multi-byte comparisons. Z is only kept set when the bytes \
+         are equal and Z was already set.",
+    ),
+    mnemonic(
+        "DISABLE",
+        "DISABLE INTERRUPT",
+        "Stops the interrupt input from being taken.",
+    ),
+    mnemonic(
+        "ENABLE",
+        "ENABLE INTERRUPT",
+        "Lets the interrupt input be taken.",
+    ),
+    mnemonic(
+        "FETCH",
+        "FETCH sX, (sY) | FETCH sX, ss",
+        "Reads a scratch pad memory location into sX.",
+    ),
+    mnemonic(
+        "HWBUILD",
+        "HWBUILD sX",
+        "Loads the hwbuild value of the processor into sX. Sets C, sets Z when the value is 0.",
+    ),
+    mnemonic(
+        "INPUT",
+        "INPUT sX, (sY) | INPUT sX, pp",
+        "Reads an input port into sX, with a read strobe.",
+    ),
+    mnemonic(
+        "JUMP",
+        "JUMP aaa | JUMP Z|NZ|C|NC, aaa",
+        "Continues at aaa, if the condition holds.",
+    ),
+    mnemonic(
+        "JUMP@",
+        "JUMP@ (sX, sY)",
+        "Jumps to the address in sX (upper 4 bits) and sY (lower 8 bits).",
+    ),
+    mnemonic(
+        "LOAD",
+        "LOAD sX, sY | LOAD sX, kk",
+        "Copies a register or a constant into sX. Flags are unchanged.",
+    ),
+    mnemonic(
+        "LOAD&RETURN",
+        "LOAD&RETURN sX, kk",
+        "Loads a constant into sX and returns. Used to build tables.",
+    ),
+    mnemonic(
+        "OR",
+        "OR sX, sY | OR sX, kk",
+        "Bitwise OR into sX. Clears C, sets Z when the result is 0.",
+    ),
+    mnemonic(
+        "OUTPUT",
+        "OUTPUT sX, (sY) | OUTPUT sX, pp",
+        "Writes sX to an output port, with a write strobe.",
+    ),
+    mnemonic(
+        "OUTPUTK",
+        "OUTPUTK kk, p",
+        "Writes a constant to one of the 16 constant-optimised output ports, with a k_write \
+         strobe.",
+    ),
+    mnemonic(
+        "REGBANK",
+        "REGBANK A|B",
+        "Switches to register bank A or B.",
+    ),
+    mnemonic(
+        "RETURN",
+        "RETURN | RETURN Z|NZ|C|NC",
+        "Continues after the last CALL, if the condition holds.",
+    ),
+    mnemonic(
+        "RETURNI",
+        "RETURNI ENABLE|DISABLE",
+        "Returns from an interrupt, restoring the flags and the register bank, and enables or \
+         disables interrupts.",
+    ),
+    mnemonic(
+        "RL",
+        "RL sX",
+        "Rotates sX left. C is the bit moved around, Z is set when sX is 0.",
+    ),
+    mnemonic(
+        "RR",
+        "RR sX",
+        "Rotates sX right. C is the bit moved around, Z is set when sX is 0.",
+    ),
+    mnemonic(
+        "SL0",
+        "SL0 sX",
+        "Shifts sX left, shifting in a 0. C is the bit shifted out.",
+    ),
+    mnemonic(
+        "SL1",
+        "SL1 sX",
+        "Shifts sX left, shifting in a 1. C is the bit shifted out, Z is cleared.",
+    ),
+    mnemonic(
+        "SLA",
+        "SLA sX",
+        "Shifts sX left, shifting in the carry. C is the bit shifted out.",
+    ),
+    mnemonic(
+        "SLX",
+        "SLX sX",
+        "Shifts sX left, shifting in its bit 0. C is the bit shifted out.",
+    ),
+    mnemonic(
+        "SR0",
+        "SR0 sX",
+        "Shifts sX right, shifting in a 0. C is the bit shifted out.",
+    ),
+    mnemonic(
+        "SR1",
+        "SR1 sX",
+        "Shifts sX right, shifting in a 1. C is the bit shifted out, Z is cleared.",
+    ),
+    mnemonic(
+        "SRA",
+        "SRA sX",
+        "Shifts sX right, shifting in the carry. C is the bit shifted out.",
+    ),
+    mnemonic(
+        "SRX",
+        "SRX sX",
+        "Shifts sX right, shifting in its bit 7 (sign extension). C is the bit shifted out.",
+    ),
+    mnemonic(
+        "STAR",
+        "STAR sX, sY | STAR sX, kk",
+        "Copies a register of the active bank, or a constant, into sX of the other bank.",
+    ),
+    mnemonic(
+        "STORE",
+        "STORE sX, (sY) | STORE sX, ss",
+        "Writes sX to a scratch pad memory location.",
+    ),
+    mnemonic(
+        "SUB",
+        "SUB sX, sY | SUB sX, kk",
+        "Subtracts from sX. Sets C on underflow and Z when the result is 0.",
+    ),
+    mnemonic(
+        "SUBCY",
+        "SUBCY sX, sY | SUBCY sX, kk",
+        "Subtracts from sX with the carry (borrow). Sets C on underflow; Z is only kept set when \
+         the result is 0 and Z was already set.",
+    ),
+    mnemonic(
+        "TEST",
+        "TEST sX, sY | TEST sX, kk",
+        "ANDs with sX without storing the result. Sets Z when the result is 0 and C when it has \
+         an odd number of 1s.",
+    ),
+    mnemonic(
+        "TESTCY",
+        "TESTCY sX, sY | TESTCY sX, kk",
+        "TEST with the carry, for multi-byte tests.",
+    ),
+    mnemonic(
+        "XOR",
+        "XOR sX, sY | XOR sX, kk",
+        "Bitwise XOR into sX. Clears C, sets Z when the result is 0.",
+    ),
+    mnemonic(
+        "ADDRESS",
+        "ADDRESS aaa",
+        "Directive: the next instructions are assembled from address aaa.",
+    ),
+    mnemonic(
+        "CONSTANT",
+        "CONSTANT name, kk",
+        "Directive: gives a name to a constant value.",
+    ),
+    mnemonic(
+        "NAMEREG",
+        "NAMEREG sX, name",
+        "Directive: gives a name to a register from this line on. The old name can't be used \
+         until it's given back.",
+    ),
+];
+
+/// Looks up an instruction or directive, ignoring case.
+pub fn find_mnemonic(name: &str) -> Option<&'static Mnemonic> {
+    MNEMONICS
+        .iter()
+        .find(|mnemonic| mnemonic.name.eq_ignore_ascii_case(name))
+}
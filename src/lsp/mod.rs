@@ -0,0 +1,831 @@
+mod document;
+mod mnemonics;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+pub use document::{Document, Range, Word};
+pub use mnemonics::{find_mnemonic, Mnemonic, MNEMONICS};
+
+use crate::json::Json;
+use crate::protocol::{read_message, write_message};
+use crate::{Alias, Constant, Label, Severity, Symbol, SymbolKind};
+
+// JSON-RPC error codes.
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+/// A JSON-RPC error code and message.
+type RpcError = (i32, String);
+type Response = Result<Json, RpcError>;
+
+/// Runs a language server for PSM files on the given streams (standard input and output in
+/// practice) until the client sends `exit` or closes the input.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shut_down: false,
+    };
+
+    while let Some(message) = read_message(&mut input)? {
+        if !server.handle(&message)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+struct Server<W: Write> {
+    output: W,
+    /// Open documents by URI.
+    documents: HashMap<String, Document>,
+    shut_down: bool,
+}
+
+fn invalid_params(message: &str) -> RpcError {
+    (INVALID_PARAMS, message.to_string())
+}
+
+fn get_uri(params: &Json) -> Result<&str, RpcError> {
+    params
+        .get("textDocument")
+        .and_then(|document| document.get("uri"))
+        .and_then(Json::as_str)
+        .ok_or_else(|| invalid_params("missing textDocument.uri"))
+}
+
+/// The `position` of a request, as a (line, character) pair.
+fn get_position(params: &Json) -> Result<(usize, usize), RpcError> {
+    let position = params.get("position");
+    let field = |name: &str| {
+        position
+            .and_then(|position| position.get(name))
+            .and_then(Json::as_u64)
+            .map(|value| value as usize)
+    };
+
+    match (field("line"), field("character")) {
+        (Some(line), Some(character)) => Ok((line, character)),
+        _ => Err(invalid_params("missing position")),
+    }
+}
+
+/// The path of a `file://` URI, where a document's included files are found.
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+
+    while i < encoded.len() {
+        let escaped = (encoded[i] == b'%')
+            .then(|| encoded.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn location(uri: &str, range: Range) -> Json {
+    Json::object([("uri", Json::from(uri)), ("range", range.to_json())])
+}
+
+/// Whether a name can be given to a label, constant or register.
+fn is_valid_name(name: &str) -> bool {
+    let is_register = name.len() == 2
+        && name.to_lowercase().starts_with('s')
+        && name[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+    !name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !is_register
+        && find_mnemonic(name).is_none()
+}
+
+/// Register number of `s0` to `sF`.
+fn parse_register(name: &str) -> Option<u8> {
+    let digit = name.strip_prefix('s').or_else(|| name.strip_prefix('S'))?;
+
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+impl<W: Write> Server<W> {
+    /// Handles a message, `false` once the client asked the server to exit.
+    fn handle(&mut self, message: &Json) -> io::Result<bool> {
+        let Some(method) = message.get("method").and_then(Json::as_str) else {
+            // Responses to requests the server never sends.
+            return Ok(true);
+        };
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+
+        let Some(id) = message.get("id") else {
+            return self.notification(method, &params);
+        };
+
+        let response = match method {
+            _ if self.shut_down => Err((INVALID_REQUEST, "the server was shut down".to_string())),
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Json::Null)
+            }
+            "textDocument/hover" => self.hover(&params),
+            "textDocument/definition" => self.definition(&params),
+            "textDocument/references" => self.references(&params),
+            "textDocument/rename" => self.rename(&params),
+            "textDocument/documentSymbol" => self.document_symbols(&params),
+            "textDocument/completion" => self.completion(&params),
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method '{}'", method))),
+        };
+
+        let mut reply = vec![("jsonrpc", Json::from("2.0")), ("id", id.clone())];
+
+        match response {
+            Ok(result) => reply.push(("result", result)),
+            Err((code, message)) => reply.push((
+                "error",
+                Json::object([("code", Json::from(code)), ("message", Json::from(message))]),
+            )),
+        }
+
+        write_message(&mut self.output, &Json::object(reply))?;
+        Ok(true)
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> io::Result<bool> {
+        let uri = get_uri(params).map(str::to_string);
+
+        match (method, uri) {
+            ("exit", _) => return Ok(false),
+            ("textDocument/didOpen", Ok(uri)) => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+
+                let document = Document::new(text, uri_path(&uri).as_deref());
+
+                self.documents.insert(uri.clone(), document);
+                self.publish_diagnostics(&uri)?;
+            }
+            ("textDocument/didChange", Ok(uri)) => {
+                // Only full document updates are asked for, the last one wins.
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+
+                if let Some(text) = text {
+                    let document = Document::new(text, uri_path(&uri).as_deref());
+
+                    self.documents.insert(uri.clone(), document);
+                    self.publish_diagnostics(&uri)?;
+                }
+            }
+            ("textDocument/didClose", Ok(uri)) => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri)?;
+            }
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = self.documents.get(uri).map_or_else(Vec::new, |document| {
+            document
+                .get_diagnostics()
+                .iter()
+                .map(|diagnostic| {
+                    let severity = match diagnostic.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    };
+
+                    Json::object([
+                        (
+                            "range",
+                            document
+                                .line_range(diagnostic.line.saturating_sub(1))
+                                .to_json(),
+                        ),
+                        ("severity", Json::from(severity)),
+                        ("code", Json::from(diagnostic.lint)),
+                        ("source", Json::from("kcpsm6sim")),
+                        ("message", Json::from(diagnostic.message.as_str())),
+                    ])
+                })
+                .collect()
+        });
+
+        let notification = Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from("textDocument/publishDiagnostics")),
+            (
+                "params",
+                Json::object([
+                    ("uri", Json::from(uri)),
+                    ("diagnostics", Json::Array(diagnostics)),
+                ]),
+            ),
+        ]);
+
+        write_message(&mut self.output, &notification)
+    }
+
+    fn get_document(&self, params: &Json) -> Result<&Document, RpcError> {
+        let uri = get_uri(params)?;
+
+        self.documents
+            .get(uri)
+            .ok_or_else(|| invalid_params(&format!("'{}' isn't open", uri)))
+    }
+
+    /// The word at the position of a request, and the symbol it names if it does.
+    fn find_word(&self, params: &Json) -> Result<Option<(Word, Option<Symbol>)>, RpcError> {
+        let document = self.get_document(params)?;
+        let (line, character) = get_position(params)?;
+
+        Ok(document.word_at(line, character).map(|word| {
+            let symbol = document.find_definition(&word.text, line).cloned();
+
+            (word, symbol)
+        }))
+    }
+
+    fn hover(&self, params: &Json) -> Response {
+        let document = self.get_document(params)?;
+        let Some((word, symbol)) = self.find_word(params)? else {
+            return Ok(Json::Null);
+        };
+
+        let text = if let Some(symbol) = symbol {
+            let program = document.get_program();
+            let line = match program.get_location(symbol.line) {
+                Some((Some(file), line)) if document.own_line(symbol.line).is_none() => {
+                    let name = file.file_name().unwrap_or(file.as_os_str());
+
+                    format!("{} of {}", line, name.to_string_lossy())
+                }
+                _ => document.own_line(symbol.line).unwrap_or(symbol.line).to_string(),
+            };
+
+            format!(
+                "`{}`: {}\n\nDefined on line {}.",
+                symbol.name,
+                document.describe_value(&symbol).unwrap_or_default(),
+                line
+            )
+        } else if let Some(mnemonic) = find_mnemonic(&word.text) {
+            format!("```\n{}\n```\n{}", mnemonic.syntax, mnemonic.description)
+        } else if let Some(register) = parse_register(&word.text) {
+            match document.find_register_name(register, word.range.line) {
+                Some(name) => format!("register s{:X}, named `{}`", register, name),
+                None => format!("register s{:X}", register),
+            }
+        } else {
+            return Ok(Json::Null);
+        };
+
+        Ok(Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", Json::from("markdown")),
+                    ("value", Json::from(text)),
+                ]),
+            ),
+            ("range", word.range.to_json()),
+        ]))
+    }
+
+    fn definition(&self, params: &Json) -> Response {
+        let document = self.get_document(params)?;
+        let uri = get_uri(params)?;
+
+        let included = |symbol: &Symbol| match document.get_program().get_location(symbol.line) {
+            Some((Some(file), line)) if document.own_line(symbol.line).is_none() => {
+                let range = Range {
+                    line: line - 1,
+                    start: 0,
+                    end: 0,
+                };
+
+                Some(location(&format!("file://{}", file.display()), range))
+            }
+            _ => None,
+        };
+
+        Ok(match self.find_word(params)? {
+            // Definitions in included files are shown in them.
+            Some((_, Some(symbol))) => included(&symbol).unwrap_or_else(|| {
+                document
+                    .find_occurrences(&symbol, true)
+                    .first()
+                    .map_or(Json::Null, |range| location(uri, *range))
+            }),
+            _ => Json::Null,
+        })
+    }
+
+    fn references(&self, params: &Json) -> Response {
+        let document = self.get_document(params)?;
+        let uri = get_uri(params)?;
+        let declaration = params
+            .get("context")
+            .and_then(|context| context.get("includeDeclaration"))
+            .and_then(Json::as_bool)
+            .unwrap_or(true);
+
+        Ok(match self.find_word(params)? {
+            Some((_, Some(symbol))) => Json::Array(
+                document
+                    .find_occurrences(&symbol, declaration)
+                    .into_iter()
+                    .map(|range| location(uri, range))
+                    .collect(),
+            ),
+            _ => Json::Array(Vec::new()),
+        })
+    }
+
+    fn rename(&self, params: &Json) -> Response {
+        let document = self.get_document(params)?;
+        let uri = get_uri(params)?;
+        let name = params
+            .get("newName")
+            .and_then(Json::as_str)
+            .ok_or_else(|| invalid_params("missing newName"))?;
+
+        if !is_valid_name(name) {
+            return Err(invalid_params(&format!("'{}' isn't a valid name", name)));
+        }
+
+        let Some((_, Some(symbol))) = self.find_word(params)? else {
+            return Err(invalid_params(
+                "only labels, constants and register names can be renamed",
+            ));
+        };
+
+        let edits = document
+            .find_occurrences(&symbol, true)
+            .into_iter()
+            .map(|range| Json::object([("range", range.to_json()), ("newText", Json::from(name))]));
+
+        Ok(Json::object([(
+            "changes",
+            Json::object([(uri, Json::Array(edits.collect()))]),
+        )]))
+    }
+
+    fn document_symbols(&self, params: &Json) -> Response {
+        let document = self.get_document(params)?;
+        let symbols = document
+            .get_program()
+            .get_definitions()
+            .iter()
+            .filter_map(|symbol| Some((symbol, document.own_line(symbol.line)?)))
+            .map(|(symbol, line)| {
+                // LSP symbol kinds.
+                let kind = match symbol.kind {
+                    SymbolKind::Label => 12,
+                    SymbolKind::Constant => 14,
+                    SymbolKind::RegisterName => 13,
+                };
+                let range = document.line_range(line - 1);
+                let selection = document
+                    .find_occurrences(symbol, true)
+                    .first()
+                    .copied()
+                    .unwrap_or(range);
+
+                Json::object([
+                    ("name", Json::from(symbol.name.as_str())),
+                    ("detail", Json::from(document.describe_value(symbol))),
+                    ("kind", Json::from(kind)),
+                    ("range", range.to_json()),
+                    ("selectionRange", selection.to_json()),
+                ])
+            });
+
+        Ok(Json::Array(symbols.collect()))
+    }
+
+    fn completion(&self, params: &Json) -> Response {
+        let program = self.get_document(params)?.get_program();
+        // LSP completion item kinds.
+        let item = |label: String, kind: u32, detail: String| {
+            Json::object([
+                ("label", Json::from(label)),
+                ("kind", Json::from(kind)),
+                ("detail", Json::from(detail)),
+            ])
+        };
+
+        let mut items: Vec<Json> = MNEMONICS
+            .iter()
+            .map(|mnemonic| {
+                let mut item = item(mnemonic.name.to_string(), 14, mnemonic.syntax.to_string());
+
+                if let Json::Object(members) = &mut item {
+                    members.push((
+                        "documentation".to_string(),
+                        Json::from(mnemonic.description),
+                    ));
+                }
+
+                item
+            })
+            .collect();
+
+        items.extend(program.get_labels().iter().map(|Label(name, address)| {
+            item(name.clone(), 3, format!("label at 0x{:03X}", address))
+        }));
+        items.extend(program.get_constants().iter().map(|Constant(name, value)| {
+            item(name.clone(), 21, format!("constant 0x{:02X}", value))
+        }));
+        items.extend(program.get_aliases().iter().map(|Alias(name, register)| {
+            item(name.clone(), 6, format!("register s{:X}", register))
+        }));
+        items.extend(
+            (0..16).map(|register| item(format!("s{:X}", register), 6, "register".to_string())),
+        );
+        items.extend(
+            ["Z", "NZ", "C", "NC"]
+                .iter()
+                .map(|condition| item(condition.to_string(), 14, "condition".to_string())),
+        );
+
+        Ok(Json::Array(items))
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // Full document updates.
+                ("textDocumentSync", Json::from(1u32)),
+                ("hoverProvider", Json::from(true)),
+                ("definitionProvider", Json::from(true)),
+                ("referencesProvider", Json::from(true)),
+                ("renameProvider", Json::from(true)),
+                ("documentSymbolProvider", Json::from(true)),
+                (
+                    "completionProvider",
+                    Json::object(Vec::<(String, Json)>::new()),
+                ),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", Json::from("kcpsm6sim")),
+                ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///program.psm";
+    const SOURCE: &str = "\
+constant limit, 0A
+namereg s5, counter
+start: load counter, limit
+loop:  sub counter, 01
+       jump nz, loop
+       jump start
+";
+
+    fn request(id: u32, method: &str, params: Json) -> Json {
+        Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", Json::from(id)),
+            ("method", Json::from(method)),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ])
+    }
+
+    fn at(line: u32, character: u32) -> Json {
+        Json::object([
+            ("textDocument", Json::object([("uri", Json::from(URI))])),
+            (
+                "position",
+                Json::object([
+                    ("line", Json::from(line)),
+                    ("character", Json::from(character)),
+                ]),
+            ),
+        ])
+    }
+
+    /// Sends messages to a server, returns what it wrote back.
+    fn run(messages: &[Json]) -> Vec<Json> {
+        let mut input = Vec::new();
+        let mut output = Vec::new();
+
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+
+        serve(&input[..], &mut output).unwrap();
+
+        let mut replies = Vec::new();
+        let mut output = &output[..];
+
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+
+        replies
+    }
+
+    fn open(source: &str) -> Json {
+        notification(
+            "textDocument/didOpen",
+            Json::object([(
+                "textDocument",
+                Json::object([
+                    ("uri", Json::from(URI)),
+                    ("languageId", Json::from("psm")),
+                    ("version", Json::from(1u32)),
+                    ("text", Json::from(source)),
+                ]),
+            )]),
+        )
+    }
+
+    fn result(reply: &Json) -> &Json {
+        reply.get("result").unwrap()
+    }
+
+    #[test]
+    fn lifecycle_and_diagnostics() {
+        let replies = run(&[
+            request(1, "initialize", Json::object(Vec::<(String, Json)>::new())),
+            open("start: load s0, 01\n  jump nowhere\n"),
+            request(2, "textDocument/formatting", at(0, 0)),
+            request(3, "shutdown", Json::Null),
+            notification("exit", Json::Null),
+            request(4, "shutdown", Json::Null),
+        ]);
+
+        assert_eq!(replies.len(), 4);
+        assert_eq!(
+            result(&replies[0])
+                .get("capabilities")
+                .and_then(|capabilities| capabilities.get("hoverProvider")),
+            Some(&Json::Bool(true))
+        );
+
+        let diagnostics = replies[1]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        let diagnostic = &diagnostics.as_array().unwrap()[0];
+
+        assert_eq!(
+            diagnostic.get("range").unwrap(),
+            &Range {
+                line: 1,
+                start: 2,
+                end: 14
+            }
+            .to_json()
+        );
+        assert_eq!(diagnostic.get("severity").and_then(Json::as_u64), Some(1));
+        assert_eq!(
+            replies[2]
+                .get("error")
+                .and_then(|error| error.get("code"))
+                .and_then(Json::as_f64),
+            Some(METHOD_NOT_FOUND as f64)
+        );
+        assert!(result(&replies[3]).is_null());
+    }
+
+    #[test]
+    fn navigation() {
+        let references = Json::object([
+            ("textDocument", Json::object([("uri", Json::from(URI))])),
+            (
+                "position",
+                Json::object([("line", Json::from(4u32)), ("character", Json::from(17u32))]),
+            ),
+            (
+                "context",
+                Json::object([("includeDeclaration", Json::from(false))]),
+            ),
+        ]);
+        let replies = run(&[
+            open(SOURCE),
+            request(1, "textDocument/hover", at(2, 24)),
+            request(2, "textDocument/hover", at(3, 8)),
+            request(3, "textDocument/hover", at(1, 9)),
+            request(4, "textDocument/definition", at(2, 15)),
+            request(5, "textDocument/references", references),
+            request(6, "textDocument/hover", at(0, 30)),
+        ]);
+        let hover = |reply: &Json| {
+            result(reply)
+                .get("contents")
+                .and_then(|contents| contents.get("value"))
+                .and_then(Json::as_str)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(
+            hover(&replies[1]),
+            "`limit`: constant 0x0A (10'd, 00001010'b)\n\nDefined on line 1."
+        );
+        assert!(hover(&replies[2]).starts_with("```\nSUB sX, sY | SUB sX, kk\n```\nSubtracts"));
+        assert_eq!(hover(&replies[3]), "register s5, named `counter`");
+        assert_eq!(
+            result(&replies[4]),
+            &location(
+                URI,
+                Range {
+                    line: 1,
+                    start: 12,
+                    end: 19
+                }
+            )
+        );
+        assert_eq!(
+            result(&replies[5]),
+            &Json::Array(vec![location(
+                URI,
+                Range {
+                    line: 4,
+                    start: 16,
+                    end: 20
+                }
+            )])
+        );
+        assert!(result(&replies[6]).is_null());
+    }
+
+    #[test]
+    fn register_hover_follows_renames() {
+        let replies = run(&[
+            open("namereg s1, cnt\nload cnt, 05\nnamereg cnt, s1\nload s1, 01\n"),
+            request(1, "textDocument/hover", at(0, 8)),
+            request(2, "textDocument/hover", at(3, 5)),
+        ]);
+        let hover = |reply: &Json| {
+            result(reply)
+                .get("contents")
+                .and_then(|contents| contents.get("value"))
+                .and_then(Json::as_str)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(hover(&replies[1]), "register s1, named `cnt`");
+        assert_eq!(hover(&replies[2]), "register s1");
+    }
+
+    #[test]
+    fn editing() {
+        let mut rename = at(3, 2);
+
+        if let Json::Object(members) = &mut rename {
+            members.push(("newName".to_string(), Json::from("again")));
+        }
+
+        let mut bad_rename = rename.clone();
+
+        if let Json::Object(members) = &mut bad_rename {
+            members.last_mut().unwrap().1 = Json::from("sA");
+        }
+
+        let replies = run(&[
+            open(SOURCE),
+            request(1, "textDocument/rename", rename),
+            request(2, "textDocument/rename", bad_rename),
+            request(3, "textDocument/documentSymbol", at(0, 0)),
+            request(4, "textDocument/completion", at(5, 7)),
+        ]);
+
+        let edits = result(&replies[1])
+            .get("changes")
+            .unwrap()
+            .get(URI)
+            .unwrap();
+
+        assert_eq!(edits.as_array().unwrap().len(), 2);
+        assert!(replies[2].get("error").is_some());
+
+        let symbols = result(&replies[3]).as_array().unwrap();
+        let names: Vec<&str> = symbols
+            .iter()
+            .map(|symbol| symbol.get("name").and_then(Json::as_str).unwrap())
+            .collect();
+
+        assert_eq!(names, vec!["limit", "counter", "start", "loop"]);
+        assert_eq!(symbols[2].get("kind").and_then(Json::as_u64), Some(12));
+
+        let labels: Vec<&str> = result(&replies[4])
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|item| item.get("label").and_then(Json::as_str))
+            .collect();
+
+        assert!(labels.contains(&"LOAD&RETURN"));
+        assert!(labels.contains(&"loop"));
+        assert!(labels.contains(&"limit"));
+        assert!(labels.contains(&"counter"));
+        assert!(labels.contains(&"sF"));
+    }
+
+    #[test]
+    fn redefined_register_names() {
+        let source =
+            "NAMEREG s1, cnt\nLOAD cnt, 01\nNAMEREG cnt, s1\nNAMEREG s2, cnt\nLOAD cnt, 02\n";
+        let rename = |line, name: &str| {
+            let mut params = at(line, 6);
+
+            if let Json::Object(members) = &mut params {
+                members.push(("newName".to_string(), Json::from(name)));
+            }
+
+            params
+        };
+        let replies = run(&[
+            open(source),
+            request(1, "textDocument/hover", at(4, 6)),
+            request(2, "textDocument/definition", at(4, 6)),
+            request(3, "textDocument/rename", rename(4, "total")),
+            request(4, "textDocument/rename", rename(1, "count")),
+        ]);
+        let edited_lines = |reply: &Json| -> Vec<u64> {
+            result(reply)
+                .get("changes")
+                .and_then(|changes| changes.get(URI))
+                .and_then(Json::as_array)
+                .unwrap()
+                .iter()
+                .filter_map(|edit| edit.get("range")?.get("start")?.get("line")?.as_u64())
+                .collect()
+        };
+
+        assert_eq!(
+            result(&replies[1])
+                .get("contents")
+                .and_then(|contents| contents.get("value"))
+                .and_then(Json::as_str),
+            Some("`cnt`: register s2\n\nDefined on line 4.")
+        );
+        assert_eq!(
+            result(&replies[2]),
+            &location(
+                URI,
+                Range {
+                    line: 3,
+                    start: 12,
+                    end: 15
+                }
+            )
+        );
+        assert_eq!(edited_lines(&replies[3]), vec![3, 4]);
+        assert_eq!(edited_lines(&replies[4]), vec![0, 1, 2]);
+    }
+}
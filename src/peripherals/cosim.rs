@@ -0,0 +1,653 @@
+//! Co-simulation with an HDL testbench, or anything else that plays the peripherals, over a
+//! socket. The simulator is the server; the protocol is made of text lines so a testbench can
+//! speak it with its own file or socket I/O.
+//!
+//! The simulator sends, with cycles in decimal and ports and values in hexadecimal:
+//!
+//! | Line                            | When                                  | Answer  |
+//! |---------------------------------|---------------------------------------|---------|
+//! | `HELLO <version> <clock Hz>`    | once the client connects              |         |
+//! | `SYNC <cycle>`                  | at cycle 0, then when the `RUN` is up | `RUN`   |
+//! | `READ <cycle> <port>`           | INPUT                                 | `VALUE` |
+//! | `WRITE <cycle> <port> <value>`  | OUTPUT                                |         |
+//! | `WRITEK <cycle> <port> <value>` | OUTPUTK                               |         |
+//! | `ACK <cycle>`                   | the interrupt is acknowledged         |         |
+//! | `END <cycle>`                   | the run is over                       |         |
+//!
+//! The client answers `READ` with `VALUE <value>`, and `SYNC` with `RUN <cycles>` to run that
+//! many more clock cycles before the next `SYNC`, or a bare `RUN` for no more of them. The
+//! simulation waits for the answer, so it stays in step with the testbench. Before answering,
+//! the client can send `INTERRUPT 1` or `INTERRUPT 0` to set the level of the interrupt input,
+//! and instead of answering, `STOP` ends the run.
+//!
+//! [`CoSimClient`] is a reference client. [`CoSimulation::spawn`] speaks the same protocol with
+//! a program on its standard input and output, without `SYNC`s, so a script can play a device:
+//!
+//! ```sh
+//! # A sensor that reads as the last value written to it.
+//! last=00
+//! while read request cycle port value; do
+//!     case $request in
+//!         READ) echo "VALUE $last" ;;
+//!         WRITE) last=$value ;;
+//!     esac
+//! done
+//! ```
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::PortHandler;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// What the simulator sends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    Hello { version: u32, clock: f64 },
+    Sync { cycle: u64 },
+    Read { cycle: u64, port: u8 },
+    Write { cycle: u64, port: u8, value: u8 },
+    WriteK { cycle: u64, port: u8, value: u8 },
+    Ack { cycle: u64 },
+    End { cycle: u64 },
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Hello { version, clock } => write!(f, "HELLO {} {}", version, clock),
+            Request::Sync { cycle } => write!(f, "SYNC {}", cycle),
+            Request::Read { cycle, port } => write!(f, "READ {} {:02X}", cycle, port),
+            Request::Write { cycle, port, value } => {
+                write!(f, "WRITE {} {:02X} {:02X}", cycle, port, value)
+            }
+            Request::WriteK { cycle, port, value } => {
+                write!(f, "WRITEK {} {:X} {:02X}", cycle, port, value)
+            }
+            Request::Ack { cycle } => write!(f, "ACK {}", cycle),
+            Request::End { cycle } => write!(f, "END {}", cycle),
+        }
+    }
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Request, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || format!("'{}' isn't a request", line);
+        let cycle = |index: usize| -> Result<u64, String> {
+            words
+                .get(index)
+                .and_then(|word| word.parse().ok())
+                .ok_or_else(invalid)
+        };
+        let byte = |index: usize| -> Result<u8, String> {
+            words
+                .get(index)
+                .and_then(|word| u8::from_str_radix(word, 16).ok())
+                .ok_or_else(invalid)
+        };
+
+        match words.first().copied() {
+            Some("HELLO") if words.len() == 3 => Ok(Request::Hello {
+                version: words[1].parse().map_err(|_| invalid())?,
+                clock: words[2].parse().map_err(|_| invalid())?,
+            }),
+            Some("SYNC") if words.len() == 2 => Ok(Request::Sync { cycle: cycle(1)? }),
+            Some("READ") if words.len() == 3 => Ok(Request::Read {
+                cycle: cycle(1)?,
+                port: byte(2)?,
+            }),
+            Some("WRITE") if words.len() == 4 => Ok(Request::Write {
+                cycle: cycle(1)?,
+                port: byte(2)?,
+                value: byte(3)?,
+            }),
+            Some("WRITEK") if words.len() == 4 => Ok(Request::WriteK {
+                cycle: cycle(1)?,
+                port: byte(2)?,
+                value: byte(3)?,
+            }),
+            Some("ACK") if words.len() == 2 => Ok(Request::Ack { cycle: cycle(1)? }),
+            Some("END") if words.len() == 2 => Ok(Request::End { cycle: cycle(1)? }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// What the client sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Value(u8),
+    /// Cycles to run before the next `SYNC`, or none for no more of them.
+    Run(Option<u64>),
+    Interrupt(bool),
+    Stop,
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Value(value) => write!(f, "VALUE {:02X}", value),
+            Reply::Run(Some(cycles)) => write!(f, "RUN {}", cycles),
+            Reply::Run(None) => f.write_str("RUN"),
+            Reply::Interrupt(level) => write!(f, "INTERRUPT {}", *level as u8),
+            Reply::Stop => f.write_str("STOP"),
+        }
+    }
+}
+
+impl FromStr for Reply {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Reply, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let reply = match words[..] {
+            ["VALUE", value] => u8::from_str_radix(value, 16).ok().map(Reply::Value),
+            ["RUN"] => Some(Reply::Run(None)),
+            ["RUN", cycles] => cycles.parse().ok().map(|cycles| Reply::Run(Some(cycles))),
+            ["INTERRUPT", "0"] => Some(Reply::Interrupt(false)),
+            ["INTERRUPT", "1"] => Some(Reply::Interrupt(true)),
+            ["STOP"] => Some(Reply::Stop),
+            _ => None,
+        };
+
+        reply.ok_or_else(|| format!("'{}' isn't a reply", line))
+    }
+}
+
+/// Where the answers come from.
+enum Lines {
+    Reader(Box<dyn BufRead + Send>),
+    /// Lines read on a thread of their own, so waiting for them can time out.
+    Channel(Receiver<io::Result<String>>, Duration),
+}
+
+impl Lines {
+    /// The next line, `None` at the end of the input. `asked` is what it answers, for timeouts.
+    fn next(&mut self, asked: &str) -> io::Result<Option<String>> {
+        match self {
+            Lines::Reader(reader) => {
+                let mut line = String::new();
+
+                match reader.read_line(&mut line)? {
+                    0 => Ok(None),
+                    _ => Ok(Some(line)),
+                }
+            }
+            Lines::Channel(receiver, timeout) => match receiver.recv_timeout(*timeout) {
+                Ok(line) => line.map(Some),
+                Err(RecvTimeoutError::Disconnected) => Ok(None),
+                Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no answer to {} within {:?}", asked, timeout),
+                )),
+            },
+        }
+    }
+}
+
+/// The connection to the client, shared with the [`CoSimHandle`].
+struct Link {
+    lines: Lines,
+    /// What the other end is called in errors.
+    peer: &'static str,
+    writer: Box<dyn Write + Send>,
+    interrupt: bool,
+    /// Cycle of the next `SYNC`.
+    sync_at: Option<u64>,
+    error: Option<String>,
+    /// Whether the error is an answer that didn't come in time.
+    timed_out: bool,
+    stopped: bool,
+}
+
+impl Link {
+    /// Whether the run goes on with the client.
+    fn is_live(&self) -> bool {
+        self.error.is_none() && !self.stopped
+    }
+
+    fn send(&mut self, request: Request) {
+        // The client still gets told about the end of a run it stopped.
+        if self.error.is_some() {
+            return;
+        }
+
+        let sent = writeln!(self.writer, "{}", request).and_then(|_| self.writer.flush());
+
+        if let Err(error) = sent {
+            self.error = Some(error.to_string());
+        }
+    }
+
+    /// The answer to a request, described by `asked`, after the interrupt levels sent before it.
+    /// `None` if the client stopped the run or something went wrong.
+    fn answer(&mut self, asked: &str) -> Option<Reply> {
+        while self.is_live() {
+            match self.lines.next(asked) {
+                Ok(None) => self.error = Some(format!("{} disconnected", self.peer)),
+                Ok(Some(line)) if line.trim().is_empty() => {}
+                Ok(Some(line)) => match line.trim().parse::<Reply>() {
+                    Ok(Reply::Interrupt(level)) => self.interrupt = level,
+                    Ok(Reply::Stop) => self.stopped = true,
+                    Ok(reply) => return Some(reply),
+                    Err(message) => self.error = Some(message),
+                },
+                Err(error) => {
+                    self.timed_out = error.kind() == io::ErrorKind::TimedOut;
+                    self.error = Some(error.to_string());
+                }
+            }
+        }
+
+        None
+    }
+
+    fn unexpected(&mut self, expected: &str, reply: Reply) {
+        self.error = Some(format!("expected {}, got '{}'", expected, reply));
+    }
+}
+
+/// Ports played by a co-simulation client: every access is sent to it, and INPUTs wait for its
+/// answer.
+pub struct CoSimulation {
+    link: Arc<Mutex<Link>>,
+    /// The program playing the ports, for those that were spawned.
+    child: Option<(Child, Duration)>,
+}
+
+impl CoSimulation {
+    /// Starts the protocol on a connection, `clock` being the clock frequency in Hz.
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+        clock: f64,
+    ) -> CoSimulation {
+        let lines = Lines::Reader(Box::new(BufReader::new(reader)));
+
+        CoSimulation::start(lines, "the client", Box::new(writer), Some(0), clock)
+    }
+
+    fn start(
+        lines: Lines,
+        peer: &'static str,
+        writer: Box<dyn Write + Send>,
+        sync_at: Option<u64>,
+        clock: f64,
+    ) -> CoSimulation {
+        let mut link = Link {
+            lines,
+            peer,
+            writer,
+            interrupt: false,
+            sync_at,
+            error: None,
+            timed_out: false,
+            stopped: false,
+        };
+
+        link.send(Request::Hello {
+            version: PROTOCOL_VERSION,
+            clock,
+        });
+
+        CoSimulation {
+            link: Arc::new(Mutex::new(link)),
+            child: None,
+        }
+    }
+
+    /// Starts a program that plays the ports on its standard input and output, without `SYNC`s.
+    /// Answers that take longer than `timeout` are errors.
+    pub fn spawn(command: &mut Command, clock: f64, timeout: Duration) -> io::Result<CoSimulation> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let lines = Lines::Channel(receiver, timeout);
+        let mut cosim = CoSimulation::start(lines, "the process", Box::new(stdin), None, clock);
+
+        cosim.child = Some((child, timeout));
+        Ok(cosim)
+    }
+
+    pub fn from_tcp(stream: TcpStream, clock: f64) -> io::Result<CoSimulation> {
+        stream.set_nodelay(true)?;
+
+        Ok(CoSimulation::new(stream.try_clone()?, stream, clock))
+    }
+
+    #[cfg(unix)]
+    pub fn from_unix(
+        stream: std::os::unix::net::UnixStream,
+        clock: f64,
+    ) -> io::Result<CoSimulation> {
+        Ok(CoSimulation::new(stream.try_clone()?, stream, clock))
+    }
+
+    /// What the simulation loop needs to know about the client.
+    pub fn handle(&self) -> CoSimHandle {
+        CoSimHandle {
+            link: Arc::clone(&self.link),
+        }
+    }
+}
+
+impl PortHandler for CoSimulation {
+    fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        let mut link = self.link.lock().unwrap();
+
+        link.send(Request::Read { cycle, port });
+
+        match link.answer(&format!("INPUT port {:02X} at cycle {}", port, cycle)) {
+            Some(Reply::Value(value)) => value,
+            Some(reply) => {
+                link.unexpected("VALUE", reply);
+                0
+            }
+            None => 0,
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        let request = Request::Write { cycle, port, value };
+
+        self.link.lock().unwrap().send(request);
+    }
+
+    fn output_k(&mut self, port: u8, value: u8, cycle: u64) {
+        let request = Request::WriteK { cycle, port, value };
+
+        self.link.lock().unwrap().send(request);
+    }
+
+    fn interrupt(&mut self, _cycle: u64) -> bool {
+        self.link.lock().unwrap().interrupt
+    }
+
+    fn interrupt_ack(&mut self, cycle: u64) {
+        self.link.lock().unwrap().send(Request::Ack { cycle });
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        let mut link = self.link.lock().unwrap();
+
+        if link.sync_at.is_none_or(|at| cycle < at) {
+            return;
+        }
+
+        link.send(Request::Sync { cycle });
+
+        match link.answer(&format!("SYNC at cycle {}", cycle)) {
+            Some(Reply::Run(cycles)) => link.sync_at = cycles.map(|cycles| cycle + cycles.max(1)),
+            Some(reply) => link.unexpected("RUN", reply),
+            None => {}
+        }
+    }
+}
+
+impl Drop for CoSimulation {
+    /// Closes the standard input of a spawned program, and kills it if it doesn't exit in time.
+    fn drop(&mut self) {
+        let Some((child, timeout)) = &mut self.child else {
+            return;
+        };
+
+        if let Ok(mut link) = self.link.lock() {
+            link.writer = Box::new(io::sink());
+        }
+
+        let start = Instant::now();
+
+        while let Ok(None) = child.try_wait() {
+            if start.elapsed() >= *timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+/// The simulation's side of a [`CoSimulation`].
+#[derive(Clone)]
+pub struct CoSimHandle {
+    link: Arc<Mutex<Link>>,
+}
+
+impl CoSimHandle {
+    /// What went wrong with the client, after which its ports read as 0.
+    pub fn get_error(&self) -> Option<String> {
+        self.link.lock().unwrap().error.clone()
+    }
+
+    /// Whether the error is the client taking too long to answer, which is a fault of the run
+    /// rather than of the link.
+    pub fn is_timed_out(&self) -> bool {
+        self.link.lock().unwrap().timed_out
+    }
+
+    /// Whether the client sent `STOP`.
+    pub fn is_stopped(&self) -> bool {
+        self.link.lock().unwrap().stopped
+    }
+
+    /// Tells the client the run ended at `cycle`.
+    pub fn finish(&self, cycle: u64) {
+        self.link.lock().unwrap().send(Request::End { cycle });
+    }
+}
+
+/// The reference client: it connects to a simulator and answers its requests.
+pub struct CoSimClient {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+}
+
+impl CoSimClient {
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> CoSimClient {
+        CoSimClient {
+            reader: Box::new(BufReader::new(reader)),
+            writer: Box::new(writer),
+        }
+    }
+
+    pub fn connect_tcp(address: impl ToSocketAddrs) -> io::Result<CoSimClient> {
+        let stream = TcpStream::connect(address)?;
+
+        stream.set_nodelay(true)?;
+        Ok(CoSimClient::new(stream.try_clone()?, stream))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix(path: impl AsRef<Path>) -> io::Result<CoSimClient> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+
+        Ok(CoSimClient::new(stream.try_clone()?, stream))
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect_unix(_path: impl AsRef<Path>) -> io::Result<CoSimClient> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets aren't supported on this platform",
+        ))
+    }
+
+    /// The next request, `None` once the simulator closed the connection.
+    pub fn receive(&mut self) -> io::Result<Option<Request>> {
+        let mut line = String::new();
+
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        line.trim()
+            .parse()
+            .map(Some)
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+    }
+
+    pub fn send(&mut self, reply: Reply) -> io::Result<()> {
+        writeln!(self.writer, "{}", reply)?;
+        self.writer.flush()
+    }
+
+    /// Answers requests with the replies `device` gives for them until the run ends, and returns
+    /// the cycle it ended at. The replies to a `READ` must end with a `VALUE`, those to a `SYNC`
+    /// with a `RUN`, unless they're a `STOP`.
+    pub fn run(mut self, mut device: impl FnMut(&Request) -> Vec<Reply>) -> io::Result<u64> {
+        while let Some(request) = self.receive()? {
+            if let Request::End { cycle } = request {
+                return Ok(cycle);
+            }
+
+            for reply in device(&request) {
+                self.send(reply)?;
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the simulator closed the connection before the end of the run",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, StepEvent};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn messages() {
+        for line in [
+            "HELLO 1 100000000",
+            "SYNC 0",
+            "READ 12 01",
+            "WRITE 14 04 2A",
+            "WRITEK 16 1 FF",
+            "ACK 20",
+            "END 40",
+        ] {
+            assert_eq!(line.parse::<Request>().unwrap().to_string(), line);
+        }
+
+        for line in [
+            "VALUE 0A",
+            "RUN 100",
+            "RUN",
+            "INTERRUPT 1",
+            "INTERRUPT 0",
+            "STOP",
+        ] {
+            assert_eq!(line.parse::<Reply>().unwrap().to_string(), line);
+        }
+
+        assert!("READ 12".parse::<Request>().is_err());
+        assert!("VALUE 100".parse::<Reply>().is_err());
+        assert!("INTERRUPT 2".parse::<Reply>().is_err());
+    }
+
+    #[test]
+    fn testbench_plays_the_ports() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // A counter on port 01 that a testbench increments on each write to port 02, and an
+        // interrupt raised at cycle 20.
+        let client = thread::spawn(move || {
+            let client = CoSimClient::connect_tcp(address).unwrap();
+            let mut counter = 0x10;
+            let mut log = Vec::new();
+            let end = client
+                .run(|request| {
+                    log.push(request.to_string());
+
+                    match request {
+                        Request::Sync { cycle: 0 } => vec![Reply::Run(Some(20))],
+                        Request::Sync { .. } => vec![Reply::Interrupt(true), Reply::Run(None)],
+                        Request::Ack { .. } => vec![],
+                        Request::Read { .. } => vec![Reply::Value(counter)],
+                        Request::Write { .. } => {
+                            counter += 1;
+                            vec![]
+                        }
+                        _ => vec![],
+                    }
+                })
+                .unwrap();
+
+            (end, log)
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let cosim = CoSimulation::from_tcp(stream, 100e6).unwrap();
+        let handle = cosim.handle();
+        let program = assemble_str(
+            "enable interrupt\nloop: input s0, 01\noutput s0, 02\njump loop\naddress 3FF\nreturni disable\n",
+        );
+        let mut sim = program.create_simulation();
+
+        sim.set_port_handler(Box::new(cosim));
+
+        while sim.get_cycles() < 30 {
+            if let Ok(StepEvent::Halted) | Err(_) = sim.step() {
+                break;
+            }
+        }
+
+        handle.finish(sim.get_cycles());
+        assert_eq!(handle.get_error(), None);
+
+        let (end, log) = client.join().unwrap();
+
+        assert_eq!(end, 30);
+        assert_eq!(
+            log,
+            [
+                "HELLO 1 100000000",
+                "SYNC 0",
+                "READ 2 01",
+                "WRITE 4 02 10",
+                "READ 8 01",
+                "WRITE 10 02 11",
+                "READ 14 01",
+                "WRITE 16 02 12",
+                "SYNC 20",
+                "ACK 20",
+                "READ 24 01",
+                "WRITE 26 02 13",
+            ]
+        );
+        assert!(!sim.is_interrupt_enabled());
+    }
+}
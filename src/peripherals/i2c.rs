@@ -0,0 +1,940 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::PortHandler;
+
+/// A device on an I2C bus. The bus decodes the bits, devices only see whole bytes: times are in
+/// nanoseconds of simulated time, from the start of the simulation.
+///
+/// Devices are `Send`, so the bus can be looked at from another thread while it runs.
+pub trait I2cDevice: Send {
+    /// Called with the 7-bit address after every START, whether it's the device's or not. Returns
+    /// whether the device acknowledges it.
+    fn address(&mut self, address: u8, read: bool, now: u64) -> bool;
+
+    /// A byte written by the master to the device. Returns whether the device acknowledges it.
+    fn write(&mut self, value: u8, now: u64) -> bool;
+
+    /// The next byte the master reads from the device.
+    fn read(&mut self, now: u64) -> u8;
+
+    /// Called on every STOP.
+    fn stop(&mut self, _now: u64) {}
+
+    /// Hardware reset.
+    fn reset(&mut self) {}
+
+    /// Writes whatever the device keeps across simulations to where it's kept.
+    fn save(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The timing the bus must meet, in nanoseconds, as named in the I2C specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cTiming {
+    /// tLOW, the shortest low period of SCL.
+    pub low: u64,
+    /// tHIGH, the shortest high period of SCL.
+    pub high: u64,
+    /// tSU;STA, from SCL rising to a repeated START.
+    pub setup_start: u64,
+    /// tHD;STA, from a START to SCL falling.
+    pub hold_start: u64,
+    /// tSU;STO, from SCL rising to a STOP.
+    pub setup_stop: u64,
+    /// tBUF, between a STOP and the next START.
+    pub bus_free: u64,
+    /// tSU;DAT, from SDA changing to SCL rising.
+    pub setup_data: u64,
+    /// tHD;DAT, from SCL falling to SDA changing. The specification's minimum is 0, but it asks
+    /// devices to hold SDA 300ns past the falling edge of SCL, which is what the master has to
+    /// give them when it bit-bangs the lines.
+    pub hold_data: u64,
+}
+
+impl I2cTiming {
+    /// Standard mode, up to 100kHz.
+    pub const STANDARD: I2cTiming = I2cTiming {
+        low: 4700,
+        high: 4000,
+        setup_start: 4700,
+        hold_start: 4000,
+        setup_stop: 4000,
+        bus_free: 4700,
+        setup_data: 250,
+        hold_data: 300,
+    };
+
+    /// Fast mode, up to 400kHz.
+    pub const FAST: I2cTiming = I2cTiming {
+        low: 1300,
+        high: 600,
+        setup_start: 600,
+        hold_start: 600,
+        setup_stop: 600,
+        bus_free: 1300,
+        setup_data: 100,
+        hold_data: 300,
+    };
+}
+
+/// Where the bus is connected. The default is the KC705 setup of `i2c_routines.psm`: SCL on bit 0
+/// and SDA on bit 1 of input port 02 and output port 08, in standard mode with a 100MHz clock, and
+/// the active low reset of the PCA9548 switch on bit 0 of OUTPUTK port 2.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct I2cConfig {
+    pub input_port: u8,
+    pub output_port: u8,
+    pub scl: u8,
+    pub sda: u8,
+    /// Constant-optimised port resetting the devices, so only the lower 4 bits are compared.
+    pub reset_port: Option<u8>,
+    /// Bits of the reset port that reset the devices while they're low.
+    pub reset_mask: u8,
+    /// Clock frequency of the processor in Hz.
+    pub clock: f64,
+    pub timing: I2cTiming,
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        I2cConfig {
+            input_port: 0x02,
+            output_port: 0x08,
+            scl: 0x01,
+            sda: 0x02,
+            reset_port: Some(0x2),
+            reset_mask: 0x01,
+            clock: 100e6,
+            timing: I2cTiming::STANDARD,
+        }
+    }
+}
+
+/// A timing requirement the master didn't meet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct I2cViolation {
+    pub cycle: u64,
+    /// Name of the parameter in the I2C specification, e.g. `tSU;STA`.
+    pub parameter: &'static str,
+    pub required: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for I2cViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} was {}ns at cycle {}, needs at least {}ns",
+            self.parameter, self.actual, self.cycle, self.required
+        )
+    }
+}
+
+/// What the bus is doing between a START and a STOP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Address,
+    Write(usize),
+    Read(usize),
+    /// Nobody is listening until the next START or STOP.
+    Ignore,
+}
+
+struct I2cState {
+    config: I2cConfig,
+    devices: Vec<Box<dyn I2cDevice>>,
+    // Levels the master and the devices drive SDA to (false pulls it low), and the resolved
+    // levels of the lines. Devices never hold SCL low.
+    master_sda: bool,
+    device_sda: bool,
+    scl: bool,
+    sda: bool,
+    phase: Phase,
+    shift: u8,
+    bits: u8,
+    // In the ninth clock of a byte, the one acknowledging it. The master acknowledges the bytes
+    // it reads, to ask for another one.
+    acknowledging: bool,
+    acknowledged: bool,
+    master_acknowledges: bool,
+    // Times of the last edges and conditions, in nanoseconds.
+    scl_rose: Option<u64>,
+    scl_fell: Option<u64>,
+    sda_changed: Option<u64>,
+    started: Option<u64>,
+    stopped: Option<u64>,
+    transactions: u64,
+    nacks: u64,
+    violations: Vec<I2cViolation>,
+}
+
+impl I2cState {
+    fn nanoseconds(&self, cycle: u64) -> u64 {
+        (cycle as f64 * 1e9 / self.config.clock).round() as u64
+    }
+
+    fn check(&mut self, cycle: u64, parameter: &'static str, required: u64, since: Option<u64>) {
+        let now = self.nanoseconds(cycle);
+
+        if let Some(since) = since {
+            if now - since < required {
+                self.violations.push(I2cViolation {
+                    cycle,
+                    parameter,
+                    required,
+                    actual: now - since,
+                });
+            }
+        }
+    }
+
+    /// Moves the lines to what the master drives now, SCL first when both change. Devices change
+    /// what they drive on SCL falling.
+    fn drive(&mut self, scl: bool, sda: bool, cycle: u64) {
+        if self.scl != scl {
+            self.scl = scl;
+
+            match scl {
+                true => self.scl_rising(cycle),
+                false => self.scl_falling(cycle),
+            }
+        }
+
+        // SDA only changes while SCL is low for data, as START and STOP are changes while it's high.
+        if self.master_sda != sda && !self.scl {
+            self.check(
+                cycle,
+                "tHD;DAT",
+                self.config.timing.hold_data,
+                self.scl_fell,
+            );
+        }
+
+        self.master_sda = sda;
+        self.update_sda(cycle);
+    }
+
+    fn update_sda(&mut self, cycle: u64) {
+        let sda = self.master_sda && self.device_sda;
+
+        if self.sda == sda {
+            return;
+        }
+
+        self.sda = sda;
+        self.sda_changed = Some(self.nanoseconds(cycle));
+
+        if self.scl {
+            match sda {
+                false => self.start(cycle),
+                true => self.stop(cycle),
+            }
+        }
+    }
+
+    fn start(&mut self, cycle: u64) {
+        let timing = self.config.timing;
+        let now = self.nanoseconds(cycle);
+
+        if self.phase == Phase::Idle {
+            self.check(cycle, "tBUF", timing.bus_free, self.stopped);
+        } else {
+            self.check(cycle, "tSU;STA", timing.setup_start, self.scl_rose);
+        }
+
+        self.started = Some(now);
+        self.phase = Phase::Address;
+        self.bits = 0;
+        self.acknowledging = false;
+        self.device_sda = true;
+    }
+
+    fn stop(&mut self, cycle: u64) {
+        let now = self.nanoseconds(cycle);
+
+        self.check(
+            cycle,
+            "tSU;STO",
+            self.config.timing.setup_stop,
+            self.scl_rose,
+        );
+        self.stopped = Some(now);
+        self.phase = Phase::Idle;
+        self.acknowledging = false;
+        self.device_sda = true;
+
+        for device in &mut self.devices {
+            device.stop(now);
+        }
+    }
+
+    fn scl_rising(&mut self, cycle: u64) {
+        let timing = self.config.timing;
+
+        self.check(cycle, "tLOW", timing.low, self.scl_fell);
+
+        if self.scl_fell.is_some() && self.sda_changed > self.scl_fell {
+            self.check(cycle, "tSU;DAT", timing.setup_data, self.sda_changed);
+        }
+
+        self.scl_rose = Some(self.nanoseconds(cycle));
+
+        let sda = self.master_sda && self.device_sda;
+
+        match self.phase {
+            Phase::Read(_) if self.acknowledging && self.master_acknowledges => {
+                self.acknowledged = !sda
+            }
+            Phase::Address | Phase::Write(_) if !self.acknowledging && self.bits < 8 => {
+                self.shift = self.shift << 1 | sda as u8;
+                self.bits += 1;
+            }
+            Phase::Read(_) if self.bits < 8 => self.bits += 1,
+            _ => {}
+        }
+    }
+
+    fn scl_falling(&mut self, cycle: u64) {
+        let timing = self.config.timing;
+        let now = self.nanoseconds(cycle);
+
+        self.check(cycle, "tHIGH", timing.high, self.scl_rose);
+
+        if self.started > self.scl_fell {
+            self.check(cycle, "tHD;STA", timing.hold_start, self.started);
+        }
+
+        self.scl_fell = Some(now);
+
+        if self.acknowledging {
+            self.acknowledging = false;
+            self.bits = 0;
+            self.device_sda = true;
+
+            match self.phase {
+                Phase::Read(device) if self.acknowledged => {
+                    self.shift = self.devices[device].read(now);
+                    self.device_sda = self.shift & 0x80 != 0;
+                }
+                Phase::Write(_) if self.acknowledged => {}
+                _ => self.phase = Phase::Ignore,
+            }
+        } else if self.bits == 8 {
+            self.acknowledging = true;
+            self.master_acknowledges = matches!(self.phase, Phase::Read(_));
+            self.acknowledged = match self.phase {
+                Phase::Address => self.address(now),
+                Phase::Write(device) => self.devices[device].write(self.shift, now),
+                // The master acknowledges what it reads, the device lets go of SDA for it.
+                _ => {
+                    self.acknowledged = false;
+                    self.device_sda = true;
+                    return;
+                }
+            };
+
+            if !self.acknowledged {
+                self.nacks += 1;
+            }
+
+            self.device_sda = !self.acknowledged;
+        } else if let Phase::Read(_) = self.phase {
+            self.device_sda = self.shift << self.bits & 0x80 != 0;
+        }
+    }
+
+    /// Offers the address byte to every device. The first one acknowledging it is the one the
+    /// rest of the transaction is with.
+    fn address(&mut self, now: u64) -> bool {
+        let (address, read) = (self.shift >> 1, self.shift & 1 != 0);
+        let mut found = None;
+
+        self.transactions += 1;
+
+        for (index, device) in self.devices.iter_mut().enumerate() {
+            if device.address(address, read, now) && found.is_none() {
+                found = Some(index);
+            }
+        }
+
+        self.phase = match (found, read) {
+            (Some(device), true) => Phase::Read(device),
+            (Some(device), false) => Phase::Write(device),
+            (None, _) => Phase::Ignore,
+        };
+
+        found.is_some()
+    }
+}
+
+/// An I2C bus driven through port bits, the way `i2c_routines.psm` does it: writing 1 to a bit of
+/// the output port releases the line, 0 pulls it low, and the input port reads the levels of the
+/// lines. Devices pull SDA low to acknowledge and send data, so every line is the wired AND of
+/// what is driving it.
+///
+/// The bus only takes its bits of the ports. Every access also goes to the inner handler, and
+/// reading the input port gives the inner handler's value for its other bits.
+///
+/// START, STOP and acknowledgements are decoded from the edges of the lines, and the timing of
+/// the master is checked against simulated time.
+pub struct I2cBus {
+    config: I2cConfig,
+    state: Arc<Mutex<I2cState>>,
+    inner: Box<dyn PortHandler>,
+}
+
+impl I2cBus {
+    pub fn new(config: I2cConfig, inner: Box<dyn PortHandler>) -> I2cBus {
+        let state = I2cState {
+            config,
+            devices: Vec::new(),
+            master_sda: true,
+            device_sda: true,
+            scl: true,
+            sda: true,
+            phase: Phase::Idle,
+            shift: 0,
+            bits: 0,
+            acknowledging: false,
+            acknowledged: false,
+            master_acknowledges: false,
+            scl_rose: None,
+            scl_fell: None,
+            sda_changed: None,
+            started: None,
+            stopped: None,
+            transactions: 0,
+            nacks: 0,
+            violations: Vec::new(),
+        };
+
+        I2cBus {
+            config,
+            state: Arc::new(Mutex::new(state)),
+            inner,
+        }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn I2cDevice>) {
+        self.state.lock().unwrap().devices.push(device);
+    }
+
+    /// What went on on the bus, and access to its devices once the simulation is over.
+    pub fn handle(&self) -> I2cHandle {
+        I2cHandle {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    pub fn get_config(&self) -> &I2cConfig {
+        &self.config
+    }
+}
+
+impl PortHandler for I2cBus {
+    fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        let value = self.inner.input(port, cycle);
+
+        if port != self.config.input_port {
+            return value;
+        }
+
+        let state = self.state.lock().unwrap();
+        let mut lines = 0;
+
+        if state.scl {
+            lines |= self.config.scl;
+        }
+
+        if state.sda {
+            lines |= self.config.sda;
+        }
+
+        value & !(self.config.scl | self.config.sda) | lines
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        if port == self.config.output_port {
+            let (scl, sda) = (value & self.config.scl != 0, value & self.config.sda != 0);
+
+            self.state.lock().unwrap().drive(scl, sda, cycle);
+        }
+
+        self.inner.output(port, value, cycle);
+    }
+
+    fn output_k(&mut self, port: u8, value: u8, cycle: u64) {
+        let reset = self.config.reset_port.map(|port| port & 0xF);
+
+        if reset == Some(port & 0xF) && value & self.config.reset_mask != self.config.reset_mask {
+            for device in &mut self.state.lock().unwrap().devices {
+                device.reset();
+            }
+        }
+
+        self.inner.output_k(port, value, cycle);
+    }
+
+    fn interrupt(&mut self, cycle: u64) -> bool {
+        self.inner.interrupt(cycle)
+    }
+
+    fn interrupt_ack(&mut self, cycle: u64) {
+        self.inner.interrupt_ack(cycle);
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        self.inner.tick(cycle);
+    }
+}
+
+/// Access to an [`I2cBus`] from outside the simulation.
+#[derive(Clone)]
+pub struct I2cHandle {
+    state: Arc<Mutex<I2cState>>,
+}
+
+impl I2cHandle {
+    /// Addresses sent after a START, whether a device answered or not.
+    pub fn get_transactions(&self) -> u64 {
+        self.state.lock().unwrap().transactions
+    }
+
+    /// Addresses and bytes written that no device acknowledged.
+    pub fn get_nacks(&self) -> u64 {
+        self.state.lock().unwrap().nacks
+    }
+
+    pub fn get_violations(&self) -> Vec<I2cViolation> {
+        self.state.lock().unwrap().violations.clone()
+    }
+
+    /// Saves every device that keeps something across simulations.
+    pub fn save(&self) -> io::Result<()> {
+        for device in &mut self.state.lock().unwrap().devices {
+            device.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A PCA9548 8-channel switch: downstream devices are on the bus when their channel is enabled
+/// in the control register. A written control value takes effect on the next STOP.
+pub struct Pca9548 {
+    address: u8,
+    control: u8,
+    pending: Option<u8>,
+    selected: bool,
+    devices: Vec<(u8, Box<dyn I2cDevice>)>,
+    // The downstream device the transaction is with.
+    target: Option<usize>,
+}
+
+impl Pca9548 {
+    pub fn new(address: u8) -> Pca9548 {
+        Pca9548 {
+            address,
+            control: 0,
+            pending: None,
+            selected: false,
+            devices: Vec::new(),
+            target: None,
+        }
+    }
+
+    /// Connects a device to a channel, from 0 to 7.
+    pub fn connect(&mut self, channel: u8, device: Box<dyn I2cDevice>) {
+        self.devices.push((channel & 7, device));
+    }
+
+    pub fn get_control(&self) -> u8 {
+        self.control
+    }
+}
+
+impl I2cDevice for Pca9548 {
+    fn address(&mut self, address: u8, read: bool, now: u64) -> bool {
+        self.selected = address == self.address;
+        self.target = None;
+
+        for (index, (channel, device)) in self.devices.iter_mut().enumerate() {
+            if self.control & 1 << *channel != 0
+                && device.address(address, read, now)
+                && self.target.is_none()
+            {
+                self.target = Some(index);
+            }
+        }
+
+        self.selected || self.target.is_some()
+    }
+
+    fn write(&mut self, value: u8, now: u64) -> bool {
+        match (self.selected, self.target) {
+            (true, _) => {
+                self.pending = Some(value);
+                true
+            }
+            (false, Some(index)) => self.devices[index].1.write(value, now),
+            (false, None) => false,
+        }
+    }
+
+    fn read(&mut self, now: u64) -> u8 {
+        match (self.selected, self.target) {
+            (true, _) => self.control,
+            (false, Some(index)) => self.devices[index].1.read(now),
+            (false, None) => 0xFF,
+        }
+    }
+
+    fn stop(&mut self, now: u64) {
+        for (channel, device) in &mut self.devices {
+            if self.control & 1 << *channel != 0 {
+                device.stop(now);
+            }
+        }
+
+        if let Some(control) = self.pending.take() {
+            self.control = control;
+        }
+
+        self.selected = false;
+        self.target = None;
+    }
+
+    fn reset(&mut self) {
+        self.control = 0;
+        self.pending = None;
+        self.selected = false;
+        self.target = None;
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        for (_, device) in &mut self.devices {
+            device.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of the M24C08 in bytes.
+pub const M24C08_SIZE: usize = 1024;
+
+// Bytes written in one go, and how long the M24C08 is busy writing them.
+const PAGE_SIZE: usize = 16;
+const WRITE_TIME: u64 = 5_000_000;
+
+/// An M24C08 8Kbit EEPROM. Its 1024 bytes are in 4 blocks of 256, the block being the lower 2
+/// bits of the address the device answers to. Written bytes wait in a page buffer until a STOP,
+/// and the device doesn't answer while it writes them.
+///
+/// Opened from a file, the memory can be saved back to it.
+pub struct M24c08 {
+    address: u8,
+    memory: Vec<u8>,
+    path: Option<PathBuf>,
+    dirty: bool,
+    pointer: usize,
+    selected: bool,
+    // The first byte written after the address is the address within the block.
+    expect_word: bool,
+    block: usize,
+    page: Vec<(usize, u8)>,
+    busy_until: u64,
+}
+
+impl M24c08 {
+    /// An erased EEPROM (every byte is FF), answering to `address` to `address + 3`.
+    pub fn new(address: u8) -> M24c08 {
+        M24c08 {
+            address: address & 0x7C,
+            memory: vec![0xFF; M24C08_SIZE],
+            path: None,
+            dirty: false,
+            pointer: 0,
+            selected: false,
+            expect_word: false,
+            block: 0,
+            page: Vec::new(),
+            busy_until: 0,
+        }
+    }
+
+    /// An EEPROM holding the contents of a file, erased if the file doesn't exist yet.
+    pub fn open(address: u8, path: impl AsRef<Path>) -> io::Result<M24c08> {
+        let mut eeprom = M24c08::new(address);
+
+        match fs::read(path.as_ref()) {
+            Ok(bytes) => {
+                let length = bytes.len().min(M24C08_SIZE);
+
+                eeprom.memory[..length].copy_from_slice(&bytes[..length]);
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        eeprom.path = Some(path.as_ref().to_path_buf());
+        Ok(eeprom)
+    }
+
+    pub fn get_memory(&self) -> &[u8] {
+        &self.memory
+    }
+}
+
+impl I2cDevice for M24c08 {
+    fn address(&mut self, address: u8, _read: bool, now: u64) -> bool {
+        // Anything but a STOP after the written bytes, a repeated START included, drops them.
+        self.page.clear();
+        self.selected = address & 0x7C == self.address && now >= self.busy_until;
+
+        if self.selected {
+            self.block = (address & 3) as usize;
+            self.expect_word = true;
+        }
+
+        self.selected
+    }
+
+    fn write(&mut self, value: u8, _now: u64) -> bool {
+        if !self.selected {
+            return false;
+        }
+
+        if self.expect_word {
+            self.pointer = self.block << 8 | value as usize;
+            self.expect_word = false;
+        } else {
+            // Addresses roll over within the page.
+            if self.page.len() == PAGE_SIZE {
+                self.page.remove(0);
+            }
+
+            self.page.push((self.pointer, value));
+            self.pointer = self.pointer & !(PAGE_SIZE - 1) | (self.pointer + 1) & (PAGE_SIZE - 1);
+        }
+
+        true
+    }
+
+    fn read(&mut self, _now: u64) -> u8 {
+        let value = self.memory[self.pointer];
+
+        self.expect_word = false;
+        self.pointer = (self.pointer + 1) % M24C08_SIZE;
+        value
+    }
+
+    fn stop(&mut self, now: u64) {
+        if self.selected && !self.page.is_empty() {
+            for (address, value) in self.page.drain(..) {
+                self.memory[address] = value;
+            }
+
+            self.dirty = true;
+            self.busy_until = now + WRITE_TIME;
+        }
+
+        self.selected = false;
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        match &self.path {
+            Some(path) if self.dirty => {
+                fs::write(path, &self.memory)?;
+                self.dirty = false;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, PortState, StepEvent};
+
+    #[test]
+    fn kc705_devices() {
+        // The EEPROM is only on the bus once the switch connects channel 3 to it.
+        let driver = "CALL M24C08_read\n\
+                      LOAD s0, 00\n\
+                      ADDCY s0, 00\n\
+                      STORE s0, 00\n\
+                      LOAD sD, 08\n\
+                      CALL PCA9548_mux_write\n\
+                      LOAD sD, 00\n\
+                      CALL PCA9548_mux_read\n\
+                      STORE sD, 01\n\
+                      LOAD s8, 01\n\
+                      LOAD s7, 23\n\
+                      LOAD sD, 5A\n\
+                      CALL M24C08_write\n\
+                      LOAD sD, 00\n\
+                      CALL M24C08_read\n\
+                      STORE sD, 02\n\
+                      OUTPUTK 00, 2\n\
+                      CALL PCA9548_mux_read\n\
+                      STORE sD, 03\n\
+                      done: JUMP done\n";
+        let mut source = driver.to_string();
+
+        for routines in [
+            &include_bytes!("../../tests/i2c_routines.psm")[..],
+            include_bytes!("../../tests/kc705_i2c_devices.psm"),
+            include_bytes!("../../tests/soft_delays_100mhz.psm"),
+        ] {
+            source.push_str(&String::from_utf8_lossy(routines));
+            source.push('\n');
+        }
+
+        let program = assemble_str(&source);
+
+        assert!(!program.has_errors());
+
+        let done = program.find_label("done").unwrap() as usize;
+        let mut sim = program.create_simulation();
+        let mut bus = I2cBus::new(I2cConfig::default(), Box::new(PortState::new()));
+        let mut switch = Pca9548::new(0x74);
+        let handle = bus.handle();
+
+        switch.connect(3, Box::new(M24c08::new(0x54)));
+        bus.connect(Box::new(switch));
+        sim.set_port_handler(Box::new(bus));
+
+        while sim.get_program_counter() != done {
+            assert!(!matches!(sim.step(), Ok(StepEvent::Halted) | Err(_)));
+            assert!(sim.get_cycles() < 4_000_000);
+        }
+
+        let results: Vec<u8> = (0..4)
+            .map(|addr| sim.get_scratch_pad_memory(addr).unwrap())
+            .collect();
+
+        assert_eq!(results, [1, 0x08, 0x5A, 0x00]);
+        assert_eq!(handle.get_transactions(), 7);
+        assert_eq!(handle.get_nacks(), 1);
+        assert_eq!(handle.get_violations(), []);
+    }
+
+    #[test]
+    fn conditions_and_timing() {
+        let mut ports = PortState::new();
+
+        ports.set_input(0x02, 0xF0);
+
+        let mut bus = I2cBus::new(I2cConfig::default(), Box::new(ports));
+        let handle = bus.handle();
+
+        assert_eq!(bus.input(0x02, 0), 0xF3);
+
+        // A START, then address 0x50 clocked far too fast: nobody answers it.
+        bus.output(0x08, 0x01, 100);
+        let mut cycle = 200;
+
+        for bit in [1, 0, 1, 0, 0, 0, 0, 0, 1] {
+            bus.output(0x08, bit << 1, cycle);
+            bus.output(0x08, bit << 1 | 0x01, cycle + 10);
+            bus.output(0x08, bit << 1, cycle + 20);
+            cycle += 30;
+        }
+
+        assert_eq!(bus.input(0x02, cycle), 0xF2);
+        assert_eq!(handle.get_transactions(), 1);
+        assert_eq!(handle.get_nacks(), 1);
+
+        let violations = handle.get_violations();
+        let parameters: Vec<&str> = violations
+            .iter()
+            .map(|violation| violation.parameter)
+            .take(4)
+            .collect();
+
+        assert_eq!(parameters, ["tHD;STA", "tHD;DAT", "tLOW", "tHIGH"]);
+        assert_eq!(
+            violations[0].to_string(),
+            "tHD;STA was 1000ns at cycle 200, needs at least 4000ns"
+        );
+    }
+
+    #[test]
+    fn data_hold_time() {
+        let config = I2cConfig {
+            timing: I2cTiming::FAST,
+            ..I2cConfig::default()
+        };
+        let mut bus = I2cBus::new(config, Box::new(PortState::new()));
+        let handle = bus.handle();
+
+        // SDA changes 200ns after SCL falls, then 400ns after it falls again.
+        bus.output(0x08, 0x02, 100);
+        bus.output(0x08, 0x00, 120);
+        bus.output(0x08, 0x01, 200);
+        bus.output(0x08, 0x00, 300);
+        bus.output(0x08, 0x02, 340);
+
+        let holds: Vec<String> = handle
+            .get_violations()
+            .iter()
+            .filter(|violation| violation.parameter == "tHD;DAT")
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            holds,
+            ["tHD;DAT was 200ns at cycle 120, needs at least 300ns"]
+        );
+    }
+
+    #[test]
+    fn eeprom_pages_and_file() {
+        let path = std::env::temp_dir().join(format!("kcpsm6sim-m24c08-{}", std::process::id()));
+        let mut eeprom = M24c08::open(0x54, &path).unwrap();
+
+        // Block 2, the last bytes of a page roll over to its start.
+        assert!(eeprom.address(0x56, false, 0));
+        assert!(eeprom.write(0x1E, 0));
+
+        for value in [0xA1, 0xA2, 0xA3] {
+            assert!(eeprom.write(value, 0));
+        }
+
+        eeprom.stop(1000);
+        assert!(!eeprom.address(0x56, false, 2000));
+        assert!(eeprom.address(0x56, false, 5_001_000));
+        assert_eq!(eeprom.get_memory()[0x21E..0x220], [0xA1, 0xA2]);
+        assert_eq!(eeprom.get_memory()[0x210], 0xA3);
+
+        // Written bytes are dropped without a STOP.
+        eeprom.write(0x00, 0);
+        eeprom.write(0x55, 0);
+        assert!(!eeprom.address(0x74, false, 0));
+        eeprom.stop(0);
+        assert_eq!(eeprom.get_memory()[0x200], 0xFF);
+
+        eeprom.save().unwrap();
+
+        let mut eeprom = M24c08::open(0x54, &path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(eeprom.get_memory()[0x21E..0x220], [0xA1, 0xA2]);
+
+        // Reads go on past the end of the page.
+        assert!(eeprom.address(0x56, false, 0));
+        eeprom.write(0x1F, 0);
+        assert!(eeprom.address(0x56, true, 0));
+        assert_eq!(eeprom.read(0), 0xA2);
+        assert_eq!(eeprom.read(0), 0xFF);
+    }
+}
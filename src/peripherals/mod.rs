@@ -0,0 +1,7 @@
+//! Models of the peripherals PicoBlaze designs are built around, to connect to the ports of a
+//! simulation in place of (or in front of) a [`PortState`](crate::PortState).
+
+pub mod cosim;
+pub mod i2c;
+pub mod picoterm;
+pub mod uart;
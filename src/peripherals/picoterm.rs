@@ -0,0 +1,561 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::interpreter::predefined::Timestamp;
+
+use super::uart::UartHost;
+
+/// Device Control String, which starts a PicoTerm request or response.
+pub const DCS: u8 = 0x90;
+/// String Terminator, which ends it.
+pub const ST: u8 = 0x9C;
+
+const ESC: u8 = 0x1B;
+
+/// Segments lit for each hexadecimal digit, as in `nibble_to_7seg`.
+const SEVEN_SEGMENT: [u8; 16] = [
+    0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+];
+
+/// Something the program did to the virtual devices of PicoTerm, besides displaying text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicoTermEvent {
+    /// The red, amber and green rows of 8 LEDs were set.
+    Leds([u8; 3]),
+    /// The segments of the 4 digits of the 7-segment display were set, digit 0 first.
+    SevenSegment([u8; 4]),
+    /// The 16 virtual switches were set by the program.
+    Switches(u16),
+    /// The LOG file was opened (true) or closed.
+    Log(bool),
+    /// A DCS sequence PicoTerm doesn't know, by its identifying character.
+    Unknown(u8),
+}
+
+impl fmt::Display for PicoTermEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PicoTermEvent::Leds([red, amber, green]) => write!(
+                f,
+                "LEDs red {} amber {} green {}",
+                format_leds(*red),
+                format_leds(*amber),
+                format_leds(*green)
+            ),
+            PicoTermEvent::SevenSegment(digits) => {
+                write!(f, "7-segment {}", format_seven_segment(digits))
+            }
+            PicoTermEvent::Switches(value) => write!(f, "switches {:016b}", value),
+            PicoTermEvent::Log(true) => write!(f, "LOG file opened"),
+            PicoTermEvent::Log(false) => write!(f, "LOG file closed"),
+            PicoTermEvent::Unknown(command) => {
+                write!(f, "unknown DCS sequence {:02X}", command)
+            }
+        }
+    }
+}
+
+/// Bit 7 first, `*` for a LED that's on.
+fn format_leds(value: u8) -> String {
+    (0..8)
+        .rev()
+        .map(|bit| match value & (1 << bit) {
+            0 => '.',
+            _ => '*',
+        })
+        .collect()
+}
+
+/// The digits as they read, digit 3 first, with `?` for segments that aren't a hexadecimal digit
+/// and `.` after a digit with its decimal point (segment bit 7) lit.
+fn format_seven_segment(digits: &[u8; 4]) -> String {
+    let mut text = String::new();
+
+    for segments in digits.iter().rev() {
+        text.push(
+            match SEVEN_SEGMENT
+                .iter()
+                .position(|value| *value == segments & 0x7F)
+            {
+                Some(digit) => char::from_digit(digit as u32, 16)
+                    .unwrap()
+                    .to_ascii_uppercase(),
+                None if segments & 0x7F == 0 => ' ',
+                None => '?',
+            },
+        );
+
+        if segments & 0x80 != 0 {
+            text.push('.');
+        }
+    }
+
+    format!("[{}]", text)
+}
+
+/// Where the parser is in what the program sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Parse {
+    Text,
+    Escape,
+    // After `ESC [`, with the characters of a standard sequence so far.
+    Sequence(Vec<u8>),
+    Dcs,
+    // A DCS sequence by its identifying character, with its payload so far and how long the
+    // payload is known to be. Payloads can contain `ST`, so they're read by length.
+    DcsPayload(u8, Vec<u8>, usize),
+}
+
+/// The PicoTerm terminal, at the host end of a [`Uart`](super::uart::Uart): it interprets the
+/// escape sequences and Device Control Strings the program sends, answers its requests for the
+/// time, the date, the virtual switches, random numbers and pings, and keeps the state of the
+/// virtual LEDs, 7-segment display and switches.
+///
+/// What's read from it is the text to display, with the PicoTerm escape sequences translated to
+/// ANSI ones. What's written to it is typed on the keyboard. Like [`UartHost`], reading never
+/// blocks.
+pub struct PicoTerm {
+    host: UartHost,
+    parse: Parse,
+    display: VecDeque<u8>,
+    events: Vec<PicoTermEvent>,
+    // Whether the last character displayed was a carriage return, to merge CR LF.
+    after_cr: bool,
+    time: Option<u64>,
+    random: u64,
+    leds: [u8; 3],
+    digits: [u8; 4],
+    switches: u16,
+    logging: bool,
+}
+
+impl PicoTerm {
+    /// A terminal connected to the host end of a UART, with everything off, answering with the
+    /// system time.
+    pub fn new(host: UartHost) -> PicoTerm {
+        PicoTerm {
+            host,
+            parse: Parse::Text,
+            display: VecDeque::new(),
+            events: Vec::new(),
+            after_cr: false,
+            time: None,
+            random: 0x2545_F491_4F6C_DD1D,
+            leds: [0; 3],
+            digits: [0; 4],
+            switches: 0,
+            logging: false,
+        }
+    }
+
+    /// Answers requests for the time and date with a fixed time, in seconds since the Unix epoch,
+    /// instead of the system time.
+    pub fn set_time(&mut self, time: Option<u64>) {
+        self.time = time;
+    }
+
+    /// Sets the virtual switches, as if they were clicked.
+    pub fn set_switches(&mut self, switches: u16) {
+        self.switches = switches;
+    }
+
+    /// Starts the pseudo random numbers over from a seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.random = seed.max(1);
+    }
+
+    /// The red, amber and green LEDs.
+    pub fn get_leds(&self) -> [u8; 3] {
+        self.leds
+    }
+
+    /// The segments of the 7-segment display, digit 0 first.
+    pub fn get_digits(&self) -> [u8; 4] {
+        self.digits
+    }
+
+    pub fn get_switches(&self) -> u16 {
+        self.switches
+    }
+
+    /// Whether the program has a LOG file open.
+    pub fn is_logging(&self) -> bool {
+        self.logging
+    }
+
+    /// The state of the virtual devices, on one line.
+    pub fn format_devices(&self) -> String {
+        format!(
+            "{}, {}, {}",
+            PicoTermEvent::Leds(self.leds),
+            PicoTermEvent::SevenSegment(self.digits),
+            PicoTermEvent::Switches(self.switches)
+        )
+    }
+
+    /// Takes what the program did to the virtual devices since the last call.
+    pub fn take_events(&mut self) -> Vec<PicoTermEvent> {
+        self.receive();
+        std::mem::take(&mut self.events)
+    }
+
+    /// Interprets everything the program has sent so far.
+    fn receive(&mut self) {
+        for value in self.host.take_transmitted() {
+            self.interpret(value);
+        }
+    }
+
+    fn interpret(&mut self, value: u8) {
+        self.parse = match std::mem::replace(&mut self.parse, Parse::Text) {
+            Parse::Text => match value {
+                DCS => Parse::Dcs,
+                ESC => Parse::Escape,
+                _ => {
+                    self.display(value);
+                    Parse::Text
+                }
+            },
+            Parse::Escape => match value {
+                b'[' => Parse::Sequence(Vec::new()),
+                _ => {
+                    self.show(&[ESC]);
+                    self.display(value);
+                    Parse::Text
+                }
+            },
+            Parse::Sequence(mut sequence) => match value {
+                // The colours are sent as a single character, 30 to 38.
+                0x1E..=0x26 if sequence.is_empty() => {
+                    self.show(colour(value).as_bytes());
+                    Parse::Text
+                }
+                // Clearing the screen also moves the cursor home and makes the text black.
+                b'J' if sequence == b"2" => {
+                    self.show(b"\x1B[2J\x1B[H\x1B[39m");
+                    Parse::Text
+                }
+                0x40..=0x7E => {
+                    sequence.push(value);
+                    self.show(b"\x1B[");
+                    self.show(&sequence);
+                    Parse::Text
+                }
+                _ => {
+                    sequence.push(value);
+                    Parse::Sequence(sequence)
+                }
+            },
+            Parse::Dcs => match value {
+                ST => {
+                    self.request(0, &[]);
+                    Parse::Text
+                }
+                command => {
+                    let length = match command {
+                        b'L' | b'N' => 3,
+                        b'7' => 4,
+                        b's' => 2,
+                        _ => 0,
+                    };
+
+                    Parse::DcsPayload(command, Vec::new(), length)
+                }
+            },
+            Parse::DcsPayload(command, mut payload, length) => {
+                if payload.len() < length || value != ST {
+                    payload.push(value);
+                    Parse::DcsPayload(command, payload, length)
+                } else {
+                    self.request(command, &payload);
+                    Parse::Text
+                }
+            }
+        };
+    }
+
+    /// Displays a character of text. PicoTerm starts a new line on a carriage return, so the line
+    /// feed of CR LF is dropped and the others are displayed as CR LF.
+    fn display(&mut self, value: u8) {
+        match value {
+            b'\r' => self.show(b"\r\n"),
+            b'\n' if self.after_cr => {}
+            b'\n' => self.show(b"\r\n"),
+            _ => self.show(&[value]),
+        }
+
+        self.after_cr = value == b'\r';
+    }
+
+    fn show(&mut self, bytes: &[u8]) {
+        self.display.extend(bytes);
+    }
+
+    /// Acts on a DCS sequence and sends its response, if it has one.
+    fn request(&mut self, command: u8, payload: &[u8]) {
+        let response = match (command, payload) {
+            (b'p', _) => Some(vec![b'P']),
+            (b'T', _) => Some([b"T", self.now().format_time().as_bytes()].concat()),
+            (b't', _) => {
+                let now = self.now();
+
+                Some(vec![
+                    b't',
+                    now.hours as u8,
+                    now.minutes as u8,
+                    now.seconds as u8,
+                ])
+            }
+            (b'D', _) => Some([b"D", self.now().format_date().as_bytes()].concat()),
+            (b'd', _) => {
+                let now = self.now();
+
+                Some(vec![
+                    b'd',
+                    (now.year % 100) as u8,
+                    now.month as u8,
+                    now.day as u8,
+                ])
+            }
+            (b'S', _) => {
+                let [low, high] = self.switches.to_le_bytes();
+
+                Some(vec![b'S', low, high])
+            }
+            (b'N', &[low, middle, high]) => {
+                let value = self.next_random(u32::from_le_bytes([low, middle, high, 0]));
+                let [low, middle, high, _] = value.to_le_bytes();
+
+                Some(vec![b'N', low, middle, high])
+            }
+            (b'L', &[red, amber, green]) => {
+                self.leds = [red, amber, green];
+                self.events.push(PicoTermEvent::Leds(self.leds));
+                None
+            }
+            (b'7', &[digit0, digit1, digit2, digit3]) => {
+                self.digits = [digit0, digit1, digit2, digit3];
+                self.events.push(PicoTermEvent::SevenSegment(self.digits));
+                None
+            }
+            (b's', &[low, high]) => {
+                self.switches = u16::from_le_bytes([low, high]);
+                self.events.push(PicoTermEvent::Switches(self.switches));
+                None
+            }
+            (b'W', _) | (b'w', _) => {
+                self.logging = command == b'W';
+                self.events.push(PicoTermEvent::Log(self.logging));
+                None
+            }
+            // Hides the window listing DCS transactions, which isn't shown here anyway.
+            (b'h', _) => None,
+            _ => {
+                self.events.push(PicoTermEvent::Unknown(command));
+                None
+            }
+        };
+
+        if let Some(response) = response {
+            self.host
+                .write_all(&[&[DCS], response.as_slice(), &[ST]].concat())
+                .unwrap();
+        }
+    }
+
+    fn now(&self) -> Timestamp {
+        Timestamp::from_unix(self.time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0)
+        }))
+    }
+
+    /// A pseudo random number from 0 to `max`, from a xorshift generator.
+    fn next_random(&mut self, max: u32) -> u32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+
+        (self.random % (max as u64 + 1)) as u32
+    }
+}
+
+/// The ANSI sequence for a PicoTerm colour. Black is the default colour of PicoTerm, so it's the
+/// default colour of the terminal here, which may not be black.
+fn colour(value: u8) -> &'static str {
+    match value {
+        0x1F => "\x1B[31m",
+        0x20 => "\x1B[32m",
+        0x21 => "\x1B[33m",
+        0x22 => "\x1B[34m",
+        0x23 => "\x1B[35m",
+        0x24 => "\x1B[36m",
+        0x25 => "\x1B[37m",
+        0x26 => "\x1B[97m",
+        _ => "\x1B[39m",
+    }
+}
+
+impl Read for PicoTerm {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.receive();
+
+        if self.display.is_empty() && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let count = buf.len().min(self.display.len());
+
+        for (byte, value) in buf.iter_mut().zip(self.display.drain(..count)) {
+            *byte = value;
+        }
+
+        Ok(count)
+    }
+}
+
+impl Write for PicoTerm {
+    /// Types characters on the keyboard, sending them to the program.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.host.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripherals::uart::{Uart, UartConfig};
+    use crate::{assemble_str, PortState, StepEvent};
+
+    /// A terminal fed directly, through a UART nothing runs.
+    fn picoterm(bytes: &[u8]) -> PicoTerm {
+        let uart = Uart::new(UartConfig::default(), Box::new(PortState::new()));
+        let mut picoterm = PicoTerm::new(uart.host());
+
+        for value in bytes {
+            picoterm.interpret(*value);
+        }
+
+        picoterm
+    }
+
+    #[test]
+    fn text_and_escape_sequences() {
+        let mut picoterm = picoterm(b"a\r\nb\rc\nd\x1B[2J\x1B[\x1Fred\x1B[Hx\x1B[1;2Hy\x1Bz");
+        let mut display = Vec::new();
+
+        picoterm.read_to_end(&mut display).unwrap_err();
+
+        assert_eq!(
+            String::from_utf8(display).unwrap(),
+            "a\r\nb\r\nc\r\nd\x1B[2J\x1B[H\x1B[39m\x1B[31mred\x1B[Hx\x1B[1;2Hy\x1Bz"
+        );
+    }
+
+    #[test]
+    fn virtual_devices() {
+        // The amber LEDs are 9C, the code of ST, which doesn't end the sequence early.
+        let mut picoterm = picoterm(&[
+            DCS, b'L', 0x81, ST, 0x00, ST, DCS, b'7', 0x06, 0xDB, 0x00, 0x55, ST, DCS, b's', 0x34,
+            0x12, ST, DCS, b'W', ST, DCS, b'h', ST, DCS, b'?', ST, b'!',
+        ]);
+
+        assert_eq!(picoterm.get_leds(), [0x81, 0x9C, 0x00]);
+        assert_eq!(picoterm.get_digits(), [0x06, 0xDB, 0x00, 0x55]);
+        assert_eq!(picoterm.get_switches(), 0x1234);
+        assert!(picoterm.is_logging());
+        assert_eq!(picoterm.display, [b'!']);
+        assert_eq!(
+            picoterm.take_events(),
+            vec![
+                PicoTermEvent::Leds([0x81, 0x9C, 0x00]),
+                PicoTermEvent::SevenSegment([0x06, 0xDB, 0x00, 0x55]),
+                PicoTermEvent::Switches(0x1234),
+                PicoTermEvent::Log(true),
+                PicoTermEvent::Unknown(b'?'),
+            ]
+        );
+        assert_eq!(
+            picoterm.format_devices(),
+            "LEDs red *......* amber *..***.. green ........, 7-segment [? 2.1], \
+             switches 0001001000110100"
+        );
+    }
+
+    #[test]
+    fn requests_from_picoterm_routines() {
+        // Keeps the parts of each response in registers, then the date string in place. UART_RX
+        // intercepts the responses, and `wait` polls until one has been stored.
+        let driver = "CALL reset_UART_macros\n\
+                      CALL PicoTerm_Ping\n\
+                      CALL wait\n\
+                      FETCH sA, PicoTerm_Response0\n\
+                      CALL PicoTerm_Time_Value\n\
+                      CALL wait\n\
+                      FETCH sB, PicoTerm_Response1\n\
+                      FETCH sC, PicoTerm_Response2\n\
+                      FETCH sD, PicoTerm_Response3\n\
+                      CALL PicoTerm_read_Switches\n\
+                      CALL wait\n\
+                      FETCH sE, PicoTerm_Response1\n\
+                      FETCH sF, PicoTerm_Response2\n\
+                      LOAD s9, 00\n\
+                      LOAD s8, 00\n\
+                      LOAD s7, 09\n\
+                      CALL PicoTerm_Random\n\
+                      CALL wait\n\
+                      FETCH s9, PicoTerm_Response1\n\
+                      CALL PicoTerm_Date_String\n\
+                      CALL wait\n\
+                      done: JUMP done\n\
+                      wait: LOAD s2, 00\n\
+                      STORE s2, PicoTerm_Response0\n\
+                      poll: CALL UART_RX\n\
+                      FETCH s2, PicoTerm_Response0\n\
+                      COMPARE s2, 00\n\
+                      JUMP Z, poll\n\
+                      RETURN\n";
+        let routines = include_bytes!("../../tests/PicoTerm_routines.psm");
+        let source = format!("{}{}", driver, String::from_utf8_lossy(routines));
+        let program = assemble_str(&source);
+
+        assert!(!program.has_errors());
+
+        let mut sim = program.create_simulation();
+        let uart = Uart::new(UartConfig::default(), Box::new(PortState::new()));
+        let mut picoterm = PicoTerm::new(uart.host());
+
+        // 31 Jul 2012 14:17:58.
+        picoterm.set_time(Some(1_343_744_278));
+        picoterm.set_switches(0xA59C);
+        sim.set_port_handler(Box::new(uart));
+
+        while sim.get_cycles() < 2_000_000 {
+            assert!(!matches!(sim.step(), Ok(StepEvent::Halted) | Err(_)));
+
+            if sim.get_cycles().is_multiple_of(1000) {
+                picoterm.receive();
+            }
+        }
+
+        let registers = sim.get_registers();
+        let date: Vec<u8> = (0x08..0x13)
+            .map(|addr| sim.get_scratch_pad_memory(addr).unwrap())
+            .collect();
+
+        assert_eq!(registers[0xA], b'P');
+        assert_eq!(registers[0xB..=0xD], [14, 17, 58]);
+        assert_eq!(registers[0xE..=0xF], [0x9C, 0xA5]);
+        assert!(registers[0x9] <= 9);
+        assert_eq!(date, b"31 Jul 2012");
+        assert!(picoterm.take_events().is_empty());
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::PortHandler;
+
+/// Size of the FIFO buffers of `uart_tx6` and `uart_rx6`.
+pub const FIFO_SIZE: usize = 16;
+
+// Bits of the status port, as in the reference designs.
+pub const TX_DATA_PRESENT: u8 = 0x01;
+pub const TX_HALF_FULL: u8 = 0x02;
+pub const TX_FULL: u8 = 0x04;
+pub const RX_DATA_PRESENT: u8 = 0x08;
+pub const RX_HALF_FULL: u8 = 0x10;
+pub const RX_FULL: u8 = 0x20;
+
+// Bits of the reset port, written through OUTPUTK.
+pub const TX_RESET: u8 = 0x01;
+pub const RX_RESET: u8 = 0x02;
+
+/// Where the UART macros are connected and how fast they run. The default is the PicoTerm
+/// setup of `PicoTerm_routines.psm`: status on input port 00, data on port 01 both ways, the
+/// buffer resets on OUTPUTK port 1, at 115200 baud with a 100MHz clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UartConfig {
+    pub status_port: u8,
+    pub tx_port: u8,
+    pub rx_port: u8,
+    /// Constant-optimised port, so only the lower 4 bits are compared.
+    pub reset_port: u8,
+    pub baud: u32,
+    /// Clock frequency of the processor in Hz.
+    pub clock: f64,
+}
+
+impl Default for UartConfig {
+    fn default() -> Self {
+        UartConfig {
+            status_port: 0x00,
+            tx_port: 0x01,
+            rx_port: 0x01,
+            reset_port: 0x1,
+            baud: 115200,
+            clock: 100e6,
+        }
+    }
+}
+
+impl UartConfig {
+    /// Clock cycles a character takes on the line: a start bit, 8 data bits and a stop bit.
+    pub fn character_cycles(&self) -> u64 {
+        ((10.0 * self.clock / self.baud as f64).round() as u64).max(1)
+    }
+}
+
+/// One direction of the serial line: characters wait in a queue until the line is free, then
+/// take `character_cycles` to go through it.
+#[derive(Debug, Default)]
+struct Line {
+    // Characters along with the cycle they were queued in.
+    queue: VecDeque<(u8, u64)>,
+    // Character going through the line, and the cycle it's done in.
+    shifting: Option<(u8, u64)>,
+    free_at: u64,
+}
+
+impl Line {
+    /// Moves the line on to `cycle`, handing every character that's through it to `deliver`.
+    fn advance(&mut self, cycle: u64, character_cycles: u64, mut deliver: impl FnMut(u8)) {
+        loop {
+            if let Some((value, done)) = self.shifting {
+                if done > cycle {
+                    return;
+                }
+
+                deliver(value);
+                self.shifting = None;
+                self.free_at = done;
+            }
+
+            match self.queue.front() {
+                Some((value, queued)) if self.free_at.max(*queued) <= cycle => {
+                    let start = self.free_at.max(*queued);
+
+                    self.shifting = Some((*value, start + character_cycles));
+                    self.queue.pop_front();
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct UartState {
+    cycle: u64,
+    character_cycles: u64,
+    // `uart_tx6`: characters written by the program wait in its FIFO (the queue of the line), and
+    // leave it when they start going through the line to the host.
+    tx_line: Line,
+    transmitted: VecDeque<u8>,
+    // `uart_rx6`: characters sent by the host go through the line into its FIFO, where the
+    // program reads them from.
+    rx_line: Line,
+    rx_fifo: VecDeque<u8>,
+    overruns: u64,
+}
+
+impl UartState {
+    fn advance(&mut self, cycle: u64) {
+        self.cycle = self.cycle.max(cycle);
+
+        let transmitted = &mut self.transmitted;
+
+        self.tx_line
+            .advance(self.cycle, self.character_cycles, |value| {
+                transmitted.push_back(value)
+            });
+
+        let rx_fifo = &mut self.rx_fifo;
+        let overruns = &mut self.overruns;
+
+        self.rx_line
+            .advance(self.cycle, self.character_cycles, |value| {
+                match rx_fifo.len() < FIFO_SIZE {
+                    true => rx_fifo.push_back(value),
+                    // Like the hardware, characters received with a full FIFO are lost.
+                    false => *overruns += 1,
+                }
+            });
+    }
+
+    fn status(&self) -> u8 {
+        let tx = self.tx_line.queue.len();
+        let rx = self.rx_fifo.len();
+        let mut status = 0;
+
+        for (set, bit) in [
+            (tx > 0, TX_DATA_PRESENT),
+            (tx >= FIFO_SIZE / 2, TX_HALF_FULL),
+            (tx >= FIFO_SIZE, TX_FULL),
+            (rx > 0, RX_DATA_PRESENT),
+            (rx >= FIFO_SIZE / 2, RX_HALF_FULL),
+            (rx >= FIFO_SIZE, RX_FULL),
+        ] {
+            if set {
+                status |= bit;
+            }
+        }
+
+        status
+    }
+}
+
+/// Models of the `uart_tx6` and `uart_rx6` macros, in front of whatever else is connected to the
+/// ports. Accesses to the UART ports are handled here, every other one goes to the inner handler.
+///
+/// Characters take as long as the baud rate says to go through the serial line, measured in
+/// simulated time. The other end of the line is a [`UartHost`].
+pub struct Uart {
+    config: UartConfig,
+    state: Arc<Mutex<UartState>>,
+    inner: Box<dyn PortHandler>,
+}
+
+impl Uart {
+    pub fn new(config: UartConfig, inner: Box<dyn PortHandler>) -> Uart {
+        let state = UartState {
+            character_cycles: config.character_cycles(),
+            ..UartState::default()
+        };
+
+        Uart {
+            config,
+            state: Arc::new(Mutex::new(state)),
+            inner,
+        }
+    }
+
+    /// The other end of the serial line.
+    pub fn host(&self) -> UartHost {
+        UartHost {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    pub fn get_config(&self) -> &UartConfig {
+        &self.config
+    }
+
+    fn state(&self, cycle: u64) -> MutexGuard<'_, UartState> {
+        let mut state = self.state.lock().unwrap();
+
+        state.advance(cycle);
+        state
+    }
+}
+
+impl PortHandler for Uart {
+    fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        if port == self.config.status_port {
+            self.state(cycle).status()
+        } else if port == self.config.rx_port {
+            // The FIFO gives 0 once it's empty.
+            self.state(cycle).rx_fifo.pop_front().unwrap_or(0)
+        } else {
+            self.inner.input(port, cycle)
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        if port != self.config.tx_port {
+            return self.inner.output(port, value, cycle);
+        }
+
+        let mut state = self.state(cycle);
+
+        // Writes to a full FIFO are lost, which is why firmware checks the status first.
+        if state.tx_line.queue.len() < FIFO_SIZE {
+            state.tx_line.queue.push_back((value, cycle));
+        }
+    }
+
+    fn output_k(&mut self, port: u8, value: u8, cycle: u64) {
+        if port & 0xF != self.config.reset_port & 0xF {
+            return self.inner.output_k(port, value, cycle);
+        }
+
+        let mut state = self.state(cycle);
+
+        if value & TX_RESET != 0 {
+            state.tx_line.queue.clear();
+        }
+
+        if value & RX_RESET != 0 {
+            state.rx_fifo.clear();
+        }
+    }
+
+    fn interrupt(&mut self, cycle: u64) -> bool {
+        self.inner.interrupt(cycle)
+    }
+
+    fn interrupt_ack(&mut self, cycle: u64) {
+        self.inner.interrupt_ack(cycle);
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        self.state.lock().unwrap().advance(cycle);
+        self.inner.tick(cycle);
+    }
+}
+
+/// The host end of the serial line of a [`Uart`], as byte streams: what's read from it was sent
+/// by the program, what's written to it is received by the program.
+///
+/// Reading never blocks. When the program hasn't sent anything since the last read, it fails with
+/// [`io::ErrorKind::WouldBlock`], like a non-blocking socket.
+#[derive(Clone)]
+pub struct UartHost {
+    state: Arc<Mutex<UartState>>,
+}
+
+impl UartHost {
+    /// Takes everything the program has sent so far.
+    pub fn take_transmitted(&self) -> Vec<u8> {
+        self.state.lock().unwrap().transmitted.drain(..).collect()
+    }
+
+    /// Clock cycles a character takes on the line.
+    pub fn get_character_cycles(&self) -> u64 {
+        self.state.lock().unwrap().character_cycles
+    }
+
+    /// Characters the program didn't read in time, lost because the receiver FIFO was full.
+    pub fn get_overruns(&self) -> u64 {
+        self.state.lock().unwrap().overruns
+    }
+
+    /// Whether every character written to the host end has made it into the receiver FIFO.
+    pub fn is_rx_idle(&self) -> bool {
+        let state = self.state.lock().unwrap();
+
+        state.rx_line.queue.is_empty() && state.rx_line.shifting.is_none()
+    }
+
+    /// Whether nothing is going on: every character sent both ways is through the line, the
+    /// program has read its receiver FIFO and its transmitter FIFO is empty.
+    pub fn is_idle(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let line_idle = |line: &Line| line.queue.is_empty() && line.shifting.is_none();
+
+        line_idle(&state.rx_line) && line_idle(&state.tx_line) && state.rx_fifo.is_empty()
+    }
+}
+
+impl Read for UartHost {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.transmitted.is_empty() && !buf.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        let count = buf.len().min(state.transmitted.len());
+
+        for (byte, value) in buf.iter_mut().zip(state.transmitted.drain(..count)) {
+            *byte = value;
+        }
+
+        Ok(count)
+    }
+}
+
+impl Write for UartHost {
+    /// Sends characters to the program. They go through the line one after the other, from the
+    /// current simulated time.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let cycle = state.cycle;
+
+        state
+            .rx_line
+            .queue
+            .extend(buf.iter().map(|value| (*value, cycle)));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, PortState, StepEvent};
+
+    fn uart() -> (Uart, UartHost) {
+        // 10 clock cycles per bit, so 100 per character.
+        let config = UartConfig {
+            baud: 10_000_000,
+            ..UartConfig::default()
+        };
+        let uart = Uart::new(config, Box::new(PortState::new()));
+        let host = uart.host();
+
+        (uart, host)
+    }
+
+    #[test]
+    fn transmitter_fifo_and_timing() {
+        let (mut uart, mut host) = uart();
+
+        assert_eq!(uart.get_config().character_cycles(), 100);
+
+        for value in 0..20 {
+            uart.output(0x01, value, 0);
+        }
+
+        // The first character left the FIFO for the line as soon as it was written, making room
+        // for one more. The last three didn't fit.
+        uart.tick(0);
+        assert_eq!(
+            uart.input(0x00, 0),
+            TX_DATA_PRESENT | TX_HALF_FULL | TX_FULL
+        );
+        assert!(host.take_transmitted().is_empty());
+
+        uart.tick(99);
+        assert_eq!(
+            host.read(&mut [0; 4]).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        uart.tick(250);
+        assert_eq!(host.take_transmitted(), vec![0, 1]);
+
+        uart.tick(2000);
+        assert_eq!(host.take_transmitted(), (2..17).collect::<Vec<u8>>());
+        assert_eq!(uart.input(0x00, 2000), 0);
+    }
+
+    #[test]
+    fn receiver_fifo_and_overruns() {
+        let (mut uart, mut host) = uart();
+
+        host.write_all(b"Hello").unwrap();
+        uart.tick(150);
+        assert_eq!(uart.input(0x00, 150), RX_DATA_PRESENT);
+        assert_eq!(uart.input(0x01, 150), b'H');
+        assert_eq!(uart.input(0x00, 150), 0);
+        assert!(!host.is_rx_idle());
+
+        uart.tick(500);
+        assert!(host.is_rx_idle());
+        assert_eq!(uart.input(0x01, 500), b'e');
+
+        host.write_all(&[b'.'; 30]).unwrap();
+        uart.tick(10_000);
+        assert_eq!(
+            uart.input(0x00, 10_000),
+            RX_DATA_PRESENT | RX_HALF_FULL | RX_FULL
+        );
+        assert_eq!(host.get_overruns(), 30 + 3 - FIFO_SIZE as u64);
+
+        uart.output_k(0x01, RX_RESET, 10_000);
+        assert_eq!(uart.input(0x00, 10_000), 0);
+        assert_eq!(uart.input(0x01, 10_000), 0);
+    }
+
+    #[test]
+    fn other_ports_go_to_the_inner_handler() {
+        let mut ports = PortState::new();
+
+        ports.set_input(0x02, 0x5A);
+
+        let mut uart = Uart::new(UartConfig::default(), Box::new(ports));
+
+        uart.output(0x04, 0x12, 0);
+        uart.output_k(0x2, 0x34, 0);
+
+        assert_eq!(uart.input(0x02, 0), 0x5A);
+        assert!(uart.host().take_transmitted().is_empty());
+    }
+
+    #[test]
+    fn picoterm_routines() {
+        // Echoes what it receives after a greeting, using the routines unmodified.
+        let driver = "main: CALL reset_UART_macros\n\
+                      LOAD s5, \"H\"\n\
+                      CALL UART_TX\n\
+                      LOAD s5, \"i\"\n\
+                      CALL UART_TX\n\
+                      echo: CALL UART_RX\n\
+                      JUMP Z, echo\n\
+                      CALL UART_TX\n\
+                      JUMP echo\n";
+        // Its copyright header is in Windows-1252, like most of the Xilinx examples.
+        let routines = include_bytes!("../../tests/PicoTerm_routines.psm");
+        let source = format!("{}{}", driver, String::from_utf8_lossy(routines));
+        let program = assemble_str(&source);
+
+        assert!(!program.has_errors());
+
+        let mut sim = program.create_simulation();
+        let uart = Uart::new(UartConfig::default(), Box::new(PortState::new()));
+        let mut host = uart.host();
+
+        sim.set_port_handler(Box::new(uart));
+        host.write_all(b"abc").unwrap();
+
+        // 5ms at 100MHz is long enough for 57 characters at 115200 baud.
+        while sim.get_cycles() < 500_000 {
+            assert!(!matches!(sim.step(), Ok(StepEvent::Halted) | Err(_)));
+        }
+
+        assert_eq!(host.take_transmitted(), b"Hiabc");
+        assert_eq!(host.get_overruns(), 0);
+    }
+}
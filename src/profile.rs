@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::analysis::stack::describe_routine;
+use crate::{Program, SimulationContext, StepEvent};
+
+/// Cycles spent at an address or in a routine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cycles {
+    /// Instructions executed there, or calls of the routine.
+    pub count: u64,
+    /// Cycles of the instructions themselves.
+    pub self_cycles: u64,
+    /// Cycles including the routines called from there.
+    pub inclusive: u64,
+}
+
+/// A routine running on the profiler's copy of the call stack.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    entry: usize,
+    /// Address of the CALL that started it, `None` for the reset address and interrupts.
+    call_site: Option<usize>,
+}
+
+/// The deepest the call stack got.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighWaterMark {
+    pub depth: usize,
+    pub cycle: u64,
+    /// Entries of the routines running at that point, outermost first.
+    pub routines: Vec<usize>,
+}
+
+/// Attributes the clock cycles of a simulation to addresses and routines, following the call
+/// stack, to find where the time goes.
+///
+/// An instruction belongs to the routine it runs in, so a CALL counts for the caller and a RETURN
+/// for the routine that returns. Taking an interrupt counts for the interrupt routine.
+///
+/// ```
+/// use kcpsm6sim::{assemble_str, profile::Profiler};
+///
+/// let program = assemble_str("main: call wait\njump main\nwait: load s0, 02\nloop: sub s0, 01\njump nz, loop\nreturn\n");
+/// let mut sim = program.create_simulation();
+/// let mut profiler = Profiler::new(&sim);
+///
+/// while sim.get_cycles() < 100 {
+///     let event = sim.step().unwrap();
+///
+///     profiler.record(&sim, &event);
+/// }
+///
+/// assert_eq!(profiler.get_routine(2).unwrap().self_cycles, 74);
+/// assert_eq!(profiler.get_routine(0).unwrap().inclusive, 100);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    frames: Vec<Frame>,
+    addresses: BTreeMap<usize, Cycles>,
+    routines: BTreeMap<usize, Cycles>,
+    /// Executions and cycles of each mnemonic.
+    mix: BTreeMap<String, (u64, u64)>,
+    /// Self cycles of each chain of routines, for flame graphs.
+    stacks: BTreeMap<Vec<usize>, u64>,
+    high_water_mark: HighWaterMark,
+    interrupts: u64,
+    /// Cycles of the simulation when profiling started and after the last step.
+    start: u64,
+    cycles: u64,
+}
+
+impl Profiler {
+    pub fn new(sim: &SimulationContext) -> Profiler {
+        let entry = sim.get_program_counter();
+        let mut routines = BTreeMap::new();
+
+        routines.insert(
+            entry,
+            Cycles {
+                count: 1,
+                ..Cycles::default()
+            },
+        );
+
+        Profiler {
+            frames: vec![Frame {
+                entry,
+                call_site: None,
+            }],
+            addresses: BTreeMap::new(),
+            routines,
+            mix: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            high_water_mark: HighWaterMark {
+                depth: sim.get_call_stack().len(),
+                cycle: sim.get_cycles(),
+                routines: vec![entry],
+            },
+            interrupts: 0,
+            start: sim.get_cycles(),
+            cycles: sim.get_cycles(),
+        }
+    }
+
+    /// Records a step of the simulation, right after it happened.
+    pub fn record(&mut self, sim: &SimulationContext, event: &StepEvent) {
+        let cycles = sim.get_cycles() - self.cycles;
+
+        self.cycles = sim.get_cycles();
+
+        let address = match event {
+            StepEvent::Executed(executed) => {
+                let text = executed.instruction.to_string();
+                let mnemonic = text.split(' ').next().unwrap_or_default();
+                let mix = self.mix.entry(mnemonic.to_string()).or_default();
+
+                mix.0 += 1;
+                mix.1 += cycles;
+
+                Some(executed.address)
+            }
+            StepEvent::Interrupt { .. } => {
+                // The interrupt is a call of the vector, which its own frame pays for.
+                self.interrupts += 1;
+                self.push(sim.get_program_counter(), None);
+                None
+            }
+            StepEvent::Halted => return,
+        };
+
+        // The instruction belongs to the routine that was running when it started.
+        if let Some(address) = address {
+            let at = self.addresses.entry(address).or_default();
+
+            at.count += 1;
+            at.self_cycles += cycles;
+        }
+
+        let current = self.frames[self.frames.len() - 1].entry;
+        let mut seen = BTreeSet::new();
+
+        self.routines.entry(current).or_default().self_cycles += cycles;
+        *self
+            .stacks
+            .entry(self.frames.iter().map(|frame| frame.entry).collect())
+            .or_default() += cycles;
+
+        for frame in &self.frames {
+            // Recursive routines only count once.
+            if seen.insert(frame.entry) {
+                self.routines.entry(frame.entry).or_default().inclusive += cycles;
+            }
+        }
+
+        let sites: BTreeSet<usize> = self
+            .frames
+            .iter()
+            .filter_map(|frame| frame.call_site)
+            .chain(address)
+            .collect();
+
+        for site in sites {
+            self.addresses.entry(site).or_default().inclusive += cycles;
+        }
+
+        // Follow calls and returns.
+        let depth = sim.get_call_stack().len();
+
+        if let Some(address) = address {
+            if depth + 1 > self.frames.len() {
+                self.push(sim.get_program_counter(), Some(address));
+            } else {
+                self.frames.truncate((depth + 1).max(1));
+            }
+        }
+
+        if depth > self.high_water_mark.depth {
+            self.high_water_mark = HighWaterMark {
+                depth,
+                cycle: sim.get_cycles(),
+                routines: self.frames.iter().map(|frame| frame.entry).collect(),
+            };
+        }
+    }
+
+    fn push(&mut self, entry: usize, call_site: Option<usize>) {
+        self.frames.push(Frame { entry, call_site });
+        self.routines.entry(entry).or_default().count += 1;
+    }
+
+    /// Cycles spent at an address. The inclusive cycles of a CALL are those of the calls it made.
+    pub fn get_address(&self, address: usize) -> Option<Cycles> {
+        self.addresses.get(&address).copied()
+    }
+
+    /// Cycles spent in the routine starting at `entry`.
+    pub fn get_routine(&self, entry: usize) -> Option<Cycles> {
+        self.routines.get(&entry).copied()
+    }
+
+    /// Executions and cycles of each mnemonic, such as `LOAD` or `JUMP`.
+    pub fn get_instruction_mix(&self) -> &BTreeMap<String, (u64, u64)> {
+        &self.mix
+    }
+
+    pub fn get_high_water_mark(&self) -> &HighWaterMark {
+        &self.high_water_mark
+    }
+
+    pub fn get_interrupts(&self) -> u64 {
+        self.interrupts
+    }
+
+    /// Cycles since profiling started.
+    pub fn get_total_cycles(&self) -> u64 {
+        self.cycles - self.start
+    }
+
+    /// Self cycles of each chain of routines in the folded format read by flame graph tools
+    /// (`main;send;delay 1234`), one chain per line.
+    pub fn to_folded(&self, program: &Program) -> String {
+        self.stacks
+            .iter()
+            .filter(|(_, cycles)| **cycles > 0)
+            .map(|(stack, cycles)| {
+                let names: Vec<String> = stack
+                    .iter()
+                    .map(|entry| describe_routine(program, *entry))
+                    .collect();
+
+                format!("{} {}\n", names.join(";"), cycles)
+            })
+            .collect()
+    }
+
+    /// Routines by inclusive cycles, the `top` addresses that took the most cycles, the
+    /// instruction mix and the call stack high-water mark.
+    pub fn format_report(&self, program: &Program, top: usize) -> String {
+        let total = self.get_total_cycles();
+        let percent = |cycles: u64| match total {
+            0 => 0.0,
+            _ => cycles as f64 * 100.0 / total as f64,
+        };
+        let mut report = format!(
+            "{} clock cycles, {} instructions, {} interrupts\n",
+            total,
+            self.addresses.values().map(|at| at.count).sum::<u64>(),
+            self.interrupts
+        );
+
+        let chain: Vec<String> = self
+            .high_water_mark
+            .routines
+            .iter()
+            .map(|entry| describe_routine(program, *entry))
+            .collect();
+
+        report.push_str(&format!(
+            "Call stack high-water mark: {} (at cycle {}, {})\n",
+            self.high_water_mark.depth,
+            self.high_water_mark.cycle,
+            chain.join(" -> ")
+        ));
+
+        let mut routines: Vec<(&usize, &Cycles)> = self.routines.iter().collect();
+
+        routines.sort_by_key(|(entry, cycles)| (std::cmp::Reverse(cycles.inclusive), **entry));
+
+        let names: Vec<String> = routines
+            .iter()
+            .map(|(entry, _)| describe_routine(program, **entry))
+            .collect();
+        let width = names
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(7);
+
+        report.push_str(&format!(
+            "\n{:width$}  {:>8}  {:>12}  {:>6}  {:>12}  {:>6}  {:>10}\n",
+            "Routine",
+            "Calls",
+            "Self",
+            "%",
+            "Inclusive",
+            "%",
+            "Per call",
+            width = width
+        ));
+
+        for ((_, cycles), name) in routines.iter().zip(&names) {
+            report.push_str(&format!(
+                "{:width$}  {:>8}  {:>12}  {:>5.1}%  {:>12}  {:>5.1}%  {:>10.1}\n",
+                name,
+                cycles.count,
+                cycles.self_cycles,
+                percent(cycles.self_cycles),
+                cycles.inclusive,
+                percent(cycles.inclusive),
+                cycles.inclusive as f64 / cycles.count.max(1) as f64,
+                width = width
+            ));
+        }
+
+        let mut addresses: Vec<(&usize, &Cycles)> = self
+            .addresses
+            .iter()
+            .filter(|(_, cycles)| cycles.count > 0)
+            .collect();
+
+        addresses
+            .sort_by_key(|(address, cycles)| (std::cmp::Reverse(cycles.self_cycles), **address));
+        report.push_str(&format!(
+            "\nAddress  {:16}  {:20}  {:>8}  {:>12}  {:>6}  {:>12}  Instruction\n",
+            "Source", "Location", "Count", "Self", "%", "Inclusive"
+        ));
+
+        for (address, cycles) in addresses.into_iter().take(top) {
+            let line = program
+                .get_source_map()
+                .get_line(*address)
+                .map(|line| program.describe_line(line))
+                .unwrap_or_default();
+            let location = match program.find_label_before(*address) {
+                Some((label, 0)) => label.to_string(),
+                Some((label, offset)) => format!("{}+{}", label, offset),
+                None => String::new(),
+            };
+            let instruction = program
+                .get_instruction(*address)
+                .map(|instruction| instruction.to_string())
+                .unwrap_or_default();
+
+            report.push_str(&format!(
+                "  {:03X}    {:16}  {:20}  {:>8}  {:>12}  {:>5.1}%  {:>12}  {}\n",
+                address,
+                line,
+                location,
+                cycles.count,
+                cycles.self_cycles,
+                percent(cycles.self_cycles),
+                cycles.inclusive,
+                instruction
+            ));
+        }
+
+        let mut mix: Vec<(&String, &(u64, u64))> = self.mix.iter().collect();
+
+        mix.sort_by_key(|(mnemonic, (count, _))| (std::cmp::Reverse(*count), *mnemonic));
+        report.push_str(&format!(
+            "\n{:12}  {:>8}  {:>6}  {:>12}\n",
+            "Instruction", "Count", "%", "Cycles"
+        ));
+
+        let instructions: u64 = self.mix.values().map(|(count, _)| count).sum();
+
+        for (mnemonic, (count, cycles)) in mix {
+            report.push_str(&format!(
+                "{:12}  {:>8}  {:>5.1}%  {:>12}\n",
+                mnemonic,
+                count,
+                *count as f64 * 100.0 / instructions.max(1) as f64,
+                cycles
+            ));
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, PortState};
+
+    const SOURCE: &str = "\
+main:  enable interrupt
+loop:  call send
+       jump loop
+send:  load s0, 02
+wait:  call tick
+       sub s0, 01
+       jump nz, wait
+       return
+tick:  return
+       address 3FF
+isr:   returni enable
+";
+
+    fn profile(cycles: u64, interrupt: Option<u64>) -> (Program, Profiler) {
+        let program = assemble_str(SOURCE);
+        let mut sim = program.create_simulation();
+        let mut ports = PortState::new();
+
+        ports.raise_interrupt_at(interrupt.unwrap_or(u64::MAX));
+        sim.set_port_handler(Box::new(ports));
+
+        let mut profiler = Profiler::new(&sim);
+
+        while sim.get_cycles() < cycles {
+            let event = sim.step().unwrap();
+
+            profiler.record(&sim, &event);
+        }
+
+        (program, profiler)
+    }
+
+    #[test]
+    fn self_and_inclusive() {
+        // ENABLE and CALL, send and the two calls of tick, then JUMP.
+        let (_, profiler) = profile(26, None);
+
+        assert_eq!(profiler.get_total_cycles(), 26);
+        assert_eq!(
+            profiler.get_routine(0),
+            Some(Cycles {
+                count: 1,
+                self_cycles: 6,
+                inclusive: 26
+            })
+        );
+        assert_eq!(
+            profiler.get_routine(3),
+            Some(Cycles {
+                count: 1,
+                self_cycles: 16,
+                inclusive: 20
+            })
+        );
+        assert_eq!(
+            profiler.get_routine(8),
+            Some(Cycles {
+                count: 2,
+                self_cycles: 4,
+                inclusive: 4
+            })
+        );
+        // The CALL at 001 includes the whole of send.
+        assert_eq!(
+            profiler.get_address(1),
+            Some(Cycles {
+                count: 1,
+                self_cycles: 2,
+                inclusive: 22
+            })
+        );
+        assert_eq!(
+            profiler.get_address(4),
+            Some(Cycles {
+                count: 2,
+                self_cycles: 4,
+                inclusive: 8
+            })
+        );
+        assert_eq!(profiler.get_instruction_mix()["CALL"], (3, 6));
+        assert_eq!(profiler.get_instruction_mix()["RETURN"], (3, 6));
+        assert_eq!(profiler.get_high_water_mark().depth, 2);
+        assert_eq!(profiler.get_high_water_mark().routines, vec![0, 3, 8]);
+    }
+
+    #[test]
+    fn interrupts_and_folded_stacks() {
+        // The interrupt comes during the first call of tick.
+        let (program, profiler) = profile(30, Some(8));
+
+        assert_eq!(profiler.get_interrupts(), 1);
+        // Taking the interrupt and RETURNI.
+        assert_eq!(profiler.get_routine(0x3FF).unwrap().self_cycles, 4);
+        assert_eq!(
+            profiler.get_high_water_mark().routines,
+            vec![0, 3, 8, 0x3FF]
+        );
+
+        let folded = profiler.to_folded(&program);
+
+        assert_eq!(
+            folded,
+            "main 6\nmain;send 16\nmain;send;tick 4\nmain;send;tick;isr 4\n"
+        );
+    }
+
+    #[test]
+    fn report() {
+        let (program, profiler) = profile(26, None);
+        let report = profiler.format_report(&program, 3);
+
+        assert!(report.starts_with("26 clock cycles, 13 instructions, 0 interrupts\n"));
+        assert!(
+            report.contains("Call stack high-water mark: 2 (at cycle 8, main -> send -> tick)\n")
+        );
+        assert!(report.contains(
+            "main            1             6   23.1%            26  100.0%        26.0\n"
+        ));
+        assert!(report.contains("  004    line 5            wait                         2"));
+        assert_eq!(report.matches("\n  0").count(), 3);
+        assert!(report.contains("CALL                 3   23.1%             6\n"));
+    }
+}
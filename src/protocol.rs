@@ -0,0 +1,73 @@
+use std::io::{self, BufRead, Write};
+
+use crate::json::Json;
+
+/// Reads a message of the base protocol shared by LSP and DAP: `Content-Length` headers, an
+/// empty line, then that many bytes of JSON. `None` at the end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            // Blank lines between messages are tolerated.
+            if length.is_some() {
+                break;
+            }
+
+            continue;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+
+    input.read_exact(&mut body)?;
+
+    let text = String::from_utf8_lossy(&body);
+
+    Json::parse(&text)
+        .map(Some)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let message = Json::object([("id", Json::from(1u32)), ("text", Json::from("é"))]);
+        let mut buffer = Vec::new();
+
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &Json::Null).unwrap();
+
+        assert!(buffer.starts_with(b"Content-Length: 20\r\n\r\n{\"id\":1"));
+
+        let mut input = &buffer[..];
+
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), Some(Json::Null));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,538 @@
+//! Port stimulus replayed at given times and the outputs a program is expected to make, read from
+//! CSV or JSON files for regression tests.
+//!
+//! A CSV file starts with a header naming its columns. Stimulus files have `cycle` or `time`,
+//! `port` and `value`: from that point on, the port reads as the value.
+//!
+//! ```text
+//! # Switch A goes up after 1ms, switch B after 2000 cycles
+//! time, port, value
+//! 0,    01,   00
+//! 1ms,  01,   2A
+//! ```
+//!
+//! Expected-output files have `port` and `value`, and optionally `access` (`output`, the
+//! default, or `outputk`) and `cycle` or `time` for outputs that must happen at that moment.
+//! JSON files are arrays of objects with the same keys. Ports and values are hexadecimal as in
+//! PSM files unless written as `10'd`, `00001010'b` or `0x0A`, or as JSON numbers; times are in
+//! seconds unless they end with `ms`, `us` or `ns`.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::json::Json;
+use crate::{PortAccess, PortState, PortTransaction};
+
+/// When something happens, in clock cycles or in time since reset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Offset {
+    Cycle(u64),
+    /// In seconds.
+    Time(f64),
+}
+
+impl Offset {
+    /// The clock cycle at a clock frequency in Hz.
+    pub fn to_cycles(&self, clock: f64) -> u64 {
+        match self {
+            Offset::Cycle(cycle) => *cycle,
+            Offset::Time(seconds) => (seconds * clock).round() as u64,
+        }
+    }
+}
+
+/// A port taking a new value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputChange {
+    pub at: Offset,
+    pub port: u8,
+    pub value: u8,
+}
+
+/// Input port values replayed at given times.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stimulus {
+    changes: Vec<InputChange>,
+}
+
+impl Stimulus {
+    /// Reads a stimulus file, JSON if its extension is `.json` and CSV otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Stimulus, String> {
+        let rows = load_rows(path.as_ref())?;
+        let changes = rows
+            .iter()
+            .map(|row| {
+                Ok(InputChange {
+                    at: row
+                        .at
+                        .ok_or_else(|| row.error("it needs a cycle or a time"))?,
+                    port: row.port,
+                    value: row.value,
+                })
+            })
+            .collect::<Result<Vec<InputChange>, String>>();
+
+        changes
+            .map(|changes| Stimulus { changes })
+            .map_err(|message| format!("{}: {}", path.as_ref().display(), message))
+    }
+
+    pub fn new(changes: Vec<InputChange>) -> Stimulus {
+        Stimulus { changes }
+    }
+
+    pub fn get_changes(&self) -> &Vec<InputChange> {
+        &self.changes
+    }
+
+    /// Schedules the changes on ports, with times converted at a clock frequency in Hz.
+    pub fn apply(&self, ports: &mut PortState, clock: f64) {
+        for change in &self.changes {
+            ports.set_input_at(change.at.to_cycles(clock), change.port, change.value);
+        }
+    }
+}
+
+/// An OUTPUT or OUTPUTK a program is expected to make.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpectedOutput {
+    /// When it must happen, if it matters.
+    pub at: Option<Offset>,
+    pub access: PortAccess,
+    pub port: u8,
+    pub value: u8,
+}
+
+impl fmt::Display for ExpectedOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        format_transaction(f, self.access, self.port, self.value)
+    }
+}
+
+fn format_transaction(
+    f: &mut fmt::Formatter<'_>,
+    access: PortAccess,
+    port: u8,
+    value: u8,
+) -> fmt::Result {
+    match access {
+        PortAccess::OutputK => write!(f, "OUTPUTK port {:X} = {:02X}", port, value),
+        PortAccess::Output => write!(f, "OUTPUT port {:02X} = {:02X}", port, value),
+        PortAccess::Input => write!(f, "INPUT port {:02X} = {:02X}", port, value),
+    }
+}
+
+/// An output that isn't the expected one, or an expected one that never came.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// Clock cycle of the output, or the end of the run for missing ones.
+    pub cycle: u64,
+    /// Address of the OUTPUT or OUTPUTK instruction.
+    pub address: Option<usize>,
+    pub expected: Option<ExpectedOutput>,
+    pub actual: Option<PortTransaction>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => {
+                write!(f, "expected {}", expected)?;
+
+                if let Some(Offset::Cycle(cycle)) = expected.at {
+                    write!(f, " at cycle {}", cycle)?;
+                }
+
+                f.write_str(", got ")?;
+                format_transaction(f, actual.access, actual.port, actual.value)?;
+                write!(f, " at cycle {}", self.cycle)
+            }
+            (None, Some(actual)) => {
+                f.write_str("unexpected ")?;
+                format_transaction(f, actual.access, actual.port, actual.value)?;
+                write!(f, " at cycle {}", self.cycle)
+            }
+            (Some(expected), None) => write!(f, "expected {}, the run ended first", expected),
+            (None, None) => f.write_str("mismatch"),
+        }
+    }
+}
+
+/// Compares the outputs of a run with the expected ones, in order.
+pub struct OutputCheck {
+    expected: Vec<ExpectedOutput>,
+    clock: f64,
+    next: usize,
+    matched: usize,
+    mismatches: Vec<Mismatch>,
+}
+
+impl OutputCheck {
+    /// Times of the expected outputs are converted at a clock frequency in Hz.
+    pub fn new(expected: Vec<ExpectedOutput>, clock: f64) -> OutputCheck {
+        OutputCheck {
+            expected,
+            clock,
+            next: 0,
+            matched: 0,
+            mismatches: Vec::new(),
+        }
+    }
+
+    /// Reads the expected outputs from a file, JSON if its extension is `.json` and CSV
+    /// otherwise.
+    pub fn load(path: impl AsRef<Path>, clock: f64) -> Result<OutputCheck, String> {
+        let expected = load_rows(path.as_ref())?
+            .iter()
+            .map(|row| ExpectedOutput {
+                at: row.at,
+                access: row.access,
+                port: row.port,
+                value: row.value,
+            })
+            .collect();
+
+        Ok(OutputCheck::new(expected, clock))
+    }
+
+    /// Checks an output the program made, at `address` in clock cycle `cycle`. Inputs are
+    /// ignored.
+    pub fn check(&mut self, cycle: u64, address: usize, actual: &PortTransaction) {
+        if actual.access == PortAccess::Input {
+            return;
+        }
+
+        let expected = self.expected.get(self.next).copied();
+
+        self.next += 1;
+
+        let matches = expected.is_some_and(|expected| {
+            expected.access == actual.access
+                && expected.port == actual.port
+                && expected.value == actual.value
+                && expected
+                    .at
+                    .is_none_or(|at| at.to_cycles(self.clock) == cycle)
+        });
+
+        match matches {
+            true => self.matched += 1,
+            false => self.mismatches.push(Mismatch {
+                cycle,
+                address: Some(address),
+                expected,
+                actual: Some(*actual),
+            }),
+        }
+    }
+
+    /// Reports the expected outputs the program didn't get to, once the run ended at `cycle`.
+    pub fn finish(&mut self, cycle: u64) {
+        while let Some(expected) = self.expected.get(self.next) {
+            self.mismatches.push(Mismatch {
+                cycle,
+                address: None,
+                expected: Some(*expected),
+                actual: None,
+            });
+            self.next += 1;
+        }
+    }
+
+    pub fn get_expected(&self) -> &Vec<ExpectedOutput> {
+        &self.expected
+    }
+
+    /// How many outputs were the expected ones.
+    pub fn get_matched(&self) -> usize {
+        self.matched
+    }
+
+    pub fn get_mismatches(&self) -> &Vec<Mismatch> {
+        &self.mismatches
+    }
+}
+
+/// A row of a stimulus or expected-output file.
+struct Row {
+    /// Line in CSV files, entry in JSON ones.
+    location: String,
+    at: Option<Offset>,
+    access: PortAccess,
+    port: u8,
+    value: u8,
+}
+
+impl Row {
+    fn error(&self, message: &str) -> String {
+        format!("{} on {}", message, self.location)
+    }
+}
+
+fn load_rows(path: &Path) -> Result<Vec<Row>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let rows = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => Json::parse(&text).and_then(|document| parse_json(&document)),
+        _ => parse_csv(&text),
+    };
+
+    rows.map_err(|message| format!("{}: {}", path.display(), message))
+}
+
+fn parse_csv(text: &str) -> Result<Vec<Row>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+    let columns: Vec<String> = header
+        .split(',')
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+
+    for column in &columns {
+        if !["cycle", "time", "access", "port", "value"].contains(&column.as_str()) {
+            return Err(format!(
+                "'{}' isn't a column, there can be cycle or time, access, port and value",
+                column
+            ));
+        }
+    }
+
+    for needed in ["port", "value"] {
+        if !columns.iter().any(|column| column == needed) {
+            return Err(format!("there's no {} column", needed));
+        }
+    }
+
+    lines
+        .map(|(line, text)| {
+            let fields: Vec<&str> = text.split(',').map(str::trim).collect();
+
+            if fields.len() != columns.len() {
+                return Err(format!(
+                    "expected {} fields, got {} on line {}",
+                    columns.len(),
+                    fields.len(),
+                    line
+                ));
+            }
+
+            let field = |name: &str| {
+                columns
+                    .iter()
+                    .position(|column| column == name)
+                    .map(|index| fields[index])
+            };
+
+            let location = format!("line {}", line);
+
+            parse_row(&location, field).map_err(|message| format!("{} on {}", message, location))
+        })
+        .collect()
+}
+
+fn parse_json(document: &Json) -> Result<Vec<Row>, String> {
+    let Json::Array(values) = document else {
+        return Err("expected an array of objects".to_string());
+    };
+
+    values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let texts: Vec<(&str, String)> = ["cycle", "time", "access", "port", "value"]
+                .into_iter()
+                .filter_map(|key| {
+                    let text = match value.get(key)? {
+                        Json::String(text) => text.clone(),
+                        // Numbers are decimal, unlike the notation of strings.
+                        Json::Number(number) if key == "port" || key == "value" => {
+                            format!("{}'d", number)
+                        }
+                        Json::Number(number) => number.to_string(),
+                        _ => String::new(),
+                    };
+
+                    Some((key, text))
+                })
+                .collect();
+            let field = |name: &str| {
+                texts
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, text)| text.as_str())
+            };
+
+            let location = format!("entry {}", index + 1);
+
+            parse_row(&location, field).map_err(|message| format!("{} on {}", message, location))
+        })
+        .collect()
+}
+
+fn parse_row<'a>(location: &str, field: impl Fn(&str) -> Option<&'a str>) -> Result<Row, String> {
+    let at = match (field("cycle"), field("time")) {
+        (Some(cycle), _) if !cycle.is_empty() => Some(Offset::Cycle(
+            cycle
+                .replace('_', "")
+                .parse()
+                .map_err(|_| format!("'{}' isn't a cycle", cycle))?,
+        )),
+        (_, Some(time)) if !time.is_empty() => Some(Offset::Time(parse_time(time)?)),
+        _ => None,
+    };
+    let access = match field("access").map(str::to_lowercase).as_deref() {
+        None | Some("" | "output") => PortAccess::Output,
+        Some("outputk") => PortAccess::OutputK,
+        Some(access) => return Err(format!("'{}' isn't output or outputk", access)),
+    };
+    let byte = |name: &str| parse_byte(field(name).unwrap_or_default());
+
+    Ok(Row {
+        location: location.to_string(),
+        at,
+        access,
+        port: byte("port")?,
+        value: byte("value")?,
+    })
+}
+
+/// A byte in the notation of PSM files: hexadecimal unless written as `10'd`, `00001010'b` or
+/// `0x0A`.
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = if let Some(decimal) = text.strip_suffix("'d") {
+        decimal.parse::<u8>().ok()
+    } else if let Some(binary) = text.strip_suffix("'b") {
+        u8::from_str_radix(binary, 2).ok()
+    } else {
+        let hex = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix("0X"))
+            .unwrap_or(text);
+
+        u8::from_str_radix(hex, 16).ok()
+    };
+
+    value.ok_or_else(|| format!("'{}' isn't a byte", text))
+}
+
+/// Seconds, unless the time ends with `ms`, `us` or `ns`.
+fn parse_time(text: &str) -> Result<f64, String> {
+    let lower = text.to_lowercase();
+    let (number, scale) = [("ns", 1e-9), ("us", 1e-6), ("ms", 1e-3), ("s", 1.0)]
+        .iter()
+        .find_map(|(unit, scale)| lower.strip_suffix(unit).map(|number| (number, *scale)))
+        .unwrap_or((lower.as_str(), 1.0));
+
+    match number.trim().parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(value * scale),
+        _ => Err(format!("'{}' isn't a time", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, PortHandler, StepEvent};
+    use std::path::PathBuf;
+
+    fn write(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kcpsm6sim-{}-{}", std::process::id(), name));
+
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn stimulus_files() {
+        let csv = write(
+            "stimulus.csv",
+            "# switches\ntime, port, value\n0, 01, 00\n1us, 01, 2A\n\n1.5us, 2, 10'd\n",
+        );
+        let json = write(
+            "stimulus.json",
+            r#"[{ "cycle": 100, "port": 1, "value": 42 }, { "cycle": 150, "port": "2", "value": "0x0A" }]"#,
+        );
+        let from_csv = Stimulus::load(&csv).unwrap();
+
+        assert_eq!(from_csv.get_changes()[1].at, Offset::Time(1e-6));
+        assert_eq!(from_csv.get_changes()[2].value, 10);
+
+        let mut ports = PortState::new();
+
+        from_csv.apply(&mut ports, 100e6);
+        assert_eq!(ports.input(1, 98), 0x00);
+        assert_eq!(ports.input(1, 100), 0x2A);
+        assert_eq!(ports.input(2, 140), 0x00);
+        assert_eq!(ports.input(2, 150), 0x0A);
+
+        let from_json = Stimulus::load(&json).unwrap();
+
+        assert_eq!(
+            from_json
+                .get_changes()
+                .iter()
+                .map(|change| (change.at.to_cycles(100e6), change.port, change.value))
+                .collect::<Vec<_>>(),
+            [(100, 1, 0x2A), (150, 2, 0x0A)]
+        );
+
+        let bad = write("bad.csv", "cycle,port,value\n10,01\n");
+
+        assert!(Stimulus::load(&bad)
+            .unwrap_err()
+            .ends_with("bad.csv: expected 3 fields, got 2 on line 2"));
+
+        let bad = write("bad2.csv", "port,value\n01,02\n");
+
+        assert!(Stimulus::load(&bad)
+            .unwrap_err()
+            .ends_with("it needs a cycle or a time on line 2"));
+    }
+
+    #[test]
+    fn outputs_are_checked() {
+        let program =
+            assemble_str("input s0, 01\noutput s0, 04\nadd s0, 01\noutput s0, 04\noutputk 55, 2\n");
+        let expected = write(
+            "expected.csv",
+            "access, port, value, cycle\noutput, 04, 07,\noutput, 04, 07, 6\noutputk, 2, 55, 8\noutput, 04, 00,\n",
+        );
+        let mut check = OutputCheck::load(&expected, 100e6).unwrap();
+        let mut sim = program.create_simulation();
+        let mut ports = PortState::new();
+
+        ports.set_input(1, 0x07);
+        sim.set_port_handler(Box::new(ports));
+
+        while let Ok(StepEvent::Executed(executed)) = sim.step() {
+            if let Some(port) = &executed.port {
+                check.check(executed.cycle, executed.address, port);
+            }
+        }
+
+        check.finish(sim.get_cycles());
+
+        let mismatches: Vec<String> = check
+            .get_mismatches()
+            .iter()
+            .map(|mismatch| mismatch.to_string())
+            .collect();
+
+        assert_eq!(check.get_matched(), 2);
+        assert_eq!(
+            mismatches,
+            [
+                "expected OUTPUT port 04 = 07 at cycle 6, got OUTPUT port 04 = 08 at cycle 6",
+                "expected OUTPUT port 04 = 00, the run ended first"
+            ]
+        );
+        assert_eq!(check.get_mismatches()[0].address, Some(3));
+    }
+}
@@ -0,0 +1,1064 @@
+//! System descriptions: the processor generics, the clock and what is connected to which ports,
+//! read from a TOML or JSON file so every board doesn't need its peripherals wired in code.
+//!
+//! ```toml
+//! [processor]
+//! program_memory = 2048     # 1024, 2048 or 4096 instructions
+//! scratch_pad = 64          # 64, 128 or 256 bytes
+//! hwbuild = 0x41
+//! interrupt_vector = 0x7F0
+//! clock = "100MHz"          # or 100e6 (Hz)
+//!
+//! [[peripheral]]
+//! name = "console"
+//! type = "uart"
+//! baud = 115200
+//!
+//! [[port]]
+//! input = 0x00
+//! mask = 0x01               # only bit 0 is decoded, so every even port is the status
+//! to = "console.status"
+//! ```
+//!
+//! Each `port` entry connects an `input`, `output` or `outputk` port to a register of a
+//! peripheral. An access matches when the bits of `mask` (all 8 by default, the 4 of OUTPUTK for
+//! it) are equal, so partial decoding mirrors registers over several ports. The first matching
+//! entry wins, and accesses nothing matches go to the ports the description is put in front of.
+//!
+//! A `process` peripheral is a program started in the directory of the description, which plays
+//! the ports mapped to it over the protocol of [`cosim`](crate::peripherals::cosim): it's sent
+//! `READ`, `WRITE` and `WRITEK` lines on its standard input, with the port of the access, and
+//! answers each `READ` with a `VALUE` line on its standard output within `timeout` seconds.
+//!
+//! ```toml
+//! [[peripheral]]
+//! name = "keypad"
+//! type = "process"
+//! command = ["python3", "keypad.py"]
+//! timeout = 0.5             # seconds, 1 by default
+//!
+//! [[port]]
+//! input = 0x10
+//! mask = 0xF0               # ports 10 to 1F
+//! to = "keypad"
+//! ```
+
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::json::Json;
+use crate::peripherals::cosim::{CoSimHandle, CoSimulation};
+use crate::peripherals::i2c::{
+    I2cBus, I2cConfig, I2cDevice, I2cHandle, I2cTiming, M24c08, Pca9548,
+};
+use crate::peripherals::uart::{Uart, UartConfig, UartHost};
+use crate::toml::parse_toml;
+use crate::{PortAccess, PortHandler, PortState, SimulationContext, DEFAULT_INTERRUPT_VECTOR};
+
+/// The generics of the processor and its clock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Processor {
+    /// Instructions of program memory, the smallest size the program fits in when not given.
+    pub program_memory: Option<usize>,
+    pub scratch_pad: usize,
+    pub hwbuild: u8,
+    pub interrupt_vector: usize,
+    /// Clock frequency in Hz, if the description gives it.
+    pub clock: Option<f64>,
+}
+
+impl Default for Processor {
+    fn default() -> Self {
+        Processor {
+            program_memory: None,
+            scratch_pad: 64,
+            hwbuild: 0,
+            interrupt_vector: DEFAULT_INTERRUPT_VECTOR,
+            clock: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DeviceKind {
+    Pca9548,
+    M24c08 { file: Option<PathBuf> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceConfig {
+    kind: DeviceKind,
+    name: Option<String>,
+    address: u8,
+    /// The switch the device is behind, and its channel.
+    switch: Option<(String, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PeripheralKind {
+    Uart {
+        baud: u32,
+    },
+    I2c {
+        config: I2cConfig,
+        devices: Vec<DeviceConfig>,
+    },
+    Constant {
+        value: u8,
+    },
+    Process {
+        command: Vec<String>,
+        directory: PathBuf,
+        timeout: Duration,
+    },
+}
+
+impl PeripheralKind {
+    /// Registers of the peripheral, with the access and port the peripheral model has them on.
+    fn registers(&self) -> &'static [(&'static str, PortAccess, u8)] {
+        match self {
+            PeripheralKind::Uart { .. } => &[
+                ("status", PortAccess::Input, 0x00),
+                ("rx", PortAccess::Input, 0x01),
+                ("tx", PortAccess::Output, 0x01),
+                ("reset", PortAccess::OutputK, 0x01),
+            ],
+            PeripheralKind::I2c { .. } => &[
+                ("lines", PortAccess::Input, 0x00),
+                ("drive", PortAccess::Output, 0x00),
+                ("reset", PortAccess::OutputK, 0x00),
+            ],
+            PeripheralKind::Constant { .. } => &[("value", PortAccess::Input, 0x00)],
+            // Processes are given the port itself.
+            PeripheralKind::Process { .. } => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Peripheral {
+    name: String,
+    kind: PeripheralKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Mapping {
+    access: PortAccess,
+    address: u8,
+    mask: u8,
+    peripheral: usize,
+    /// How the peripheral model is accessed, the way the processor does when it's `None`.
+    register: Option<(PortAccess, u8)>,
+}
+
+impl Mapping {
+    fn matches(&self, access: PortAccess, port: u8) -> bool {
+        self.access == access && port & self.mask == self.address & self.mask
+    }
+}
+
+/// A system read from a description. See the [module documentation](self) for the format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemDescription {
+    processor: Processor,
+    peripherals: Vec<Peripheral>,
+    mappings: Vec<Mapping>,
+}
+
+impl SystemDescription {
+    /// Reads a description from a file, TOML unless its extension is `.json`. Files it names are
+    /// relative to its directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<SystemDescription, String> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let document = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Json::parse(&text),
+            _ => parse_toml(&text),
+        };
+
+        document
+            .and_then(|document| SystemDescription::from_json(&document, directory))
+            .map_err(|message| format!("{}: {}", path.display(), message))
+    }
+
+    /// Reads a description from a TOML or JSON document. Files it names are relative to
+    /// `directory`.
+    pub fn from_json(document: &Json, directory: &Path) -> Result<SystemDescription, String> {
+        let processor = match document.get("processor") {
+            Some(processor) => {
+                parse_processor(processor).map_err(|m| format!("processor: {}", m))?
+            }
+            None => Processor::default(),
+        };
+        let peripherals = list(document, "peripheral")?
+            .iter()
+            .enumerate()
+            .map(|(index, peripheral)| {
+                parse_peripheral(peripheral, directory)
+                    .map_err(|message| format!("peripheral {}: {}", index + 1, message))
+            })
+            .collect::<Result<Vec<Peripheral>, String>>()?;
+
+        for (index, peripheral) in peripherals.iter().enumerate() {
+            if peripherals[..index]
+                .iter()
+                .any(|other| other.name == peripheral.name)
+            {
+                return Err(format!(
+                    "there are two peripherals named '{}'",
+                    peripheral.name
+                ));
+            }
+        }
+
+        let mappings = list(document, "port")?
+            .iter()
+            .enumerate()
+            .map(|(index, port)| {
+                parse_mapping(port, &peripherals)
+                    .map_err(|message| format!("port {}: {}", index + 1, message))
+            })
+            .collect::<Result<Vec<Mapping>, String>>()?;
+
+        Ok(SystemDescription {
+            processor,
+            peripherals,
+            mappings,
+        })
+    }
+
+    pub fn get_processor(&self) -> &Processor {
+        &self.processor
+    }
+
+    /// Names of the peripherals, in the order they're described.
+    pub fn get_peripheral_names(&self) -> Vec<&str> {
+        self.peripherals
+            .iter()
+            .map(|peripheral| peripheral.name.as_str())
+            .collect()
+    }
+
+    /// Gives a simulation the generics of the processor. Fails if the program doesn't fit in its
+    /// program memory.
+    pub fn configure(&self, sim: &mut SimulationContext) -> Result<(), String> {
+        let processor = &self.processor;
+
+        if let Some(size) = processor.program_memory {
+            let used = (0..sim.get_program_memory_size())
+                .rev()
+                .find(|addr| sim.get_instruction(*addr).is_some())
+                .map_or(0, |addr| addr + 1);
+
+            if used > size {
+                return Err(format!(
+                    "the program needs {} instructions of program memory, the processor has {}",
+                    used, size
+                ));
+            }
+
+            sim.set_program_memory_size(size);
+        }
+
+        if processor.interrupt_vector >= sim.get_program_memory_size() {
+            return Err(format!(
+                "the interrupt vector {:03X} is past the end of program memory",
+                processor.interrupt_vector
+            ));
+        }
+
+        sim.set_scratch_pad_size(processor.scratch_pad);
+        sim.set_hwbuild(processor.hwbuild);
+        sim.set_interrupt_vector(processor.interrupt_vector);
+        Ok(())
+    }
+
+    /// Connects the peripherals of the description in front of `inner`, which gets the accesses
+    /// to ports the description doesn't map. `clock` is the clock frequency the peripherals time
+    /// themselves with.
+    pub fn create_ports(
+        &self,
+        clock: f64,
+        inner: Box<dyn PortHandler>,
+    ) -> Result<(SystemPorts, Peripherals), String> {
+        let mut instances: Vec<Box<dyn PortHandler>> = Vec::new();
+        let mut peripherals = Peripherals::default();
+
+        for peripheral in &self.peripherals {
+            let name = peripheral.name.clone();
+
+            instances.push(match &peripheral.kind {
+                PeripheralKind::Uart { baud } => {
+                    let config = UartConfig {
+                        baud: *baud,
+                        clock,
+                        ..UartConfig::default()
+                    };
+                    let uart = Uart::new(config, Box::new(PortState::new()));
+
+                    peripherals.uarts.push((name, uart.host()));
+                    Box::new(uart)
+                }
+                PeripheralKind::I2c { config, devices } => {
+                    let config = I2cConfig { clock, ..*config };
+                    let mut bus = I2cBus::new(config, Box::new(PortState::new()));
+
+                    for device in create_devices(devices)
+                        .map_err(|message| format!("{}: {}", name, message))?
+                    {
+                        bus.connect(device);
+                    }
+
+                    peripherals.buses.push((name, bus.handle()));
+                    Box::new(bus)
+                }
+                PeripheralKind::Constant { value } => {
+                    let mut ports = PortState::new();
+
+                    ports.set_input(0x00, *value);
+                    Box::new(ports)
+                }
+                PeripheralKind::Process {
+                    command,
+                    directory,
+                    timeout,
+                } => {
+                    let failure =
+                        |error| format!("{}: can't start '{}': {}", name, command[0], error);
+                    // Relative paths to the program are relative to the description too, and
+                    // made absolute since it's ambiguous which directory they'd be taken from.
+                    let program = match command[0].contains('/') {
+                        true => path::absolute(directory.join(&command[0])).map_err(failure)?,
+                        false => PathBuf::from(&command[0]),
+                    };
+                    let mut process = Command::new(program);
+
+                    process.args(&command[1..]);
+
+                    if !directory.as_os_str().is_empty() {
+                        process.current_dir(directory);
+                    }
+
+                    let process =
+                        CoSimulation::spawn(&mut process, clock, *timeout).map_err(failure)?;
+
+                    peripherals.processes.push((name, process.handle()));
+                    Box::new(process)
+                }
+            });
+        }
+
+        let ports = SystemPorts {
+            mappings: self.mappings.clone(),
+            instances,
+            inner,
+        };
+
+        Ok((ports, peripherals))
+    }
+}
+
+/// The devices on an I2C bus, those behind a switch connected to it.
+fn create_devices(configs: &[DeviceConfig]) -> Result<Vec<Box<dyn I2cDevice>>, String> {
+    let mut switches: Vec<(Option<&str>, Pca9548)> = Vec::new();
+    let mut devices: Vec<Box<dyn I2cDevice>> = Vec::new();
+
+    for config in configs {
+        if config.kind == DeviceKind::Pca9548 {
+            switches.push((config.name.as_deref(), Pca9548::new(config.address)));
+        }
+    }
+
+    for config in configs {
+        let device: Box<dyn I2cDevice> = match &config.kind {
+            DeviceKind::Pca9548 => continue,
+            DeviceKind::M24c08 { file: Some(file) } => Box::new(
+                M24c08::open(config.address, file)
+                    .map_err(|error| format!("{}: {}", file.display(), error))?,
+            ),
+            DeviceKind::M24c08 { file: None } => Box::new(M24c08::new(config.address)),
+        };
+
+        match &config.switch {
+            Some((name, channel)) => switches
+                .iter_mut()
+                .find(|(switch, _)| *switch == Some(name.as_str()))
+                .ok_or_else(|| format!("there's no switch named '{}'", name))?
+                .1
+                .connect(*channel, device),
+            None => devices.push(device),
+        }
+    }
+
+    for (_, switch) in switches {
+        devices.push(Box::new(switch));
+    }
+
+    Ok(devices)
+}
+
+/// The ports of a system: accesses go to the peripheral register the port is mapped to, or to
+/// the inner handler when it isn't.
+pub struct SystemPorts {
+    mappings: Vec<Mapping>,
+    instances: Vec<Box<dyn PortHandler>>,
+    inner: Box<dyn PortHandler>,
+}
+
+impl SystemPorts {
+    fn find(&self, access: PortAccess, port: u8) -> Option<Mapping> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.matches(access, port))
+            .copied()
+    }
+
+    fn write(&mut self, access: PortAccess, port: u8, value: u8, cycle: u64) {
+        let (handler, (access, port)) = match self.find(access, port) {
+            Some(mapping) => (
+                &mut self.instances[mapping.peripheral],
+                mapping.register.unwrap_or((access, port)),
+            ),
+            None => (&mut self.inner, (access, port)),
+        };
+
+        match access {
+            PortAccess::OutputK => handler.output_k(port, value, cycle),
+            _ => handler.output(port, value, cycle),
+        }
+    }
+}
+
+impl PortHandler for SystemPorts {
+    fn input(&mut self, port: u8, cycle: u64) -> u8 {
+        match self.find(PortAccess::Input, port) {
+            Some(mapping) => {
+                let register = mapping.register.map_or(port, |(_, register)| register);
+
+                self.instances[mapping.peripheral].input(register, cycle)
+            }
+            None => self.inner.input(port, cycle),
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8, cycle: u64) {
+        self.write(PortAccess::Output, port, value, cycle);
+    }
+
+    fn output_k(&mut self, port: u8, value: u8, cycle: u64) {
+        self.write(PortAccess::OutputK, port & 0xF, value, cycle);
+    }
+
+    fn interrupt(&mut self, cycle: u64) -> bool {
+        let mut raised = self.inner.interrupt(cycle);
+
+        for instance in &mut self.instances {
+            raised |= instance.interrupt(cycle);
+        }
+
+        raised
+    }
+
+    fn interrupt_ack(&mut self, cycle: u64) {
+        self.inner.interrupt_ack(cycle);
+
+        for instance in &mut self.instances {
+            instance.interrupt_ack(cycle);
+        }
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        self.inner.tick(cycle);
+
+        for instance in &mut self.instances {
+            instance.tick(cycle);
+        }
+    }
+}
+
+/// What the peripherals of a system are reached through from outside the simulation, by name.
+#[derive(Clone, Default)]
+pub struct Peripherals {
+    uarts: Vec<(String, UartHost)>,
+    buses: Vec<(String, I2cHandle)>,
+    processes: Vec<(String, CoSimHandle)>,
+}
+
+impl Peripherals {
+    pub fn get_uarts(&self) -> &Vec<(String, UartHost)> {
+        &self.uarts
+    }
+
+    pub fn get_uart(&self, name: &str) -> Option<&UartHost> {
+        self.uarts
+            .iter()
+            .find(|(uart, _)| uart == name)
+            .map(|(_, host)| host)
+    }
+
+    pub fn get_i2c_buses(&self) -> &Vec<(String, I2cHandle)> {
+        &self.buses
+    }
+
+    /// The `process` peripherals, to tell when they fail and when the run ends.
+    pub fn get_processes(&self) -> &Vec<(String, CoSimHandle)> {
+        &self.processes
+    }
+}
+
+/// An array of tables, empty if it isn't there.
+fn list<'a>(document: &'a Json, name: &str) -> Result<&'a [Json], String> {
+    match document.get(name) {
+        Some(Json::Array(values)) => Ok(values),
+        Some(_) => Err(format!("'{}' must be an array", name)),
+        None => Ok(&[]),
+    }
+}
+
+/// A whole number, given as a number or as a string with a `0x` or `0b` prefix.
+fn number(table: &Json, key: &str, max: u64) -> Result<Option<u64>, String> {
+    let value = match table.get(key) {
+        None => return Ok(None),
+        Some(Json::String(text)) => {
+            let text = text.replace('_', "");
+            let parsed = match text.get(..2) {
+                Some("0x" | "0X") => u64::from_str_radix(&text[2..], 16).ok(),
+                Some("0b" | "0B") => u64::from_str_radix(&text[2..], 2).ok(),
+                _ => text.parse().ok(),
+            };
+
+            parsed.ok_or_else(|| format!("{}: '{}' isn't a number", key, text))?
+        }
+        Some(value) => value
+            .as_u64()
+            .ok_or_else(|| format!("{} must be a whole number", key))?,
+    };
+
+    match value <= max {
+        true => Ok(Some(value)),
+        false => Err(format!("{} can't be more than {}", key, max)),
+    }
+}
+
+fn text<'a>(table: &'a Json, key: &str) -> Result<Option<&'a str>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| format!("{} must be a string", key)),
+    }
+}
+
+fn parse_processor(table: &Json) -> Result<Processor, String> {
+    let mut processor = Processor::default();
+
+    if let Some(size) = number(table, "program_memory", 4096)? {
+        if ![1024, 2048, 4096].contains(&size) {
+            return Err("program_memory must be 1024, 2048 or 4096".to_string());
+        }
+
+        processor.program_memory = Some(size as usize);
+    }
+
+    if let Some(size) = number(table, "scratch_pad", 256)? {
+        if ![64, 128, 256].contains(&size) {
+            return Err("scratch_pad must be 64, 128 or 256".to_string());
+        }
+
+        processor.scratch_pad = size as usize;
+    }
+
+    if let Some(hwbuild) = number(table, "hwbuild", 0xFF)? {
+        processor.hwbuild = hwbuild as u8;
+    }
+
+    if let Some(vector) = number(table, "interrupt_vector", 0xFFF)? {
+        processor.interrupt_vector = vector as usize;
+    }
+
+    processor.clock = match table.get("clock") {
+        None => None,
+        Some(Json::String(text)) => {
+            Some(parse_frequency(text).map_err(|message| format!("clock: {}", message))?)
+        }
+        Some(clock) => match clock.as_f64() {
+            Some(clock) if clock > 0.0 => Some(clock),
+            _ => return Err("clock must be a frequency in Hz".to_string()),
+        },
+    };
+
+    Ok(processor)
+}
+
+/// Reads a frequency in Hz, with an optional `Hz`, `kHz`, `MHz` or `GHz` unit.
+pub fn parse_frequency(text: &str) -> Result<f64, String> {
+    let lower = text.trim().to_lowercase();
+    let (number, scale) = [("ghz", 1e9), ("mhz", 1e6), ("khz", 1e3), ("hz", 1.0)]
+        .iter()
+        .find_map(|(unit, scale)| lower.strip_suffix(unit).map(|number| (number, *scale)))
+        .unwrap_or((lower.as_str(), 1.0));
+
+    match number.trim().parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value * scale),
+        _ => Err(format!("'{}' isn't a valid frequency", text)),
+    }
+}
+
+fn parse_peripheral(table: &Json, directory: &Path) -> Result<Peripheral, String> {
+    let kind = text(table, "type")?.ok_or("it needs a type")?;
+    let name = text(table, "name")?.unwrap_or(kind).to_string();
+    let kind = match kind {
+        "uart" => PeripheralKind::Uart {
+            baud: match number(table, "baud", u32::MAX as u64)? {
+                Some(0) => return Err("baud can't be 0".to_string()),
+                Some(baud) => baud as u32,
+                None => UartConfig::default().baud,
+            },
+        },
+        "i2c" => {
+            let timing = match text(table, "mode")? {
+                None | Some("standard") => I2cTiming::STANDARD,
+                Some("fast") => I2cTiming::FAST,
+                Some(mode) => {
+                    return Err(format!("mode '{}' isn't standard or fast", mode));
+                }
+            };
+            let defaults = I2cConfig::default();
+            let config = I2cConfig {
+                input_port: 0x00,
+                output_port: 0x00,
+                scl: number(table, "scl", 0xFF)?.map_or(defaults.scl, |bit| bit as u8),
+                sda: number(table, "sda", 0xFF)?.map_or(defaults.sda, |bit| bit as u8),
+                reset_port: Some(0x00),
+                reset_mask: number(table, "reset_mask", 0xFF)?
+                    .map_or(defaults.reset_mask, |mask| mask as u8),
+                timing,
+                ..defaults
+            };
+            let devices = list(table, "devices")?
+                .iter()
+                .map(|device| parse_device(device, directory))
+                .collect::<Result<Vec<DeviceConfig>, String>>()?;
+
+            PeripheralKind::I2c { config, devices }
+        }
+        "constant" => PeripheralKind::Constant {
+            value: number(table, "value", 0xFF)?.unwrap_or(0) as u8,
+        },
+        "process" => {
+            let command: Vec<String> = match table.get("command") {
+                Some(Json::String(command)) => {
+                    command.split_whitespace().map(str::to_string).collect()
+                }
+                Some(Json::Array(words)) => words
+                    .iter()
+                    .map(|word| word.as_str().map(str::to_string))
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or("command must be a string or an array of strings")?,
+                _ => Vec::new(),
+            };
+            let timeout = match table.get("timeout") {
+                None => Duration::from_secs(1),
+                Some(timeout) => timeout
+                    .as_f64()
+                    .filter(|timeout| *timeout > 0.0 && timeout.is_finite())
+                    .map(Duration::from_secs_f64)
+                    .ok_or("timeout must be a number of seconds")?,
+            };
+
+            if command.is_empty() {
+                return Err("it needs a command".to_string());
+            }
+
+            PeripheralKind::Process {
+                command,
+                directory: directory.to_path_buf(),
+                timeout,
+            }
+        }
+        kind => return Err(format!("'{}' isn't a kind of peripheral", kind)),
+    };
+
+    Ok(Peripheral { name, kind })
+}
+
+fn parse_device(table: &Json, directory: &Path) -> Result<DeviceConfig, String> {
+    let address = |default: u8| -> Result<u8, String> {
+        Ok(number(table, "address", 0x7F)?.map_or(default, |address| address as u8))
+    };
+    let (kind, address) = match text(table, "type")?.ok_or("devices need a type")? {
+        "pca9548" => (DeviceKind::Pca9548, address(0x74)?),
+        "m24c08" => (
+            DeviceKind::M24c08 {
+                file: text(table, "file")?.map(|file| directory.join(file)),
+            },
+            address(0x54)?,
+        ),
+        kind => return Err(format!("'{}' isn't a kind of I2C device", kind)),
+    };
+    let switch = match text(table, "switch")? {
+        Some(name) => Some((
+            name.to_string(),
+            number(table, "channel", 7)?.ok_or("devices behind a switch need a channel")? as u8,
+        )),
+        None => None,
+    };
+
+    Ok(DeviceConfig {
+        kind,
+        name: text(table, "name")?.map(str::to_string),
+        address,
+        switch,
+    })
+}
+
+fn parse_mapping(table: &Json, peripherals: &[Peripheral]) -> Result<Mapping, String> {
+    let accesses = [
+        ("input", PortAccess::Input),
+        ("output", PortAccess::Output),
+        ("outputk", PortAccess::OutputK),
+    ];
+    let mut found = Vec::new();
+
+    for (key, access) in accesses {
+        if let Some(address) = number(table, key, 0xFF)? {
+            found.push((access, address as u8));
+        }
+    }
+
+    let [(access, address)] = found[..] else {
+        return Err("it needs one of input, output or outputk".to_string());
+    };
+    let target = text(table, "to")?.ok_or("it needs a peripheral register to go to")?;
+    let (name, register) = target.split_once('.').unwrap_or((target, ""));
+    let (index, peripheral) = peripherals
+        .iter()
+        .enumerate()
+        .find(|(_, peripheral)| peripheral.name == name)
+        .ok_or_else(|| format!("there's no peripheral named '{}'", name))?;
+    let register = match &peripheral.kind {
+        PeripheralKind::Process { .. } if register.is_empty() => None,
+        PeripheralKind::Process { .. } => {
+            return Err(format!(
+                "'{}': processes have no registers, so the port goes to '{}'",
+                target, name
+            ));
+        }
+        kind => Some(find_register(kind, target, name, register, access)?),
+    };
+
+    let mask = match access {
+        PortAccess::OutputK => number(table, "mask", 0xF)?.unwrap_or(0xF),
+        _ => number(table, "mask", 0xFF)?.unwrap_or(0xFF),
+    } as u8;
+
+    Ok(Mapping {
+        access,
+        address,
+        mask,
+        peripheral: index,
+        register,
+    })
+}
+
+/// How the peripheral model has a register the processor accesses with `access`.
+fn find_register(
+    kind: &PeripheralKind,
+    target: &str,
+    name: &str,
+    register: &str,
+    access: PortAccess,
+) -> Result<(PortAccess, u8), String> {
+    let registers = kind.registers();
+    let &(_, register_access, register_port) = registers
+        .iter()
+        .find(|(known, _, _)| *known == register)
+        .ok_or_else(|| {
+            let names: Vec<&str> = registers.iter().map(|(known, _, _)| *known).collect();
+
+            format!(
+                "'{}' isn't a register of {} ({})",
+                target,
+                name,
+                names.join(", ")
+            )
+        })?;
+
+    if (access == PortAccess::Input) != (register_access == PortAccess::Input) {
+        return Err(match access {
+            PortAccess::Input => format!("'{}' can't be read", target),
+            _ => format!("'{}' can't be written", target),
+        });
+    }
+
+    Ok((register_access, register_port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripherals::uart::RX_DATA_PRESENT;
+    use std::io::Write;
+
+    const BOARD: &str = "[processor]\n\
+                         program_memory = 2048\n\
+                         scratch_pad = 128\n\
+                         hwbuild = 0x41\n\
+                         interrupt_vector = 0x7F0\n\
+                         clock = 50e6\n\
+                         \n\
+                         [[peripheral]]\n\
+                         name = \"console\"\n\
+                         type = \"uart\"\n\
+                         baud = 500000\n\
+                         \n\
+                         [[peripheral]]\n\
+                         name = \"id\"\n\
+                         type = \"constant\"\n\
+                         value = 0xA5\n\
+                         \n\
+                         [[port]]\n\
+                         input = 0x00\n\
+                         mask = 0x81\n\
+                         to = \"console.status\"\n\
+                         [[port]]\n\
+                         input = 0x01\n\
+                         mask = 0x81\n\
+                         to = \"console.rx\"\n\
+                         [[port]]\n\
+                         output = 0x10\n\
+                         to = \"console.tx\"\n\
+                         [[port]]\n\
+                         outputk = 0x3\n\
+                         to = \"console.reset\"\n\
+                         [[port]]\n\
+                         input = 0x80\n\
+                         to = \"id.value\"\n";
+
+    #[test]
+    fn ports_are_decoded() {
+        let description =
+            SystemDescription::from_json(&parse_toml(BOARD).unwrap(), Path::new("")).unwrap();
+        let mut inner = PortState::new();
+
+        inner.set_input(0x81, 0x5A);
+
+        let (mut ports, peripherals) = description.create_ports(50e6, Box::new(inner)).unwrap();
+        let mut host = peripherals.get_uart("console").unwrap().clone();
+
+        assert_eq!(description.get_peripheral_names(), ["console", "id"]);
+        host.write_all(b"A").unwrap();
+        ports.tick(1000);
+
+        // The status and data are mirrored on every port with bit 7 low.
+        assert_eq!(ports.input(0x02, 1000), RX_DATA_PRESENT);
+        assert_eq!(ports.input(0x7C, 1000), RX_DATA_PRESENT);
+        assert_eq!(ports.input(0x33, 1000), b'A');
+        assert_eq!(ports.input(0x80, 1000), 0xA5);
+        assert_eq!(ports.input(0x81, 1000), 0x5A);
+
+        ports.output(0x10, b'!', 1000);
+        ports.output(0x11, b'?', 1000);
+        ports.tick(5000);
+        assert_eq!(host.take_transmitted(), b"!");
+
+        host.write_all(b"B").unwrap();
+        ports.tick(10_000);
+        ports.output_k(0xF3, 0x02, 10_000);
+        assert_eq!(ports.input(0x00, 10_000), 0);
+    }
+
+    #[test]
+    fn processor_generics() {
+        let description =
+            SystemDescription::from_json(&parse_toml(BOARD).unwrap(), Path::new("")).unwrap();
+        let mut sim = SimulationContext::new();
+
+        assert_eq!(description.get_processor().clock, Some(50e6));
+        description.configure(&mut sim).unwrap();
+        assert_eq!(sim.get_program_memory_size(), 2048);
+        assert_eq!(sim.get_scratch_pad_size(), 128);
+        assert_eq!(sim.get_hwbuild(), 0x41);
+        assert_eq!(sim.get_interrupt_vector(), 0x7F0);
+
+        let small = SystemDescription::from_json(
+            &Json::parse(
+                r#"{ "processor": { "program_memory": 1024, "interrupt_vector": 2032 } }"#,
+            )
+            .unwrap(),
+            Path::new(""),
+        )
+        .unwrap();
+
+        assert_eq!(
+            small.configure(&mut sim).unwrap_err(),
+            "the interrupt vector 7F0 is past the end of program memory"
+        );
+
+        let clocked = |clock: &str| {
+            SystemDescription::from_json(
+                &parse_toml(&format!("[processor]\nclock = {}\n", clock)).unwrap(),
+                Path::new(""),
+            )
+            .map(|description| description.get_processor().clock)
+        };
+
+        assert_eq!(clocked("\"100MHz\""), Ok(Some(100e6)));
+        assert_eq!(clocked("\"12.5 kHz\""), Ok(Some(12.5e3)));
+        assert_eq!(clocked("32768"), Ok(Some(32768.0)));
+        assert_eq!(
+            clocked("\"fast\""),
+            Err("processor: clock: 'fast' isn't a valid frequency".to_string())
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |json: &str| {
+            SystemDescription::from_json(&Json::parse(json).unwrap(), Path::new("")).unwrap_err()
+        };
+
+        assert_eq!(
+            error(r#"{ "processor": { "scratch_pad": 100 } }"#),
+            "processor: scratch_pad must be 64, 128 or 256"
+        );
+        assert_eq!(
+            error(r#"{ "peripheral": [{ "type": "uart" }, { "type": "gpio" }] }"#),
+            "peripheral 2: 'gpio' isn't a kind of peripheral"
+        );
+        assert_eq!(
+            error(r#"{ "peripheral": [{ "type": "uart" }, { "type": "uart" }] }"#),
+            "there are two peripherals named 'uart'"
+        );
+        assert_eq!(
+            error(
+                r#"{ "peripheral": [{ "type": "uart" }], "port": [{ "input": 0, "to": "uart.data" }] }"#
+            ),
+            "port 1: 'uart.data' isn't a register of uart (status, rx, tx, reset)"
+        );
+        assert_eq!(
+            error(
+                r#"{ "peripheral": [{ "type": "uart" }], "port": [{ "output": "0x01", "to": "uart.rx" }] }"#
+            ),
+            "port 1: 'uart.rx' can't be written"
+        );
+        assert_eq!(
+            error(r#"{ "port": [{ "input": 0, "output": 1, "to": "uart.rx" }] }"#),
+            "port 1: it needs one of input, output or outputk"
+        );
+        assert_eq!(
+            error(
+                r#"{ "peripheral": [{ "type": "uart" }], "port": [{ "outputk": 0, "mask": "0x1F", "to": "uart.reset" }] }"#
+            ),
+            "port 1: mask can't be more than 15"
+        );
+        assert_eq!(
+            error(r#"{ "peripheral": [{ "name": "keypad", "type": "process" }] }"#),
+            "peripheral 1: it needs a command"
+        );
+        assert_eq!(
+            error(
+                r#"{ "peripheral": [{ "name": "keypad", "type": "process", "command": "keypad" }], "port": [{ "input": 0, "to": "keypad.keys" }] }"#
+            ),
+            "port 1: 'keypad.keys': processes have no registers, so the port goes to 'keypad'"
+        );
+    }
+
+    /// A sensor that reads as the last value written to any of its ports plus the port read.
+    const SENSOR: &str = "last=0; \
+                          while read request cycle port value; do \
+                              case $request in \
+                                  READ) printf 'VALUE %X\\n' $((0x$last + 0x$port)) ;; \
+                                  WRITE*) last=$value ;; \
+                              esac; \
+                          done";
+
+    fn process(script: &str, timeout: f64) -> SystemDescription {
+        let json = Json::parse(&format!(
+            r#"{{ "peripheral": [{{ "name": "sensor", "type": "process", "timeout": {},
+                                    "command": ["sh", "-c", "{}"] }}],
+                 "port": [{{ "input": "0x20", "mask": "0xF0", "to": "sensor" }},
+                          {{ "output": "0x20", "mask": "0xF0", "to": "sensor" }},
+                          {{ "outputk": "0x2", "to": "sensor" }}] }}"#,
+            timeout, script
+        ))
+        .unwrap();
+
+        SystemDescription::from_json(&json, Path::new("")).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn processes_play_their_ports() {
+        let mut inner = PortState::new();
+
+        inner.set_input(0x30, 0x77);
+
+        let (mut ports, peripherals) = process(SENSOR, 5.0)
+            .create_ports(100e6, Box::new(inner))
+            .unwrap();
+        let (name, handle) = &peripherals.get_processes()[0];
+
+        assert_eq!(name, "sensor");
+        assert_eq!(ports.input(0x21, 10), 0x21);
+        ports.output(0x2F, 0x40, 12);
+        assert_eq!(ports.input(0x23, 14), 0x63);
+        ports.output_k(0x2, 0x10, 16);
+        assert_eq!(ports.input(0x20, 18), 0x30);
+
+        // Ports nothing is mapped to aren't sent.
+        assert_eq!(ports.input(0x30, 20), 0x77);
+        ports.output(0x30, 0x55, 22);
+        assert_eq!(ports.input(0x20, 24), 0x30);
+        assert_eq!(handle.get_error(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn processes_time_out() {
+        let (mut ports, peripherals) = process("while read line; do :; done", 0.2)
+            .create_ports(100e6, Box::new(PortState::new()))
+            .unwrap();
+
+        assert_eq!(ports.input(0x20, 10), 0);
+        let handle = &peripherals.get_processes()[0].1;
+
+        assert_eq!(
+            handle.get_error().unwrap(),
+            "no answer to INPUT port 20 at cycle 10 within 200ms"
+        );
+        assert!(handle.is_timed_out());
+
+        let error = process("exit 0", 1.0);
+        let (mut ports, peripherals) = error
+            .create_ports(100e6, Box::new(PortState::new()))
+            .unwrap();
+
+        assert_eq!(ports.input(0x20, 10), 0);
+        assert!(!peripherals.get_processes()[0].1.is_timed_out());
+        assert!(peripherals.get_processes()[0]
+            .1
+            .get_error()
+            .is_some_and(|error| error == "the process disconnected" || error.contains("pipe")));
+
+        let missing = SystemDescription::from_json(
+            &Json::parse(
+                r#"{ "peripheral": [{ "name": "sensor", "type": "process", "command": "./missing" }] }"#,
+            )
+            .unwrap(),
+            Path::new("/nonexistent"),
+        )
+        .unwrap();
+
+        assert!(missing
+            .create_ports(100e6, Box::new(PortState::new()))
+            .err()
+            .unwrap()
+            .starts_with("sensor: can't start './missing': "));
+    }
+}
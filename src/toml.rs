@@ -0,0 +1,468 @@
+use crate::json::Json;
+
+/// Reads a TOML document into the same values as JSON, so both can describe the same things.
+///
+/// Tables, arrays of tables, dotted keys, strings, integers (decimal, hexadecimal, octal and
+/// binary), floats, booleans, arrays and inline tables are supported. Dates and multi-line
+/// strings aren't.
+pub fn parse_toml(text: &str) -> Result<Json, String> {
+    let mut parser = TomlParser {
+        chars: text.chars().collect(),
+        position: 0,
+        line: 1,
+    };
+    let mut root = Json::Object(Vec::new());
+    // Keys of the table that key/value pairs go in.
+    let mut current: Vec<String> = Vec::new();
+
+    loop {
+        parser.skip_blank_lines();
+
+        match parser.peek() {
+            None => return Ok(root),
+            Some('[') if parser.peek_at(1) == Some('[') => {
+                parser.position += 2;
+
+                let path = parser.keys()?;
+
+                parser.expect(']')?;
+                parser.expect(']')?;
+                parser.end_of_line()?;
+
+                let (last, parent) = path.split_last().unwrap();
+                let members = parser.table(&mut root, parent)?;
+
+                match members.iter_mut().find(|(key, _)| key == last) {
+                    Some((_, Json::Array(tables))) => tables.push(Json::Object(Vec::new())),
+                    Some(_) => return Err(parser.error(&format!("'{}' isn't an array", last))),
+                    None => {
+                        members.push((last.clone(), Json::Array(vec![Json::Object(Vec::new())])))
+                    }
+                }
+
+                current = path;
+            }
+            Some('[') => {
+                parser.position += 1;
+
+                let path = parser.keys()?;
+
+                parser.expect(']')?;
+                parser.end_of_line()?;
+                parser.table(&mut root, &path)?;
+                current = path;
+            }
+            Some(_) => {
+                let keys = parser.keys()?;
+
+                parser.expect('=')?;
+
+                let value = parser.value()?;
+
+                parser.end_of_line()?;
+
+                let (last, parent) = keys.split_last().unwrap();
+                let mut path = current.clone();
+
+                path.extend_from_slice(parent);
+
+                let members = parser.table(&mut root, &path)?;
+
+                if members.iter().any(|(key, _)| key == last) {
+                    return Err(parser.error(&format!("'{}' is defined twice", last)));
+                }
+
+                members.push((last.clone(), value));
+            }
+        }
+    }
+}
+
+struct TomlParser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl TomlParser {
+    fn error(&self, message: &str) -> String {
+        format!("{} on line {}", message, self.line)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+
+        self.position += 1;
+
+        if c == '\n' {
+            self.line += 1;
+        }
+
+        Some(c)
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.position += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.position += 1;
+            }
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+
+            match self.peek() {
+                Some('\n' | '\r') => {
+                    self.next();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skips blank lines and comments inside arrays and inline tables.
+    fn skip_whitespace(&mut self) {
+        self.skip_blank_lines();
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_spaces();
+
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        self.skip_comment();
+
+        match self.peek() {
+            None | Some('\n' | '\r') => Ok(()),
+            _ => Err(self.error("unexpected text after the value")),
+        }
+    }
+
+    /// A key, dotted or not.
+    fn keys(&mut self) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+
+        loop {
+            self.skip_spaces();
+
+            let key = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.position;
+
+                    while self
+                        .peek()
+                        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        self.position += 1;
+                    }
+
+                    if start == self.position {
+                        return Err(self.error("expected a key"));
+                    }
+
+                    self.chars[start..self.position].iter().collect()
+                }
+            };
+
+            keys.push(key);
+            self.skip_spaces();
+
+            match self.peek() {
+                Some('.') => self.position += 1,
+                _ => return Ok(keys),
+            }
+        }
+    }
+
+    /// Members of the table at `path`, created along the way. Arrays of tables lead to their last
+    /// table.
+    fn table<'a>(
+        &self,
+        root: &'a mut Json,
+        path: &[String],
+    ) -> Result<&'a mut Vec<(String, Json)>, String> {
+        let mut table = root;
+
+        for key in path {
+            let Json::Object(members) = table else {
+                unreachable!()
+            };
+            let index = match members.iter().position(|(member, _)| member == key) {
+                Some(index) => index,
+                None => {
+                    members.push((key.clone(), Json::Object(Vec::new())));
+                    members.len() - 1
+                }
+            };
+
+            let value = &mut members[index].1;
+
+            table = match value {
+                Json::Object(_) => value,
+                Json::Array(tables) if matches!(tables.last(), Some(Json::Object(_))) => {
+                    tables.last_mut().unwrap()
+                }
+                _ => return Err(self.error(&format!("'{}' isn't a table", key))),
+            };
+        }
+
+        match table {
+            Json::Object(members) => Ok(members),
+            _ => unreachable!(),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_spaces();
+
+        match self.peek() {
+            Some('"') => Ok(Json::String(self.basic_string()?)),
+            Some('\'') => Ok(Json::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => self.scalar(),
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+
+        self.position += 1;
+
+        loop {
+            match self.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex: String = (0..4).filter_map(|_| self.next()).collect();
+
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+
+                    text.push(c);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+
+        self.position += 1;
+
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(text),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        let mut values = Vec::new();
+
+        self.position += 1;
+
+        loop {
+            self.skip_whitespace();
+
+            if self.peek() == Some(']') {
+                self.position += 1;
+                return Ok(Json::Array(values));
+            }
+
+            values.push(self.value()?);
+            self.skip_whitespace();
+
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Json::Array(values)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Json, String> {
+        let mut table = Json::Object(Vec::new());
+
+        self.position += 1;
+        self.skip_spaces();
+
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(table);
+        }
+
+        loop {
+            let keys = self.keys()?;
+
+            self.expect('=')?;
+
+            let value = self.value()?;
+            let (last, parent) = keys.split_last().unwrap();
+            let members = self.table(&mut table, parent)?;
+
+            if members.iter().any(|(key, _)| key == last) {
+                return Err(self.error(&format!("'{}' is defined twice", last)));
+            }
+
+            members.push((last.clone(), value));
+            self.skip_spaces();
+
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(table),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    /// Booleans and numbers, which end at a separator.
+    fn scalar(&mut self) -> Result<Json, String> {
+        let start = self.position;
+
+        while !matches!(
+            self.peek(),
+            None | Some(' ' | '\t' | '\n' | '\r' | ',' | ']' | '}' | '#')
+        ) {
+            self.position += 1;
+        }
+
+        let text: String = self.chars[start..self.position].iter().collect();
+        let digits = text.replace('_', "");
+        let (negative, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let radix = match unsigned.get(..2) {
+            Some("0x") => Some(16),
+            Some("0o") => Some(8),
+            Some("0b") => Some(2),
+            _ => None,
+        };
+        let number = match radix {
+            Some(radix) if !negative => u64::from_str_radix(&unsigned[2..], radix)
+                .ok()
+                .map(|value| value as f64),
+            Some(_) => None,
+            None => digits.parse::<f64>().ok().filter(|value| value.is_finite()),
+        };
+
+        match (text.as_str(), number) {
+            ("true", _) => Ok(Json::Bool(true)),
+            ("false", _) => Ok(Json::Bool(false)),
+            (_, Some(number)) => Ok(Json::Number(number)),
+            _ => Err(self.error(&format!("'{}' isn't a value", text))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents() {
+        let document = parse_toml(
+            "# A board\n\
+             name = \"KC705\" # inline comment\n\
+             [processor]\n\
+             clock = 100e6\n\
+             hwbuild = 0x41\n\
+             size.words = 1_024\n\
+             \n\
+             [[peripheral]]\n\
+             type = 'uart'\n\
+             ports = [0x00, 0b1,\n   -2, ] # trailing comma\n\
+             [[peripheral]]\n\
+             type = \"i2c\"\n\
+             devices = [{ type = \"m24c08\", file = \"a\\\\b.bin\" }]\n\
+             [peripheral.extra]\n\
+             on = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            document,
+            Json::parse(
+                r#"{
+                    "name": "KC705",
+                    "processor": { "clock": 100000000, "hwbuild": 65, "size": { "words": 1024 } },
+                    "peripheral": [
+                        { "type": "uart", "ports": [0, 1, -2] },
+                        {
+                            "type": "i2c",
+                            "devices": [{ "type": "m24c08", "file": "a\\b.bin" }],
+                            "extra": { "on": true }
+                        }
+                    ]
+                }"#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_toml("a = 1\nb = 2 3\n").unwrap_err(),
+            "unexpected text after the value on line 2"
+        );
+        assert_eq!(
+            parse_toml("a = 1\na = 2\n").unwrap_err(),
+            "'a' is defined twice on line 2"
+        );
+        assert_eq!(
+            parse_toml("a = \"open\n").unwrap_err(),
+            "unterminated string on line 2"
+        );
+        assert_eq!(
+            parse_toml("a = 1\n[a]\n").unwrap_err(),
+            "'a' isn't a table on line 2"
+        );
+        assert_eq!(
+            parse_toml("a = 0xZZ").unwrap_err(),
+            "'0xZZ' isn't a value on line 1"
+        );
+    }
+}
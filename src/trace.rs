@@ -0,0 +1,836 @@
+use std::io::{self, Read, Write};
+
+use crate::json::Json;
+use crate::{
+    decode, encode, Instruction, MemoryOperation, PortAccess, PortTransaction, Program,
+    SimulationContext, StepEvent,
+};
+
+/// Start of binary traces, followed by a version byte.
+const MAGIC: &[u8; 8] = b"KC6TRACE";
+const VERSION: u8 = 1;
+
+/// A register written by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    /// `a` or `b`.
+    pub bank: char,
+    pub register: u8,
+    pub from: u8,
+    pub to: u8,
+}
+
+/// A scratch pad access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Fetch { address: u8, value: u8 },
+    Store { address: u8, value: u8 },
+}
+
+/// What happened in a step of the processor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Clock cycle the step started in.
+    pub cycle: u64,
+    pub address: usize,
+    /// `None` when the processor took an interrupt instead of executing the instruction at
+    /// `address`.
+    pub instruction: Option<Instruction>,
+    pub registers: Vec<RegisterChange>,
+    // New values of the flags and processor state that changed.
+    pub zero: Option<bool>,
+    pub carry: Option<bool>,
+    pub bank: Option<char>,
+    pub interrupt_enabled: Option<bool>,
+    pub memory: Option<MemoryAccess>,
+    pub port: Option<PortTransaction>,
+}
+
+/// Which steps are recorded. Everything is when there are no ranges nor routines.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    ranges: Vec<(usize, usize)>,
+    routines: Vec<usize>,
+}
+
+impl TraceFilter {
+    pub fn new() -> TraceFilter {
+        TraceFilter::default()
+    }
+
+    /// Records the instructions from `first` to `last`, both included.
+    pub fn add_range(&mut self, first: usize, last: usize) {
+        self.ranges.push((first, last));
+    }
+
+    /// Records everything from when the routine at `entry` starts until it returns, including
+    /// the routines it calls.
+    pub fn add_routine(&mut self, entry: usize) {
+        self.routines.push(entry);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty() && self.routines.is_empty()
+    }
+}
+
+/// State of the processor before a step, to find what the step changed.
+struct Snapshot {
+    banks: [[u8; 16]; 2],
+    zero: bool,
+    carry: bool,
+    bank: char,
+    interrupt_enabled: bool,
+    depth: usize,
+}
+
+impl Snapshot {
+    fn new(sim: &SimulationContext) -> Snapshot {
+        Snapshot {
+            banks: [sim.get_bank_registers('a'), sim.get_bank_registers('b')],
+            zero: sim.get_zero_flag(),
+            carry: sim.get_carry_flag(),
+            bank: sim.get_register_bank(),
+            interrupt_enabled: sim.is_interrupt_enabled(),
+            depth: sim.get_call_stack().len(),
+        }
+    }
+}
+
+/// Turns the steps of a simulation into trace entries.
+///
+/// ```
+/// use kcpsm6sim::{assemble_str, trace::{TraceFilter, Tracer}};
+///
+/// let program = assemble_str("load s0, 01\nadd s0, 01\n");
+/// let mut sim = program.create_simulation();
+/// let mut tracer = Tracer::new(&sim, TraceFilter::new());
+/// let event = sim.step().unwrap();
+/// let entry = tracer.record(&sim, &event).unwrap();
+///
+/// assert_eq!(entry.registers[0].to, 0x01);
+/// ```
+pub struct Tracer {
+    filter: TraceFilter,
+    previous: Snapshot,
+    /// Call stack depth of the routine being traced, if any.
+    routine_depth: Option<usize>,
+}
+
+impl Tracer {
+    pub fn new(sim: &SimulationContext, filter: TraceFilter) -> Tracer {
+        Tracer {
+            filter,
+            previous: Snapshot::new(sim),
+            routine_depth: None,
+        }
+    }
+
+    /// Records a step, right after it happened. `None` if it's filtered out or the processor
+    /// halted.
+    pub fn record(&mut self, sim: &SimulationContext, event: &StepEvent) -> Option<TraceEntry> {
+        let previous = std::mem::replace(&mut self.previous, Snapshot::new(sim));
+        let (cycle, address, instruction, memory_op, port) = match event {
+            StepEvent::Executed(executed) => (
+                executed.cycle,
+                executed.address,
+                Some(executed.instruction.clone()),
+                executed.memory_op,
+                executed.port,
+            ),
+            StepEvent::Interrupt { cycle, address } => (*cycle, *address, None, None, None),
+            StepEvent::Halted => return None,
+        };
+
+        if !self.is_traced(address, previous.depth) {
+            return None;
+        }
+
+        let current = &self.previous;
+        let mut registers = Vec::new();
+
+        for (index, bank) in ['a', 'b'].into_iter().enumerate() {
+            for register in 0..16 {
+                let (from, to) = (
+                    previous.banks[index][register],
+                    current.banks[index][register],
+                );
+
+                if from != to {
+                    registers.push(RegisterChange {
+                        bank,
+                        register: register as u8,
+                        from,
+                        to,
+                    });
+                }
+            }
+        }
+
+        fn changed<T: PartialEq>(before: T, after: T) -> Option<T> {
+            (before != after).then_some(after)
+        }
+
+        Some(TraceEntry {
+            cycle,
+            address,
+            instruction,
+            registers,
+            zero: changed(previous.zero, current.zero),
+            carry: changed(previous.carry, current.carry),
+            bank: changed(previous.bank, current.bank),
+            interrupt_enabled: changed(previous.interrupt_enabled, current.interrupt_enabled),
+            memory: memory_op.map(|operation| match operation {
+                MemoryOperation::Fetch(address, register) => MemoryAccess::Fetch {
+                    address: address as u8,
+                    value: sim.get_register(register as usize).unwrap_or(0),
+                },
+                MemoryOperation::Store(address, value) => MemoryAccess::Store {
+                    address: address as u8,
+                    value,
+                },
+            }),
+            port,
+        })
+    }
+
+    /// Whether a step at `address`, with `depth` return addresses on the call stack, is traced.
+    fn is_traced(&mut self, address: usize, depth: usize) -> bool {
+        if self.filter.is_empty() {
+            return true;
+        }
+
+        if self.routine_depth.is_some_and(|routine| depth < routine) {
+            self.routine_depth = None;
+        }
+
+        if self.routine_depth.is_none() && self.filter.routines.contains(&address) {
+            self.routine_depth = Some(depth);
+        }
+
+        self.routine_depth.is_some()
+            || self
+                .filter
+                .ranges
+                .iter()
+                .any(|(first, last)| (*first..=*last).contains(&address))
+    }
+}
+
+/// How trace entries are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A line per entry, to read.
+    Text,
+    /// A JSON object per line.
+    JsonLines,
+    /// A compact binary form for long runs, read back with [`TraceReader`].
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "jsonl" | "json" => Some(TraceFormat::JsonLines),
+            "binary" | "bin" => Some(TraceFormat::Binary),
+            _ => None,
+        }
+    }
+}
+
+fn describe_location(program: Option<&Program>, address: usize) -> Option<String> {
+    let (label, offset) = program?.find_label_before(address)?;
+
+    Some(match offset {
+        0 => label.to_string(),
+        offset => format!("{}+{}", label, offset),
+    })
+}
+
+fn register_name(change: &RegisterChange) -> String {
+    match change.bank {
+        'b' => format!("B.s{:X}", change.register),
+        _ => format!("s{:X}", change.register),
+    }
+}
+
+fn access_name(access: PortAccess) -> &'static str {
+    match access {
+        PortAccess::Input => "input",
+        PortAccess::Output => "output",
+        PortAccess::OutputK => "outputk",
+    }
+}
+
+/// An entry as a line of text, with the location of its address in `program` if given.
+pub fn format_entry(entry: &TraceEntry, program: Option<&Program>) -> String {
+    let mut changes: Vec<String> = entry
+        .registers
+        .iter()
+        .map(|change| {
+            format!(
+                "{} {:02X}->{:02X}",
+                register_name(change),
+                change.from,
+                change.to
+            )
+        })
+        .collect();
+
+    if let Some(zero) = entry.zero {
+        changes.push(format!("Z={}", zero as u8));
+    }
+
+    if let Some(carry) = entry.carry {
+        changes.push(format!("C={}", carry as u8));
+    }
+
+    if let Some(bank) = entry.bank {
+        changes.push(format!("bank {}", bank.to_ascii_uppercase()));
+    }
+
+    if let Some(enabled) = entry.interrupt_enabled {
+        changes.push(format!(
+            "interrupts {}",
+            if enabled { "enabled" } else { "disabled" }
+        ));
+    }
+
+    match entry.memory {
+        Some(MemoryAccess::Fetch { address, value }) => {
+            changes.push(format!("fetch [{:02X}]={:02X}", address, value))
+        }
+        Some(MemoryAccess::Store { address, value }) => {
+            changes.push(format!("store [{:02X}]={:02X}", address, value))
+        }
+        None => {}
+    }
+
+    if let Some(port) = entry.port {
+        changes.push(match port.access {
+            PortAccess::Input => format!("in {:02X}={:02X}", port.port, port.value),
+            PortAccess::Output => format!("out {:02X}={:02X}", port.port, port.value),
+            PortAccess::OutputK => format!("outk {:X}={:02X}", port.port, port.value),
+        });
+    }
+
+    let instruction = match &entry.instruction {
+        Some(instruction) => instruction.to_string(),
+        None => "(interrupt)".to_string(),
+    };
+    let line = format!(
+        "{:>10}  {:03X}  {:<20}  {:<24}  {}",
+        entry.cycle,
+        entry.address,
+        describe_location(program, entry.address).unwrap_or_default(),
+        instruction,
+        changes.join("  ")
+    );
+
+    line.trim_end().to_string()
+}
+
+/// An entry as a JSON object. Only what changed is included.
+pub fn entry_to_json(entry: &TraceEntry, program: Option<&Program>) -> Json {
+    // The file the instruction was written in and its line there.
+    let (file, line) = program
+        .and_then(|program| {
+            let line = program.get_source_map().get_line(entry.address)?;
+
+            program.get_location(line)
+        })
+        .map_or((None, None), |(file, line)| {
+            (file.map(|file| file.display().to_string()), Some(line))
+        });
+    let mut members = vec![
+        ("cycle", Json::from(entry.cycle)),
+        ("address", Json::from(entry.address)),
+        (
+            "location",
+            Json::from(describe_location(program, entry.address)),
+        ),
+        ("file", Json::from(file)),
+        ("line", Json::from(line)),
+    ];
+
+    match &entry.instruction {
+        Some(instruction) => members.push(("instruction", Json::from(instruction.to_string()))),
+        None => members.push(("interrupt", Json::from(true))),
+    }
+
+    if !entry.registers.is_empty() {
+        members.push((
+            "registers",
+            Json::array(entry.registers.iter().map(|change| {
+                Json::object([
+                    (
+                        "bank",
+                        Json::from(change.bank.to_ascii_uppercase().to_string()),
+                    ),
+                    ("register", Json::from(format!("s{:X}", change.register))),
+                    ("from", Json::from(change.from)),
+                    ("to", Json::from(change.to)),
+                ])
+            })),
+        ));
+    }
+
+    if let Some(zero) = entry.zero {
+        members.push(("zero", Json::from(zero)));
+    }
+
+    if let Some(carry) = entry.carry {
+        members.push(("carry", Json::from(carry)));
+    }
+
+    if let Some(bank) = entry.bank {
+        members.push(("bank", Json::from(bank.to_ascii_uppercase().to_string())));
+    }
+
+    if let Some(enabled) = entry.interrupt_enabled {
+        members.push(("interruptsEnabled", Json::from(enabled)));
+    }
+
+    if let Some(memory) = entry.memory {
+        let (access, address, value) = match memory {
+            MemoryAccess::Fetch { address, value } => ("fetch", address, value),
+            MemoryAccess::Store { address, value } => ("store", address, value),
+        };
+
+        members.push((
+            "memory",
+            Json::object([
+                ("access", Json::from(access)),
+                ("address", Json::from(address)),
+                ("value", Json::from(value)),
+            ]),
+        ));
+    }
+
+    if let Some(port) = entry.port {
+        members.push((
+            "port",
+            Json::object([
+                ("access", Json::from(access_name(port.access))),
+                ("port", Json::from(port.port)),
+                ("value", Json::from(port.value)),
+            ]),
+        ));
+    }
+
+    Json::object(members)
+}
+
+/// Writes trace entries in one of the [`TraceFormat`]s.
+pub struct TraceWriter<'a, W: Write> {
+    output: W,
+    format: TraceFormat,
+    program: Option<&'a Program>,
+    /// Cycle of the last binary entry, which the next one is relative to.
+    cycle: u64,
+}
+
+impl<'a, W: Write> TraceWriter<'a, W> {
+    /// `program` gives the labels of text and JSON entries.
+    pub fn new(
+        mut output: W,
+        format: TraceFormat,
+        program: Option<&'a Program>,
+    ) -> io::Result<TraceWriter<'a, W>> {
+        if format == TraceFormat::Binary {
+            output.write_all(MAGIC)?;
+            output.write_all(&[VERSION])?;
+        }
+
+        Ok(TraceWriter {
+            output,
+            format,
+            program,
+            cycle: 0,
+        })
+    }
+
+    pub fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.output, "{}", format_entry(entry, self.program)),
+            TraceFormat::JsonLines => {
+                writeln!(self.output, "{}", entry_to_json(entry, self.program))
+            }
+            TraceFormat::Binary => self.write_binary(entry),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    /// A record is a byte of flags saying what's in it and another with the new flag values, the
+    /// cycle relative to the previous record (LEB128), the address (u16 LE), the opcode (3 bytes
+    /// LE) unless it's an interrupt, the register changes (a count, then bank << 4 | register,
+    /// from and to for each), then the memory access (0 fetch or 1 store, address, value) and
+    /// the port transaction (0 input, 1 output or 2 outputk, port, value) when there are.
+    fn write_binary(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        let mut record = Vec::with_capacity(16);
+        let kind = (entry.instruction.is_none() as u8)
+            | (entry.memory.is_some() as u8) << 1
+            | (entry.port.is_some() as u8) << 2
+            | (entry.zero.is_some() as u8) << 3
+            | (entry.carry.is_some() as u8) << 4
+            | (entry.bank.is_some() as u8) << 5
+            | (entry.interrupt_enabled.is_some() as u8) << 6;
+        let values = (entry.zero == Some(true)) as u8
+            | ((entry.carry == Some(true)) as u8) << 1
+            | ((entry.bank == Some('b')) as u8) << 2
+            | ((entry.interrupt_enabled == Some(true)) as u8) << 3;
+
+        record.extend([kind, values]);
+
+        let mut delta = entry.cycle.wrapping_sub(self.cycle);
+
+        loop {
+            let byte = (delta & 0x7F) as u8;
+
+            delta >>= 7;
+
+            if delta == 0 {
+                record.push(byte);
+                break;
+            }
+
+            record.push(byte | 0x80);
+        }
+
+        self.cycle = entry.cycle;
+        record.extend((entry.address as u16).to_le_bytes());
+
+        if let Some(instruction) = &entry.instruction {
+            let opcode = encode(instruction).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("can't encode '{}'", instruction),
+                )
+            })?;
+
+            record.extend(&opcode.to_le_bytes()[..3]);
+        }
+
+        record.push(entry.registers.len() as u8);
+
+        for change in &entry.registers {
+            record.extend([
+                ((change.bank == 'b') as u8) << 4 | change.register,
+                change.from,
+                change.to,
+            ]);
+        }
+
+        match entry.memory {
+            Some(MemoryAccess::Fetch { address, value }) => record.extend([0, address, value]),
+            Some(MemoryAccess::Store { address, value }) => record.extend([1, address, value]),
+            None => {}
+        }
+
+        if let Some(port) = entry.port {
+            let access = match port.access {
+                PortAccess::Input => 0,
+                PortAccess::Output => 1,
+                PortAccess::OutputK => 2,
+            };
+
+            record.extend([access, port.port, port.value]);
+        }
+
+        self.output.write_all(&record)
+    }
+}
+
+/// Reads the entries of a binary trace.
+pub struct TraceReader<R: Read> {
+    input: R,
+    cycle: u64,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+        let mut header = [0; 9];
+
+        input.read_exact(&mut header)?;
+
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a binary trace"));
+        }
+
+        if header[8] != VERSION {
+            return Err(invalid("unsupported binary trace version"));
+        }
+
+        Ok(TraceReader { input, cycle: 0 })
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_entry(&mut self, kind: u8) -> io::Result<TraceEntry> {
+        let [values] = self.read_bytes()?;
+        let mut delta = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.read_bytes()?;
+
+            delta |= ((byte & 0x7F) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        self.cycle = self.cycle.wrapping_add(delta);
+
+        let address = u16::from_le_bytes(self.read_bytes()?) as usize;
+        let instruction = match kind & 1 {
+            0 => {
+                let [low, middle, high] = self.read_bytes()?;
+                let opcode = u32::from_le_bytes([low, middle, high, 0]);
+
+                Some(decode(opcode).ok_or_else(|| invalid("invalid opcode in a binary trace"))?)
+            }
+            _ => None,
+        };
+        let [count] = self.read_bytes()?;
+        let mut registers = Vec::new();
+
+        for _ in 0..count {
+            let [register, from, to] = self.read_bytes()?;
+
+            registers.push(RegisterChange {
+                bank: if register & 0x10 != 0 { 'b' } else { 'a' },
+                register: register & 0xF,
+                from,
+                to,
+            });
+        }
+
+        let memory = match kind & 2 {
+            0 => None,
+            _ => Some(match self.read_bytes()? {
+                [0, address, value] => MemoryAccess::Fetch { address, value },
+                [_, address, value] => MemoryAccess::Store { address, value },
+            }),
+        };
+        let port = match kind & 4 {
+            0 => None,
+            _ => {
+                let [access, port, value] = self.read_bytes()?;
+
+                Some(PortTransaction {
+                    access: match access {
+                        0 => PortAccess::Input,
+                        1 => PortAccess::Output,
+                        _ => PortAccess::OutputK,
+                    },
+                    port,
+                    value,
+                })
+            }
+        };
+        let flag = |present: u8, value: u8| (kind & present != 0).then_some(values & value != 0);
+
+        Ok(TraceEntry {
+            cycle: self.cycle,
+            address,
+            instruction,
+            registers,
+            zero: flag(1 << 3, 1),
+            carry: flag(1 << 4, 1 << 1),
+            bank: flag(1 << 5, 1 << 2).map(|b| if b { 'b' } else { 'a' }),
+            interrupt_enabled: flag(1 << 6, 1 << 3),
+            memory,
+            port,
+        })
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceEntry>;
+
+    fn next(&mut self) -> Option<io::Result<TraceEntry>> {
+        let mut kind = [0];
+
+        match self.input.read(&mut kind) {
+            Ok(0) => None,
+            Ok(_) => Some(
+                self.read_entry(kind[0])
+                    .map_err(|error| match error.kind() {
+                        io::ErrorKind::UnexpectedEof => invalid("truncated binary trace"),
+                        _ => error,
+                    }),
+            ),
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, PortState};
+
+    const PROGRAM: &str = "\
+start:  load s0, 03
+loop:   call send
+        sub s0, 01
+        jump nz, loop
+        regbank b
+        load s1, 55
+        regbank a
+        enable interrupt
+        jump done
+send:   output s0, 04
+        store s0, 10
+        fetch s2, 10
+        input s3, 01
+        return
+done:   jump done
+";
+
+    fn trace(filter: TraceFilter) -> (crate::Program, Vec<TraceEntry>) {
+        let program = assemble_str(PROGRAM);
+        let mut sim = program.create_simulation();
+        let mut ports = PortState::new();
+
+        ports.set_input(1, 0x5A);
+        ports.raise_interrupt_at(60);
+        sim.set_port_handler(Box::new(ports));
+        sim.set_interrupt_vector(0x0E);
+
+        let mut tracer = Tracer::new(&sim, filter);
+        let mut entries = Vec::new();
+
+        while sim.get_cycles() < 70 {
+            let event = sim.step().unwrap();
+
+            entries.extend(tracer.record(&sim, &event));
+        }
+
+        (program, entries)
+    }
+
+    #[test]
+    fn entries_and_text() {
+        let (program, entries) = trace(TraceFilter::new());
+        let text: Vec<String> = entries
+            .iter()
+            .map(|entry| format_entry(entry, Some(&program)))
+            .collect();
+
+        assert_eq!(entries.len(), 35);
+        assert_eq!(
+            text[0],
+            "         0  000  start                 LOAD s0, 03               s0 00->03"
+        );
+        assert_eq!(
+            text[2],
+            "         4  009  send                  OUTPUT s0, 04             out 04=03"
+        );
+        assert_eq!(
+            text[3],
+            "         6  00A  send+1                STORE s0, 10              store [10]=03"
+        );
+        assert!(text[4].ends_with("s2 00->03  fetch [10]=03"));
+        assert!(text[5].ends_with("s3 00->5A  in 01=5A"));
+        assert!(text[7].ends_with("s0 03->02"));
+        assert!(text[23].ends_with("SUB s0, 01                s0 01->00  Z=1"));
+        assert!(text
+            .iter()
+            .any(|line| line.ends_with("REGBANK B                 bank B")));
+        assert!(text.iter().any(|line| line.ends_with("B.s1 00->55")));
+        assert!(text
+            .iter()
+            .any(|line| line.contains("(interrupt)               interrupts disabled")));
+        assert_eq!(entries.last().unwrap().address, 0x0E);
+    }
+
+    #[test]
+    fn filters() {
+        let mut filter = TraceFilter::new();
+
+        filter.add_routine(0x09);
+
+        let (_, entries) = trace(filter);
+
+        // Three calls of send, five instructions each.
+        assert_eq!(entries.len(), 15);
+        assert!(entries
+            .iter()
+            .all(|entry| (0x09..=0x0D).contains(&entry.address)));
+
+        let mut filter = TraceFilter::new();
+
+        filter.add_range(0x04, 0x06);
+
+        let (_, entries) = trace(filter);
+        let addresses: Vec<usize> = entries.iter().map(|entry| entry.address).collect();
+
+        assert_eq!(addresses, vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn json_lines() {
+        let (program, entries) = trace(TraceFilter::new());
+        let mut output = Vec::new();
+        let mut writer =
+            TraceWriter::new(&mut output, TraceFormat::JsonLines, Some(&program)).unwrap();
+
+        for entry in &entries[..4] {
+            writer.write(entry).unwrap();
+        }
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            r#"{"cycle":0,"address":0,"location":"start","file":null,"line":1,"instruction":"LOAD s0, 03","registers":[{"bank":"A","register":"s0","from":0,"to":3}]}"#
+        );
+        assert!(lines[2].ends_with(r#""port":{"access":"output","port":4,"value":3}}"#));
+        assert!(lines[3].ends_with(r#""memory":{"access":"store","address":16,"value":3}}"#));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let (_, entries) = trace(TraceFilter::new());
+        let mut output = Vec::new();
+        let mut writer = TraceWriter::new(&mut output, TraceFormat::Binary, None).unwrap();
+
+        for entry in &entries {
+            writer.write(entry).unwrap();
+        }
+
+        // Most entries take 7 to 10 bytes.
+        assert!(output.len() < entries.len() * 11);
+
+        let read: Vec<TraceEntry> = TraceReader::new(&output[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert_eq!(read, entries);
+
+        let truncated = TraceReader::new(&output[..output.len() - 1])
+            .unwrap()
+            .last()
+            .unwrap();
+
+        assert!(truncated.is_err());
+        assert!(TraceReader::new(&b"not a trace"[..]).is_err());
+    }
+}
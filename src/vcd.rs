@@ -0,0 +1,465 @@
+use std::io::{self, Write};
+
+use crate::{encode, PortAccess, SimulationContext, StepEvent};
+
+/// A signal of the dump: its name, width in bits and identifier code.
+struct Signal {
+    name: String,
+    width: usize,
+    code: String,
+    value: Option<u32>,
+}
+
+// Indices of the fixed signals, the selected registers follow.
+const CLK: usize = 0;
+const ADDRESS: usize = 1;
+const INSTRUCTION: usize = 2;
+const PORT_ID: usize = 3;
+const IN_PORT: usize = 4;
+const OUT_PORT: usize = 5;
+const WRITE_STROBE: usize = 6;
+const K_WRITE_STROBE: usize = 7;
+const READ_STROBE: usize = 8;
+const INTERRUPT: usize = 9;
+const INTERRUPT_ACK: usize = 10;
+const ZERO: usize = 11;
+const CARRY: usize = 12;
+const BANK: usize = 13;
+const REGISTERS: usize = 14;
+
+const FIXED_SIGNALS: [(&str, usize); REGISTERS] = [
+    ("clk", 1),
+    ("address", 12),
+    ("instruction", 18),
+    ("port_id", 8),
+    ("in_port", 8),
+    ("out_port", 8),
+    ("write_strobe", 1),
+    ("k_write_strobe", 1),
+    ("read_strobe", 1),
+    ("interrupt", 1),
+    ("interrupt_ack", 1),
+    ("zero", 1),
+    ("carry", 1),
+    ("regbank", 1),
+];
+
+/// Identifier codes are printable characters from `!` on, with more of them as needed.
+fn identifier(mut index: usize) -> String {
+    let mut code = String::new();
+
+    loop {
+        code.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+
+        if index == 0 {
+            return code;
+        }
+
+        index -= 1;
+    }
+}
+
+/// Writes a Value Change Dump of the processor's signals as it runs, for waveform viewers.
+///
+/// Signals follow the ports of the KCPSM6 macro: every instruction takes two clock cycles, the
+/// address and instruction change on the first rising edge, `port_id`, `out_port` and `in_port`
+/// are valid for both cycles, and the strobes and `interrupt_ack` are high for the second one. The
+/// flags and registers take their new values when the next instruction starts. Time is in
+/// picoseconds, from the clock frequency.
+///
+/// ```
+/// use kcpsm6sim::{assemble_str, vcd::VcdWriter};
+///
+/// let program = assemble_str("load s0, 2A\noutput s0, 01\n");
+/// let mut sim = program.create_simulation();
+/// let mut vcd = VcdWriter::new(Vec::new(), &mut sim, 100e6, &[("s0".to_string(), 0)]).unwrap();
+///
+/// while sim.get_program_counter() < 2 {
+///     let event = sim.step().unwrap();
+///
+///     vcd.record(&mut sim, &event).unwrap();
+/// }
+///
+/// let dump = String::from_utf8(vcd.finish(&sim).unwrap()).unwrap();
+///
+/// assert!(dump.contains("$timescale 1ps $end"));
+/// assert!(dump.contains("#30000\n1'"));
+/// ```
+pub struct VcdWriter<W: Write> {
+    output: W,
+    signals: Vec<Signal>,
+    /// Register of the selected bank shown by each signal after the fixed ones.
+    registers: Vec<usize>,
+    /// Half of the clock period, in picoseconds.
+    half_period: u64,
+    /// Changes due when the next instruction starts.
+    pending: Vec<(usize, u32)>,
+}
+
+impl<W: Write> VcdWriter<W> {
+    /// Starts a dump of the simulation with its current state, with the given registers of the
+    /// selected bank (a name and a register index each) alongside the processor's signals.
+    pub fn new(
+        output: W,
+        sim: &mut SimulationContext,
+        clock: f64,
+        registers: &[(String, usize)],
+    ) -> io::Result<VcdWriter<W>> {
+        let mut signals: Vec<Signal> = FIXED_SIGNALS
+            .iter()
+            .map(|(name, width)| (name.to_string(), *width))
+            .chain(registers.iter().map(|(name, _)| (name.clone(), 8)))
+            .enumerate()
+            .map(|(index, (name, width))| Signal {
+                name,
+                width,
+                code: identifier(index),
+                value: None,
+            })
+            .collect();
+
+        for signal in &mut signals {
+            // Names can't have spaces in them.
+            signal.name = signal.name.replace(char::is_whitespace, "_");
+        }
+
+        let mut vcd = VcdWriter {
+            output,
+            signals,
+            registers: registers.iter().map(|(_, register)| *register).collect(),
+            half_period: (0.5e12 / clock).round().max(1.0) as u64,
+            pending: Vec::new(),
+        };
+
+        vcd.write_header()?;
+
+        let cycle = sim.get_cycles();
+        let mut initial = vec![
+            (CLK, 1),
+            (ADDRESS, sim.get_program_counter() as u32),
+            (
+                INSTRUCTION,
+                sim.get_instruction(sim.get_program_counter())
+                    .and_then(encode)
+                    .unwrap_or(0),
+            ),
+            (PORT_ID, 0),
+            (IN_PORT, 0),
+            (OUT_PORT, 0),
+            (WRITE_STROBE, 0),
+            (K_WRITE_STROBE, 0),
+            (READ_STROBE, 0),
+            (
+                INTERRUPT,
+                sim.get_port_handler_mut().interrupt(cycle) as u32,
+            ),
+            (INTERRUPT_ACK, 0),
+        ];
+
+        initial.extend(vcd.state(sim));
+        writeln!(vcd.output, "$dumpvars")?;
+        vcd.write_values(&initial)?;
+        writeln!(vcd.output, "$end")?;
+
+        Ok(vcd)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        writeln!(self.output, "$version KCPSM6Sim $end")?;
+        writeln!(self.output, "$timescale 1ps $end")?;
+        writeln!(self.output, "$scope module kcpsm6 $end")?;
+
+        for signal in &self.signals {
+            match signal.width {
+                1 => writeln!(
+                    self.output,
+                    "$var wire 1 {} {} $end",
+                    signal.code, signal.name
+                )?,
+                width => writeln!(
+                    self.output,
+                    "$var wire {} {} {} [{}:0] $end",
+                    width,
+                    signal.code,
+                    signal.name,
+                    width - 1
+                )?,
+            }
+        }
+
+        writeln!(self.output, "$upscope $end")?;
+        writeln!(self.output, "$enddefinitions $end")
+    }
+
+    /// Flags, register bank and selected registers, as they are now.
+    fn state(&self, sim: &SimulationContext) -> Vec<(usize, u32)> {
+        let mut state = vec![
+            (ZERO, sim.get_zero_flag() as u32),
+            (CARRY, sim.get_carry_flag() as u32),
+            (BANK, (sim.get_register_bank() == 'b') as u32),
+        ];
+
+        for (index, register) in self.registers.iter().enumerate() {
+            state.push((
+                REGISTERS + index,
+                sim.get_register(*register).unwrap_or(0) as u32,
+            ));
+        }
+
+        state
+    }
+
+    /// Writes the values that changed.
+    fn write_values(&mut self, values: &[(usize, u32)]) -> io::Result<()> {
+        for (index, value) in values {
+            let signal = &mut self.signals[*index];
+
+            if signal.value == Some(*value) {
+                continue;
+            }
+
+            signal.value = Some(*value);
+
+            match signal.width {
+                1 => writeln!(self.output, "{}{}", value, signal.code)?,
+                width => writeln!(
+                    self.output,
+                    "b{:0width$b} {}",
+                    value,
+                    signal.code,
+                    width = width
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the changes at a time, if there are any.
+    fn write_at(&mut self, time: u64, values: &[(usize, u32)]) -> io::Result<()> {
+        let changed = values
+            .iter()
+            .any(|(index, value)| self.signals[*index].value != Some(*value));
+
+        if changed {
+            writeln!(self.output, "#{}", time)?;
+            self.write_values(values)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records a step of the simulation, right after it happened.
+    pub fn record(&mut self, sim: &mut SimulationContext, event: &StepEvent) -> io::Result<()> {
+        // What the previous instruction changed, as this one starts.
+        let mut start = std::mem::take(&mut self.pending);
+        let (cycle, mut first, mut second) = match event {
+            StepEvent::Executed(executed) => {
+                let mut first = vec![
+                    (ADDRESS, executed.address as u32),
+                    (INSTRUCTION, encode(&executed.instruction).unwrap_or(0)),
+                ];
+                let mut second = Vec::new();
+
+                if let Some(port) = executed.port {
+                    first.push((PORT_ID, port.port as u32));
+
+                    let strobe = match port.access {
+                        PortAccess::Input => {
+                            first.push((IN_PORT, port.value as u32));
+                            READ_STROBE
+                        }
+                        PortAccess::Output => {
+                            first.push((OUT_PORT, port.value as u32));
+                            WRITE_STROBE
+                        }
+                        PortAccess::OutputK => {
+                            first.push((OUT_PORT, port.value as u32));
+                            K_WRITE_STROBE
+                        }
+                    };
+
+                    second.push((strobe, 1));
+                    self.pending.push((strobe, 0));
+                }
+
+                (executed.cycle, first, second)
+            }
+            StepEvent::Interrupt { cycle, address } => {
+                self.pending.push((INTERRUPT_ACK, 0));
+                (
+                    *cycle,
+                    vec![(INTERRUPT, 1), (ADDRESS, *address as u32)],
+                    vec![(INTERRUPT_ACK, 1)],
+                )
+            }
+            StepEvent::Halted => {
+                self.pending = start;
+                return Ok(());
+            }
+        };
+
+        start.append(&mut first);
+        start.push((CLK, 1));
+
+        // Strobes go low again, and the new state shows, when the next instruction starts.
+        let end = sim.get_cycles();
+
+        self.pending.extend(self.state(sim));
+        self.pending
+            .push((INTERRUPT, sim.get_port_handler_mut().interrupt(end) as u32));
+
+        let time = cycle * 2 * self.half_period;
+
+        second.push((CLK, 1));
+        self.write_at(time, &start)?;
+        self.write_at(time + self.half_period, &[(CLK, 0)])?;
+
+        for clock in cycle + 1..end {
+            let time = clock * 2 * self.half_period;
+
+            self.write_at(time, &second)?;
+            self.write_at(time + self.half_period, &[(CLK, 0)])?;
+            second.clear();
+            second.push((CLK, 1));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last changes and returns the output.
+    pub fn finish(mut self, sim: &SimulationContext) -> io::Result<W> {
+        let pending = std::mem::take(&mut self.pending);
+
+        self.write_at(sim.get_cycles() * 2 * self.half_period, &pending)?;
+        writeln!(
+            self.output,
+            "#{}",
+            sim.get_cycles() * 2 * self.half_period + self.half_period
+        )?;
+        self.output.flush()?;
+
+        Ok(self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble_str, PortState};
+
+    fn dump(source: &str, clock: f64, cycles: u64, ports: PortState) -> String {
+        let program = assemble_str(source);
+        let mut sim = program.create_simulation();
+
+        sim.set_port_handler(Box::new(ports));
+
+        let mut vcd = VcdWriter::new(
+            Vec::new(),
+            &mut sim,
+            clock,
+            &[("s0".to_string(), 0), ("counter".to_string(), 1)],
+        )
+        .unwrap();
+
+        while sim.get_cycles() < cycles {
+            let event = sim.step().unwrap();
+
+            vcd.record(&mut sim, &event).unwrap();
+        }
+
+        String::from_utf8(vcd.finish(&sim).unwrap()).unwrap()
+    }
+
+    /// The changes at a time, as `code value` pairs sorted by code.
+    fn changes_at(dump: &str, time: u64) -> Vec<String> {
+        let mut lines = dump
+            .lines()
+            .skip_while(|line| *line != format!("#{}", time));
+
+        lines.next();
+
+        let mut changes: Vec<String> = lines
+            .take_while(|line| !line.starts_with('#'))
+            .map(|line| match line.strip_prefix('b') {
+                Some(vector) => {
+                    let (value, code) = vector.split_once(' ').unwrap();
+
+                    format!("{} {:X}", code, u32::from_str_radix(value, 2).unwrap())
+                }
+                None => format!("{} {}", &line[1..], &line[..1]),
+            })
+            .collect();
+
+        changes.sort();
+        changes
+    }
+
+    #[test]
+    fn header() {
+        let dump = dump("load s0, 01\n", 100e6, 2, PortState::new());
+
+        assert!(dump.starts_with(
+            "$version KCPSM6Sim $end\n$timescale 1ps $end\n$scope module kcpsm6 $end\n\
+             $var wire 1 ! clk $end\n$var wire 12 \" address [11:0] $end\n"
+        ));
+        assert!(dump.contains("$var wire 8 / s0 [7:0] $end\n$var wire 8 0 counter [7:0] $end\n"));
+        assert!(dump.contains("$enddefinitions $end\n$dumpvars\n1!\nb000000000000 \"\n"));
+    }
+
+    #[test]
+    fn port_strobes_and_flags() {
+        let mut ports = PortState::new();
+
+        ports.set_input(0x02, 0x33);
+
+        let dump = dump(
+            "load s0, 2A\noutput s0, 01\ninput s1, 02\nsub s0, 2A\noutputk 55, 3\n",
+            50e6,
+            10,
+            ports,
+        );
+
+        // 50MHz: 20ns cycles. OUTPUT starts at cycle 2, its strobe is high in cycle 3.
+        assert_eq!(
+            changes_at(&dump, 40_000),
+            vec!["! 1", "\" 1", "# 2D001", "$ 1", "& 2A", "/ 2A"]
+        );
+        assert_eq!(changes_at(&dump, 50_000), vec!["! 0"]);
+        assert_eq!(changes_at(&dump, 60_000), vec!["! 1", "' 1"]);
+        assert_eq!(
+            changes_at(&dump, 80_000),
+            vec!["! 1", "\" 2", "# 9102", "$ 2", "% 33", "' 0"]
+        );
+        assert_eq!(changes_at(&dump, 100_000), vec!["! 1", ") 1"]);
+        // SUB sets Z once it's done, as OUTPUTK starts.
+        assert_eq!(
+            changes_at(&dump, 160_000),
+            vec!["! 1", "\" 4", "# 2B553", "$ 3", "& 55", ", 1", "/ 0"]
+        );
+        assert_eq!(changes_at(&dump, 180_000), vec!["! 1", "( 1"]);
+    }
+
+    #[test]
+    fn interrupts() {
+        let mut ports = PortState::new();
+
+        ports.raise_interrupt_at(4);
+
+        let dump = dump(
+            "enable interrupt\nloop: jump loop\naddress 3FF\nreturni disable\n",
+            100e6,
+            10,
+            ports,
+        );
+
+        // The interrupt is taken at cycle 4, acknowledged in cycle 5, then goes away.
+        assert_eq!(changes_at(&dump, 40_000), vec!["! 1", "* 1"]);
+        assert_eq!(changes_at(&dump, 50_000), vec!["! 1", "+ 1"]);
+        assert_eq!(
+            changes_at(&dump, 60_000),
+            vec!["! 1", "\" 3FF", "# 29000", "* 0", "+ 0"]
+        );
+    }
+}
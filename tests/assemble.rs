@@ -0,0 +1,44 @@
+use kcpsm6sim::{assemble_file, assemble_str, Severity};
+
+#[test]
+fn assemble_and_run_file() {
+    let program = assemble_file("tests/test.s").unwrap();
+
+    assert!(!program.has_errors());
+    assert_eq!(program.get_instructions().len(), 4);
+
+    let mut sim = program.create_simulation();
+    sim.run().unwrap();
+
+    assert_eq!(sim.get_register(0), Some(1));
+}
+
+#[test]
+fn assemble_file_with_symbols() {
+    let program = assemble_file("tests/test2.txt").unwrap();
+
+    assert!(!program.has_errors());
+    assert_eq!(program.find_constant("led"), Some(4));
+    assert!(program.find_label("max").is_some());
+    assert!(program.get_path().is_some());
+}
+
+#[test]
+fn assemble_missing_file() {
+    assert!(assemble_file("tests/does_not_exist.psm").is_err());
+}
+
+#[test]
+fn diagnostics_are_collected() {
+    let program = assemble_str("Loop:\n  load s0, 300'd\n  jump loop\n");
+
+    assert!(program.has_errors());
+    assert!(program
+        .get_diagnostics()
+        .iter()
+        .any(|d| d.severity == Severity::Error && d.line == 2));
+    assert!(program
+        .get_diagnostics()
+        .iter()
+        .any(|d| d.line == 3 && d.message.contains("Did you mean 'Loop'?")));
+}
@@ -0,0 +1,1026 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use kcpsm6sim::peripherals::cosim::{CoSimClient, Reply, Request};
+
+fn command(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// Writes a PSM file into a directory of its own, so tests can run in parallel.
+fn write_source(name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kcpsm6sim-{}-{}", name, std::process::id()));
+
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(format!("{}.psm", name));
+
+    fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn check_reports_errors() {
+    assert_eq!(command(&["check", "tests/test.s"]).status.code(), Some(0));
+
+    let path = write_source("check", "load s0, 01\njump nowhere\n");
+    let output = command(&["check", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("check.psm:2: error:"));
+}
+
+#[test]
+fn check_runs_lints() {
+    let path = write_source(
+        "lints",
+        "start: load s0, 01\njump z, start\nunused: jump start\n",
+    );
+    let path = path.to_str().unwrap();
+    let output = command(&["check", path]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr.contains("lints.psm:2: warning: 'JUMP Z' tests the zero flag"));
+    assert!(stderr.contains("lints.psm:3: warning: Label 'unused' is never used. [unused-label]"));
+
+    assert_eq!(
+        command(&["check", path, "-D", "unset-flags"]).status.code(),
+        Some(1)
+    );
+    assert!(command(&["check", path, "--allow=all"]).stderr.is_empty());
+    assert_eq!(
+        command(&["check", path, "-W", "typo"]).status.code(),
+        Some(64)
+    );
+
+    // The interrupt service routine is reachable from wherever the vector is.
+    let path = write_source(
+        "vector",
+        "start: jump start
+address 7F0
+returni enable
+",
+    );
+    let path = path.to_str().unwrap();
+
+    assert!(String::from_utf8_lossy(&command(&["check", path]).stderr)
+        .contains("vector.psm:3: warning: The instruction at 0x7F0 can never run."));
+    assert!(command(&["check", path, "--interrupt-vector", "7F0"])
+        .stderr
+        .is_empty());
+}
+
+#[test]
+fn stack_depth() {
+    let path = write_source(
+        "stack",
+        "start: call outer\njump start\nouter: call inner\nreturn\ninner: call@ (s0, s1)\nreturn\n",
+    );
+    let output = command(&["stack", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains(
+        "Reset:       3  start -> outer (stack.psm:1) -> inner (stack.psm:3) -> ? (stack.psm:5)"
+    ));
+    assert!(stdout(&output).contains("Worst case:  3 of 30"));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("stack.psm:5: warning: Unable to tell")
+    );
+}
+
+#[test]
+fn delay_timing() {
+    let args = ["timing", "tests/soft_delays_100mhz.psm", "delay_20ms"];
+    let output = command(&[&args[..], &["--loop", "software_delay=200000"]].concat());
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("From delay_20ms until it returns:"));
+    assert!(stdout(&output)
+        .contains("Worst     1000005 instructions     2000010 clock cycles  20.000 ms"));
+
+    let output = command(&args);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs an iteration bound"));
+}
+
+#[test]
+fn lines_of_included_files() {
+    let path = write_source(
+        "included",
+        "start: call wait\njump start\nINCLUDE \"delay.psm\"\n",
+    );
+    let path = path.to_str().unwrap();
+
+    fs::write(
+        path.replace("included.psm", "delay.psm"),
+        "; Delays\nwait: load s0, 10\nloop: sub s0, 01\njump nz, loop\nreturn\n",
+    )
+    .unwrap();
+
+    let output = command(&["stack", path]);
+
+    assert!(stdout(&output).contains("start -> wait (included.psm:1)"));
+
+    let output = command(&["timing", path, "wait", "--loop", "delay.psm:3=16"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("Worst          34 instructions"));
+
+    let output = command(&["timing", path, "wait", "--loop", "delay.psm:9=16"]);
+
+    assert_eq!(output.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--loop: there's no line 9 in 'delay.psm'"));
+
+    let output = command(&["graph", path, "--calls"]);
+
+    assert!(stdout(&output).contains("wait\\n002-005  delay.psm:2-5"));
+    assert!(stdout(&output).contains("r000 -> r002 [label=\"included.psm:1\"];"));
+
+    let output = command(&["graph", path, "--calls", "--format", "json"]);
+
+    assert!(stdout(&output).contains("delay.psm\",\"lines\":[2,5]"));
+}
+
+#[test]
+fn graphs() {
+    let output = command(&["graph", "tests/test2.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("digraph cfg {"));
+    assert!(stdout(&output).contains("[style=dashed, label=\"call\"]"));
+
+    let path = std::env::temp_dir().join(format!("kcpsm6sim-calls-{}.json", std::process::id()));
+    let output = command(&[
+        "graph",
+        "tests/test2.txt",
+        "--calls",
+        "--format",
+        "json",
+        "-o",
+        path.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .starts_with("{\"routines\":[{\"entry\":0,\"label\":\"main\""));
+    assert_eq!(
+        command(&["graph", "tests/test2.txt", "--format", "svg"])
+            .status
+            .code(),
+        Some(64)
+    );
+}
+
+#[test]
+fn language_server() {
+    let mut input = String::new();
+
+    for message in [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.psm","languageId":"psm","version":1,"text":"jump nowhere\n"}}}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#,
+        r#"{"jsonrpc":"2.0","method":"exit"}"#,
+    ] {
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        ));
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains(r#""hoverProvider":true"#));
+    assert!(
+        stdout(&output).contains("Unable to find a label, constant or register called 'nowhere'")
+    );
+    assert!(stdout(&output).contains(r#"{"jsonrpc":"2.0","id":2,"result":null}"#));
+}
+
+#[test]
+fn debug_adapter() {
+    let path = write_source("dap", "start: load s0, 01\nadd s0, 01\n");
+    let mut input = String::new();
+
+    for message in [
+        r#"{"seq":1,"type":"request","command":"initialize","arguments":{}}"#.to_string(),
+        format!(
+            r#"{{"seq":2,"type":"request","command":"launch","arguments":{{"program":{:?},"stopOnEntry":true}}}}"#,
+            path.to_str().unwrap()
+        ),
+        r#"{"seq":3,"type":"request","command":"configurationDone"}"#.to_string(),
+        r#"{"seq":4,"type":"request","command":"next"}"#.to_string(),
+        r#"{"seq":5,"type":"request","command":"evaluate","arguments":{"expression":"s0"}}"#
+            .to_string(),
+        r#"{"seq":6,"type":"request","command":"disconnect"}"#.to_string(),
+    ] {
+        input.push_str(&format!(
+            "Content-Length: {}\r\n\r\n{}",
+            message.len(),
+            message
+        ));
+    }
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains(r#""event":"initialized""#));
+    assert!(stdout(&output).contains(r#""reason":"entry""#));
+    assert!(stdout(&output).contains(r#""reason":"step""#));
+    assert!(stdout(&output).contains(r#""result":"0x01 (1)""#));
+}
+
+#[test]
+fn terminal_debugger_options() {
+    let output = command(&["tui", "tests/test.s", "--baud", "0"]);
+
+    assert_eq!(output.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--baud: the baud rate can't be 0"));
+
+    let output = command(&["tui", "missing.psm"]);
+
+    assert_eq!(output.status.code(), Some(74));
+}
+
+#[test]
+fn execution_traces() {
+    let output = command(&[
+        "trace",
+        "tests/test2.txt",
+        "--max-cycles",
+        "40",
+        "--input",
+        "01=05",
+        "--input",
+        "02=03",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with(
+        "         0  000  main_getxy            INPUT s0, 01              s0 00->05  in 01=05\n"
+    ));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("20 trace entries."));
+
+    let output = command(&[
+        "trace",
+        "tests/test2.txt",
+        "--max-cycles=40",
+        "--routine",
+        "max",
+        "--format",
+        "jsonl",
+    ]);
+    let lines = stdout(&output);
+
+    assert!(lines.lines().count() > 0);
+    assert!(lines
+        .lines()
+        .all(|line| line.contains(r#""location":"max"#)));
+
+    let dir = write_source("trace", "").with_extension("bin");
+    let path = dir.to_str().unwrap();
+    let output = command(&[
+        "trace",
+        "tests/test2.txt",
+        "--max-cycles=40",
+        "--format=binary",
+        "-o",
+        path,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = command(&["trace", "--read", path, "tests/test2.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("         0  000  main_getxy  "));
+    assert_eq!(stdout(&output).lines().count(), 20);
+
+    // Nothing is written when the program doesn't assemble.
+    let source = write_source("trace-error", "call sub\nsub: return\n");
+    let trace = source.with_extension("bin");
+    let output = command(&[
+        "trace",
+        source.to_str().unwrap(),
+        "-o",
+        trace.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'sub' is a mnemonic"));
+    assert!(!trace.exists());
+}
+
+#[test]
+fn run_a_program() {
+    let output = command(&["run", "tests/test.s"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("s0=01"));
+
+    let output = command(&[
+        "run",
+        "tests/test2.txt",
+        "--max-cycles",
+        "100",
+        "--input",
+        "00=05",
+        "--clock=50MHz",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("OUTPUT   port 04"));
+    assert!(stdout(&output).contains("Reached the cycle limit"));
+    assert!(stdout(&output).contains("after 100 clock cycles (2.000 us)"));
+}
+
+#[test]
+fn waveforms() {
+    let path = write_source("waves", "").with_extension("vcd");
+    let vcd = path.to_str().unwrap();
+    let output = command(&[
+        "run",
+        "tests/test2.txt",
+        "--max-cycles=100",
+        "--input=00=05",
+        "--clock=50MHz",
+        "--vcd",
+        vcd,
+        "--vcd-reg",
+        "s0,s1",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+
+    let dump = fs::read_to_string(&path).unwrap();
+
+    assert!(dump.contains("$var wire 1 ' write_strobe $end"));
+    assert!(dump.contains("$var wire 8 / s0 [7:0] $end"));
+    // 50MHz: 20ns per clock cycle.
+    assert!(dump.contains("\n#10000\n0!\n#20000\n1)\n1!\n#30000\n0!\n#40000\n0)\n"));
+    assert!(dump.trim_end().ends_with("#2010000"));
+
+    let output = command(&["run", "tests/test2.txt", "--vcd", vcd, "--vcd-reg", "total"]);
+
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn coverage_reports() {
+    let path = write_source("coverage", "").with_extension("info");
+    let lcov = path.to_str().unwrap();
+    let output = command(&[
+        "coverage",
+        "tests/test2.txt",
+        "--max-cycles=200",
+        "--input=01=05",
+        "--input=02=03",
+        "--lcov",
+        lcov,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("File "));
+    assert!(stdout(&output).contains("\nRoutine "));
+    assert!(stdout(&output).contains("\nmax "));
+
+    let tracefile = fs::read_to_string(&path).unwrap();
+
+    assert!(tracefile.starts_with("TN:test2\nSF:tests/test2.txt\n"));
+    assert!(tracefile.contains("\nBRDA:"));
+    assert!(tracefile.ends_with("end_of_record\n"));
+}
+
+#[test]
+fn profiles() {
+    let path = write_source("profile", "").with_extension("folded");
+    let folded = path.to_str().unwrap();
+    let output = command(&[
+        "profile",
+        "tests/test2.txt",
+        "--max-cycles=200",
+        "--input=01=05",
+        "--input=02=03",
+        "--top=5",
+        "--folded",
+        folded,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("200 clock cycles, 100 instructions, 0 interrupts\n"));
+    assert!(stdout(&output).contains("\nmain "));
+    assert!(stdout(&output).contains("\nInstruction "));
+
+    let stacks = fs::read_to_string(&path).unwrap();
+
+    assert!(stacks.lines().any(|line| line.starts_with("main;max ")));
+    assert_eq!(
+        stacks
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+            .sum::<u64>(),
+        200
+    );
+}
+
+#[test]
+fn runtime_faults_fail() {
+    let path = write_source("fault", "start: call start\n");
+    let output = command(&["run", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("runtime fault at 000 (start, line 1)")
+    );
+}
+
+#[test]
+fn assemble_and_disassemble() {
+    let path = write_source("image", "start: load s0, 41\noutputk 01, 2\njump start\n");
+    let output = command(&["assemble", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+
+    let hex = path.with_extension("hex");
+    let listing = fs::read_to_string(path.with_extension("log")).unwrap();
+
+    assert_eq!(fs::read_to_string(&hex).unwrap().lines().count(), 4096);
+    assert!(listing.contains("000   01041      1  start: load s0, 41"));
+
+    let output = command(&["disasm", hex.to_str().unwrap()]);
+
+    assert_eq!(
+        stdout(&output),
+        "000  01041  LOAD s0, 41\n001  2B012  OUTPUTK 01, 2\n002  22000  JUMP 000\n"
+    );
+
+    // A malformed image is reported like an assembly error.
+    fs::write(&hex, "01041\n2B01G\n").unwrap();
+
+    let output = command(&["disasm", hex.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("image.hex:2: error: '2B01G' isn't an 18-bit hexadecimal opcode."));
+
+    // Nothing is written when the program doesn't fit in the program memory.
+    let path = write_source("overflow", "address FFF\nload s0, 41\nload s1, 42\n");
+    let output = command(&["assemble", path.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("overflow.psm:3: error: Program memory ends at address 0xFFF"));
+    assert!(!path.with_extension("hex").exists());
+}
+
+/// Firmware that echoes what it receives through the UART after a greeting, with the PicoTerm
+/// routines as they are.
+fn write_echo_source(name: &str) -> PathBuf {
+    let routines = fs::read("tests/PicoTerm_routines.psm").unwrap();
+
+    write_source(
+        name,
+        &format!(
+            "main: CALL reset_UART_macros\nLOAD s5, \"H\"\nCALL UART_TX\nLOAD s5, \"i\"\nCALL UART_TX\necho: CALL UART_RX\nJUMP Z, echo\nCALL UART_TX\nJUMP echo\n{}",
+            String::from_utf8_lossy(&routines)
+        ),
+    )
+}
+
+#[test]
+fn uart() {
+    let path = write_echo_source("uart");
+    let input = path.with_extension("txt");
+
+    fs::write(&input, "ok\r\n").unwrap();
+
+    let output = command(&[
+        "run",
+        path.to_str().unwrap(),
+        "-q",
+        "--max-cycles=200000",
+        "--uart-input",
+        input.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("UART transmitted 6 characters: \"Hiok\\r\\n\"\n"));
+
+    // At 9600 baud a character takes over 1ms, so only the first one is through after 2ms.
+    let output = command(&[
+        "run",
+        path.to_str().unwrap(),
+        "-q",
+        "--max-cycles=200000",
+        "--baud=9600",
+        "--uart-input",
+        input.to_str().unwrap(),
+    ]);
+
+    assert!(stdout(&output).starts_with("UART transmitted 1 characters: \"H\"\n"));
+}
+
+#[test]
+fn system_description() {
+    let path = write_echo_source("system");
+    let input = path.with_extension("txt");
+    let system = path.with_extension("toml");
+
+    fs::write(&input, "ok\r\n").unwrap();
+    // The UART data port is mirrored on every odd port below 80, and the status on even ones.
+    fs::write(
+        &system,
+        "[processor]\n\
+         program_memory = 1024\n\
+         hwbuild = 0x41\n\
+         clock = 50e6\n\
+         \n\
+         [[peripheral]]\n\
+         name = \"console\"\n\
+         type = \"uart\"\n\
+         baud = 57600\n\
+         \n\
+         [[port]]\n\
+         input = 0x00\n\
+         mask = 0x81\n\
+         to = \"console.status\"\n\
+         [[port]]\n\
+         input = 0x01\n\
+         mask = 0x81\n\
+         to = \"console.rx\"\n\
+         [[port]]\n\
+         output = 0x01\n\
+         to = \"console.tx\"\n\
+         [[port]]\n\
+         outputk = 0x1\n\
+         to = \"console.reset\"\n",
+    )
+    .unwrap();
+
+    let output = command(&[
+        "run",
+        path.to_str().unwrap(),
+        "-q",
+        "--max-cycles=200000",
+        "--system",
+        system.to_str().unwrap(),
+        "--uart-input",
+        input.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("UART transmitted 6 characters: \"Hiok\\r\\n\"\n"));
+    assert!(stdout(&output).contains("after 200000 clock cycles (4.000 ms)"));
+
+    fs::write(&system, "[[port]]\ninput = 0x00\nto = \"console.status\"\n").unwrap();
+
+    let output = command(&[
+        "run",
+        path.to_str().unwrap(),
+        "--system",
+        system.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("system.toml: port 1: there's no peripheral named 'console'"));
+}
+
+#[test]
+fn uart_bridges() {
+    let path = write_echo_source("bridges");
+    let path = path.to_str().unwrap();
+
+    // The run ends a little after standard input does.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+        .args(["run", path, "--uart-bridge", "stdio"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(b"ok\r").unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "Hiok\r");
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("The other end of the UART closed"));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+        .args(["run", path, "-q", "--uart-bridge", "tcp:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+
+    BufReader::new(child.stderr.as_mut().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+
+    let address = line.trim().rsplit(' ').next().unwrap();
+    let mut stream = TcpStream::connect(address).unwrap();
+    let mut received = Vec::new();
+
+    stream.write_all(b"tcp").unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    stream.read_to_end(&mut received).unwrap();
+
+    assert_eq!(received, b"Hitcp");
+    assert_eq!(child.wait().unwrap().code(), Some(0));
+
+    let output = command(&["run", path, "--uart-bridge", "serial"]);
+
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn picoterm() {
+    // Shows the switches on the red and amber LEDs and the hours on the green ones, once PicoTerm
+    // has answered a ping.
+    let routines = fs::read("tests/PicoTerm_routines.psm").unwrap();
+    let driver = "CALL reset_UART_macros
+                  CALL PicoTerm_CLS
+                  CALL PicoTerm_Ping
+                  CALL wait
+                  CALL PicoTerm_read_Switches
+                  CALL wait
+                  FETCH s0, PicoTerm_Response1
+                  STORE s0, PicoTerm_LEDs_Red
+                  FETCH s0, PicoTerm_Response2
+                  STORE s0, PicoTerm_LEDs_Amber
+                  CALL PicoTerm_Time_Value
+                  CALL wait
+                  FETCH s0, PicoTerm_Response1
+                  STORE s0, PicoTerm_LEDs_Green
+                  CALL PicoTerm_LEDs
+                  LOAD s5, \"!\"
+                  CALL UART_TX
+            done: JUMP done
+            wait: LOAD s2, 00
+                  STORE s2, PicoTerm_Response0
+            poll: CALL UART_RX
+                  FETCH s2, PicoTerm_Response0
+                  COMPARE s2, 00
+                  JUMP Z, poll
+                  RETURN
+";
+    let path = write_source(
+        "picoterm",
+        &format!("{}{}", driver, String::from_utf8_lossy(&routines)),
+    );
+    let output = command(&[
+        "run",
+        path.to_str().unwrap(),
+        "-q",
+        "--max-cycles=1000000",
+        "--switches",
+        "A5F0",
+        "--picoterm-time",
+        "1343744278",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with(
+        "PicoTerm displayed 13 characters: \"\\x1b[2J\\x1b[H\\x1b[39m!\"\n\
+         PicoTerm LEDs red ****.... amber *.*..*.* green ....***., 7-segment [    ], \
+         switches 1010010111110000\n"
+    ));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("PicoTerm LEDs red ****.... amber *.*..*.* green ....***.\n"));
+
+    let output = command(&["run", path.to_str().unwrap(), "--switches", "12345"]);
+
+    assert_eq!(output.status.code(), Some(64));
+}
+
+/// Reads from a stream until `text` comes, as a prompt does.
+fn read_until(stream: &mut TcpStream, text: &[u8]) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0; 256];
+
+    while !received.windows(text.len()).any(|window| window == text) {
+        let count = stream.read(&mut buffer).unwrap();
+
+        assert!(count > 0, "{}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buffer[..count]);
+    }
+
+    String::from_utf8_lossy(&received).into_owned()
+}
+
+#[test]
+fn i2c_eeprom_bridge() {
+    // The example writes a byte to the EEPROM when asked to through PicoTerm, after a second of
+    // start-up delay and a check of the bus.
+    let path = std::env::temp_dir().join(format!("kcpsm6sim-eeprom-{}.bin", std::process::id()));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+        .args([
+            "run",
+            "tests/m24c08_i2c_uart_bridge.psm",
+            "-q",
+            "--picoterm",
+        ])
+        .args(["--eeprom", path.to_str().unwrap(), "--uart-bridge", "tcp:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+
+    BufReader::new(child.stderr.as_mut().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+
+    let address = line.trim().rsplit(' ').next().unwrap();
+    let mut stream = TcpStream::connect(address).unwrap();
+    let welcome = read_until(&mut stream, b"> ");
+
+    assert!(welcome.contains("Bus Switch (PCA9548)... Pass"));
+    assert!(welcome.contains("1KB EEPROM (M24C08).... Pass"));
+
+    for input in [&b"W"[..], b"123", b"5A"] {
+        stream.write_all(input).unwrap();
+        read_until(&mut stream, if input == b"5A" { b"Ok" } else { b"> " });
+    }
+
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    let memory = fs::read(&path).unwrap();
+
+    fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("I2C: 6 transactions, 0 not acknowledged\n"));
+    assert_eq!(memory.len(), 1024);
+    assert_eq!(memory[0x123], 0x5A);
+    assert!(memory.iter().filter(|value| **value != 0xFF).count() == 1);
+}
+
+#[test]
+fn stimulus_and_expected_outputs() {
+    let path = write_source("stimulus", &fs::read_to_string("tests/test2.txt").unwrap());
+    let stimulus = path.with_extension("csv");
+    let expected = path.with_extension("json");
+
+    // Switch A goes from 40 to 10 after 2us, so the LEDs go from 48 to 24.
+    fs::write(
+        &stimulus,
+        "time, port, value\n0, 01, 40\n0, 02, 20\n2us, 01, 10\n",
+    )
+    .unwrap();
+    fs::write(
+        &expected,
+        r#"[
+            { "port": 4, "value": "48", "cycle": 76 },
+            { "port": 4, "value": "48" },
+            { "port": 4, "value": "48" },
+            { "port": 4, "value": "24" }
+        ]"#,
+    )
+    .unwrap();
+
+    let run = |expected: &PathBuf| {
+        command(&[
+            "run",
+            path.to_str().unwrap(),
+            "-q",
+            "--max-cycles=320",
+            "--stimulus",
+            stimulus.to_str().unwrap(),
+            "--expect",
+            expected.to_str().unwrap(),
+        ])
+    };
+    let output = run(&expected);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).contains("Outputs: 4 of 4 expected matched, 0 mismatches\n"));
+
+    let expected = path.with_extension("txt");
+
+    fs::write(
+        &expected,
+        "port, value\n04, 48\n04, 48\n04, 48\n04, 25\n04, 24\n",
+    )
+    .unwrap();
+
+    let output = run(&expected);
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stdout(&output).contains(
+        "Outputs: 3 of 5 expected matched, 2 mismatches\n\
+         Mismatch: expected OUTPUT port 04 = 25, got OUTPUT port 04 = 24 at cycle 312, at 015 \
+         (main_arth+7, line 43)\n\
+         Mismatch: expected OUTPUT port 04 = 24, the run ended first\n"
+    ));
+}
+
+/// Plays the switches and LEDs of `test2.txt` over the co-simulation protocol, and stops the run
+/// at the third write to the LEDs.
+fn play_test2(client: CoSimClient) -> (u64, Vec<String>) {
+    let mut writes = Vec::new();
+    let end = client
+        .run(|request| match request {
+            Request::Sync { .. } => vec![Reply::Run(None)],
+            Request::Read { port: 1, .. } => vec![Reply::Value(0x40)],
+            Request::Read { .. } if writes.len() == 3 => vec![Reply::Stop],
+            Request::Read { .. } => vec![Reply::Value(0x20)],
+            Request::Write { .. } => {
+                writes.push(request.to_string());
+                vec![]
+            }
+            _ => vec![],
+        })
+        .unwrap();
+
+    (end, writes)
+}
+
+#[test]
+fn cosimulation() {
+    let spawn = |socket: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_KCPSM6Sim"))
+            .args(["run", "tests/test2.txt", "-q", "--cosim", socket])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+
+        BufReader::new(child.stderr.as_mut().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+
+        (child, line.trim().rsplit(' ').next().unwrap().to_string())
+    };
+
+    let (child, address) = spawn("tcp:0");
+    let (end, writes) = play_test2(CoSimClient::connect_tcp(address).unwrap());
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        writes,
+        ["WRITE 76 04 48", "WRITE 156 04 48", "WRITE 236 04 48"]
+    );
+    assert_eq!(end, 244);
+    assert!(stdout(&output).starts_with(
+        "Stopped by the co-simulation client at 002 (main_getxy+2, line 14) after 244 clock cycles"
+    ));
+
+    if cfg!(unix) {
+        let path = std::env::temp_dir().join(format!("kcpsm6sim-{}.sock", std::process::id()));
+        let (child, _) = spawn(&format!("unix:{}", path.display()));
+        let (_, writes) = play_test2(CoSimClient::connect_unix(&path).unwrap());
+
+        assert_eq!(child.wait_with_output().unwrap().status.code(), Some(0));
+        assert_eq!(writes.len(), 3);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn process_peripherals() {
+    let path = write_source("process", &fs::read_to_string("tests/test2.txt").unwrap());
+    let system = path.with_extension("toml");
+    let expected = path.with_extension("csv");
+    let log = path.with_file_name("leds.log");
+
+    // The switches of test2.txt read 40 and 20, and the run stops after the LEDs are lit thrice.
+    fs::write(
+        path.with_file_name("switches.sh"),
+        "writes=0
+         while read request cycle port value; do
+             case $request in
+                 READ) if [ $writes -ge 3 ]; then echo STOP
+                       elif [ $port = 01 ]; then echo 'VALUE 40'
+                       else echo 'VALUE 20'; fi ;;
+                 WRITE) writes=$((writes + 1)); echo $cycle $value >> leds.log ;;
+             esac
+         done
+",
+    )
+    .unwrap();
+    fs::write(
+        &expected,
+        "port, value
+04, 48
+04, 48
+04, 48
+",
+    )
+    .unwrap();
+
+    let run = |command: &str, timeout: f64| {
+        fs::write(
+            &system,
+            format!(
+                "[[peripheral]]
+                 name = \"switches\"
+                 type = \"process\"
+                 command = {}
+                 timeout = {}
+                 
+                 [[port]]
+                 input = 0x00
+                 mask = 0xFC
+                 to = \"switches\"
+                 [[port]]
+                 output = 0x04
+                 to = \"switches\"
+",
+                command, timeout
+            ),
+        )
+        .unwrap();
+        self::command(&[
+            "run",
+            path.to_str().unwrap(),
+            "-q",
+            "--max-cycles=1000",
+            "--system",
+            system.to_str().unwrap(),
+            "--expect",
+            expected.to_str().unwrap(),
+        ])
+    };
+    let _ = fs::remove_file(&log);
+    let output = run("\"sh switches.sh\"", 5.0);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout(&output).starts_with("Stopped by peripheral switches at "));
+    assert!(stdout(&output).contains("Outputs: 3 of 3 expected matched, 0 mismatches\n"));
+    assert_eq!(fs::read_to_string(&log).unwrap(), "76 48\n156 48\n236 48\n");
+
+    let output = run(r#"["sh", "-c", "while read line; do :; done"]"#, 0.1);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "after 2 clock cycles (20 ns): peripheral switches: \
+         no answer to INPUT port 01 at cycle 0 within 100ms"
+    ));
+}
+
+#[test]
+fn usage_and_io_errors() {
+    assert_eq!(command(&[]).status.code(), Some(64));
+    assert_eq!(command(&["frobnicate"]).status.code(), Some(64));
+    assert_eq!(
+        command(&["run", "tests/test.s", "--max-cycles"])
+            .status
+            .code(),
+        Some(64)
+    );
+    assert_eq!(
+        command(&["check", "tests/does_not_exist.psm"])
+            .status
+            .code(),
+        Some(74)
+    );
+}